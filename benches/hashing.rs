@@ -0,0 +1,52 @@
+//! Benchmarks the throughput of calculate_hash with different buffer sizes and memory mapping
+
+#[macro_use]
+extern crate criterion;
+extern crate arkhash;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use arkhash::util::calculate_hash;
+use arkhash::{Algorithm, Options};
+use criterion::{BenchmarkId, Criterion, Throughput};
+
+const FOLDER: &str = "benchenvironment";
+const FILE_SIZE: usize = 64 * 1024 * 1024;
+
+/// Hashes a file of FILE_SIZE bytes, reading it in chunks of different sizes and memory mapping it
+fn hashing(c: &mut Criterion) {
+    fs::create_dir_all(FOLDER).unwrap();
+    let content: Vec<u8> = (0..FILE_SIZE).map(|i| (i % 251) as u8).collect();
+    fs::write(Path::new(FOLDER).join("large"), content).unwrap();
+
+    let mut group = c.benchmark_group("calculate_hash");
+    group.throughput(Throughput::Bytes(FILE_SIZE as u64));
+    group.sample_size(10);
+
+    for &buffer_size in &[1024, 64 * 1024, 1024 * 1024] {
+        let opts = Options::builder()
+            .algorithm(Algorithm::Sha1)
+            .buffer_size(buffer_size)
+            .build()
+            .unwrap();
+        group.bench_with_input(BenchmarkId::new("read", buffer_size), &opts, |b, opts| {
+            b.iter(|| calculate_hash(PathBuf::from("./large"), Path::new(FOLDER), opts).unwrap())
+        });
+    }
+
+    let opts = Options::builder()
+        .algorithm(Algorithm::Sha1)
+        .mmap_threshold(Some(0))
+        .build()
+        .unwrap();
+    group.bench_with_input(BenchmarkId::new("mmap", FILE_SIZE), &opts, |b, opts| {
+        b.iter(|| calculate_hash(PathBuf::from("./large"), Path::new(FOLDER), opts).unwrap())
+    });
+
+    group.finish();
+    fs::remove_dir_all(FOLDER).unwrap();
+}
+
+criterion_group!(benches, hashing);
+criterion_main!(benches);
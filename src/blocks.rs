@@ -0,0 +1,351 @@
+//! This module implements hashing of files in blocks.
+//!
+//! With a block size, update mode hashes every block of that many bytes of a file on its own, besides the whole file.
+//! The hashes of the blocks are kept next to the _algorithm_sum.txt in _algorithm_sum.blocks.txt,
+//! one line per file: `<path>\t<block size>\t<hash of block 0>\t<hash of block 1>...`.
+//! Paths are escaped like in the _algorithm_sum.txt and tabs in paths are written as `\x09`.
+//! When verify mode finds a file whose hash changed, it compares the hashes of its blocks
+//! to tell which byte ranges of the file changed.
+
+extern crate serde_json;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+
+use super::hasher::Hasher;
+use super::manifest::{escape_path, unescape_path};
+use super::util::portable_path;
+use super::util::Algorithm;
+
+/// The hashes of the blocks of a single file
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockHashes {
+    /// Path to the file, relative to the directory of the _algorithm_sum.blocks.txt
+    pub path: PathBuf,
+    /// Number of bytes of every block but the last one
+    pub block_size: u64,
+    /// The hashes of the blocks in the order of the blocks
+    pub hashes: Vec<String>,
+}
+
+impl BlockHashes {
+    /// Returns the line of the file in the _algorithm_sum.blocks.txt, including the newline
+    pub fn to_line(&self) -> String {
+        let path = portable_path(&self.path);
+        let path = match escape_path(&path) {
+            Some(escaped) => escaped,
+            None => path.to_string_lossy().to_string(),
+        };
+
+        let mut line = format!("{}\t{}", path.replace('\t', "\\x09"), self.block_size);
+        for hash in &self.hashes {
+            line.push('\t');
+            line.push_str(hash);
+        }
+        line.push('\n');
+        line
+    }
+
+    /// Parses a line of an _algorithm_sum.blocks.txt, returns None if the line is malformed
+    ///
+    /// # Arguments
+    ///
+    /// * `line` The line without its newline
+    pub fn from_line(line: &str) -> Option<BlockHashes> {
+        let mut fields = line.split('\t');
+        let path = unescape_path(fields.next()?)?;
+        let block_size = fields.next()?.parse().ok().filter(|&size| size > 0)?;
+        let hashes = fields.map(str::to_string).collect();
+
+        Some(BlockHashes {
+            path,
+            block_size,
+            hashes,
+        })
+    }
+
+    /// Compares the hashes of the blocks to the ones of the same file hashed later.
+    /// Returns the ranges of consecutive blocks that differ, as indices of their first and last block.
+    /// Blocks that only one of both has, because the size of the file changed, differ as well.
+    /// Every block differs if the block sizes are not the same.
+    ///
+    /// # Arguments
+    ///
+    /// * `current` The hashes of the blocks of the file as it is now
+    pub fn differing_blocks(&self, current: &BlockHashes) -> Vec<(u64, u64)> {
+        let blocks = self.hashes.len().max(current.hashes.len());
+        let mut ranges: Vec<(u64, u64)> = Vec::new();
+
+        for i in 0..blocks {
+            let same = self.block_size == current.block_size
+                && self.hashes.get(i).map(|hash| hash.to_lowercase())
+                    == current.hashes.get(i).map(|hash| hash.to_lowercase());
+            if same {
+                continue;
+            }
+
+            let i = i as u64;
+            match ranges.last_mut() {
+                Some(range) if range.1 + 1 == i => range.1 = i,
+                _ => ranges.push((i, i)),
+            }
+        }
+
+        ranges
+    }
+}
+
+/// The blocks of a file whose hash changed since it was recorded
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangedBlocks {
+    /// Path to the file
+    pub path: String,
+    /// Number of bytes of every block but the last one
+    pub block_size: u64,
+    /// Number of blocks of the file, the larger one of the recorded and the current number
+    pub blocks: u64,
+    /// Ranges of consecutive blocks that changed, as indices of their first and last block
+    pub ranges: Vec<(u64, u64)>,
+}
+
+impl ChangedBlocks {
+    /// Describes which blocks changed, eg `blocks 3, 7-9 of 12 differ (1048576 bytes each)`
+    pub fn describe(&self) -> String {
+        let ranges: Vec<String> = self
+            .ranges
+            .iter()
+            .map(|&(first, last)| {
+                if first == last {
+                    first.to_string()
+                } else {
+                    format!("{}-{}", first, last)
+                }
+            })
+            .collect();
+
+        format!(
+            "blocks {} of {} differ ({} bytes each)",
+            ranges.join(", "),
+            self.blocks,
+            self.block_size
+        )
+    }
+
+    /// Returns the changed blocks as a JSON object, byte ranges include their start and exclude their end
+    pub fn to_json(&self) -> serde_json::Value {
+        let ranges: Vec<serde_json::Value> = self
+            .ranges
+            .iter()
+            .map(|&(first, last)| {
+                serde_json::json!({
+                    "blocks": [first, last],
+                    "bytes": [first * self.block_size, (last + 1) * self.block_size],
+                })
+            })
+            .collect();
+
+        serde_json::json!({
+            "path": self.path,
+            "block_size": self.block_size,
+            "blocks": self.blocks,
+            "ranges": ranges,
+        })
+    }
+}
+
+/// Asks a worker thread to hash the blocks of a file while it hashes the file
+#[derive(Debug, Clone)]
+pub struct BlockRequest {
+    /// Number of bytes of every block but the last one
+    pub block_size: u64,
+    /// A channel to return the hashes of the blocks to the task generator
+    pub result_chan: Sender<BlockHashes>,
+}
+
+/// The contents of an _algorithm_sum.blocks.txt
+#[derive(Debug, Clone, Default)]
+pub struct BlockManifest {
+    /// The hashes of the blocks of every listed file by its path
+    entries: BTreeMap<PathBuf, BlockHashes>,
+}
+
+impl BlockManifest {
+    /// Returns the path to the _algorithm_sum.blocks.txt file of a directory
+    ///
+    /// # Arguments
+    ///
+    /// * `workdir` Path to the directory containing the _algorithm_sum.blocks.txt
+    /// * `algorithm` The used algorithm
+    pub fn path(workdir: &Path, algorithm: Algorithm) -> PathBuf {
+        workdir.join(format!("{}sum.blocks.txt", algorithm))
+    }
+
+    /// Returns the path to the temporary file the hashes are written to before they replace the _algorithm_sum.blocks.txt
+    ///
+    /// # Arguments
+    ///
+    /// * `workdir` Path to the directory containing the _algorithm_sum.blocks.txt
+    /// * `algorithm` The used algorithm
+    fn temp_path(workdir: &Path, algorithm: Algorithm) -> PathBuf {
+        workdir.join(format!(".{}sum.blocks.txt.tmp", algorithm))
+    }
+
+    /// Reads the _algorithm_sum.blocks.txt of a directory, a directory without one has no block hashes.
+    /// Malformed lines are skipped.
+    ///
+    /// # Arguments
+    ///
+    /// * `workdir` Path to the directory containing the _algorithm_sum.blocks.txt
+    /// * `algorithm` The used algorithm
+    pub fn read(workdir: &Path, algorithm: Algorithm) -> Result<BlockManifest, io::Error> {
+        let file = match fs::File::open(BlockManifest::path(workdir, algorithm)) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                return Ok(BlockManifest::default())
+            }
+            Err(e) => return Err(e),
+        };
+
+        let mut manifest = BlockManifest::default();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if let Some(hashes) = BlockHashes::from_line(line.trim_end_matches('\r')) {
+                manifest.insert(hashes);
+            }
+        }
+
+        Ok(manifest)
+    }
+
+    /// Returns the hashes of the blocks of a file, if there are any
+    ///
+    /// # Arguments
+    ///
+    /// * `path` Path to the file, relative to the directory of the _algorithm_sum.blocks.txt
+    pub fn get(&self, path: &Path) -> Option<&BlockHashes> {
+        self.entries.get(path)
+    }
+
+    /// Inserts the hashes of the blocks of a file, replacing the previous ones
+    ///
+    /// # Arguments
+    ///
+    /// * `hashes` The hashes to insert
+    pub fn insert(&mut self, hashes: BlockHashes) {
+        self.entries.insert(hashes.path.clone(), hashes);
+    }
+
+    /// Removes the hashes of every file for which keep returns false
+    ///
+    /// # Arguments
+    ///
+    /// * `keep` Function that decides whether or not the hashes of a file are kept
+    pub fn retain<F: FnMut(&Path) -> bool>(&mut self, mut keep: F) {
+        self.entries.retain(|path, _| keep(path));
+    }
+
+    /// Indicates that no file has block hashes
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Writes the hashes sorted by path to the _algorithm_sum.blocks.txt of a directory.
+    /// Like the _algorithm_sum.txt, the file is replaced atomically. Without any hashes, the file is removed.
+    ///
+    /// # Arguments
+    ///
+    /// * `workdir` Path to the directory containing the _algorithm_sum.blocks.txt
+    /// * `algorithm` The used algorithm
+    pub fn write(&self, workdir: &Path, algorithm: Algorithm) -> Result<(), io::Error> {
+        let path = BlockManifest::path(workdir, algorithm);
+        if self.is_empty() {
+            return match fs::remove_file(path) {
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+                result => result,
+            };
+        }
+
+        let temp_path = BlockManifest::temp_path(workdir, algorithm);
+        super::util::write_atomically(&path, &temp_path, |file| {
+            for hashes in self.entries.values() {
+                file.write_all(hashes.to_line().as_bytes())?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Returns whether the given path is the path to the _algorithm_sum.blocks.txt or its temporary file
+    ///
+    /// # Arguments
+    ///
+    /// * `path` The path to be checked, relative to the directory of the _algorithm_sum.blocks.txt
+    /// * `algorithm` The used algorithm
+    pub fn is_own_file(path: &Path, algorithm: Algorithm) -> bool {
+        let current = Path::new(".");
+        path == current.join(format!("{}sum.blocks.txt", algorithm))
+            || path == current.join(format!(".{}sum.blocks.txt.tmp", algorithm))
+    }
+}
+
+/// Hashes the data fed to it in blocks of a fixed size
+pub struct BlockHasher {
+    /// The algorithm every block is hashed with
+    algorithm: Algorithm,
+    /// Number of bytes of every block but the last one
+    block_size: u64,
+    /// The hasher of the current block
+    current: Box<dyn Hasher>,
+    /// Number of bytes fed to the hasher of the current block
+    filled: u64,
+    /// The hashes of the finished blocks
+    hashes: Vec<String>,
+}
+
+impl BlockHasher {
+    /// Creates a BlockHasher
+    ///
+    /// # Arguments
+    ///
+    /// * `algorithm` The algorithm every block is hashed with
+    /// * `block_size` Number of bytes of every block, must be greater than 0
+    pub fn new(algorithm: Algorithm, block_size: u64) -> BlockHasher {
+        BlockHasher {
+            algorithm,
+            block_size,
+            current: algorithm.hasher(),
+            filled: 0,
+            hashes: Vec::new(),
+        }
+    }
+
+    /// Feeds the next bytes of the file to the hasher
+    ///
+    /// # Arguments
+    ///
+    /// * `data` The bytes following the ones fed before
+    pub fn input(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let remaining = (self.block_size - self.filled).min(data.len() as u64) as usize;
+            self.current.update(&data[..remaining]);
+            self.filled += remaining as u64;
+            data = &data[remaining..];
+
+            if self.filled == self.block_size {
+                let finished = std::mem::replace(&mut self.current, self.algorithm.hasher());
+                self.hashes.push(finished.finalize());
+                self.filled = 0;
+            }
+        }
+    }
+
+    /// Returns the hashes of all blocks, an empty file has no blocks
+    pub fn finish(mut self) -> Vec<String> {
+        if self.filled > 0 {
+            self.hashes.push(self.current.finalize());
+        }
+        self.hashes
+    }
+}
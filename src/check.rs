@@ -0,0 +1,210 @@
+//! This module implements the check manifest mode, which looks for problems in _algorithm_sum.txt files without hashing
+
+extern crate log;
+
+use std::path::{Path, PathBuf};
+
+use self::log::error;
+
+use super::manifest::{DuplicatePath, Manifest};
+use super::util::Options;
+
+/// The problems found in a single _algorithm_sum.txt
+#[derive(Debug, Clone, Default)]
+pub struct ManifestCheck {
+    /// Path to the _algorithm_sum.txt
+    pub path: PathBuf,
+    /// Numbers of the lines that could not be read, starting at 1
+    pub malformed_lines: Vec<usize>,
+    /// The paths that are listed on more than one line
+    pub duplicates: Vec<DuplicatePath>,
+    /// Whether or not the duplicate lines were removed, keeping the last line of every path
+    pub deduplicated: bool,
+}
+
+impl ManifestCheck {
+    /// Indicates that every line could be read and no path is listed more than once, or the duplicates were removed
+    pub fn is_ok(&self) -> bool {
+        self.malformed_lines.is_empty() && (self.duplicates.is_empty() || self.deduplicated)
+    }
+
+    /// Returns the number of lines that are removed when the duplicates are removed
+    pub fn duplicate_lines(&self) -> usize {
+        self.duplicates
+            .iter()
+            .map(|duplicate| duplicate.lines.len() - 1)
+            .sum()
+    }
+}
+
+/// The result of checking the _algorithm_sum.txt files of some directories
+#[derive(Debug, Clone, Default)]
+pub struct CheckReport {
+    /// The _algorithm_sum.txt files that were checked
+    pub manifests: Vec<ManifestCheck>,
+    /// Descriptions of _algorithm_sum.txt files that could not be read or written
+    pub failed: Vec<String>,
+}
+
+impl CheckReport {
+    /// Indicates that every _algorithm_sum.txt could be checked and no problems are left in any of them
+    pub fn is_ok(&self) -> bool {
+        self.failed.is_empty() && self.manifests.iter().all(ManifestCheck::is_ok)
+    }
+
+    /// Returns the number of paths that are listed more than once
+    pub fn duplicate_paths(&self) -> usize {
+        self.manifests
+            .iter()
+            .map(|manifest| manifest.duplicates.len())
+            .sum()
+    }
+
+    /// Returns the number of paths that are listed more than once with different hashes
+    pub fn conflicting_paths(&self) -> usize {
+        self.manifests
+            .iter()
+            .flat_map(|manifest| &manifest.duplicates)
+            .filter(|duplicate| duplicate.conflicting)
+            .count()
+    }
+
+    /// Returns the number of lines that could not be read
+    pub fn malformed_lines(&self) -> usize {
+        self.manifests
+            .iter()
+            .map(|manifest| manifest.malformed_lines.len())
+            .sum()
+    }
+}
+
+/// Reads the _algorithm_sum.txt files of some directories and reports lines that could not be read,
+/// paths that are listed more than once and whether those lines list different hashes. Nothing is hashed.
+/// With opts.detect_algorithm, every _algorithm_sum.txt of a directory is checked.
+/// With opts.dedupe, the duplicate lines are removed, keeping the last line of every path, which is the one
+/// update and verify mode use. A file with lines that could not be read is not rewritten, they would be lost.
+/// Errors are emitted through the log crate unless in loglevel quiet, the problems found are left to the caller
+/// to print. In a dry run, nothing is written.
+///
+/// # Arguments
+///
+/// * `opts` An Options object containing information about the program behavior
+///
+/// # Returns
+/// A CheckReport describing every _algorithm_sum.txt.
+pub fn check_manifest(opts: &Options) -> CheckReport {
+    let directories: Vec<PathBuf> = if opts.subdir_mode {
+        opts.folders
+            .iter()
+            .flat_map(|folder| {
+                super::util::find_subdirectories(
+                    folder,
+                    opts.max_depth,
+                    opts.hidden,
+                    Vec::new(),
+                    &opts.exclude,
+                )
+            })
+            .collect()
+    } else {
+        opts.folders.clone()
+    };
+
+    let mut report = CheckReport::default();
+    for directory in &directories {
+        check_directory(directory, opts, &mut report);
+    }
+
+    report
+}
+
+/// Checks the _algorithm_sum.txt files of a directory and removes their duplicate lines with opts.dedupe
+///
+/// # Arguments
+///
+/// * `path` The path to the directory
+/// * `opts` An Options object containing the algorithms and whether or not this is a dry run
+/// * `report` The CheckReport the _algorithm_sum.txt files are recorded in
+fn check_directory(path: &Path, opts: &Options, report: &mut CheckReport) {
+    let algorithms = if opts.detect_algorithm {
+        Manifest::find_algorithms(path)
+    } else {
+        opts.algorithms()
+    };
+
+    for algorithm in algorithms {
+        let manifest_path = match Manifest::find(path, algorithm) {
+            Some(manifest_path) => manifest_path,
+            None => continue,
+        };
+        let opts = Options {
+            algorithm,
+            compress: manifest_path == Manifest::gzip_path(path, algorithm),
+            xattr: false,
+            ..opts.clone()
+        };
+
+        let manifest = match Manifest::read_file(&manifest_path, &opts) {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                record_failure(
+                    &format!("could not read {}: {}", manifest_path.display(), e),
+                    &opts,
+                    report,
+                );
+                continue;
+            }
+        };
+
+        let mut check = ManifestCheck {
+            path: manifest_path,
+            malformed_lines: manifest.malformed_lines().to_vec(),
+            duplicates: manifest.duplicates().to_vec(),
+            deduplicated: false,
+        };
+        // a file with lines that could not be read keeps its duplicates, nothing is written in a dry run
+        if opts.dedupe
+            && !check.duplicates.is_empty()
+            && check.malformed_lines.is_empty()
+            && !opts.dry_run
+        {
+            match manifest.write(path, &opts) {
+                Ok(()) => check.deduplicated = true,
+                Err(e) => record_failure(
+                    &format!("could not write {}: {}", check.path.display(), e),
+                    &opts,
+                    report,
+                ),
+            }
+        }
+
+        report.manifests.push(check);
+    }
+}
+
+/// Records an _algorithm_sum.txt that could not be checked in the report and emits it as an error
+///
+/// # Arguments
+///
+/// * `message` Description of the failure
+/// * `opts` The Options object containing the loglevel
+/// * `report` The CheckReport the failure is recorded in
+fn record_failure(message: &str, opts: &Options, report: &mut CheckReport) {
+    if !opts.loglevel_quiet() {
+        error!("{}", message);
+    }
+    report.failed.push(message.to_string());
+}
+
+/// Formats line numbers for messages, eg "1, 4, 7"
+///
+/// # Arguments
+///
+/// * `lines` The line numbers
+pub fn format_lines(lines: &[usize]) -> String {
+    lines
+        .iter()
+        .map(|line| line.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
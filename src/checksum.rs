@@ -0,0 +1,65 @@
+//! This module implements the checksum algorithms CRC32 and Adler-32 as hashers of the digest crate.
+//!
+//! Both detect accidental corruption like flipped bits much faster than the hash functions,
+//! but they are not cryptographic: anyone can change a file without changing its checksum on purpose.
+//! The 32 bit checksum is written as 8 hexadecimal characters, most significant byte first.
+
+extern crate adler;
+extern crate crc32fast;
+extern crate digest;
+
+use self::digest::generic_array::typenum::U4;
+use self::digest::generic_array::GenericArray;
+use self::digest::{FixedOutput, Input, Reset};
+
+/// CRC32 with the polynomial of zlib and PNG, as calculated by `crc32` of Archive::Zip or `7z h`
+#[derive(Clone, Default)]
+pub struct Crc32 {
+    hasher: crc32fast::Hasher,
+}
+
+impl Input for Crc32 {
+    fn input<B: AsRef<[u8]>>(&mut self, data: B) {
+        self.hasher.update(data.as_ref());
+    }
+}
+
+impl FixedOutput for Crc32 {
+    type OutputSize = U4;
+
+    fn fixed_result(self) -> GenericArray<u8, U4> {
+        GenericArray::clone_from_slice(&self.hasher.finalize().to_be_bytes())
+    }
+}
+
+impl Reset for Crc32 {
+    fn reset(&mut self) {
+        self.hasher.reset();
+    }
+}
+
+/// The Adler-32 checksum of zlib
+#[derive(Clone, Default)]
+pub struct Adler32 {
+    hasher: adler::Adler32,
+}
+
+impl Input for Adler32 {
+    fn input<B: AsRef<[u8]>>(&mut self, data: B) {
+        self.hasher.write_slice(data.as_ref());
+    }
+}
+
+impl FixedOutput for Adler32 {
+    type OutputSize = U4;
+
+    fn fixed_result(self) -> GenericArray<u8, U4> {
+        GenericArray::clone_from_slice(&self.hasher.checksum().to_be_bytes())
+    }
+}
+
+impl Reset for Adler32 {
+    fn reset(&mut self) {
+        self.hasher = adler::Adler32::new();
+    }
+}
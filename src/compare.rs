@@ -0,0 +1,213 @@
+//! This module implements the compare mode, which compares the files of two directories by their hashes
+
+extern crate crossbeam_deque;
+extern crate log;
+extern crate serde_json;
+
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Arc;
+
+use self::crossbeam_deque::Injector;
+use self::log::error;
+
+use super::util::{walk_files, HashResult, HashTask, Options, Symlinks};
+
+/// The result of comparing two directories, every list is sorted by path
+#[derive(Debug, Clone, Default)]
+pub struct CompareReport {
+    /// Files with the same content in both directories
+    pub identical: Vec<PathBuf>,
+    /// Files whose content differs between the directories
+    pub differing: Vec<PathBuf>,
+    /// Files that only exist in the first directory
+    pub only_in_first: Vec<PathBuf>,
+    /// Files that only exist in the second directory
+    pub only_in_second: Vec<PathBuf>,
+    /// Descriptions of files that could not be hashed
+    pub failed: Vec<String>,
+}
+
+impl CompareReport {
+    /// Indicates that both directories contain the same files with the same content
+    pub fn is_identical(&self) -> bool {
+        self.differing.is_empty()
+            && self.only_in_first.is_empty()
+            && self.only_in_second.is_empty()
+            && self.failed.is_empty()
+    }
+
+    /// Returns a JSON object listing the files of every category
+    pub fn to_json(&self) -> serde_json::Value {
+        let paths = |paths: &[PathBuf]| -> Vec<String> {
+            paths
+                .iter()
+                .map(|path| path.to_string_lossy().to_string())
+                .collect()
+        };
+
+        serde_json::json!({
+            "identical": paths(&self.identical),
+            "differing": paths(&self.differing),
+            "only_in_first": paths(&self.only_in_first),
+            "only_in_second": paths(&self.only_in_second),
+            "failed": self.failed,
+        })
+    }
+}
+
+/// Compares the files of the two directories given in opts.folders, including their subdirectories.
+/// Files that exist in both directories are hashed on opts.threads worker threads,
+/// files whose sizes differ are reported as differing without hashing them.
+/// No _algorithm_sum.txt is read or written.
+/// Files that can not be hashed are emitted as errors through the log crate unless in loglevel quiet,
+/// the outcome is left to the caller to print.
+///
+/// # Arguments
+///
+/// * `opts` An Options object containing the directories and the algorithm
+///
+/// # Returns
+/// A CompareReport describing the outcome for every file, or an error if a directory does not exist.
+pub fn compare(opts: &Options) -> Result<CompareReport, io::Error> {
+    let (first, second) = match opts.folders.as_slice() {
+        [first, second] => (first.as_path(), second.as_path()),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "compare mode compares exactly two directories",
+            ))
+        }
+    };
+    for directory in &[first, second] {
+        if !directory.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{}: not a directory", directory.display()),
+            ));
+        }
+    }
+
+    let walk =
+        |directory: &Path| -> BTreeSet<PathBuf> { walk_files(directory, true, opts).collect() };
+    let (first_files, second_files) = (walk(first), walk(second));
+
+    let mut report = CompareReport {
+        only_in_first: first_files.difference(&second_files).cloned().collect(),
+        only_in_second: second_files.difference(&first_files).cloned().collect(),
+        ..CompareReport::default()
+    };
+
+    // two files are compared with a single algorithm, further ones would not tell them apart any better
+    let shared_opts = Arc::new(opts.with_algorithm(opts.algorithm));
+    let q = Arc::new(Injector::new());
+    let producer_finished = Arc::new(AtomicBool::new(false));
+    let mut worker_handles = Vec::new();
+    let num_threads = opts.threads.count();
+    super::util::log_thread_count(num_threads, opts);
+    super::util::execute_workers(
+        num_threads,
+        Arc::clone(&q),
+        Arc::clone(&producer_finished),
+        &mut worker_handles,
+    );
+
+    let (first_sender, first_receiver) = channel();
+    let (second_sender, second_receiver) = channel();
+    for path in first_files.intersection(&second_files) {
+        if file_size(&first.join(path), opts) != file_size(&second.join(path), opts) {
+            report.differing.push(path.clone());
+            continue;
+        }
+
+        for (workdir, sender) in &[(first, &first_sender), (second, &second_sender)] {
+            q.push(HashTask {
+                path: path.clone(),
+                workdir: workdir.to_path_buf(),
+                opts: Arc::clone(&shared_opts),
+                cmp: String::new(),
+                result_chan: (*sender).clone(),
+                blocks: None,
+                cancelled: None,
+                read_bytes: None,
+            });
+        }
+    }
+    drop(first_sender);
+    drop(second_sender);
+    producer_finished.store(true, Ordering::Relaxed);
+
+    let first_hashes = receive_hashes(first_receiver, opts, &mut report.failed);
+    let second_hashes = receive_hashes(second_receiver, opts, &mut report.failed);
+    for handle in worker_handles {
+        handle.join().unwrap();
+    }
+
+    // files that could not be hashed on one side are only reported as failed
+    for (path, hash) in &first_hashes {
+        match second_hashes.get(path) {
+            Some(second_hash) if second_hash == hash => report.identical.push(path.clone()),
+            Some(_) => report.differing.push(path.clone()),
+            None => {}
+        }
+    }
+    report.identical.sort();
+    report.differing.sort();
+    report.failed.sort();
+
+    Ok(report)
+}
+
+/// Returns the size of a file, or None if its metadata can not be read
+///
+/// # Arguments
+///
+/// * `path` Path to the file
+/// * `opts` An Options object describing how symbolic links are handled
+fn file_size(path: &Path, opts: &Options) -> Option<u64> {
+    let metadata = if opts.symlinks == Symlinks::HashLink {
+        fs::symlink_metadata(path)
+    } else {
+        fs::metadata(path)
+    };
+    metadata.ok().map(|metadata| metadata.len())
+}
+
+/// Collects the hashes of the files of one directory until every HashTask is done
+///
+/// # Arguments
+///
+/// * `receiver` The receiving end of the result channel of the HashTasks
+/// * `opts` An Options object containing the loglevel and the algorithm
+/// * `failed` The descriptions of files that could not be hashed are appended to this vector
+fn receive_hashes(
+    receiver: Receiver<HashResult>,
+    opts: &Options,
+    failed: &mut Vec<String>,
+) -> HashMap<PathBuf, String> {
+    let hashline_re = super::util::regex_from_opts(opts).unwrap();
+    let mut hashes = HashMap::new();
+
+    for task_result in receiver {
+        match task_result {
+            Ok((hashlines, _)) => {
+                if let Some((hash, path)) = super::util::parse_hashline(&hashline_re, &hashlines[0])
+                {
+                    hashes.insert(path, hash);
+                }
+            }
+            Err(e) => {
+                if !opts.loglevel_quiet() {
+                    error!("could not hash file! {}", e);
+                }
+                failed.push(e.to_string());
+            }
+        }
+    }
+
+    hashes
+}
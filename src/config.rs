@@ -0,0 +1,289 @@
+//! This module implements the optional configuration file arkhash.toml, which holds defaults for the options.
+//!
+//! The file is searched in the working directory first, then in `$XDG_CONFIG_HOME/arkhash/` or `~/.config/arkhash/`,
+//! the first one found is used. Every setting is named like a long option without its leading dashes,
+//! eg `algorithm = "sha256"`, `threads = 8` or `respect-gitignore = true`, underscores may replace the dashes.
+//! The settings are turned into arguments and placed in front of the arguments of the command line,
+//! so options given there take precedence over the file, which takes precedence over the built-in defaults.
+//! Modes and directories can only be given on the command line.
+//!
+//! The same settings can be given as environment variables, eg `ARKHASH_THREADS=8`, which take precedence
+//! over the file and are overridden by the command line. The settings of both are merged by name before they are
+//! turned into arguments, so a flag that is false in the environment switches off the flag of the file,
+//! and `--no-FLAG` on the command line switches off the flag of either.
+
+extern crate toml;
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use self::toml::Value;
+
+/// Name of the configuration file
+pub const CONFIG_FILE_NAME: &str = "arkhash.toml";
+
+/// Prefix of the environment variables holding settings
+pub const ENV_PREFIX: &str = "ARKHASH_";
+
+/// The long options that take a value and can be set in the configuration file, without their leading dashes
+pub const CONFIG_KEYS: &[&str] = &[
+    "algorithm",
+    "threads",
+    "parallel-directories",
+    "schedule",
+    "symlinks",
+    "hidden",
+    "exclude",
+    "loglevel",
+    "log-file",
+    "time-format",
+    "format",
+    "state-dir",
+    "state-template",
+    "max-depth",
+    "buffer-size",
+    "mmap-threshold",
+    "block-size",
+    "max-rate",
+    "timeout",
+    "debounce",
+    "paths",
+    "sign",
+    "verify-sig",
+    "repair-from",
+];
+
+/// The long options without a value that can be set in the configuration file, without their leading dashes
+pub const CONFIG_FLAGS: &[&str] = &[
+    "respect-gitignore",
+    "force-progress",
+    "title",
+    "quiet",
+    "emit-jsonl",
+    "si",
+    "machine",
+    "no-monthly-state",
+    "wait",
+    "fail-fast",
+    "null",
+    "tag",
+    "compat-format",
+    "compress",
+    "manifest-checksum",
+    "trust-mtime",
+    "xattr",
+    "trust-xattr",
+    "check-metadata",
+];
+
+/// The value of a setting of the configuration file or the environment
+#[derive(Debug, Clone, PartialEq)]
+pub enum Setting {
+    /// A long option without a value, which is only given if it is true
+    Flag(bool),
+    /// A long option with its value
+    Value(String),
+}
+
+/// Settings by the name of their long option without the leading dashes
+pub type Settings = BTreeMap<String, Setting>;
+
+/// Returns the paths the configuration file is searched at, in the order they are searched
+pub fn search_paths() -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from(CONFIG_FILE_NAME)];
+    let non_empty = |name| env::var_os(name).filter(|value| !value.is_empty());
+    let config_home = non_empty("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| non_empty("HOME").map(|home| PathBuf::from(home).join(".config")));
+    if let Some(config_home) = config_home {
+        paths.push(config_home.join("arkhash").join(CONFIG_FILE_NAME));
+    }
+    paths
+}
+
+/// Returns the path of the configuration file that is used, None if there is none
+pub fn find() -> Option<PathBuf> {
+    search_paths().into_iter().find(|path| path.is_file())
+}
+
+/// Inserts the settings of the configuration file and the environment between the name of the program
+/// and the arguments of the command line, so the command line takes precedence over the environment,
+/// which takes precedence over the file. With `--no-config` among the arguments, no file is read.
+///
+/// # Arguments
+///
+/// * `args` The name of the program followed by the arguments of the command line
+pub fn with_config_and_env(args: Vec<String>) -> Result<Vec<String>, String> {
+    let mut settings = Settings::new();
+    if !args.iter().skip(1).any(|arg| arg == "--no-config") {
+        if let Some(path) = find() {
+            settings.extend(read(&path)?);
+        }
+    }
+    // variables that are not valid unicode can not hold a setting
+    settings.extend(env_settings(env::vars_os().filter_map(|(name, value)| {
+        Some((name.into_string().ok()?, value.into_string().ok()?))
+    }))?);
+
+    Ok(with_settings(args, settings))
+}
+
+/// Inserts settings as arguments between the name of the program and the arguments of the command line,
+/// so the command line takes precedence over the settings. A `--no-FLAG` argument switches off a flag of the settings
+/// and is removed from the arguments.
+///
+/// # Arguments
+///
+/// * `args` The name of the program followed by the arguments of the command line
+/// * `settings` The merged settings of the configuration file and the environment
+pub fn with_settings(args: Vec<String>, mut settings: Settings) -> Vec<String> {
+    let mut command_line = Vec::new();
+    for (i, arg) in args.into_iter().enumerate() {
+        let negated = arg
+            .strip_prefix("--no-")
+            .map(|name| name.replace('_', "-"))
+            .filter(|name| i > 0 && CONFIG_FLAGS.contains(&name.as_str()));
+        match negated {
+            Some(name) => {
+                settings.insert(name, Setting::Flag(false));
+            }
+            None => command_line.push(arg),
+        }
+    }
+
+    let position = command_line.len().min(1);
+    command_line.splice(position..position, to_args(&settings));
+    command_line
+}
+
+/// Converts settings to arguments, eg `threads = 8` to `--threads 8`, in the order of their names.
+/// A flag that is true is added, one that is false is left out.
+///
+/// # Arguments
+///
+/// * `settings` The settings to be converted
+pub fn to_args(settings: &Settings) -> Vec<String> {
+    let mut args = Vec::new();
+    for (name, setting) in settings {
+        let option = format!("--{}", name);
+        match setting {
+            Setting::Flag(true) => args.push(option),
+            Setting::Flag(false) => {}
+            Setting::Value(value) => args.extend([option, value.clone()]),
+        }
+    }
+    args
+}
+
+/// Reads a configuration file, see parse_settings
+///
+/// # Arguments
+///
+/// * `path` Path to the configuration file
+pub fn read(path: &Path) -> Result<Settings, String> {
+    let data = fs::read_to_string(path)
+        .map_err(|e| format!("Could not read config file {}: {}", path.display(), e))?;
+    parse_settings(&data).map_err(|e| format!("Invalid config file {}: {}", path.display(), e))
+}
+
+/// Converts the settings of a configuration file to arguments, see parse_settings and to_args
+///
+/// # Arguments
+///
+/// * `data` The content of the configuration file
+pub fn parse(data: &str) -> Result<Vec<String>, String> {
+    parse_settings(data).map(|settings| to_args(&settings))
+}
+
+/// Reads the settings of a configuration file. Flags keep whether they are true or false.
+/// A list of strings is joined with commas, eg `algorithm = ["md5", "sha256"]` to `md5,sha256`.
+///
+/// # Arguments
+///
+/// * `data` The content of the configuration file
+pub fn parse_settings(data: &str) -> Result<Settings, String> {
+    let table: toml::value::Table = toml::from_str(data).map_err(|e| e.to_string())?;
+    let mut settings = Settings::new();
+
+    for (key, value) in table {
+        let name = key.replace('_', "-");
+
+        if CONFIG_FLAGS.contains(&name.as_str()) {
+            match value {
+                Value::Boolean(flag) => settings.insert(name, Setting::Flag(flag)),
+                _ => return Err(format!("setting {} must be true or false", key)),
+            };
+            continue;
+        }
+        if !CONFIG_KEYS.contains(&name.as_str()) {
+            return Err(format!("unknown setting {}", key));
+        }
+
+        let value = match value {
+            Value::String(value) => value,
+            Value::Integer(value) => value.to_string(),
+            Value::Array(ref values) => values
+                .iter()
+                .map(Value::as_str)
+                .collect::<Option<Vec<&str>>>()
+                .ok_or_else(|| format!("setting {} must be a list of strings", key))?
+                .join(","),
+            _ => {
+                return Err(format!(
+                    "setting {} must be a string, a number or a list of strings",
+                    key
+                ))
+            }
+        };
+        settings.insert(name, Setting::Value(value));
+    }
+
+    Ok(settings)
+}
+
+/// Converts environment variables holding settings to arguments, see env_settings and to_args
+///
+/// # Arguments
+///
+/// * `vars` Names and values of the environment variables
+pub fn env_args<I: IntoIterator<Item = (String, String)>>(vars: I) -> Result<Vec<String>, String> {
+    env_settings(vars).map(|settings| to_args(&settings))
+}
+
+/// Reads the settings of environment variables, eg `ARKHASH_THREADS=8` for `threads`.
+/// A variable is named like a setting of the configuration file in upper case, with underscores instead of dashes
+/// and the prefix ARKHASH_. Flags are true if their variable is 1, true or yes and false if it is 0, false, no
+/// or empty. Other variables are ignored.
+///
+/// # Arguments
+///
+/// * `vars` Names and values of the environment variables
+pub fn env_settings<I: IntoIterator<Item = (String, String)>>(vars: I) -> Result<Settings, String> {
+    let mut settings = Settings::new();
+    for (name, value) in vars {
+        if !name.starts_with(ENV_PREFIX) {
+            continue;
+        }
+        let key = name[ENV_PREFIX.len()..].to_lowercase().replace('_', "-");
+
+        if CONFIG_FLAGS.contains(&key.as_str()) {
+            let flag = match value.to_lowercase().as_str() {
+                "1" | "true" | "yes" => true,
+                "0" | "false" | "no" | "" => false,
+                _ => {
+                    return Err(format!(
+                        "Invalid environment variable {}: must be true or false",
+                        name
+                    ))
+                }
+            };
+            settings.insert(key, Setting::Flag(flag));
+        } else if CONFIG_KEYS.contains(&key.as_str()) {
+            settings.insert(key, Setting::Value(value));
+        }
+    }
+
+    Ok(settings)
+}
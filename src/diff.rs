@@ -0,0 +1,133 @@
+//! This module implements the diff mode, which compares two _algorithm_sum.txt files
+
+extern crate serde_json;
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::manifest::Manifest;
+use super::util::Options;
+
+/// The differences between two _algorithm_sum.txt files, every list is sorted by path
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ManifestDiff {
+    /// Paths that are listed in both files with different hashes
+    pub changed: Vec<PathBuf>,
+    /// Paths that are only listed in the second file
+    pub added: Vec<PathBuf>,
+    /// Paths that are only listed in the first file
+    pub removed: Vec<PathBuf>,
+}
+
+impl ManifestDiff {
+    /// Compares two manifests, the size and modification time of the files are ignored
+    ///
+    /// # Arguments
+    ///
+    /// * `old` The first manifest
+    /// * `new` The second manifest
+    pub fn between(old: &Manifest, new: &Manifest) -> ManifestDiff {
+        let mut diff = ManifestDiff::default();
+
+        for entry in old.entries() {
+            match new.get(&entry.path) {
+                Some(new_entry) if !new_entry.hash.eq_ignore_ascii_case(&entry.hash) => {
+                    diff.changed.push(entry.path.clone())
+                }
+                Some(_) => {}
+                None => diff.removed.push(entry.path.clone()),
+            }
+        }
+        for entry in new.entries() {
+            if old.get(&entry.path).is_none() {
+                diff.added.push(entry.path.clone());
+            }
+        }
+
+        diff.changed.sort();
+        diff.added.sort();
+        diff.removed.sort();
+        diff
+    }
+
+    /// Indicates that both files list the same paths with the same hashes
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.added.is_empty() && self.removed.is_empty()
+    }
+
+    /// Returns a human readable summary, eg "1 changed, 2 added, 0 removed"
+    pub fn summary(&self) -> String {
+        format!(
+            "{} changed, {} added, {} removed",
+            self.changed.len(),
+            self.added.len(),
+            self.removed.len()
+        )
+    }
+
+    /// Returns a JSON object listing the changed, added and removed paths
+    pub fn to_json(&self) -> serde_json::Value {
+        let paths = |paths: &[PathBuf]| -> Vec<String> {
+            paths
+                .iter()
+                .map(|path| path.to_string_lossy().to_string())
+                .collect()
+        };
+
+        serde_json::json!({
+            "changed": paths(&self.changed),
+            "added": paths(&self.added),
+            "removed": paths(&self.removed),
+        })
+    }
+}
+
+/// Compares the two _algorithm_sum.txt files given in opts.folders and returns the differences.
+/// Only the files are read, the files listed in them need not exist.
+/// With opts.detect_algorithm, the algorithm of a file is determined by its name and opts.algorithm is used otherwise.
+///
+/// # Arguments
+///
+/// * `opts` An Options object containing the paths to the files
+///
+/// # Returns
+/// The differences between the files, or an error if a file could not be read or the algorithms differ.
+pub fn diff(opts: &Options) -> Result<ManifestDiff, io::Error> {
+    let (old_path, new_path) = match opts.folders.as_slice() {
+        [old_path, new_path] => (old_path.as_path(), new_path.as_path()),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "diff mode compares exactly two hashsum files",
+            ))
+        }
+    };
+
+    let algorithm_of = |path: &Path| {
+        if opts.detect_algorithm {
+            Manifest::algorithm_from_name(path).unwrap_or(opts.algorithm)
+        } else {
+            opts.algorithm
+        }
+    };
+    let (old_algorithm, new_algorithm) = (algorithm_of(old_path), algorithm_of(new_path));
+    if old_algorithm != new_algorithm {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "the hashsum files use different algorithms ({} and {})",
+                old_algorithm, new_algorithm
+            ),
+        ));
+    }
+
+    let read = |path: &Path| {
+        let opts = Options {
+            algorithm: old_algorithm,
+            ..opts.clone()
+        };
+        Manifest::read_file(path, &opts)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+    };
+    Ok(ManifestDiff::between(&read(old_path)?, &read(new_path)?))
+}
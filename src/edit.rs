@@ -0,0 +1,294 @@
+//! This module implements the add and remove modes, which change the lines of single files
+
+extern crate log;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use self::log::{error, info};
+
+use super::blocks::{BlockHashes, BlockManifest};
+use super::manifest::{FileMetadata, FileStamp, Manifest, ManifestEntry};
+use super::util::{Algorithm, Options, Symlinks};
+
+/// The result of adding or removing the lines of some files
+#[derive(Debug, Clone, Default)]
+pub struct EditReport {
+    /// The files whose lines were added, replaced or removed
+    pub changed: Vec<PathBuf>,
+    /// Descriptions of files whose lines could not be changed
+    pub failed: Vec<String>,
+}
+
+/// Hashes the files given in opts.folders and adds their lines to the _algorithm_sum.txt they belong to.
+/// A line that already exists for a file is replaced in place, the order of the other lines is preserved.
+/// A file belongs to the nearest _algorithm_sum.txt in its directory or above,
+/// if there is none, an _algorithm_sum.txt is created in its directory.
+///
+/// # Arguments
+///
+/// * `opts` An Options object containing the files and the algorithm of new _algorithm_sum.txt files
+///
+/// # Returns
+/// An EditReport listing the added files.
+pub fn add(opts: &Options) -> EditReport {
+    add_files(&given_files(opts), opts)
+}
+
+/// Hashes the given files and adds their lines to the _algorithm_sum.txt they belong to, see add
+///
+/// # Arguments
+///
+/// * `files` Paths to the files
+/// * `opts` An Options object containing the algorithm of new _algorithm_sum.txt files
+pub(crate) fn add_files(files: &[PathBuf], opts: &Options) -> EditReport {
+    edit(files, opts, add_file)
+}
+
+/// Removes the lines of the files given in opts.folders from the _algorithm_sum.txt they belong to.
+/// The files themselves are not touched and need not exist anymore.
+///
+/// # Arguments
+///
+/// * `opts` An Options object containing the files
+///
+/// # Returns
+/// An EditReport listing the removed files.
+pub fn remove(opts: &Options) -> EditReport {
+    edit(&given_files(opts), opts, remove_file)
+}
+
+/// Returns the files given in opts.folders
+fn given_files(opts: &Options) -> Vec<PathBuf> {
+    opts.folders.clone()
+}
+
+/// Changes the _algorithm_sum.txt of every file with the given function
+/// and emits messages through the log crate according to the loglevel.
+///
+/// # Arguments
+///
+/// * `files` Paths to the files
+/// * `opts` An Options object containing the loglevel
+/// * `change` Function that changes the manifest of a file and returns a message describing the change
+fn edit<F>(files: &[PathBuf], opts: &Options, change: F) -> EditReport
+where
+    F: Fn(&Path, &Path, &Options) -> io::Result<String>,
+{
+    let mut report = EditReport::default();
+
+    for file in files.iter().cloned() {
+        let result = find_manifest(&file, opts).and_then(|(workdir, relative, opts)| {
+            change(&workdir, &relative, &opts).map(|message| (workdir, message))
+        });
+
+        match result {
+            Ok((workdir, message)) => {
+                if opts.loglevel_info() {
+                    info!("{}: {}", workdir.display(), message);
+                }
+                report.changed.push(file);
+            }
+            Err(e) => {
+                let message = format!("{}: {}", file.display(), e);
+                if !opts.loglevel_quiet() {
+                    error!("{}", message);
+                }
+                report.failed.push(message);
+            }
+        }
+    }
+
+    report
+}
+
+/// Directories of _algorithm_sum.txt files, each with the paths of some of its files relative to it
+pub(crate) type FileGroups = Vec<(PathBuf, Vec<PathBuf>)>;
+
+/// Groups files by the directory of the _algorithm_sum.txt they belong to, see find_manifest
+///
+/// # Arguments
+///
+/// * `files` Paths to the files
+/// * `opts` An Options object containing the algorithm and whether or not it is detected
+///
+/// # Returns
+/// The directories in the order their first file was given, each with the paths of its files relative to it,
+/// and the files whose _algorithm_sum.txt could not be determined together with the reason.
+pub(crate) fn group_by_manifest(
+    files: &[PathBuf],
+    opts: &Options,
+) -> (FileGroups, Vec<(PathBuf, String)>) {
+    let mut groups: FileGroups = Vec::new();
+    let mut positions: HashMap<PathBuf, usize> = HashMap::new();
+    let mut failed = Vec::new();
+
+    for file in files {
+        match find_manifest(file, opts) {
+            Ok((workdir, relative, _)) => match positions.get(&workdir) {
+                Some(&i) => groups[i].1.push(relative),
+                None => {
+                    positions.insert(workdir.clone(), groups.len());
+                    groups.push((workdir, vec![relative]));
+                }
+            },
+            Err(e) => failed.push((file.clone(), e.to_string())),
+        }
+    }
+
+    (groups, failed)
+}
+
+/// Finds the _algorithm_sum.txt a file belongs to, the nearest one in the directory of the file or above.
+/// If there is none, the _algorithm_sum.txt of opts.algorithm in the directory of the file is returned.
+///
+/// # Arguments
+///
+/// * `file` Path to the file
+/// * `opts` An Options object containing the algorithm and whether or not it is detected
+///
+/// # Returns
+/// The directory of the _algorithm_sum.txt, the path of the file relative to it as written to the _algorithm_sum.txt
+/// and the Options object with the algorithm of the _algorithm_sum.txt.
+fn find_manifest(file: &Path, opts: &Options) -> io::Result<(PathBuf, PathBuf, Options)> {
+    let name = file
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file"))?;
+    let parent = match file.parent() {
+        Some(parent) if parent != Path::new("") => parent,
+        _ => Path::new("."),
+    };
+    // the file itself need not exist anymore when it is removed
+    let directory = fs::canonicalize(parent)?.join(name);
+
+    for workdir in directory.ancestors().skip(1) {
+        let algorithms: Vec<Algorithm> = if opts.detect_algorithm {
+            Manifest::find_algorithms(workdir)
+        } else {
+            Manifest::find_algorithms(workdir)
+                .into_iter()
+                .filter(|&algorithm| algorithm == opts.algorithm)
+                .collect()
+        };
+
+        match algorithms.as_slice() {
+            [] => continue,
+            [algorithm] => {
+                let relative = Path::new(".").join(directory.strip_prefix(workdir).unwrap());
+                let opts = Options {
+                    algorithm: *algorithm,
+                    ..opts.clone()
+                };
+                return Ok((workdir.to_path_buf(), relative, opts));
+            }
+            _ => {
+                let names: Vec<String> = algorithms
+                    .iter()
+                    .map(|algorithm| format!("{}sum.txt", algorithm))
+                    .collect();
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "found more than one hashsum file ({}) in {}, choose one with --algorithm",
+                        names.join(", "),
+                        workdir.display()
+                    ),
+                ));
+            }
+        }
+    }
+
+    let workdir = directory.parent().unwrap().to_path_buf();
+    Ok((workdir, Path::new(".").join(name), opts.clone()))
+}
+
+/// Hashes a file and adds its line to the _algorithm_sum.txt of workdir.
+/// The blocks of the file are hashed as well with opts.block_size or if the file already has block hashes.
+///
+/// # Arguments
+///
+/// * `workdir` Path to the directory containing the _algorithm_sum.txt
+/// * `path` Path to the file, relative to workdir
+/// * `opts` An Options object containing the algorithm
+fn add_file(workdir: &Path, path: &Path, opts: &Options) -> io::Result<String> {
+    let mut manifest = Manifest::read_or_new(workdir, opts)?;
+
+    let metadata = if opts.symlinks == Symlinks::HashLink {
+        fs::symlink_metadata(workdir.join(path))?
+    } else {
+        fs::metadata(workdir.join(path))?
+    };
+    if metadata.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "is a directory, use update mode instead",
+        ));
+    }
+
+    let mut block_manifest = BlockManifest::read(workdir, opts.algorithm)?;
+    let block_size = opts
+        .block_size
+        .or_else(|| block_manifest.get(path).map(|hashes| hashes.block_size));
+
+    let (hashline, block_hashes) =
+        super::util::calculate_hash_with_blocks(path.to_path_buf(), workdir, block_size, opts)
+            .map_err(|e| io::Error::new(e.io_error().kind(), e.to_string()))?;
+    let hashline_re = super::util::regex_from_opts(opts)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let (hash, _) = super::util::parse_hashline(&hashline_re, &hashline)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid hashline"))?;
+
+    manifest.upsert(ManifestEntry {
+        hash,
+        path: path.to_path_buf(),
+        stamp: FileStamp::from_metadata(&metadata),
+        metadata: if opts.check_metadata {
+            FileMetadata::from_metadata(&metadata)
+        } else {
+            None
+        },
+    });
+    manifest.write(workdir, opts)?;
+    if let Some(block_size) = block_size {
+        block_manifest.insert(BlockHashes {
+            path: path.to_path_buf(),
+            block_size,
+            hashes: block_hashes,
+        });
+        block_manifest.write(workdir, opts.algorithm)?;
+    }
+
+    Ok(hashline.trim_end().to_string())
+}
+
+/// Removes the line of a file from the _algorithm_sum.txt of workdir
+///
+/// # Arguments
+///
+/// * `workdir` Path to the directory containing the _algorithm_sum.txt
+/// * `path` Path to the file, relative to workdir
+/// * `opts` An Options object containing the algorithm
+fn remove_file(workdir: &Path, path: &Path, opts: &Options) -> io::Result<String> {
+    let mut manifest = Manifest::read(workdir, opts)?;
+
+    if manifest.retain(|entry| entry.path != path).is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "not listed in {}",
+                Manifest::path(workdir, opts.algorithm).display()
+            ),
+        ));
+    }
+    manifest.write(workdir, opts)?;
+
+    let mut block_manifest = BlockManifest::read(workdir, opts.algorithm)?;
+    if block_manifest.get(path).is_some() {
+        block_manifest.retain(|file_path| file_path != path);
+        block_manifest.write(workdir, opts.algorithm)?;
+    }
+
+    Ok(format!("removed {}", path.display()))
+}
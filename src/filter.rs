@@ -1,20 +1,25 @@
 //! This module implements a filter for a BufReader that filters out filenames
 //! that have already been hashed at some point. It does this via reading the _algorithm_sum.txt file.
-
-extern crate regex;
+//! Filenames excluded by a .arkhashignore are filtered out as well.
 
 use std::collections::HashMap;
-use std::fs::OpenOptions;
 use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use super::ignore::IgnoreCache;
 
 /// The structure that gets wrapped around a BufReader to filter it
 pub struct Filter<T> {
     /// The filenames that were already hashed in the past, gathered through reading _algorithm_sum.txt
-    already_calculated_files: HashMap<String, bool>,
+    already_calculated_files: HashMap<PathBuf, bool>,
     /// The BufReader that will be read and filtered
     input: BufReader<T>,
-    /// The algorithm that was used to hash the files eg "sha1"
-    algorithm: String,
+    /// The byte that terminates every path of the input
+    separator: u8,
+    /// The directory of the _algorithm_sum.txt, which the paths of the input are relative to
+    workdir: PathBuf,
+    /// The .arkhashignore files of workdir and the directories of the input
+    ignores: IgnoreCache,
 }
 
 impl<T> Filter<T> {
@@ -32,81 +37,54 @@ impl<T> Filter<T> {
     /// an Err will be returned instead of a Filter.
     pub fn new(
         input: BufReader<T>,
-        sumfile_path: &str,
+        sumfile_path: &Path,
         opts: &super::util::Options,
     ) -> Result<Self, &'static str> {
         let mut already_calculated_files = HashMap::new();
 
-        match OpenOptions::new()
-            .read(true)
-            .append(true)
-            .create(true)
-            .open(format!("{}/{}sum.txt", sumfile_path, opts.algorithm))
-        {
+        match super::manifest::Manifest::read_or_new(sumfile_path, opts) {
             Err(_) => Err("Could not open _algorithm_sum.txt"),
 
-            Ok(file) => {
-                let file_path_re = super::util::regex_from_opts(&opts)?;
-
-                for line in BufReader::new(file).lines() {
-                    if let Ok(line) = line {
-                        if let Some(captures) = file_path_re.captures(&line) {
-                            let path = &captures[2];
-                            already_calculated_files.insert(path.to_string(), true);
-                        } else {
-                            continue;
-                        }
-                    } else {
-                        continue;
-                    }
+            Ok(manifest) => {
+                for entry in manifest.entries() {
+                    already_calculated_files.insert(entry.path.clone(), true);
                 }
 
                 Ok(Filter {
                     already_calculated_files,
                     input,
-                    algorithm: opts.algorithm.clone(),
+                    separator: if opts.null_separated { b'\0' } else { b'\n' },
+                    workdir: sumfile_path.to_path_buf(),
+                    ignores: IgnoreCache::excluding(&opts.exclude),
                 })
             }
         }
     }
-
-    /// Returns whether the given line contains the path to the database file
-    ///
-    /// # Arguments
-    ///
-    /// * `line` The String to be checked
-    /// * `algorithm` The used algorithm
-    #[cfg(unix)]
-    fn is_own_database_file(line: &str, algorithm: &str) -> bool {
-        line == format!("./{}sum.txt", algorithm)
-    }
-
-    /// Returns whether the given line contains the path to the database file
-    ///
-    /// # Arguments
-    ///
-    /// * `line` The String to be checked
-    /// * `algorithm` The used algorithm
-    #[cfg(windows)]
-    fn is_own_database_file(line: &str, algorithm: &str) -> bool {
-        line == format!(".\\{}sum.txt", algorithm)
-    }
 }
 
 impl<T: Read> Iterator for Filter<T> {
-    type Item = String;
+    type Item = PathBuf;
 
     fn next(&mut self) -> Option<Self::Item> {
-        for line in self.input.by_ref().lines() {
+        for line in self.input.by_ref().split(self.separator) {
             match line {
                 Err(_) => continue,
-                Ok(line) => {
+                Ok(mut line) => {
+                    if self.separator == b'\n' && line.last() == Some(&b'\r') {
+                        line.pop();
+                    }
+                    let line = super::util::path_from_bytes(&line);
+
                     let contained = self.already_calculated_files.contains_key(&line);
                     if contained {
                         continue;
                     }
 
-                    if Filter::<T>::is_own_database_file(&line, &self.algorithm) {
+                    if super::manifest::Manifest::is_own_file(&line) {
+                        continue;
+                    }
+
+                    if self.ignores.is_ignored(&self.workdir, &line) {
                         continue;
                     }
 
@@ -0,0 +1,266 @@
+//! This module implements the registry of the algorithms the hashes of files can be calculated with.
+//!
+//! Every algorithm is registered with its name, the tag of its BSD style lines, the length of its hashes and a
+//! function creating a new [`Hasher`]. The built-in algorithms are always registered, library users can add
+//! their own with [`register`], which makes them available by name like the built-in ones,
+//! eg for `Options::builder().algorithm_name(..)` and the _algorithm_sum.txt files named after them.
+
+extern crate digest;
+extern crate hex;
+extern crate md5;
+extern crate sha1;
+extern crate sha2;
+extern crate sha3;
+
+use std::sync::RwLock;
+
+use self::digest::{FixedOutput, Input};
+use self::md5::Md5;
+use self::sha1::Sha1;
+use self::sha2::{Sha224, Sha256, Sha384, Sha512};
+use self::sha3::{Sha3_256, Sha3_512};
+use super::checksum::{Adler32, Crc32};
+use super::util::Algorithm;
+
+/// Calculates the hash of the data fed to it
+pub trait Hasher: Send {
+    /// Feeds the next bytes of the data to the hasher
+    fn update(&mut self, data: &[u8]);
+
+    /// Returns the hash of all data fed to the hasher as hexadecimal characters in lower case.
+    /// The hasher is boxed, so it can be finished behind a `Box<dyn Hasher>`.
+    fn finalize(self: Box<Self>) -> String;
+
+    /// Returns the name of the algorithm of the hasher, eg sha256
+    fn name(&self) -> &str;
+}
+
+/// A Hasher for the hash functions of the digest crate
+struct DigestHasher<D> {
+    /// Name of the algorithm
+    name: &'static str,
+    /// The hash function
+    digest: D,
+}
+
+impl<D: Input + FixedOutput + Default + Send + 'static> DigestHasher<D> {
+    /// Creates a new boxed hasher
+    ///
+    /// # Arguments
+    ///
+    /// * `name` Name of the algorithm
+    fn boxed(name: &'static str) -> Box<dyn Hasher> {
+        Box::new(DigestHasher {
+            name,
+            digest: D::default(),
+        })
+    }
+}
+
+impl<D: Input + FixedOutput + Send> Hasher for DigestHasher<D> {
+    fn update(&mut self, data: &[u8]) {
+        self.digest.input(data);
+    }
+
+    fn finalize(self: Box<Self>) -> String {
+        hex::encode(self.digest.fixed_result())
+    }
+
+    fn name(&self) -> &str {
+        self.name
+    }
+}
+
+/// An algorithm of the registry
+#[derive(Debug, Clone, Copy)]
+pub struct Registration {
+    /// The algorithm
+    pub algorithm: Algorithm,
+    /// Name of the algorithm, which is also the prefix of the _algorithm_sum.txt file
+    pub name: &'static str,
+    /// Tag of the algorithm in BSD style lines, eg SHA1
+    pub tag: &'static str,
+    /// Number of hexadecimal characters of a hash calculated with the algorithm
+    pub hash_length: usize,
+    /// Creates a new hasher for the algorithm
+    pub constructor: fn() -> Box<dyn Hasher>,
+}
+
+/// The built-in algorithms, in the order of Algorithm::ALL
+const BUILT_IN: [Registration; 10] = [
+    Registration {
+        algorithm: Algorithm::Sha1,
+        name: "sha1",
+        tag: "SHA1",
+        hash_length: 40,
+        constructor: || DigestHasher::<Sha1>::boxed("sha1"),
+    },
+    Registration {
+        algorithm: Algorithm::Md5,
+        name: "md5",
+        tag: "MD5",
+        hash_length: 32,
+        constructor: || DigestHasher::<Md5>::boxed("md5"),
+    },
+    Registration {
+        algorithm: Algorithm::Sha224,
+        name: "sha224",
+        tag: "SHA224",
+        hash_length: 56,
+        constructor: || DigestHasher::<Sha224>::boxed("sha224"),
+    },
+    Registration {
+        algorithm: Algorithm::Sha256,
+        name: "sha256",
+        tag: "SHA256",
+        hash_length: 64,
+        constructor: || DigestHasher::<Sha256>::boxed("sha256"),
+    },
+    Registration {
+        algorithm: Algorithm::Sha384,
+        name: "sha384",
+        tag: "SHA384",
+        hash_length: 96,
+        constructor: || DigestHasher::<Sha384>::boxed("sha384"),
+    },
+    Registration {
+        algorithm: Algorithm::Sha512,
+        name: "sha512",
+        tag: "SHA512",
+        hash_length: 128,
+        constructor: || DigestHasher::<Sha512>::boxed("sha512"),
+    },
+    Registration {
+        algorithm: Algorithm::Sha3_256,
+        name: "sha3-256",
+        tag: "SHA3-256",
+        hash_length: 64,
+        constructor: || DigestHasher::<Sha3_256>::boxed("sha3-256"),
+    },
+    Registration {
+        algorithm: Algorithm::Sha3_512,
+        name: "sha3-512",
+        tag: "SHA3-512",
+        hash_length: 128,
+        constructor: || DigestHasher::<Sha3_512>::boxed("sha3-512"),
+    },
+    Registration {
+        algorithm: Algorithm::Crc32,
+        name: "crc32",
+        tag: "CRC32",
+        hash_length: 8,
+        constructor: || DigestHasher::<Crc32>::boxed("crc32"),
+    },
+    Registration {
+        algorithm: Algorithm::Adler32,
+        name: "adler32",
+        tag: "ADLER32",
+        hash_length: 8,
+        constructor: || DigestHasher::<Adler32>::boxed("adler32"),
+    },
+];
+
+/// The algorithms registered by library users, see register
+static CUSTOM: RwLock<Vec<Registration>> = RwLock::new(Vec::new());
+
+/// Returns every registered algorithm, the built-in ones first
+pub fn registrations() -> Vec<Registration> {
+    let mut registrations = BUILT_IN.to_vec();
+    registrations.extend(CUSTOM.read().unwrap().iter().cloned());
+    registrations
+}
+
+/// Returns the registered algorithm with the given name, ignoring case
+///
+/// # Arguments
+///
+/// * `name` Name of the algorithm, eg "sha1"
+pub fn find(name: &str) -> Option<Registration> {
+    registrations()
+        .into_iter()
+        .find(|registration| registration.name.eq_ignore_ascii_case(name))
+}
+
+/// Returns the registration of an algorithm. Panics for a custom algorithm that was never registered.
+///
+/// # Arguments
+///
+/// * `algorithm` The algorithm
+pub fn registration(algorithm: Algorithm) -> Registration {
+    match algorithm {
+        Algorithm::Custom(name) => CUSTOM
+            .read()
+            .unwrap()
+            .iter()
+            .find(|registration| registration.name == name)
+            .cloned()
+            .unwrap_or_else(|| panic!("the algorithm {} is not registered", name)),
+        algorithm => BUILT_IN
+            .iter()
+            .find(|registration| registration.algorithm == algorithm)
+            .cloned()
+            .expect("every built-in algorithm is registered"),
+    }
+}
+
+/// Registers a custom algorithm, which can then be used like the built-in ones.
+/// Fails if the name is taken or the name or tag can not be used in file names and BSD style lines.
+///
+/// # Arguments
+///
+/// * `name` Name of the algorithm, lower case letters, digits and dashes, eg "blake3"
+/// * `tag` Tag of the algorithm in BSD style lines, letters, digits and dashes, eg "BLAKE3"
+/// * `hash_length` Number of hexadecimal characters of the hashes the hashers return
+/// * `constructor` Creates a new hasher for the algorithm
+///
+/// # Returns
+///
+/// The algorithm, `Algorithm::Custom(name)`.
+pub fn register(
+    name: &'static str,
+    tag: &'static str,
+    hash_length: usize,
+    constructor: fn() -> Box<dyn Hasher>,
+) -> Result<Algorithm, String> {
+    let is_valid = |text: &str, letter: fn(&char) -> bool| {
+        !text.is_empty()
+            && text
+                .chars()
+                .all(|c| letter(&c) || c.is_ascii_digit() || c == '-')
+    };
+    if !is_valid(name, char::is_ascii_lowercase) {
+        return Err(format!(
+            "invalid algorithm name {}: only lower case letters, digits and dashes are allowed",
+            name
+        ));
+    }
+    if !is_valid(tag, char::is_ascii_alphabetic) {
+        return Err(format!(
+            "invalid tag {}: only letters, digits and dashes are allowed",
+            tag
+        ));
+    }
+    if hash_length == 0 {
+        return Err(format!("the hashes of {} must not be empty", name));
+    }
+
+    let mut custom = CUSTOM.write().unwrap();
+    let taken = BUILT_IN.iter().chain(custom.iter()).any(|registration| {
+        registration.name.eq_ignore_ascii_case(name)
+            || registration.name.starts_with(&format!("{}sum", name))
+            || name.starts_with(&format!("{}sum", registration.name))
+    });
+    if taken {
+        return Err(format!("the algorithm {} is already registered", name));
+    }
+
+    let algorithm = Algorithm::Custom(name);
+    custom.push(Registration {
+        algorithm,
+        name,
+        tag,
+        hash_length,
+        constructor,
+    });
+    Ok(algorithm)
+}
@@ -0,0 +1,454 @@
+//! This module implements the `.arkhashignore` files that exclude files from being hashed and verified.
+//!
+//! A `.arkhashignore` follows the rules of a `.gitignore`: every line is a glob pattern,
+//! empty lines and lines starting with `#` are skipped and a leading `!` includes files
+//! that an earlier pattern excluded. `*` and `?` match within a single path component, `**` across them
+//! and `[...]` a set of characters. A pattern containing a slash is matched against the path
+//! relative to the directory of the `.arkhashignore`, otherwise against the name of the file or directory.
+//! A trailing slash only matches directories. The last matching pattern decides, and the patterns
+//! of a `.arkhashignore` in a subdirectory take precedence over the ones of the directories above it.
+//!
+//! The patterns of `--exclude` are matched against the name of every file and directory below the processed
+//! directories. They take precedence over every `.arkhashignore`, a `!` pattern there does not include
+//! a file they exclude.
+
+extern crate regex;
+
+use self::regex::Regex;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+/// Name of the file containing the patterns of a directory
+pub const IGNORE_FILE_NAME: &str = ".arkhashignore";
+
+/// A single line of a .arkhashignore
+#[derive(Debug, Clone)]
+struct Pattern {
+    /// The pattern translated to a regular expression matching the relative path with slashes as separators
+    regex: Regex,
+    /// Whether or not the pattern starts with `!` and includes the files it matches
+    negated: bool,
+    /// Whether or not the pattern ends with a slash and only matches directories
+    directory_only: bool,
+}
+
+impl Pattern {
+    /// Parses a line of a .arkhashignore, returns None for empty lines and comments
+    ///
+    /// # Arguments
+    ///
+    /// * `line` The line without its newline
+    fn parse(line: &str) -> Option<Pattern> {
+        let line = line.trim_end_matches('\r');
+        // trailing spaces are removed unless they are escaped
+        let mut line = if line.ends_with("\\ ") {
+            line
+        } else {
+            line.trim_end()
+        };
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let negated = line.starts_with('!');
+        if negated {
+            line = &line[1..];
+        }
+        let directory_only = line.ends_with('/');
+        if directory_only {
+            line = line.trim_end_matches('/');
+        }
+        if line.is_empty() {
+            return None;
+        }
+
+        let anchored = line.contains('/');
+        let glob = glob_to_regex(line.trim_start_matches('/'));
+        let regex = if anchored {
+            format!("^{}$", glob)
+        } else {
+            format!("^(?:.*/)?{}$", glob)
+        };
+
+        Regex::new(&regex).ok().map(|regex| Pattern {
+            regex,
+            negated,
+            directory_only,
+        })
+    }
+}
+
+/// Translates a glob pattern to a regular expression without anchors
+///
+/// # Arguments
+///
+/// * `glob` The glob pattern, paths are separated by slashes
+fn glob_to_regex(glob: &str) -> String {
+    let chars: Vec<char> = glob.chars().collect();
+    let mut regex = String::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                // `**/` matches any number of directories, a trailing `**` everything below
+                if chars.get(i + 2) == Some(&'/') {
+                    regex.push_str("(?:.*/)?");
+                    i += 3;
+                } else {
+                    regex.push_str(".*");
+                    i += 2;
+                }
+                continue;
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => match character_class(&chars[i + 1..]) {
+                Some((class, length)) => {
+                    regex.push_str(&class);
+                    i += length + 2;
+                    continue;
+                }
+                None => regex.push_str("\\["),
+            },
+            '\\' if i + 1 < chars.len() => {
+                regex.push_str(&regex::escape(&chars[i + 1].to_string()));
+                i += 2;
+                continue;
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+
+    regex
+}
+
+/// Translates the set of characters following a `[` to a regular expression
+///
+/// # Arguments
+///
+/// * `chars` The characters following the `[`
+///
+/// # Returns
+/// The character class and the number of characters between the brackets, None if the bracket is not closed
+fn character_class(chars: &[char]) -> Option<(String, usize)> {
+    let negated = matches!(chars.first(), Some('!') | Some('^'));
+    let start = if negated { 1 } else { 0 };
+    // a closing bracket right at the start is part of the set
+    let end = start + 1 + chars.get(start + 1..)?.iter().position(|&c| c == ']')?;
+
+    let mut class = String::from(if negated { "[^/" } else { "[" });
+    for &c in &chars[start..end] {
+        if matches!(c, '\\' | '[' | ']' | '^' | '&' | '~') {
+            class.push('\\');
+        }
+        class.push(c);
+    }
+    class.push(']');
+
+    Some((class, end))
+}
+
+/// The patterns of the .arkhashignore of a single directory
+#[derive(Debug)]
+struct IgnoreFile {
+    /// Absolute path to the directory containing the .arkhashignore
+    directory: PathBuf,
+    /// The patterns in the order of the file
+    patterns: Vec<Pattern>,
+}
+
+impl IgnoreFile {
+    /// Reads the .arkhashignore of a directory, returns None if there is none or it can not be read
+    ///
+    /// # Arguments
+    ///
+    /// * `directory` Absolute path to the directory
+    fn read(directory: &Path) -> Option<IgnoreFile> {
+        let contents = fs::read(directory.join(IGNORE_FILE_NAME)).ok()?;
+        Some(IgnoreFile::parse(
+            directory,
+            &String::from_utf8_lossy(&contents),
+        ))
+    }
+
+    /// Parses the contents of a .arkhashignore
+    ///
+    /// # Arguments
+    ///
+    /// * `directory` Absolute path to the directory containing the .arkhashignore
+    /// * `contents` The contents of the .arkhashignore
+    fn parse(directory: &Path, contents: &str) -> IgnoreFile {
+        IgnoreFile {
+            directory: directory.to_path_buf(),
+            patterns: contents.lines().filter_map(Pattern::parse).collect(),
+        }
+    }
+
+    /// Returns Some(true) if the last pattern matching the path excludes it, Some(false) if it includes it
+    /// and None if no pattern matches
+    ///
+    /// # Arguments
+    ///
+    /// * `relative` Path relative to the directory of the .arkhashignore, with slashes as separators
+    /// * `is_dir` Whether or not the path is a directory
+    fn decide(&self, relative: &str, is_dir: bool) -> Option<bool> {
+        self.patterns
+            .iter()
+            .rev()
+            .find(|pattern| (is_dir || !pattern.directory_only) && pattern.regex.is_match(relative))
+            .map(|pattern| !pattern.negated)
+    }
+}
+
+/// The .arkhashignore files that apply to the entries of a directory,
+/// the one of the directory itself and the ones of all directories above it
+#[derive(Debug, Clone)]
+pub struct Ignores {
+    /// The .arkhashignore files from the outermost to the innermost directory
+    files: Vec<Arc<IgnoreFile>>,
+    /// The working directory relative paths are resolved against
+    current_dir: PathBuf,
+    /// The patterns of --exclude, matched against the names of the files and directories
+    excludes: Arc<Vec<Pattern>>,
+}
+
+impl Ignores {
+    /// Reads the .arkhashignore files of a directory and of every directory above it
+    ///
+    /// # Arguments
+    ///
+    /// * `directory` Path to the directory
+    pub fn for_directory(directory: &Path) -> Ignores {
+        let current_dir = env::current_dir().unwrap_or_default();
+        let directory = absolute(&current_dir, directory);
+        let mut files: Vec<Arc<IgnoreFile>> = directory
+            .ancestors()
+            .filter_map(IgnoreFile::read)
+            .map(Arc::new)
+            .collect();
+        files.reverse();
+
+        Ignores {
+            files,
+            current_dir,
+            excludes: Arc::new(Vec::new()),
+        }
+    }
+
+    /// Returns an Ignores object without any .arkhashignore files that only excludes by the patterns of --exclude
+    ///
+    /// # Arguments
+    ///
+    /// * `patterns` The patterns of --exclude
+    pub fn excludes(patterns: &[String]) -> Ignores {
+        Ignores {
+            files: Vec::new(),
+            current_dir: env::current_dir().unwrap_or_default(),
+            excludes: Arc::new(Vec::new()),
+        }
+        .excluding(patterns)
+    }
+
+    /// Adds the patterns of --exclude, which exclude the files and directories whose names match them
+    /// regardless of the .arkhashignore files
+    ///
+    /// # Arguments
+    ///
+    /// * `patterns` The patterns of --exclude
+    pub fn excluding(self, patterns: &[String]) -> Ignores {
+        Ignores {
+            excludes: Arc::new(
+                patterns
+                    .iter()
+                    .filter_map(|pattern| Pattern::parse(pattern))
+                    .collect(),
+            ),
+            ..self
+        }
+    }
+
+    /// Returns the .arkhashignore files that apply to the entries of another directory.
+    /// The ones of directories that are not above it are dropped and its own one is added,
+    /// so a directory tree can be walked depth first with a single Ignores object.
+    ///
+    /// # Arguments
+    ///
+    /// * `directory` Path to the directory, below the outermost directory with a .arkhashignore
+    pub fn enter(&self, directory: &Path) -> Ignores {
+        let directory = absolute(&self.current_dir, directory);
+        let mut files: Vec<Arc<IgnoreFile>> = self
+            .files
+            .iter()
+            .filter(|file| directory.starts_with(&file.directory))
+            .cloned()
+            .collect();
+        if files.last().is_none_or(|file| file.directory != directory) {
+            if let Some(file) = IgnoreFile::read(&directory) {
+                files.push(Arc::new(file));
+            }
+        }
+
+        Ignores {
+            files,
+            current_dir: self.current_dir.clone(),
+            excludes: Arc::clone(&self.excludes),
+        }
+    }
+
+    /// Indicates that the name of a file or directory matches one of the patterns of --exclude
+    ///
+    /// # Arguments
+    ///
+    /// * `path` Path to the file or directory
+    /// * `is_dir` Whether or not the path is a directory
+    pub fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        let name = match path.file_name() {
+            Some(name) => name.to_string_lossy(),
+            None => return false,
+        };
+        self.excludes
+            .iter()
+            .any(|pattern| (is_dir || !pattern.directory_only) && pattern.regex.is_match(&name))
+    }
+
+    /// Indicates that a file or directory is excluded by the patterns.
+    /// The directories above it are not checked.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` Path to the file or directory
+    /// * `is_dir` Whether or not the path is a directory
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        if self.is_excluded(path, is_dir) {
+            return true;
+        }
+        if self.files.is_empty() {
+            return false;
+        }
+
+        let path = absolute(&self.current_dir, path);
+        for file in self.files.iter().rev() {
+            let relative = match path.strip_prefix(&file.directory) {
+                Ok(relative) if relative != Path::new("") => relative,
+                _ => continue,
+            };
+            if let Some(ignored) = file.decide(&to_slashes(relative), is_dir) {
+                return ignored;
+            }
+        }
+
+        false
+    }
+}
+
+/// Decides whether or not files below a directory are ignored,
+/// reading the .arkhashignore of every directory only once
+pub struct IgnoreCache {
+    /// The .arkhashignore files that apply to the entries of every directory looked at so far
+    directories: HashMap<PathBuf, Ignores>,
+    /// The patterns of --exclude
+    exclude: Vec<String>,
+}
+
+impl Default for IgnoreCache {
+    fn default() -> Self {
+        IgnoreCache::new()
+    }
+}
+
+impl IgnoreCache {
+    /// Creates an empty IgnoreCache
+    pub fn new() -> IgnoreCache {
+        IgnoreCache {
+            directories: HashMap::new(),
+            exclude: Vec::new(),
+        }
+    }
+
+    /// Creates an empty IgnoreCache that also excludes by the patterns of --exclude
+    ///
+    /// # Arguments
+    ///
+    /// * `patterns` The patterns of --exclude
+    pub fn excluding(patterns: &[String]) -> IgnoreCache {
+        IgnoreCache {
+            directories: HashMap::new(),
+            exclude: patterns.to_vec(),
+        }
+    }
+
+    /// Indicates that a file is excluded, because the patterns exclude it or a directory between workdir and it.
+    /// Like DirWalker, workdir itself is never excluded.
+    ///
+    /// # Arguments
+    ///
+    /// * `workdir` Path to the directory the file belongs to
+    /// * `relative` Path to the file relative to workdir
+    pub fn is_ignored(&mut self, workdir: &Path, relative: &Path) -> bool {
+        let mut directory = workdir.to_path_buf();
+        let components: Vec<Component> = relative
+            .components()
+            .filter(|component| *component != Component::CurDir)
+            .collect();
+
+        for (i, component) in components.iter().enumerate() {
+            let path = directory.join(component);
+            let is_dir = i + 1 < components.len() || path.is_dir();
+            if self.ignores(&directory).is_ignored(&path, is_dir) {
+                return true;
+            }
+            directory = path;
+        }
+
+        false
+    }
+
+    /// Returns the .arkhashignore files that apply to the entries of a directory
+    ///
+    /// # Arguments
+    ///
+    /// * `directory` Path to the directory
+    fn ignores(&mut self, directory: &Path) -> &Ignores {
+        if !self.directories.contains_key(directory) {
+            let ignores = match directory.parent() {
+                Some(parent)
+                    if parent != Path::new("") && self.directories.contains_key(parent) =>
+                {
+                    self.directories[parent].enter(directory)
+                }
+                _ => Ignores::for_directory(directory).excluding(&self.exclude),
+            };
+            self.directories.insert(directory.to_path_buf(), ignores);
+        }
+
+        &self.directories[directory]
+    }
+}
+
+/// Returns the absolute path without `.` components, without resolving symbolic links
+///
+/// # Arguments
+///
+/// * `current_dir` The working directory relative paths are resolved against
+/// * `path` The path to resolve
+fn absolute(current_dir: &Path, path: &Path) -> PathBuf {
+    current_dir.join(path).components().collect()
+}
+
+/// Returns a relative path with slashes as separators, as the patterns expect
+///
+/// # Arguments
+///
+/// * `relative` The relative path
+fn to_slashes(relative: &Path) -> String {
+    relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
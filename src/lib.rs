@@ -0,0 +1,54 @@
+//! arkhash ensures the integrity of archived data.
+//!
+//! The hashsums of all files in a directory are stored in an _algorithm_sum.txt file by [`update`],
+//! and [`verify`] detects files whose content changed since they were hashed.
+//! Both functions are controlled by an [`Options`] object and return a report describing their outcome.
+//! Info and debug messages and errors are emitted through the `log` crate according to the loglevel of the Options,
+//! progress is only drawn by the [`Reporter`]s and nothing else is printed, rendering the reports is left to the caller.
+//! Set the loglevel to `LogLevel::Quiet` to emit nothing.
+
+pub mod blocks;
+pub mod check;
+pub mod checksum;
+pub mod compare;
+pub mod config;
+pub mod diff;
+pub mod edit;
+pub mod filter;
+pub mod hasher;
+pub mod ignore;
+pub mod lock;
+pub mod manifest;
+pub mod progress;
+pub mod prune;
+pub mod repair;
+pub mod reset;
+pub mod signature;
+pub mod status;
+pub mod update;
+pub mod util;
+pub mod verify;
+pub mod watch;
+pub mod xattrs;
+
+pub use blocks::ChangedBlocks;
+pub use check::{check_manifest, CheckReport, ManifestCheck};
+pub use compare::{compare, CompareReport};
+pub use diff::{diff, ManifestDiff};
+pub use edit::{add, remove, EditReport};
+pub use hasher::{register, Hasher, Registration};
+pub use lock::RunLock;
+pub use progress::{
+    FileCallback, FileEvent, FileOutcome, JsonHashReporter, JsonReporter, PlainReporter,
+    QuietReporter, Reporter, TtyReporter,
+};
+pub use prune::{prune, DirectoryPrune, PruneReport};
+pub use reset::{reset, ResetReport};
+pub use status::{status, BadFiles, StatusReport};
+pub use update::{update, DirectoryUpdate, DuplicateGroup, UpdateReport};
+pub use util::{
+    Algorithm, ByteFormat, Hidden, LogLevel, ManifestFormat, Mode, Options, OptionsBuilder,
+    OutputFormat, PathStyle, Sample, Schedule, Symlinks, Threads,
+};
+pub use verify::{verify, DirectoryReport, UnreadableFile, UnreadableReason, VerifyReport};
+pub use watch::{watch, WatchReport};
@@ -0,0 +1,135 @@
+//! This module implements the lock that keeps several arkhash processes from writing the same files at once.
+//!
+//! The lock is an exclusive flock on the file `.arkhash.lock` in the state directory, so runs sharing
+//! a state directory, like overlapping cron jobs, are serialized. The lock file is removed when the lock is
+//! dropped, so it does not stay behind in the working directory. The operating system releases the lock
+//! when the process exits for any reason, including Ctrl-C, so a stale lock file never blocks a later run.
+//!
+//! A process waiting for the lock may have opened the file before its holder removed it. After locking,
+//! the file is therefore compared with the one at the path, and opened and locked again if it was removed.
+
+extern crate fs2;
+
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use self::fs2::FileExt;
+
+use super::util::Options;
+
+/// An exclusive lock on the state directory, released and its file removed when it is dropped.
+/// std::process::exit skips the drop, so drop the lock before exiting.
+#[derive(Debug)]
+pub struct RunLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl RunLock {
+    /// Returns the path to the lock file of the state directory of opts
+    ///
+    /// # Arguments
+    ///
+    /// * `opts` Options object containing the state directory
+    pub fn path(opts: &Options) -> PathBuf {
+        opts.state_dir.join(".arkhash.lock")
+    }
+
+    /// Acquires the lock of the state directory, which is created if it does not exist.
+    ///
+    /// # Arguments
+    ///
+    /// * `opts` Options object containing the state directory
+    ///
+    /// # Returns
+    /// The lock, None if another process holds it.
+    pub fn try_acquire(opts: &Options) -> io::Result<Option<RunLock>> {
+        let path = RunLock::path(opts);
+        loop {
+            let file = open(&path)?;
+
+            match file.try_lock_exclusive() {
+                Ok(()) if is_current(&file, &path) => return Ok(Some(RunLock { file, path })),
+                // the holder removed the file after it was opened
+                Ok(()) => continue,
+                Err(ref e) if e.raw_os_error() == fs2::lock_contended_error().raw_os_error() => {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Acquires the lock of the state directory, waiting until another process releases it.
+    ///
+    /// # Arguments
+    ///
+    /// * `opts` Options object containing the state directory
+    pub fn acquire(opts: &Options) -> io::Result<RunLock> {
+        let path = RunLock::path(opts);
+        loop {
+            let file = open(&path)?;
+            file.lock_exclusive()?;
+
+            // the holder removed the file while this process was waiting for it
+            if is_current(&file, &path) {
+                return Ok(RunLock { file, path });
+            }
+        }
+    }
+
+    /// Returns the path to the lock file
+    pub fn lock_path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        // removed while it is still locked, so no other process locks the file that is removed
+        let _ = fs::remove_file(&self.path);
+        let _ = FileExt::unlock(&self.file);
+    }
+}
+
+/// Indicates that an opened lock file is still the file at its path, it was not removed by a previous holder
+///
+/// # Arguments
+///
+/// * `file` The opened lock file
+/// * `path` Path to the lock file
+#[cfg(unix)]
+fn is_current(file: &File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (file.metadata(), fs::metadata(path)) {
+        (Ok(opened), Ok(current)) => opened.dev() == current.dev() && opened.ino() == current.ino(),
+        _ => false,
+    }
+}
+
+/// Indicates that an opened lock file is still the file at its path.
+/// Without inodes to compare, it is assumed to be.
+#[cfg(not(unix))]
+fn is_current(_file: &File, _path: &Path) -> bool {
+    true
+}
+
+/// Opens the lock file, creating it and its directory if needed
+///
+/// # Arguments
+///
+/// * `path` Path to the lock file
+fn open(path: &Path) -> io::Result<File> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+}
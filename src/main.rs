@@ -1,31 +1,115 @@
-pub mod filter;
-pub mod update;
-pub mod util;
-pub mod verify;
+extern crate arkhash;
+extern crate chrono;
+extern crate env_logger;
+extern crate log;
+extern crate serde_json;
+
+mod render;
+
+use arkhash::{config, filter, progress, signature, util};
+use log::{debug, info};
+use std::io::Write;
 
 fn main() {
-    let opts = util::Options::new(std::env::args().collect());
+    let args = config::with_config_and_env(std::env::args().collect()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    });
+    let mut opts = util::Options::new(args);
 
     if opts.help {
         const VERSION: &str = env!("CARGO_PKG_VERSION");
         println!("{} Version {}
 
 Usage:
- {} [OPTION] [DIRECTORY]
+ {} [OPTION] [DIRECTORY...]
 
 Arguments:
  -a, --algo, --algorithm ALGORITHM      uses ALGORITHM to hash files (example: md5, default: sha1)
-                                        (available: sha1, md5, sha224, sha256, sha384, sha512)
- -s, --subdir, --subdirectories         operate on the subdirectories of DIRECTORY (only for update and verify mode)
- --loglevel LEVEL                       controls the output of the program (quiet/info/progress/debug)
-                                        progress currently only supported for verify mode
- --quiet                                sets the loglevel to quiet
- -T, --threads THREADS                  spawn a maximum of THREADS worker threads (default: 0: no cap)
+                                        several separated by commas are used in one pass (example: md5,sha256)
+                                        verify mode detects the algorithm from the hashsum file if this is not given
+                                        (available: {available})
+ -s, --subdir, --subdirectories         operate on the subdirectories of every DIRECTORY (only for update and verify mode)
+ --max-depth DEPTH                      operate on the subdirectories DEPTH levels below DIRECTORY in subdir mode (default: 1)
+ --loglevel LEVEL                       controls the output of the program (quiet/progress/info/debug/trace, default: info)
+ -v, --verbose                          set the loglevel by repetition instead: -v info, -vv debug, -vvv trace, overrides --loglevel
+ -q                                     lower the loglevel instead: -q progress, -qq quiet, overrides --loglevel
+ --quiet                                print only the files that failed and a summary if anything failed, overrides --loglevel
+ --force-progress                       draw progress bars even if stdout is not a terminal
+ --title                                show the progress of update and verify mode in the title of the terminal window
+ --log-file PATH                        append the messages of the info and debug loglevel to PATH as well
+ --time-format FORMAT                   format of the timestamps of log lines, rfc3339 or a strftime pattern (default: %Y-%m-%d %H:%M:%S)
+ --si                                   print sizes in decimal units (kB, MB, GB) instead of binary units (KiB, MiB, GiB)
+ --machine                              print sizes as exact numbers of bytes
+ --format FORMAT                        output format of verify, diff, compare and status mode (text/json, default: text)
+ --progress-fd FD                       write progress of update and verify mode as JSON lines to file descriptor FD
+ --progress-file PATH                   write progress of update and verify mode as JSON lines to the file PATH
+ --emit-jsonl                           print a line of JSON with path, hash and size of every file update mode hashed to stdout
+ -0, --null                             terminate paths and lines of the hashsum file with NUL instead of newline
+ --tag                                  write BSD style lines to the hashsum file (both styles are read)
+ --compat-format                        write plain lines without size and modification time and no header
+                                        to the hashsum file, which sha1sum -c and the like understand
+ --manifest-pattern REGEX               read the lines of the hashsum file with REGEX, which names the groups hash and path
+ --paths STYLE                          write the paths to the hashsum file relative to its directory or absolute (relative/absolute, default: relative)
+ -T, --threads THREADS                  spawn THREADS worker threads hashing files (auto/NUMBER, default and 0: auto, the available parallelism)
+ -D, --parallel-directories DIRS        process DIRS directories at the same time in subdir mode (auto/NUMBER, default and 0: auto, the available parallelism)
+ --schedule ORDER                       order in which directories are processed (discovery/size, default: discovery)
+ --symlinks POLICY                      how symbolic links are handled (skip/follow/hash-link, default: skip)
+ --buffer-size SIZE                     read files SIZE bytes at a time while hashing them, K, M and G suffixes are accepted (default: 1M)
+ --mmap-threshold SIZE                  memory map files of at least SIZE bytes instead of reading them (default: never)
+ --max-rate RATE                        read at most RATE bytes per second across all worker threads, K, M and G suffixes are accepted
+ --block-size SIZE                      also hash blocks of SIZE bytes of every file in update mode, so verify mode reports which blocks changed
+ --sample PERCENT                       verify only a random sample of PERCENT percent of the files of every directory
+ --sample-count N                       verify only a random sample of N files of every directory
+ --seed SEED                            select the same sample as a previous run with this seed (default: random)
+ --fail-fast                            stop verify mode at the first file that changed, is missing or can not be read
+ --timeout SECONDS                      give up on a directory in verify mode if nothing of its files is read within SECONDS
+ --trust-mtime                          skip directories whose hashsum file is newer than all of their files in update and verify mode
+ --compress                             write the hashsum file compressed with gzip, as ALGORITHMsum.txt.gz
+ --manifest-checksum                    write the checksum of the hashsum file to ALGORITHMsum.txt.chk, which is checked before the hashsum file is read
+ --sign KEYFILE                         sign the hashsum file with the ed25519 secret key KEYFILE, writing ALGORITHMsum.txt.sig
+ --verify-sig PUBKEY                    only read hashsum files whose signature matches the ed25519 public key PUBKEY
+ --xattr                                store hashes in the extended attribute user.arkhash.ALGORITHM of every file instead of the hashsum file
+ --trust-xattr                          skip files in verify mode whose extended attribute holds the listed hash and whose size and mtime did not change
+ --check-metadata                       record permissions, owner and group in update mode and report files where they changed in verify mode (unix only)
+ --repair-from DIR                      restore files that changed or are missing in verify mode from their copy in DIR, if it has the listed hash
+ --hidden POLICY                        whether files and directories starting with a dot are hashed (include/exclude, default: exclude)
+ --respect-gitignore                    skip the files excluded by .gitignore files, the global gitignore and .git/info/exclude
+ --exclude PATTERNS                     skip the files and directories whose names match one of the comma separated PATTERNS,
+                                        like the patterns of a .arkhashignore (example: '*.tmp,cache/')
+ --force                                verify directories already marked known good or known bad this month in subdir mode
+ --state-dir DIRECTORY                  write the known_good and to_check files of verify mode to DIRECTORY (default: .)
+ --wait                                 wait for another arkhash using the same state directory to finish instead of exiting
+ --state-template TEMPLATE              name of the known_good and to_check lists, {{list}}, {{month}} and {{year}} are replaced
+                                        (default: {{list}}_{{month}}_{{year}}.txt)
+ --no-monthly-state                     do not start new known_good and to_check lists every month (same as --state-template {{list}}.txt)
  -u, --update                           switch to update mode
- -v, --verify                           switch to verify mode
+ -c, --verify                           switch to verify mode
+ --reset                                switch to reset mode, removing the known_good and to_check files of this month
+ --recheck                              switch to verify mode, verifying only the directories in the to_check list of this month
+                                        and moving the ones that are correct now to the known_good list
+ --status                               switch to status mode, summarizing the known_good, to_check and bad files lists of this month
+ --prune                                switch to prune mode, removing lines of files that no longer exist from the hashsum files
+ --check-manifest                       switch to check manifest mode, listing improperly formatted lines and paths listed more than once
+                                        in the hashsum files, which --dedupe removes keeping the last line of every path
+ --add FILE...                          hash the files and add or replace their lines in the hashsum file they belong to
+ --remove FILE...                       remove the lines of the files from the hashsum file they belong to
+ --diff OLD NEW                         list the paths whose hashes changed, were added or removed between two hashsum files
+ --compare DIR_A DIR_B                  hash the files of two directories and list the files that differ or only exist in one of them
+ --watch                                switch to watch mode, hashing files when they change and pruning deleted ones until Ctrl-C
+ --debounce SECONDS                     wait until no file changed for SECONDS before watch mode updates the hashsum files (default: 2)
+ --keygen KEYFILE                       generate an ed25519 key pair for --sign, writing the secret key to KEYFILE and the public key to KEYFILE.pub
+ -, --stdin                             hash everything read from stdin and print the hashsum line with the path -
+ -n, --dry-run                          only print what update mode would hash, verify mode would verify, reset or prune mode would remove
+ --find-duplicates                      print groups of files with identical hashes after update mode
+ --from-stdin                           update or verify the files and directories listed on stdin, one per line (or NUL separated with --null)
+ --no-config                            do not read the configuration file arkhash.toml
+                                        options can also be set in arkhash.toml or as ARKHASH_NAME variables, eg ARKHASH_EXCLUDE,
+                                        the command line takes precedence over the environment, which takes precedence over the file
+ --no-FLAG                              switch off a flag set in arkhash.toml or the environment, eg --no-quiet
  -h, --help                             show this help message
  -V, --version                          show version"
-               , opts.program_name, VERSION, opts.program_name);
+               , opts.program_name, VERSION, opts.program_name, available = util::Algorithm::available());
         return;
     }
 
@@ -35,29 +119,352 @@ Arguments:
         return;
     }
 
+    init_logger(&opts);
     if opts.loglevel_debug() {
-        println!("{:?}", opts);
+        debug!("{:?}", opts);
+    }
+    if !matches!(opts.mode, util::Mode::Keygen) {
+        check_keys(&opts);
     }
 
     match opts.mode {
         util::Mode::Filter => {
             let reader = std::io::BufReader::new(std::io::stdin());
-            let filter = filter::Filter::new(reader, opts.folder.as_str(), &opts);
+            let filter = filter::Filter::new(reader, &opts.folders[0], &opts);
 
             match filter {
-                Err(e) => panic!(e),
+                Err(e) => panic!("{}", e),
                 Ok(filter) => {
+                    let terminator = if opts.null_separated { b'\0' } else { b'\n' };
+                    let stdout = std::io::stdout();
+                    let mut stdout = stdout.lock();
                     for line in filter {
-                        println!("{}", line);
+                        let mut line = util::path_to_bytes(&line).into_owned();
+                        line.push(terminator);
+                        if stdout.write_all(&line).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        util::Mode::Stdin => {
+            let stdin = std::io::stdin();
+            match util::calculate_hashes_of_reader(&mut stdin.lock(), &opts) {
+                Ok(hashlines) => {
+                    for hashline in hashlines {
+                        print!("{}", hashline);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Could not read stdin: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        util::Mode::Keygen => {
+            let key_file = opts.sign_key.as_ref().unwrap();
+            match signature::generate(key_file) {
+                Ok(()) => {
+                    if !opts.loglevel_quiet() {
+                        println!(
+                            "Wrote the secret key to {} and the public key to {}",
+                            key_file.display(),
+                            signature::public_key_path(key_file).display()
+                        );
                     }
                 }
+                Err(e) => {
+                    eprintln!("Could not generate key {}: {}", key_file.display(), e);
+                    std::process::exit(2);
+                }
             }
         }
         util::Mode::Update => {
-            update::update_directories(opts);
+            read_folders_from_stdin(&mut opts);
+            let lock = lock_state_dir(&opts);
+            util::install_interrupt_handler(&opts);
+            let report = arkhash::update(&opts);
+            drop(lock);
+            render::update(&report, &opts);
+            exit_if_interrupted();
+            if opts.quiet && !report.is_ok() {
+                std::process::exit(1);
+            }
+        }
+        util::Mode::Add | util::Mode::Remove => {
+            let lock = lock_state_dir(&opts);
+            let report = match opts.mode {
+                util::Mode::Add => arkhash::add(&opts),
+                _ => arkhash::remove(&opts),
+            };
+            drop(lock);
+            if !report.failed.is_empty() {
+                std::process::exit(1);
+            }
+        }
+        util::Mode::Compare => {
+            util::install_interrupt_handler(&opts);
+            let result = arkhash::compare(&opts);
+            if let Ok(ref report) = result {
+                render::compare(report, &opts);
+            }
+            exit_if_interrupted();
+            match result {
+                Ok(report) if !report.failed.is_empty() => std::process::exit(2),
+                Ok(report) if !report.is_identical() => std::process::exit(1),
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(2);
+                }
+            }
+        }
+        util::Mode::Diff => match arkhash::diff(&opts) {
+            Ok(diff) => {
+                render::diff(&diff, &opts);
+                if !diff.is_empty() {
+                    std::process::exit(1);
+                }
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(2);
+            }
+        },
+        util::Mode::Prune => {
+            let lock = lock_state_dir(&opts);
+            let report = arkhash::prune(&opts);
+            drop(lock);
+            render::prune(&report, &opts);
+            if report
+                .directories
+                .iter()
+                .any(|directory| !directory.failed.is_empty())
+            {
+                std::process::exit(1);
+            }
+        }
+        util::Mode::CheckManifest => {
+            let lock = lock_state_dir(&opts);
+            let report = arkhash::check_manifest(&opts);
+            drop(lock);
+            render::check(&report, &opts);
+            if !report.is_ok() {
+                std::process::exit(1);
+            }
+        }
+        util::Mode::Reset => {
+            let lock = lock_state_dir(&opts);
+            let report = arkhash::reset(&opts);
+            drop(lock);
+            render::reset(&report, &opts);
+            if !report.failed.is_empty() {
+                std::process::exit(1);
+            }
+        }
+        util::Mode::Status => {
+            let report = arkhash::status(&opts);
+            render::status(&report, &opts);
+            if report.needs_attention() > 0 {
+                std::process::exit(1);
+            }
+        }
+        util::Mode::Watch => {
+            util::install_interrupt_handler(&opts);
+            match arkhash::watch(&opts) {
+                Ok(report) if !report.failed.is_empty() => std::process::exit(1),
+                Ok(_) => {}
+                Err(e) => {
+                    let folders: Vec<_> = opts
+                        .folders
+                        .iter()
+                        .map(|folder| folder.display().to_string())
+                        .collect();
+                    eprintln!("Could not watch {}: {}", folders.join(", "), e);
+                    std::process::exit(2);
+                }
+            }
         }
         util::Mode::Verify => {
-            std::process::exit(verify::verify_directories(opts));
+            read_folders_from_stdin(&mut opts);
+            let lock = lock_state_dir(&opts);
+            util::install_interrupt_handler(&opts);
+            let report = arkhash::verify(&opts);
+            drop(lock);
+            render::verify(&report, &opts);
+            exit_if_interrupted();
+            if !opts.dry_run {
+                std::process::exit(report.exit_code());
+            }
+        }
+    }
+}
+
+/// Replaces the folders to operate on by the paths read from stdin if --from-stdin was given
+fn read_folders_from_stdin(opts: &mut util::Options) {
+    if !opts.from_stdin {
+        return;
+    }
+
+    match util::read_path_list(std::io::stdin().lock(), opts.null_separated) {
+        Ok(paths) => opts.folders = paths,
+        Err(e) => {
+            eprintln!("Could not read paths from stdin: {}", e);
+            std::process::exit(2);
+        }
+    }
+}
+
+/// Exits if the key of --sign or --verify-sig can not be read, before any hashsum file is written or trusted
+fn check_keys(opts: &util::Options) {
+    let result = opts
+        .sign_key
+        .as_ref()
+        .map_or(Ok(()), |key_file| signature::read_signing_key(key_file).map(|_| ()))
+        .and_then(|_| {
+            opts.verify_key.as_ref().map_or(Ok(()), |key_file| {
+                signature::read_verifying_key(key_file).map(|_| ())
+            })
+        });
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    }
+}
+
+/// Locks the state directory, so arkhash processes sharing it, like overlapping cron jobs, do not write the same files at once.
+/// Exits with status 3 if another process holds the lock, unless --wait was given. Nothing is locked in a dry run.
+/// If the lock file can not be created, eg because the state directory is read-only, the program continues without it.
+/// The lock is released and its file removed once it is dropped, which std::process::exit skips,
+/// so it is dropped before exiting. A lock file left behind by a crash does not block later runs.
+fn lock_state_dir(opts: &util::Options) -> Option<arkhash::RunLock> {
+    if opts.dry_run {
+        return None;
+    }
+
+    let result = match arkhash::RunLock::try_acquire(opts) {
+        Ok(None) if opts.wait => {
+            if opts.loglevel_info() {
+                info!(
+                    "Waiting for another arkhash using {}",
+                    arkhash::RunLock::path(opts).display()
+                );
+            }
+            arkhash::RunLock::acquire(opts).map(Some)
+        }
+        result => result,
+    };
+
+    match result {
+        Ok(Some(lock)) => Some(lock),
+        Ok(None) => {
+            eprintln!(
+                "Another arkhash is running with the state directory {} (lock file {}), exiting. Use --wait to wait for it to finish.",
+                opts.state_dir.display(),
+                arkhash::RunLock::path(opts).display()
+            );
+            std::process::exit(3);
+        }
+        Err(e) => {
+            eprintln!(
+                "Could not lock {}, continuing without lock: {}",
+                arkhash::RunLock::path(opts).display(),
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Prints the messages of the log crate prefixed with the current time, errors and warnings to stderr and
+/// everything else to stdout. The loglevel decides which messages of arkhash are printed, like RUST_LOG=arkhash=info
+/// would, with --quiet only errors are printed. RUST_LOG is applied on top of it, so it can silence modules or enable
+/// the messages of other crates. With --log-file, every message is appended to the file as well.
+/// While progress bars are drawn, the messages are printed above them.
+fn init_logger(opts: &util::Options) {
+    let file = opts.log_file.as_ref().map(|path| {
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap_or_else(|e| {
+                eprintln!("Could not open log file {}: {}", path.display(), e);
+                std::process::exit(2);
+            })
+    });
+
+    let level = if opts.quiet {
+        log::LevelFilter::Error
+    } else {
+        opts.log_level.level_filter()
+    };
+    let filter = env_logger::Builder::new()
+        .filter_module("arkhash", level)
+        .parse_env("RUST_LOG")
+        .build();
+
+    log::set_max_level(filter.filter());
+    let logger = CliLogger {
+        filter,
+        time_format: opts.time_format.clone(),
+        file: file.map(std::sync::Mutex::new),
+    };
+    if log::set_boxed_logger(Box::new(logger)).is_err() {
+        eprintln!("Could not set up the logger");
+    }
+}
+
+/// Writes the messages of the log crate through the printer of the progress bars and to the log file.
+/// env_logger only decides which messages are printed.
+struct CliLogger {
+    filter: env_logger::Logger,
+    time_format: String,
+    file: Option<std::sync::Mutex<std::fs::File>>,
+}
+
+impl log::Log for CliLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.filter.matches(record) {
+            return;
+        }
+
+        let line = format!(
+            "[{}] {}",
+            chrono::Local::now().format(&self.time_format),
+            record.args()
+        );
+        if record.level() <= log::Level::Warn {
+            progress::eprintln(&line);
+        } else {
+            progress::println(&line);
+        }
+        if let Some(ref file) = self.file {
+            if let Ok(mut file) = file.lock() {
+                let _ = writeln!(file, "{}", line);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stdout().flush();
+        if let Some(ref file) = self.file {
+            if let Ok(mut file) = file.lock() {
+                let _ = file.flush();
+            }
         }
     }
 }
+
+/// Restores the terminal and exits with the status of a process killed by SIGINT if Ctrl-C was pressed.
+fn exit_if_interrupted() {
+    if util::interrupted() {
+        util::restore_terminal();
+        std::process::exit(130);
+    }
+}
@@ -0,0 +1,1190 @@
+//! This module implements reading and writing of the _algorithm_sum.txt files.
+//!
+//! Besides the plain `<hash>  <path>` lines known from the coreutils, arkhash stores the size and
+//! the modification time of a file next to its hash: `<hash> <size> <mtime>  <path>`.
+//! This allows the update mode to skip files that did not change since they were hashed.
+//! A file in this extended format starts with a header declaring its version and columns,
+//! `# arkhash manifest v1: hash size mtime path`. Files without a header are read like version 1,
+//! a newer version is refused instead of being misread.
+//! With the check_metadata option, the permissions, owner and group of a file are stored after its modification
+//! time, `<hash> <size> <mtime> <mode> <uid> <gid>  <path>`, with the mode as four octal digits.
+//! Files with such lines declare version 2, `# arkhash manifest v2: hash size mtime mode uid gid path`,
+//! files without them are still written as version 1, so older versions of arkhash can read them.
+//! BSD style lines, `SHA1 (<path>) = <hash>` as written by the coreutils with `--tag`, and plain lines are read
+//! as well and written if the manifest_format option asks for them, without a header.
+//! They have no room for size and modification time.
+//!
+//! Like the coreutils, paths containing a backslash or a line break are escaped: the line starts
+//! with a backslash and every backslash, newline and carriage return in the path is written as
+//! `\\`, `\n` and `\r` respectively. Bytes of a path that are not valid UTF-8 are written as `\xHH`.
+//! The coreutils do not know this escape, so plain and BSD style lines keep such bytes verbatim.
+//! With the null_separated option, every line is terminated by NUL instead and paths are written verbatim.
+//!
+//! Paths are written relative to the directory of the _algorithm_sum.txt, `./pictures/1.jpg`, or absolute with the
+//! paths option. Absolute paths inside the directory are read like relative ones, so both styles work the same.
+//! Absolute paths outside of the directory are kept as they are.
+//! Every path is normalized when it is read, `foo//../bar/./baz.txt` is the same file as `./bar/baz.txt`.
+//! Paths are written with `/` as separator on every platform, so a file written on Windows can be read elsewhere.
+//!
+//! Large manifests can be compressed with gzip, they are named _algorithm_sum.txt.gz then.
+//! Only the reading and writing of the bytes differs, the lines are the same.
+//! If a directory contains both, the compressed one is read.
+//!
+//! The checksum of an _algorithm_sum.txt can be stored in the sidecar _algorithm_sum.txt.chk, a single
+//! coreutils line naming the _algorithm_sum.txt. If the sidecar exists, the _algorithm_sum.txt is only read
+//! if its checksum matches, so a corrupted manifest is reported instead of being trusted.
+
+extern crate flate2;
+extern crate hex;
+extern crate regex;
+
+use self::flate2::read::GzDecoder;
+use self::flate2::write::GzEncoder;
+use self::flate2::Compression;
+
+use self::regex::bytes::Regex;
+use std::borrow::Cow;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::{self, FromStr};
+use std::time::UNIX_EPOCH;
+
+use super::util::{normalize_path, path_from_bytes, path_to_bytes, portable_path};
+
+use super::util::{Algorithm, ManifestFormat, PathStyle};
+
+/// The version of the extended format written by this version of arkhash
+pub const MANIFEST_VERSION: u32 = 2;
+
+/// The start of the header line of the extended format, followed by the version
+const HEADER_START: &str = "# arkhash manifest v";
+
+/// Returns the header line of the extended format, without its terminator
+///
+/// # Arguments
+///
+/// * `with_metadata` Whether or not lines list the permissions, owner and group of their file
+fn header(with_metadata: bool) -> String {
+    if with_metadata {
+        format!(
+            "{}{}: hash size mtime mode uid gid path",
+            HEADER_START, MANIFEST_VERSION
+        )
+    } else {
+        format!("{}1: hash size mtime path", HEADER_START)
+    }
+}
+
+/// Size and modification time of a file at the moment it was hashed
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileStamp {
+    /// Size of the file in bytes
+    pub size: u64,
+    /// Seconds of the modification time since the unix epoch
+    pub mtime_secs: u64,
+    /// Nanoseconds of the modification time
+    pub mtime_nanos: u32,
+}
+
+impl FileStamp {
+    /// Reads size and modification time of a file from its metadata.
+    /// Returns None if the platform does not report a usable modification time.
+    ///
+    /// # Arguments
+    ///
+    /// * `metadata` Metadata of the file
+    pub fn from_metadata(metadata: &fs::Metadata) -> Option<FileStamp> {
+        let mtime = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+
+        Some(FileStamp {
+            size: metadata.len(),
+            mtime_secs: mtime.as_secs(),
+            mtime_nanos: mtime.subsec_nanos(),
+        })
+    }
+}
+
+/// Permissions, owner and group of a file at the moment it was hashed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FileMetadata {
+    /// The permission bits of the file, including setuid, setgid and sticky
+    pub mode: u32,
+    /// The id of the user owning the file
+    pub uid: u32,
+    /// The id of the group owning the file
+    pub gid: u32,
+}
+
+impl FileMetadata {
+    /// Reads permissions, owner and group of a file from its metadata.
+    /// Returns None on platforms without unix permissions.
+    ///
+    /// # Arguments
+    ///
+    /// * `metadata` Metadata of the file
+    #[cfg(unix)]
+    pub fn from_metadata(metadata: &fs::Metadata) -> Option<FileMetadata> {
+        use std::os::unix::fs::MetadataExt;
+
+        Some(FileMetadata {
+            mode: metadata.mode() & 0o7777,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+        })
+    }
+
+    /// Reads permissions, owner and group of a file from its metadata.
+    /// Returns None on platforms without unix permissions.
+    ///
+    /// # Arguments
+    ///
+    /// * `metadata` Metadata of the file
+    #[cfg(not(unix))]
+    pub fn from_metadata(_metadata: &fs::Metadata) -> Option<FileMetadata> {
+        None
+    }
+
+    /// Describes how the metadata of a file differs from the recorded one, eg "mode 0644 -> 0600"
+    ///
+    /// # Arguments
+    ///
+    /// * `current` The current metadata of the file
+    pub fn describe_change(&self, current: &FileMetadata) -> String {
+        let mut changes = Vec::new();
+        if self.mode != current.mode {
+            changes.push(format!("mode {:04o} -> {:04o}", self.mode, current.mode));
+        }
+        if self.uid != current.uid {
+            changes.push(format!("uid {} -> {}", self.uid, current.uid));
+        }
+        if self.gid != current.gid {
+            changes.push(format!("gid {} -> {}", self.gid, current.gid));
+        }
+        changes.join(", ")
+    }
+}
+
+/// A single line of an _algorithm_sum.txt file
+#[derive(Debug, Clone)]
+pub struct ManifestEntry {
+    /// The hashsum of the file
+    pub hash: String,
+    /// Path to the file, relative to the directory of the _algorithm_sum.txt
+    pub path: PathBuf,
+    /// Size and modification time of the file when it was hashed, None for plain coreutils lines
+    pub stamp: Option<FileStamp>,
+    /// Permissions, owner and group of the file when it was hashed, only recorded with the check_metadata option
+    /// and only in lines with a stamp
+    pub metadata: Option<FileMetadata>,
+}
+
+impl ManifestEntry {
+    /// Formats the entry as a line of an _algorithm_sum.txt file, including the trailing newline
+    pub fn to_line(&self) -> String {
+        let path = portable_path(&self.path);
+        match escape_path(&path) {
+            Some(escaped) => format!("\\{}{}\n", self.prefix(), escaped),
+            None => format!("{}{}\n", self.prefix(), path.to_string_lossy()),
+        }
+    }
+
+    /// Formats the entry as a NUL terminated line of an _algorithm_sum.txt file, the path is not escaped
+    pub fn to_null_terminated_line(&self) -> Vec<u8> {
+        let path = portable_path(&self.path);
+        let mut line = self.prefix().into_bytes();
+        line.extend_from_slice(&path_to_bytes(&path));
+        line.push(b'\0');
+        line
+    }
+
+    /// Formats the entry as a plain line of an _algorithm_sum.txt file, including the trailing newline.
+    /// The stamp is not written, so the line can be checked by the coreutils.
+    pub fn to_plain_line(&self) -> String {
+        let path = portable_path(&self.path);
+        match escape_path(&path) {
+            Some(escaped) => format!("\\{}  {}\n", self.hash, escaped),
+            None => format!("{}  {}\n", self.hash, path.to_string_lossy()),
+        }
+    }
+
+    /// Formats the entry as a plain line of an _algorithm_sum.txt file for the coreutils, including the trailing
+    /// newline. Unlike to_plain_line, bytes of the path that are not valid UTF-8 are written verbatim,
+    /// only backslashes and line breaks are escaped, so `sha1sum -c` finds the file.
+    pub fn to_coreutils_line(&self) -> Vec<u8> {
+        let path = portable_path(&self.path);
+        let (escape, path) = escape_path_bytes(&path);
+        let mut line = format!("{}{}  ", escape, self.hash).into_bytes();
+        line.extend_from_slice(&path);
+        line.push(b'\n');
+        line
+    }
+
+    /// Formats the entry as a NUL terminated plain line of an _algorithm_sum.txt file, the path is not escaped
+    pub fn to_plain_null_terminated_line(&self) -> Vec<u8> {
+        let path = portable_path(&self.path);
+        let mut line = format!("{}  ", self.hash).into_bytes();
+        line.extend_from_slice(&path_to_bytes(&path));
+        line.push(b'\0');
+        line
+    }
+
+    /// Formats the entry as a BSD style line of an _algorithm_sum.txt file, including the trailing newline.
+    /// The stamp is not written.
+    ///
+    /// # Arguments
+    ///
+    /// * `algorithm` The algorithm the hash was calculated with
+    pub fn to_tagged_line(&self, algorithm: Algorithm) -> String {
+        let path = portable_path(&self.path);
+        match escape_path(&path) {
+            Some(escaped) => format!("\\{} ({}) = {}\n", algorithm.tag(), escaped, self.hash),
+            None => format!(
+                "{} ({}) = {}\n",
+                algorithm.tag(),
+                path.to_string_lossy(),
+                self.hash
+            ),
+        }
+    }
+
+    /// Formats the entry as a BSD style line of an _algorithm_sum.txt file for the coreutils, including the trailing
+    /// newline. Like to_coreutils_line, bytes of the path that are not valid UTF-8 are written verbatim.
+    ///
+    /// # Arguments
+    ///
+    /// * `algorithm` The algorithm the hash was calculated with
+    pub fn to_coreutils_tagged_line(&self, algorithm: Algorithm) -> Vec<u8> {
+        let path = portable_path(&self.path);
+        let (escape, path) = escape_path_bytes(&path);
+        let mut line = format!("{}{} (", escape, algorithm.tag()).into_bytes();
+        line.extend_from_slice(&path);
+        line.extend_from_slice(format!(") = {}\n", self.hash).as_bytes());
+        line
+    }
+
+    /// Formats the entry as a NUL terminated BSD style line of an _algorithm_sum.txt file, the path is not escaped
+    ///
+    /// # Arguments
+    ///
+    /// * `algorithm` The algorithm the hash was calculated with
+    pub fn to_tagged_null_terminated_line(&self, algorithm: Algorithm) -> Vec<u8> {
+        let path = portable_path(&self.path);
+        let mut line = format!("{} (", algorithm.tag()).into_bytes();
+        line.extend_from_slice(&path_to_bytes(&path));
+        line.extend_from_slice(format!(") = {}\0", self.hash).as_bytes());
+        line
+    }
+
+    /// Formats everything in front of the path
+    fn prefix(&self) -> String {
+        match (&self.stamp, &self.metadata) {
+            (Some(stamp), Some(metadata)) => format!(
+                "{} {} {}.{:09} {:04o} {} {}  ",
+                self.hash,
+                stamp.size,
+                stamp.mtime_secs,
+                stamp.mtime_nanos,
+                metadata.mode,
+                metadata.uid,
+                metadata.gid
+            ),
+            (Some(stamp), None) => format!(
+                "{} {} {}.{:09}  ",
+                self.hash, stamp.size, stamp.mtime_secs, stamp.mtime_nanos
+            ),
+            (None, _) => format!("{}  ", self.hash),
+        }
+    }
+}
+
+/// A path that is listed on more than one line of an _algorithm_sum.txt, as appending updates of older versions left behind
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicatePath {
+    /// Path to the file, relative to the directory of the _algorithm_sum.txt
+    pub path: PathBuf,
+    /// Numbers of the lines listing the path, starting at 1
+    pub lines: Vec<usize>,
+    /// Whether or not the lines list different hashes, only the hash of the last line is used
+    pub conflicting: bool,
+}
+
+/// The parsed contents of an _algorithm_sum.txt file
+///
+/// Every path is contained only once. The order of the lines is preserved when the manifest is written.
+pub struct Manifest {
+    /// The entries in the order they appear in the file
+    entries: Vec<ManifestEntry>,
+    /// Maps every path to its position in entries
+    positions: HashMap<PathBuf, usize>,
+    /// Numbers of the lines that are neither empty nor the header nor valid lines, starting at 1
+    malformed: Vec<usize>,
+    /// The paths that are listed more than once, in the order of their first line
+    duplicates: Vec<DuplicatePath>,
+}
+
+impl Manifest {
+    /// Reads the _algorithm_sum.txt file of a directory, or the _algorithm_sum.txt.gz if there is one.
+    /// A missing file results in an empty Manifest, except with opts.verify_key: a file that was removed has no
+    /// signature either, so then a missing file is an InvalidData error.
+    /// If the checksum in its sidecar _algorithm_sum.txt.chk does not match, an InvalidData error is returned,
+    /// and so it is with opts.verify_key if the signature in the sidecar _algorithm_sum.txt.sig is missing or does not match.
+    /// If a path is listed more than once, as appending updates of older versions did, the last line wins.
+    ///
+    /// # Arguments
+    ///
+    /// * `workdir` Path to the directory containing the _algorithm_sum.txt
+    /// * `opts` A reference to the Options object containing the desired algorithm
+    pub fn read(workdir: &Path, opts: &super::util::Options) -> Result<Manifest, io::Error> {
+        Manifest::read_checked(workdir, opts, false)
+    }
+
+    /// Like read, but a missing file results in an empty Manifest even with opts.verify_key.
+    /// For the modes that create the _algorithm_sum.txt of a directory that was never hashed.
+    ///
+    /// # Arguments
+    ///
+    /// * `workdir` Path to the directory containing the _algorithm_sum.txt
+    /// * `opts` A reference to the Options object containing the desired algorithm
+    pub fn read_or_new(workdir: &Path, opts: &super::util::Options) -> Result<Manifest, io::Error> {
+        Manifest::read_checked(workdir, opts, true)
+    }
+
+    /// Reads the _algorithm_sum.txt file of a directory, see read
+    ///
+    /// # Arguments
+    ///
+    /// * `workdir` Path to the directory containing the _algorithm_sum.txt
+    /// * `opts` A reference to the Options object containing the desired algorithm
+    /// * `allow_missing` Whether or not a missing file results in an empty Manifest with opts.verify_key
+    fn read_checked(
+        workdir: &Path,
+        opts: &super::util::Options,
+        allow_missing: bool,
+    ) -> Result<Manifest, io::Error> {
+        if opts.xattr && super::xattrs::is_supported(workdir) {
+            return Ok(Manifest::read_xattrs(workdir, opts));
+        }
+
+        let path = match Manifest::find(workdir, opts.algorithm) {
+            Some(path) => path,
+            None if opts.verify_key.is_some() && !allow_missing => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{}sum.txt is missing, its signature can not be verified",
+                        opts.algorithm
+                    ),
+                ))
+            }
+            None => Manifest::path(workdir, opts.algorithm),
+        };
+        let verified = verify_checksum(&path, opts.algorithm).and_then(|_| match opts.verify_key {
+            Some(ref key_file) => super::signature::verify(&path, key_file),
+            None => Ok(()),
+        });
+        match verified.and_then(|_| Manifest::read_file(&path, opts)) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Manifest {
+                entries: Vec::new(),
+                positions: HashMap::new(),
+                malformed: Vec::new(),
+                duplicates: Vec::new(),
+            }),
+            result => result,
+        }
+    }
+
+    /// Reads a file in the format of an _algorithm_sum.txt, which need not be named like one.
+    /// Files whose name ends with .gz are decompressed. If a path is listed more than once, the last line wins.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` Path to the file
+    /// * `opts` A reference to the Options object containing the algorithm of the file
+    pub fn read_file(path: &Path, opts: &super::util::Options) -> Result<Manifest, io::Error> {
+        let mut manifest = Manifest {
+            entries: Vec::new(),
+            positions: HashMap::new(),
+            malformed: Vec::new(),
+            duplicates: Vec::new(),
+        };
+
+        let file = OpenOptions::new().read(true).open(path)?;
+        let reader: Box<dyn Read> = if is_gzip(path) {
+            Box::new(GzDecoder::new(file))
+        } else {
+            Box::new(file)
+        };
+
+        let line_re = manifest_regex_from_opts(opts)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        let separator = if opts.null_separated { b'\0' } else { b'\n' };
+        // absolute paths inside the directory of the file are read like relative ones
+        let directory = path
+            .parent()
+            .map(|directory| {
+                if directory.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    directory
+                }
+            })
+            .and_then(|directory| std::path::absolute(directory).ok());
+        // the number of the first line of every entry and the position of the duplicates of an entry
+        let mut first_lines = Vec::new();
+        let mut duplicate_positions = HashMap::new();
+
+        // lines are read as bytes, paths written by the coreutils need not be valid UTF-8.
+        // A read error, like a truncated gzip stream, fails the whole file instead of dropping its remaining lines.
+        let lines = BufReader::new(reader).split(separator);
+        for (number, line) in lines.enumerate() {
+            let mut line = line?;
+            if !opts.null_separated && line.last() == Some(&b'\r') {
+                line.pop();
+            }
+
+            if let Some(version) = line.strip_prefix(HEADER_START.as_bytes()) {
+                let version: u32 =
+                    parse_number(version.split(|&c| c == b':').next().unwrap_or(b""));
+                if version > MANIFEST_VERSION {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "manifest version {} is not supported, the newest supported version is {}",
+                            version, MANIFEST_VERSION
+                        ),
+                    ));
+                }
+                continue;
+            }
+
+            let captures = match line_re.captures(&line) {
+                Some(captures) => captures,
+                None => {
+                    if !line.iter().all(u8::is_ascii_whitespace) {
+                        manifest.malformed.push(number + 1);
+                    }
+                    continue;
+                }
+            };
+
+            // GNU style lines fill the groups hash and path, BSD style lines bsd_hash and bsd_path
+            let (hash, path) = match (captures.name("hash"), captures.name("path")) {
+                (Some(hash), Some(path)) => (hash.as_bytes(), path.as_bytes()),
+                _ => match (captures.name("bsd_hash"), captures.name("bsd_path")) {
+                    (Some(hash), Some(path)) => (hash.as_bytes(), path.as_bytes()),
+                    _ => continue,
+                },
+            };
+
+            let path = if captures.name("escaped").is_some() && !opts.null_separated {
+                match unescape_path_bytes(path) {
+                    Some(path) => path,
+                    None => {
+                        manifest.malformed.push(number + 1);
+                        continue;
+                    }
+                }
+            } else {
+                path_from_bytes(path)
+            };
+
+            let path = match directory {
+                Some(ref directory) if path.is_absolute() => relative_path(path, directory),
+                _ => path,
+            };
+            let path = normalize_path(&path);
+
+            let stamp = match (
+                captures.name("size"),
+                captures.name("secs"),
+                captures.name("nanos"),
+            ) {
+                (Some(size), Some(secs), Some(nanos)) => Some(FileStamp {
+                    size: parse_number(size.as_bytes()),
+                    mtime_secs: parse_number(secs.as_bytes()),
+                    mtime_nanos: parse_number(nanos.as_bytes()),
+                }),
+                _ => None,
+            };
+            let metadata = match (
+                captures.name("mode"),
+                captures.name("uid"),
+                captures.name("gid"),
+            ) {
+                (Some(mode), Some(uid), Some(gid)) if stamp.is_some() => Some(FileMetadata {
+                    mode: str::from_utf8(mode.as_bytes())
+                        .ok()
+                        .and_then(|mode| u32::from_str_radix(mode, 8).ok())
+                        .unwrap_or(0),
+                    uid: parse_number(uid.as_bytes()),
+                    gid: parse_number(gid.as_bytes()),
+                }),
+                _ => None,
+            };
+
+            let entry = ManifestEntry {
+                hash: String::from_utf8_lossy(hash).into_owned(),
+                path,
+                stamp,
+                metadata,
+            };
+            match manifest.positions.get(&entry.path) {
+                Some(&i) => {
+                    let conflicting = !manifest.entries[i].hash.eq_ignore_ascii_case(&entry.hash);
+                    let duplicate = match duplicate_positions.entry(i) {
+                        Entry::Occupied(position) => &mut manifest.duplicates[*position.get()],
+                        Entry::Vacant(position) => {
+                            position.insert(manifest.duplicates.len());
+                            manifest.duplicates.push(DuplicatePath {
+                                path: entry.path.clone(),
+                                lines: vec![first_lines[i]],
+                                conflicting: false,
+                            });
+                            manifest.duplicates.last_mut().unwrap()
+                        }
+                    };
+                    duplicate.lines.push(number + 1);
+                    duplicate.conflicting |= conflicting;
+                }
+                None => first_lines.push(number + 1),
+            }
+            manifest.upsert(entry);
+        }
+
+        Ok(manifest)
+    }
+
+    /// Collects the hashes stored in the extended attributes of the files in a directory and its subdirectories.
+    /// Files without an attribute of opts.algorithm are not listed, and neither are files that no longer exist.
+    ///
+    /// # Arguments
+    ///
+    /// * `workdir` Path to the directory
+    /// * `opts` A reference to the Options object containing the desired algorithm and the files to walk
+    fn read_xattrs(workdir: &Path, opts: &super::util::Options) -> Manifest {
+        let mut manifest = Manifest {
+            entries: Vec::new(),
+            positions: HashMap::new(),
+            malformed: Vec::new(),
+            duplicates: Vec::new(),
+        };
+
+        for path in super::util::walk_files(workdir, true, opts) {
+            if Manifest::is_own_file(&path) {
+                continue;
+            }
+            if let Ok(Some((hash, stamp))) =
+                super::xattrs::read(&workdir.join(&path), opts.algorithm)
+            {
+                manifest.upsert(ManifestEntry {
+                    hash,
+                    path,
+                    stamp: Some(stamp),
+                    metadata: None,
+                });
+            }
+        }
+
+        manifest
+    }
+
+    /// Returns the path to the _algorithm_sum.txt file of a directory
+    ///
+    /// # Arguments
+    ///
+    /// * `workdir` Path to the directory containing the _algorithm_sum.txt
+    /// * `algorithm` The used algorithm
+    pub fn path(workdir: &Path, algorithm: Algorithm) -> std::path::PathBuf {
+        workdir.join(format!("{}sum.txt", algorithm))
+    }
+
+    /// Returns the path to the compressed _algorithm_sum.txt.gz file of a directory
+    ///
+    /// # Arguments
+    ///
+    /// * `workdir` Path to the directory containing the _algorithm_sum.txt.gz
+    /// * `algorithm` The used algorithm
+    pub fn gzip_path(workdir: &Path, algorithm: Algorithm) -> std::path::PathBuf {
+        workdir.join(format!("{}sum.txt.gz", algorithm))
+    }
+
+    /// Returns the path to the _algorithm_sum.txt.gz of a directory if it exists, otherwise to the _algorithm_sum.txt
+    /// if that exists
+    ///
+    /// # Arguments
+    ///
+    /// * `workdir` Path to the directory containing the _algorithm_sum.txt
+    /// * `algorithm` The used algorithm
+    pub fn find(workdir: &Path, algorithm: Algorithm) -> Option<std::path::PathBuf> {
+        vec![
+            Manifest::gzip_path(workdir, algorithm),
+            Manifest::path(workdir, algorithm),
+        ]
+        .into_iter()
+        .find(|path| path.is_file())
+    }
+
+    /// Returns the algorithms whose _algorithm_sum.txt or _algorithm_sum.txt.gz exists in a directory
+    ///
+    /// # Arguments
+    ///
+    /// * `workdir` Path to the directory that is searched
+    pub fn find_algorithms(workdir: &Path) -> Vec<Algorithm> {
+        Algorithm::all()
+            .into_iter()
+            .filter(|&algorithm| Manifest::find(workdir, algorithm).is_some())
+            .collect()
+    }
+
+    /// Returns the algorithm a file is named after, eg sha256 for sha256sum.txt or sha256sum_2024.txt
+    ///
+    /// # Arguments
+    ///
+    /// * `path` Path to the file
+    pub fn algorithm_from_name(path: &Path) -> Option<Algorithm> {
+        let name = path.file_name()?.to_string_lossy();
+        Algorithm::all()
+            .into_iter()
+            .find(|algorithm| name.starts_with(&format!("{}sum", algorithm)))
+    }
+
+    /// Returns the path to the temporary file the manifest is written to before it replaces the _algorithm_sum.txt
+    ///
+    /// # Arguments
+    ///
+    /// * `workdir` Path to the directory containing the _algorithm_sum.txt
+    /// * `algorithm` The used algorithm
+    fn temp_path(workdir: &Path, algorithm: Algorithm) -> std::path::PathBuf {
+        workdir.join(format!(".{}sum.txt.tmp", algorithm))
+    }
+
+    /// Returns the path to the temporary file the manifest is written to before it replaces the _algorithm_sum.txt.gz
+    ///
+    /// # Arguments
+    ///
+    /// * `workdir` Path to the directory containing the _algorithm_sum.txt.gz
+    /// * `algorithm` The used algorithm
+    fn gzip_temp_path(workdir: &Path, algorithm: Algorithm) -> std::path::PathBuf {
+        workdir.join(format!(".{}sum.txt.gz.tmp", algorithm))
+    }
+
+    /// Returns the path to the sidecar holding the checksum of an _algorithm_sum.txt or _algorithm_sum.txt.gz,
+    /// eg sha1sum.txt.chk
+    ///
+    /// # Arguments
+    ///
+    /// * `path` Path to the _algorithm_sum.txt or _algorithm_sum.txt.gz
+    pub fn checksum_path(path: &Path) -> std::path::PathBuf {
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(".chk");
+        path.with_file_name(name)
+    }
+
+    /// Returns the entry for a path, if there is one
+    ///
+    /// # Arguments
+    ///
+    /// * `path` Path to the file, relative to the directory of the _algorithm_sum.txt, it is normalized
+    pub fn get(&self, path: &Path) -> Option<&ManifestEntry> {
+        self.positions
+            .get(&normalize_path(path))
+            .map(|&i| &self.entries[i])
+    }
+
+    /// Returns all entries in the order they appear in the file
+    pub fn entries(&self) -> &[ManifestEntry] {
+        &self.entries
+    }
+
+    /// Returns the numbers of the lines that could not be read, starting at 1,
+    /// eg lines whose hash is truncated or does not have the length of the algorithm
+    pub fn malformed_lines(&self) -> &[usize] {
+        &self.malformed
+    }
+
+    /// Returns the paths that are listed on more than one line, in the order of their first line.
+    /// The entry of such a path holds the hash of its last line.
+    pub fn duplicates(&self) -> &[DuplicatePath] {
+        &self.duplicates
+    }
+
+    /// Inserts an entry with a normalized path, see normalize_path.
+    /// If the path is already known, the previous entry is replaced in place.
+    ///
+    /// # Arguments
+    ///
+    /// * `entry` The entry to insert
+    pub fn upsert(&mut self, mut entry: ManifestEntry) {
+        entry.path = normalize_path(&entry.path);
+        match self.positions.get(&entry.path) {
+            Some(&i) => self.entries[i] = entry,
+            None => {
+                self.positions
+                    .insert(entry.path.clone(), self.entries.len());
+                self.entries.push(entry);
+            }
+        }
+    }
+
+    /// Removes every entry for which keep returns false, the order of the remaining entries is preserved.
+    /// Returns the removed entries in the order they appeared in the file.
+    ///
+    /// # Arguments
+    ///
+    /// * `keep` Function that decides whether or not an entry is kept
+    pub fn retain<F: FnMut(&ManifestEntry) -> bool>(&mut self, mut keep: F) -> Vec<ManifestEntry> {
+        let (kept, removed) = self.entries.drain(..).partition(|entry| keep(entry));
+        self.entries = kept;
+        self.positions = self
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| (entry.path.clone(), i))
+            .collect();
+
+        removed
+    }
+
+    /// Writes the manifest to the _algorithm_sum.txt of a directory, or to the _algorithm_sum.txt.gz with opts.compress.
+    /// The lines are written to a temporary file first, which is synced to disk and then renamed over
+    /// the _algorithm_sum.txt, so an interrupted write never leaves a truncated file behind.
+    /// If writing fails, the temporary file is removed and the previous _algorithm_sum.txt is kept.
+    /// The file of the other format is removed afterwards, it would be outdated.
+    /// The sidecar _algorithm_sum.txt.chk is written with opts.manifest_checksum, and always if there already is one,
+    /// so it never holds the checksum of a previous version.
+    /// With opts.sign_key, the signature is written to the sidecar _algorithm_sum.txt.sig, otherwise a previous one is removed.
+    ///
+    /// # Arguments
+    ///
+    /// * `workdir` Path to the directory containing the _algorithm_sum.txt
+    /// * `opts` A reference to the Options object containing the used algorithm
+    pub fn write(&self, workdir: &Path, opts: &super::util::Options) -> Result<(), io::Error> {
+        if opts.xattr && super::xattrs::is_supported(workdir) {
+            return self.write_xattrs(workdir, opts);
+        }
+
+        let (path, temp_path, outdated) = if opts.compress {
+            (
+                Manifest::gzip_path(workdir, opts.algorithm),
+                Manifest::gzip_temp_path(workdir, opts.algorithm),
+                Manifest::path(workdir, opts.algorithm),
+            )
+        } else {
+            (
+                Manifest::path(workdir, opts.algorithm),
+                Manifest::temp_path(workdir, opts.algorithm),
+                Manifest::gzip_path(workdir, opts.algorithm),
+            )
+        };
+
+        let directory = match opts.paths {
+            PathStyle::Absolute => Some(std::path::absolute(workdir)?),
+            PathStyle::Relative => None,
+        };
+        super::util::write_atomically(&path, &temp_path, |file| {
+            if opts.compress {
+                let mut encoder = GzEncoder::new(file, Compression::default());
+                self.write_lines(&mut encoder, directory.as_deref(), opts)?;
+                encoder.finish().map(|_| ())
+            } else {
+                self.write_lines(file, directory.as_deref(), opts)
+            }
+        })?;
+
+        let outdated_checksum = Manifest::checksum_path(&outdated);
+        if opts.manifest_checksum
+            || Manifest::checksum_path(&path).is_file()
+            || outdated_checksum.is_file()
+        {
+            write_checksum(&path, opts.algorithm)?;
+        }
+        match opts.sign_key {
+            Some(ref key_file) => super::signature::sign(&path, key_file)?,
+            None => remove_if_exists(&super::signature::signature_path(&path))?,
+        }
+        let outdated_signature = super::signature::signature_path(&outdated);
+        for outdated in [outdated, outdated_checksum, outdated_signature] {
+            remove_if_exists(&outdated)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the header, if the format has one, and every entry in the format of opts
+    ///
+    /// # Arguments
+    ///
+    /// * `out` The writer receiving the lines
+    /// * `directory` The absolute path to the directory if the paths are written absolute
+    /// * `opts` A reference to the Options object containing the used algorithm and format
+    fn write_lines(
+        &self,
+        out: &mut dyn Write,
+        directory: Option<&Path>,
+        opts: &super::util::Options,
+    ) -> io::Result<()> {
+        if opts.manifest_format == ManifestFormat::Gnu {
+            let terminator = if opts.null_separated { '\0' } else { '\n' };
+            let with_metadata = self
+                .entries
+                .iter()
+                .any(|entry| entry.stamp.is_some() && entry.metadata.is_some());
+            out.write_all(format!("{}{}", header(with_metadata), terminator).as_bytes())?;
+        }
+        for entry in &self.entries {
+            let absolute;
+            let entry = match directory {
+                Some(directory) => {
+                    absolute = ManifestEntry {
+                        path: directory.join(entry.path.strip_prefix(".").unwrap_or(&entry.path)),
+                        ..entry.clone()
+                    };
+                    &absolute
+                }
+                None => entry,
+            };
+            let line = match (opts.manifest_format, opts.null_separated) {
+                (ManifestFormat::Gnu, false) => entry.to_line().into_bytes(),
+                (ManifestFormat::Gnu, true) => entry.to_null_terminated_line(),
+                (ManifestFormat::Plain, false) => entry.to_coreutils_line(),
+                (ManifestFormat::Plain, true) => entry.to_plain_null_terminated_line(),
+                (ManifestFormat::Bsd, false) => entry.to_coreutils_tagged_line(opts.algorithm),
+                (ManifestFormat::Bsd, true) => entry.to_tagged_null_terminated_line(opts.algorithm),
+            };
+            out.write_all(&line)?;
+        }
+        Ok(())
+    }
+
+    /// Stores the entries in the extended attributes of their files, attributes that are up to date are not written again.
+    /// Entries without a stamp are skipped. Every entry is tried, even if writing one of them fails.
+    ///
+    /// # Arguments
+    ///
+    /// * `workdir` Path to the directory the paths of the entries are relative to
+    /// * `opts` A reference to the Options object containing the used algorithm
+    ///
+    /// # Returns
+    /// The last error, naming the file it occurred for
+    fn write_xattrs(&self, workdir: &Path, opts: &super::util::Options) -> Result<(), io::Error> {
+        let mut result = Ok(());
+        for entry in &self.entries {
+            let stamp = match entry.stamp {
+                Some(ref stamp) => stamp,
+                None => continue,
+            };
+            let path = workdir.join(&entry.path);
+            let stored = super::xattrs::read(&path, opts.algorithm).ok().flatten();
+            if stored.as_ref() == Some(&(entry.hash.clone(), stamp.clone())) {
+                continue;
+            }
+            if let Err(e) = super::xattrs::write(&path, opts.algorithm, &entry.hash, stamp) {
+                result = Err(io::Error::new(
+                    e.kind(),
+                    format!("{}: {}", entry.path.display(), e),
+                ));
+            }
+        }
+        result
+    }
+
+    /// Returns whether the given path is the path to the database file of any algorithm, its block hashes, its
+    /// checksum, its signature, their temporary files
+    /// or the lock file, which is in the directory if it is the state directory.
+    /// Every algorithm is checked, so the databases of the other algorithms are never hashed themselves.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` The path to be checked, relative to the directory of the _algorithm_sum.txt
+    pub fn is_own_file(path: &Path) -> bool {
+        path == Path::new(".").join(".arkhash.lock")
+            || Algorithm::all()
+                .into_iter()
+                .any(|algorithm| Manifest::is_file_of(path, algorithm))
+    }
+
+    /// Returns whether the given path is the path to the database file of an algorithm, its block hashes, its
+    /// checksum, its signature or their temporary files
+    ///
+    /// # Arguments
+    ///
+    /// * `path` The path to be checked, relative to the directory of the _algorithm_sum.txt
+    /// * `algorithm` The algorithm of the database file
+    fn is_file_of(path: &Path, algorithm: Algorithm) -> bool {
+        let current = Path::new(".");
+        let name = format!("{}sum.txt", algorithm);
+        [name.clone(), format!("{}.gz", name)]
+            .iter()
+            .flat_map(|name| {
+                vec![
+                    name.clone(),
+                    format!("{}.chk", name),
+                    format!("{}.sig", name),
+                ]
+            })
+            .any(|name| {
+                path == current.join(&name) || path == current.join(format!(".{}.tmp", name))
+            })
+            || super::blocks::BlockManifest::is_own_file(path, algorithm)
+    }
+}
+
+/// Returns the checksum of a file with an algorithm
+///
+/// # Arguments
+///
+/// * `path` Path to the file
+/// * `algorithm` The algorithm of the checksum
+fn checksum(path: &Path, algorithm: Algorithm) -> io::Result<String> {
+    let mut hasher = algorithm.hasher();
+    hasher.update(&fs::read(path)?);
+    Ok(hasher.finalize())
+}
+
+/// Writes the checksum of an _algorithm_sum.txt to its sidecar, as a line `sha1sum -c` understands
+///
+/// # Arguments
+///
+/// * `path` Path to the _algorithm_sum.txt
+/// * `algorithm` The algorithm of the _algorithm_sum.txt
+fn write_checksum(path: &Path, algorithm: Algorithm) -> io::Result<()> {
+    let checksum_path = Manifest::checksum_path(path);
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let line = format!("{}  {}\n", checksum(path, algorithm)?, name);
+    let temp_path = checksum_path.with_file_name(format!(".{}.chk.tmp", name));
+
+    super::util::write_atomically(&checksum_path, &temp_path, |file| {
+        file.write_all(line.as_bytes())
+    })
+}
+
+/// Compares the checksum of an _algorithm_sum.txt with the one in its sidecar.
+/// Succeeds if the _algorithm_sum.txt or the sidecar does not exist.
+///
+/// # Arguments
+///
+/// * `path` Path to the _algorithm_sum.txt
+/// * `algorithm` The algorithm of the _algorithm_sum.txt
+fn verify_checksum(path: &Path, algorithm: Algorithm) -> io::Result<()> {
+    let checksum_path = Manifest::checksum_path(path);
+    let expected = match fs::read_to_string(&checksum_path) {
+        Ok(line) => line,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    let actual = match checksum(path, algorithm) {
+        Ok(actual) => actual,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    let expected = expected.split_whitespace().next().unwrap_or_default();
+    if expected.eq_ignore_ascii_case(&actual) {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "manifest corrupt, its checksum does not match {}",
+                checksum_path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+            ),
+        ))
+    }
+}
+
+/// Returns a path inside a directory relative to it, eg ./pictures/1.jpg, other paths are returned unchanged
+///
+/// # Arguments
+///
+/// * `path` The absolute path
+/// * `directory` The absolute path to the directory
+fn relative_path(path: PathBuf, directory: &Path) -> PathBuf {
+    match path.strip_prefix(directory) {
+        Ok(relative) if !relative.as_os_str().is_empty() => Path::new(".").join(relative),
+        _ => path,
+    }
+}
+
+/// Removes a file, succeeds if it does not exist
+///
+/// # Arguments
+///
+/// * `path` Path to the file
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Returns whether a manifest file is compressed with gzip, judging by its name
+///
+/// # Arguments
+///
+/// * `path` Path to the manifest file
+fn is_gzip(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "gz")
+}
+
+/// Escapes a path the way the coreutils do, bytes that are not valid UTF-8 are written as `\xHH`.
+/// Returns None if the path does not need to be escaped.
+///
+/// # Arguments
+///
+/// * `path` The path to be escaped
+pub fn escape_path(path: &Path) -> Option<String> {
+    let bytes = path_to_bytes(path);
+    let needs_escaping = match str::from_utf8(&bytes) {
+        Ok(path) => path.contains(['\\', '\n', '\r']),
+        Err(_) => true,
+    };
+    if !needs_escaping {
+        return None;
+    }
+
+    let mut escaped = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => escaped.push_str("\\\\"),
+                '\n' => escaped.push_str("\\n"),
+                '\r' => escaped.push_str("\\r"),
+                c => escaped.push(c),
+            }
+        }
+        for byte in chunk.invalid() {
+            escaped.push_str(&format!("\\x{:02x}", byte));
+        }
+    }
+
+    Some(escaped)
+}
+
+/// Escapes a path the way the coreutils do, without the `\xHH` escape of escape_path:
+/// backslashes and line breaks are escaped, every other byte is kept as it is.
+///
+/// # Arguments
+///
+/// * `path` The path to be escaped
+///
+/// # Returns
+/// The backslash the line has to start with, empty if the path does not need to be escaped, and the path.
+fn escape_path_bytes(path: &Path) -> (&'static str, Cow<'_, [u8]>) {
+    let bytes = path_to_bytes(path);
+    if !bytes
+        .iter()
+        .any(|byte| matches!(byte, b'\\' | b'\n' | b'\r'))
+    {
+        return ("", bytes);
+    }
+
+    let mut escaped = Vec::with_capacity(bytes.len() + 1);
+    for &byte in bytes.iter() {
+        match byte {
+            b'\\' => escaped.extend_from_slice(b"\\\\"),
+            b'\n' => escaped.extend_from_slice(b"\\n"),
+            b'\r' => escaped.extend_from_slice(b"\\r"),
+            byte => escaped.push(byte),
+        }
+    }
+
+    ("\\", Cow::Owned(escaped))
+}
+
+/// Reverts the escaping of escape_path.
+/// Returns None if the path contains an unknown escape sequence.
+///
+/// # Arguments
+///
+/// * `path` The escaped path, as written in the _algorithm_sum.txt
+pub fn unescape_path(path: &str) -> Option<PathBuf> {
+    unescape_path_bytes(path.as_bytes())
+}
+
+/// Reverts the escaping of escape_path and escape_path_bytes, bytes that are not escaped are kept as they are.
+/// Returns None if the path contains an unknown escape sequence.
+///
+/// # Arguments
+///
+/// * `path` The escaped path, as written in the _algorithm_sum.txt
+fn unescape_path_bytes(path: &[u8]) -> Option<PathBuf> {
+    let mut unescaped = Vec::with_capacity(path.len());
+    let mut bytes = path.iter().copied();
+
+    while let Some(byte) = bytes.next() {
+        if byte != b'\\' {
+            unescaped.push(byte);
+            continue;
+        }
+
+        match bytes.next() {
+            Some(b'\\') => unescaped.push(b'\\'),
+            Some(b'n') => unescaped.push(b'\n'),
+            Some(b'r') => unescaped.push(b'\r'),
+            Some(b'x') => {
+                let digits = [bytes.next()?, bytes.next()?];
+                let digits = str::from_utf8(&digits).ok()?;
+                unescaped.push(u8::from_str_radix(digits, 16).ok()?);
+            }
+            _ => return None,
+        }
+    }
+
+    Some(path_from_bytes(&unescaped))
+}
+
+/// Parses a number from the ASCII digits of a line, 0 if they can not be parsed
+fn parse_number<T: FromStr + Default>(digits: &[u8]) -> T {
+    str::from_utf8(digits)
+        .ok()
+        .and_then(|digits| digits.parse().ok())
+        .unwrap_or_default()
+}
+
+/// Returns the pattern of the lines of an _algorithm_sum.txt: opts.manifest_pattern if it is set, otherwise one
+/// that identifies hashsum, size, modification time and path from a line in either the plain, the extended
+/// or the BSD format, escaped or not, with or without the binary mode indicator `*` of the coreutils.
+/// The path of a NUL terminated line may contain newlines and any bytes.
+///
+/// # Arguments
+/// * `opts` Options object that contains the desired algorithm and the pattern set by the user
+pub fn manifest_pattern(opts: &super::util::Options) -> String {
+    if let Some(ref pattern) = opts.manifest_pattern {
+        return pattern.clone();
+    }
+
+    format!(
+        r"(?s-u)^\s*(?P<escaped>\\)?(?:(?P<hash>[[:xdigit:]]{{{length}}})(?: (?P<size>\d+) (?P<secs>\d+)\.(?P<nanos>\d{{9}})(?: (?P<mode>[0-7]{{4}}) (?P<uid>\d+) (?P<gid>\d+))?)?\s[\s*](?P<path>.*)|{tag} \((?P<bsd_path>.*)\) = (?P<bsd_hash>[[:xdigit:]]{{{length}}}))$",
+        length = opts.algorithm.hash_length(),
+        tag = regex::escape(opts.algorithm.tag())
+    )
+}
+
+/// Compiles the pattern returned by manifest_pattern.
+/// A pattern set by the user has to contain the named groups hash and path,
+/// it may contain size, secs and nanos for the size and modification time, mode, uid and gid for the permissions,
+/// owner and group and escaped for escaped paths.
+///
+/// # Arguments
+/// * `opts` Options object that contains the desired algorithm and the pattern set by the user
+pub fn manifest_regex_from_opts(opts: &super::util::Options) -> Result<Regex, String> {
+    let re = Regex::new(&manifest_pattern(opts))
+        .map_err(|e| format!("invalid manifest pattern: {}", e))?;
+
+    for group in &["hash", "path"] {
+        if !re.capture_names().any(|name| name == Some(group)) {
+            return Err(format!(
+                "invalid manifest pattern: the named group {} is missing",
+                group
+            ));
+        }
+    }
+
+    Ok(re)
+}
@@ -0,0 +1,1151 @@
+//! This module implements the progress bars of the progress loglevel
+//!
+//! Every directory that is being updated or verified gets its own progress bar. All updates are sent over a channel
+//! to a single printer thread, which owns the bars and lets indicatif redraw them.
+//! Once a directory is finished, its bar is removed and its result printed above the remaining bars,
+//! so the bars only take up one row for every directory that is processed at the moment.
+//! Threads that hash files never write to the terminal themselves in this loglevel.
+//! Lines printed with println and eprintln of this module, including the messages of the log crate
+//! when the arkhash binary installs its logger, are passed to the printer thread as long as it draws the bars,
+//! so they scroll the bars down instead of being written between the escape sequences that redraw them.
+//!
+//! The bars fill the width of the terminal, which is queried again on every update, so resizing the window
+//! does not break the layout. On a terminal too narrow to fit a bar next to the directory and the numbers,
+//! only the numbers are drawn.
+//!
+//! If stdout is not a terminal, for example when the output of a cron job is written to a log file,
+//! the bars are replaced by a plain line every 10 percent, unless drawing them is forced.
+//! The same happens if it is unclear whether the terminal can draw them: if TERM names a terminal without
+//! cursor control or is not set, or the size of the terminal can not be queried or is empty,
+//! as happens on some CI runners. See supports_bars.
+//!
+//! With the title option, the printer thread also shows the number of finished directories in the title
+//! of the terminal window, independent of the loglevel.
+//!
+//! The printer thread passes every update to its Reporters, which decide how it is presented:
+//! the TtyReporter draws the bars, the PlainReporter prints the plain lines, the JsonReporter writes the stream
+//! described below and the QuietReporter of the quiet option prints only the files that failed. Update and verify mode only send updates,
+//! they never talk to a Reporter themselves.
+//!
+//! Library users can follow every single file instead, with a FileCallback in the Options that is called
+//! by update and verify mode whenever a file was hashed or checked, or pass a Reporter of their own.
+//!
+//! Independent of the loglevel, every update can be written as a line of JSON to a file or file descriptor,
+//! which gives other programs a clean feed to drive their own progress bars.
+//!
+//! With the emit_jsonl option, the JsonHashReporter prints a line of JSON with path, hash and size to stdout
+//! for every file update mode hashed, so another program can index the files while they are hashed.
+//! Stdout then only holds these lines, no bars or plain lines are printed.
+
+extern crate console;
+extern crate indicatif;
+extern crate log;
+extern crate serde_json;
+
+use self::console::{measure_text_width, Term};
+use self::indicatif::{
+    HumanDuration, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressState, ProgressStyle,
+};
+use self::log::error;
+use super::util::ByteFormat;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Template of a running progress bar, based on bytes. `amount` holds the processed and total bytes,
+/// the message contains throughput and ETA.
+const BAR_TEMPLATE: &str = "{prefix}{percent:>3}% [{wide_bar}] {amount} {msg}";
+
+/// Template of a running progress on a terminal too narrow for a bar, see BAR_TEMPLATE
+const COMPACT_TEMPLATE: &str = "{prefix}{percent:>3}% {amount} {msg}";
+
+/// Number of characters of BAR_TEMPLATE besides prefix, amount, message and the bar itself
+const BAR_DECORATION_WIDTH: usize = "100% [] ".len() + 1;
+
+/// Least number of characters a bar is drawn with, narrower terminals get COMPACT_TEMPLATE
+const MIN_BAR_WIDTH: usize = 10;
+
+/// Time in seconds after which an old throughput sample has lost about two thirds of its weight
+const SMOOTHING_SECS: f64 = 5.0;
+
+/// The Sender of the printer thread while it prints the progress loglevel, None otherwise
+static ACTIVE_PRINTER: Mutex<Option<Sender<ProgressEvent>>> = Mutex::new(None);
+
+/// Set while the title of the terminal window shows the progress, so it is restored on exit
+static TITLE_SHOWN: AtomicBool = AtomicBool::new(false);
+
+/// An update for the printer thread
+#[derive(Debug, Clone)]
+pub enum ProgressEvent {
+    /// The directory with the given index is started
+    Started { line: usize },
+    /// Updates the progress bar of the directory with the given index
+    Progress {
+        line: usize,
+        processed_bytes: u64,
+        all_bytes: u64,
+        bytes_per_sec: f64,
+        eta: Option<Duration>,
+    },
+    /// A file of the directory with the given index was hashed or checked
+    File { line: usize, event: FileEvent },
+    /// Removes the progress bar of the directory with the given index and prints a message about it instead
+    Message { line: usize, message: String },
+    /// Prints a message above all progress bars
+    Log(String),
+}
+
+/// Where the JSON progress stream is written to
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProgressStream {
+    /// An open file descriptor, eg 3 for `3>progress.ndjson`
+    Fd(i32),
+    /// A file that is created or truncated
+    File(PathBuf),
+}
+
+impl ProgressStream {
+    /// Opens the stream for writing
+    fn open(&self) -> Result<File, io::Error> {
+        match self {
+            ProgressStream::Fd(fd) => file_from_fd(*fd),
+            ProgressStream::File(path) => OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path),
+        }
+    }
+}
+
+/// What happened to a single file in update or verify mode
+#[derive(Debug, Clone, PartialEq)]
+pub enum FileOutcome {
+    /// The file was hashed and its hash recorded in update mode
+    Hashed,
+    /// The hash of the file matches the recorded one in verify mode
+    Ok,
+    /// The hash of the file differs from the recorded one
+    Changed,
+    /// The hash of the file matches, but its permissions, owner or group differ from the recorded ones
+    MetadataChanged,
+    /// The file does not exist
+    Missing,
+    /// The file could not be hashed, with the reason
+    Failed(String),
+}
+
+impl FileOutcome {
+    /// Returns the outcome of a file that could not be hashed
+    ///
+    /// # Arguments
+    /// * `e` The reason the file could not be hashed
+    pub fn from_error(e: &super::util::HashError) -> FileOutcome {
+        if e.is_not_found() {
+            FileOutcome::Missing
+        } else {
+            FileOutcome::Failed(e.io_error().to_string())
+        }
+    }
+}
+
+/// A single file that was hashed or checked, passed to the FileCallback of the Options
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileEvent {
+    /// The directory the file belongs to
+    pub directory: PathBuf,
+    /// Path to the file, relative to the directory
+    pub path: PathBuf,
+    /// Size of the file in bytes, 0 if it could not be read
+    pub bytes: u64,
+    /// What happened to the file
+    pub outcome: FileOutcome,
+    /// The hash recorded for the file in update mode, None if it was not hashed or in verify mode
+    pub hash: Option<String>,
+}
+
+/// A function called with a FileEvent for every file update or verify mode hashed or checked.
+/// The directories are processed by several threads, calls are serialized by a Mutex,
+/// so the function has to be Send but not Sync. Clones call the same function.
+#[derive(Clone)]
+pub struct FileCallback(Arc<Mutex<FileFn>>);
+
+/// The function of a FileCallback
+type FileFn = dyn FnMut(&FileEvent) + Send;
+
+impl FileCallback {
+    /// Wraps a function in a FileCallback
+    ///
+    /// # Arguments
+    /// * `callback` The function called for every file
+    pub fn new<F: FnMut(&FileEvent) + Send + 'static>(callback: F) -> FileCallback {
+        FileCallback(Arc::new(Mutex::new(callback)))
+    }
+
+    /// Calls the function with an event
+    ///
+    /// # Arguments
+    /// * `event` The file that was hashed or checked
+    pub fn call(&self, event: &FileEvent) {
+        let mut callback = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        callback(event);
+    }
+}
+
+impl fmt::Debug for FileCallback {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "FileCallback")
+    }
+}
+
+impl Reporter for FileCallback {
+    fn file_checked(&mut self, _line: usize, event: &FileEvent) {
+        self.call(event);
+    }
+}
+
+/// Passes a file that was hashed or checked to the printer thread, if there is one
+///
+/// # Arguments
+/// * `progress` Sender to the printer thread
+/// * `line` The index of the directory in the directories of the printer thread
+/// * `directory` The directory the file belongs to
+/// * `path` Path to the file, relative to the directory
+/// * `bytes` Size of the file in bytes
+/// * `outcome` What happened to the file
+/// * `hash` The hash recorded for the file in update mode
+pub fn send_file(
+    progress: Option<&Sender<ProgressEvent>>,
+    line: usize,
+    directory: &Path,
+    path: &Path,
+    bytes: u64,
+    outcome: FileOutcome,
+    hash: Option<&str>,
+) {
+    if let Some(progress) = progress {
+        let event = FileEvent {
+            directory: directory.to_path_buf(),
+            path: path.to_path_buf(),
+            bytes,
+            outcome,
+            hash: hash.map(str::to_string),
+        };
+        progress.send(ProgressEvent::File { line, event }).unwrap();
+    }
+}
+
+/// Presents the updates of the printer thread, which passes every update to all of its reporters in turn,
+/// so a reporter is only ever called by a single thread. Directories are addressed by their index in the list
+/// passed to begin. Every method does nothing unless it is implemented.
+pub trait Reporter: Send {
+    /// Called once before any other method
+    ///
+    /// # Arguments
+    /// * `workdirs` The directories in the order of their indices
+    fn begin(&mut self, _workdirs: &[String]) {}
+
+    /// Called when a directory is started
+    ///
+    /// # Arguments
+    /// * `line` The index of the directory
+    fn dir_started(&mut self, _line: usize) {}
+
+    /// Called whenever a directory made progress
+    ///
+    /// # Arguments
+    /// * `line` The index of the directory
+    /// * `processed_bytes` Number of bytes of the directory hashed so far
+    /// * `all_bytes` Number of bytes of the directory that are hashed
+    /// * `bytes_per_sec` The current throughput
+    /// * `eta` The estimated time until the directory is finished
+    fn progress(
+        &mut self,
+        _line: usize,
+        _processed_bytes: u64,
+        _all_bytes: u64,
+        _bytes_per_sec: f64,
+        _eta: Option<Duration>,
+    ) {
+    }
+
+    /// Called for every file that was hashed or checked
+    ///
+    /// # Arguments
+    /// * `line` The index of the directory of the file
+    /// * `event` The file and what happened to it
+    fn file_checked(&mut self, _line: usize, _event: &FileEvent) {}
+
+    /// Called when a directory is finished
+    ///
+    /// # Arguments
+    /// * `line` The index of the directory
+    /// * `message` The result of the directory, eg "SUCCESS"
+    fn dir_finished(&mut self, _line: usize, _message: &str) {}
+
+    /// Called for a line that is printed independent of the directories, eg an error
+    ///
+    /// # Arguments
+    /// * `message` The line, without the trailing newline
+    fn log(&mut self, _message: &str) {}
+
+    /// Called once after every update
+    fn end(&mut self) {}
+}
+
+/// Passes an update to the matching method of a reporter
+///
+/// # Arguments
+/// * `reporter` The reporter
+/// * `event` The update
+fn report(reporter: &mut dyn Reporter, event: &ProgressEvent) {
+    match *event {
+        ProgressEvent::Started { line } => reporter.dir_started(line),
+        ProgressEvent::Progress {
+            line,
+            processed_bytes,
+            all_bytes,
+            bytes_per_sec,
+            eta,
+        } => reporter.progress(line, processed_bytes, all_bytes, bytes_per_sec, eta),
+        ProgressEvent::File { line, ref event } => reporter.file_checked(line, event),
+        ProgressEvent::Message { line, ref message } => reporter.dir_finished(line, message),
+        ProgressEvent::Log(ref message) => reporter.log(message),
+    }
+}
+
+/// A Reporter that can be stored in the Options, whose clones share the same reporter
+#[derive(Clone)]
+pub struct SharedReporter(Arc<Mutex<Box<dyn Reporter>>>);
+
+impl SharedReporter {
+    /// Wraps a reporter in a SharedReporter
+    ///
+    /// # Arguments
+    /// * `reporter` The reporter
+    pub fn new<R: Reporter + 'static>(reporter: R) -> SharedReporter {
+        SharedReporter(Arc::new(Mutex::new(Box::new(reporter))))
+    }
+
+    /// Locks the reporter
+    fn lock(&self) -> std::sync::MutexGuard<'_, Box<dyn Reporter>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for SharedReporter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SharedReporter")
+    }
+}
+
+impl Reporter for SharedReporter {
+    fn begin(&mut self, workdirs: &[String]) {
+        self.lock().begin(workdirs);
+    }
+
+    fn dir_started(&mut self, line: usize) {
+        self.lock().dir_started(line);
+    }
+
+    fn progress(
+        &mut self,
+        line: usize,
+        processed_bytes: u64,
+        all_bytes: u64,
+        bytes_per_sec: f64,
+        eta: Option<Duration>,
+    ) {
+        self.lock()
+            .progress(line, processed_bytes, all_bytes, bytes_per_sec, eta);
+    }
+
+    fn file_checked(&mut self, line: usize, event: &FileEvent) {
+        self.lock().file_checked(line, event);
+    }
+
+    fn dir_finished(&mut self, line: usize, message: &str) {
+        self.lock().dir_finished(line, message);
+    }
+
+    fn log(&mut self, message: &str) {
+        self.lock().log(message);
+    }
+
+    fn end(&mut self) {
+        self.lock().end();
+    }
+}
+
+/// Takes ownership of an open file descriptor
+#[cfg(unix)]
+fn file_from_fd(fd: i32) -> Result<File, io::Error> {
+    use std::os::unix::io::FromRawFd;
+
+    if fd < 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid file descriptor",
+        ));
+    }
+    // the descriptor is handed to arkhash by the calling process and not used anywhere else
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+/// Takes ownership of an open file descriptor
+#[cfg(windows)]
+fn file_from_fd(_fd: i32) -> Result<File, io::Error> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "file descriptors are not supported on windows, use --progress-file",
+    ))
+}
+
+/// Handle to the printer thread
+pub struct Printer {
+    /// Sender for passing updates to the printer thread
+    sender: Sender<ProgressEvent>,
+    /// The printer thread
+    handle: JoinHandle<()>,
+}
+
+impl Printer {
+    /// Indicates that a printer thread is needed, because progress is displayed, streamed, shown in the title,
+    /// the files that failed are printed or progress is passed to the reporter or file callback of a library user
+    ///
+    /// # Arguments
+    /// * `opts` Options object containing loglevel, progress stream, whether or not the title is set,
+    ///   the reporter and the file callback
+    pub fn is_needed(opts: &super::util::Options) -> bool {
+        opts.loglevel_progress()
+            || opts.progress_stream.is_some()
+            || opts.title
+            || opts.quiet
+            || opts.on_file.is_some()
+            || opts.reporter.is_some()
+            || opts.emit_jsonl
+    }
+
+    /// Starts the printer thread.
+    /// Progress bars are only displayed in the progress loglevel.
+    ///
+    /// # Arguments
+    /// * `workdirs` The directories, addressed by their index in ProgressEvents
+    /// * `longest_folder` Number of characters in the name of the longest folder, determines how many spaces are padded
+    /// * `opts` Options object containing loglevel, progress stream, whether or not progress bars are forced
+    ///   and whether or not the title is set
+    pub fn start(
+        workdirs: Vec<String>,
+        longest_folder: usize,
+        opts: &super::util::Options,
+    ) -> Printer {
+        let (sender, receiver) = channel();
+        let byte_format = opts.byte_format;
+        console::set_colors_enabled(super::util::colors_enabled());
+
+        // the bars are drawn first, a slow stream or callback does not hold them up as long
+        let mut reporters: Vec<Box<dyn Reporter>> = Vec::new();
+        // stdout only holds the JSON lines of the hashed files with emit_jsonl
+        let draws = opts.loglevel_progress() && !opts.emit_jsonl;
+        if opts.emit_jsonl {
+            reporters.push(Box::new(JsonHashReporter::new(io::stdout())));
+        } else if opts.quiet {
+            reporters.push(Box::new(QuietReporter));
+        } else if draws && (opts.force_progress || stdout_supports_bars()) {
+            reporters.push(Box::new(TtyReporter::new(
+                longest_folder,
+                byte_format,
+                opts.force_progress,
+            )));
+        } else if draws {
+            reporters.push(Box::new(PlainReporter::new(longest_folder)));
+        }
+        if let Some(ref progress_stream) = opts.progress_stream {
+            match progress_stream.open() {
+                Ok(file) => reporters.push(Box::new(JsonReporter::new(io::BufWriter::new(file)))),
+                Err(e) => error!("Could not open progress stream: {}", e),
+            }
+        }
+        if opts.title && stdout_supports_bars() {
+            reporters.push(Box::new(WindowTitle::new()));
+        }
+        if let Some(ref on_file) = opts.on_file {
+            reporters.push(Box::new(on_file.clone()));
+        }
+        if let Some(ref reporter) = opts.reporter {
+            reporters.push(Box::new(reporter.clone()));
+        }
+
+        if draws {
+            *ACTIVE_PRINTER.lock().unwrap() = Some(sender.clone());
+        }
+
+        let handle = thread::spawn(move || {
+            for reporter in reporters.iter_mut() {
+                reporter.begin(&workdirs);
+            }
+            for event in receiver {
+                for reporter in reporters.iter_mut() {
+                    report(reporter.as_mut(), &event);
+                }
+            }
+            for reporter in reporters.iter_mut() {
+                reporter.end();
+            }
+        });
+
+        Printer { sender, handle }
+    }
+
+    /// Returns a Sender for passing updates to the printer thread
+    pub fn sender(&self) -> Sender<ProgressEvent> {
+        self.sender.clone()
+    }
+
+    /// Waits until every update is printed.
+    /// All Senders returned by sender have to be dropped before.
+    pub fn finish(self) {
+        ACTIVE_PRINTER.lock().unwrap().take();
+        drop(self.sender);
+        self.handle.join().unwrap();
+    }
+}
+
+/// Prints a line to stdout, above the progress bars if the printer thread currently draws them
+///
+/// # Arguments
+/// * `line` The line to print, without the trailing newline
+pub fn println(line: &str) {
+    if !print_above_bars(line) {
+        println!("{}", line);
+    }
+}
+
+/// Prints a line to stderr, or to stdout above the progress bars if the printer thread currently draws them.
+/// Both streams usually end up on the same terminal, where an error line would break the bars otherwise.
+///
+/// # Arguments
+/// * `line` The line to print, without the trailing newline
+pub fn eprintln(line: &str) {
+    if !print_above_bars(line) {
+        eprintln!("{}", line);
+    }
+}
+
+/// Passes a line to the printer thread of the progress loglevel.
+/// Returns false if there is no such thread, the line has to be printed by the caller then.
+///
+/// # Arguments
+/// * `line` The line to print, without the trailing newline
+fn print_above_bars(line: &str) -> bool {
+    match *ACTIVE_PRINTER.lock().unwrap() {
+        Some(ref sender) => sender.send(ProgressEvent::Log(line.to_string())).is_ok(),
+        None => false,
+    }
+}
+
+/// Draws a progress bar for every directory that is processed at the moment.
+/// A progress bar is added below the others once a directory reports its first progress
+/// and removed once the directory is finished, which frees its row for the next directory.
+pub struct TtyReporter {
+    /// The directories in the order of their indices
+    workdirs: Vec<String>,
+    /// Number of characters in the name of the longest folder
+    longest_folder: usize,
+    /// How the processed and total bytes and the throughput are printed
+    byte_format: ByteFormat,
+    /// Owns the bars and draws them
+    multi: MultiProgress,
+    /// Style of a bar on a terminal wide enough for it
+    bar_style: ProgressStyle,
+    /// Style of a bar on a terminal too narrow for it
+    compact_style: ProgressStyle,
+    /// The terminal, queried for its width
+    term: Term,
+    /// The bars of the running directories
+    bars: HashMap<usize, ProgressBar>,
+    /// Indices of the directories whose bar is drawn with compact_style
+    compact: HashSet<usize>,
+    /// Indices of the finished directories
+    finished: HashSet<usize>,
+}
+
+impl TtyReporter {
+    /// Creates a TtyReporter drawing to stdout
+    ///
+    /// # Arguments
+    /// * `longest_folder` Number of characters in the name of the longest folder, determines how many spaces are padded
+    /// * `byte_format` How the processed and total bytes and the throughput are printed
+    /// * `force` Whether or not to draw the bars even if stdout is not a terminal or does not support them
+    pub fn new(longest_folder: usize, byte_format: ByteFormat, force: bool) -> TtyReporter {
+        let target = if force && !stdout_supports_bars() {
+            // a Term target hides itself if stdout is not a terminal, a TermLike target always draws
+            ProgressDrawTarget::term_like_with_hz(Box::new(Term::stdout()), 20)
+        } else {
+            ProgressDrawTarget::stdout()
+        };
+        let style = |template| {
+            ProgressStyle::with_template(template)
+                .unwrap()
+                .with_key(
+                    "amount",
+                    move |state: &ProgressState, w: &mut dyn fmt::Write| {
+                        let _ = write!(
+                            w,
+                            "{}",
+                            format_amount(state.pos(), state.len(), byte_format)
+                        );
+                    },
+                )
+                .progress_chars("#>_")
+        };
+
+        TtyReporter {
+            workdirs: Vec::new(),
+            longest_folder,
+            byte_format,
+            multi: MultiProgress::with_draw_target(target),
+            bar_style: style(BAR_TEMPLATE),
+            compact_style: style(COMPACT_TEMPLATE),
+            term: Term::stdout(),
+            bars: HashMap::new(),
+            compact: HashSet::new(),
+            finished: HashSet::new(),
+        }
+    }
+
+    /// Prints a line above the bars
+    ///
+    /// # Arguments
+    /// * `line` The line, without the trailing newline
+    fn println(&self, line: String) {
+        if let Err(e) = self.multi.println(line) {
+            eprintln!("Error writing to terminal: {}", e);
+        }
+    }
+}
+
+impl Reporter for TtyReporter {
+    fn begin(&mut self, workdirs: &[String]) {
+        self.workdirs = workdirs.to_vec();
+    }
+
+    fn progress(
+        &mut self,
+        line: usize,
+        processed_bytes: u64,
+        all_bytes: u64,
+        bytes_per_sec: f64,
+        eta: Option<Duration>,
+    ) {
+        if self.finished.contains(&line) {
+            return;
+        }
+        let prefix = format_aligned("", &self.workdirs[line], self.longest_folder);
+        let message = format_throughput(bytes_per_sec, eta, self.byte_format);
+        let text_width = measure_text_width(&prefix)
+            + measure_text_width(&format_amount(
+                processed_bytes,
+                Some(all_bytes),
+                self.byte_format,
+            ))
+            + measure_text_width(&message);
+        // the window may have been resized since the last update
+        let fits = bar_fits(
+            self.term
+                .size_checked()
+                .map(|(_, columns)| columns as usize),
+            text_width,
+        );
+
+        let multi = &self.multi;
+        let bar_style = &self.bar_style;
+        let bar = self.bars.entry(line).or_insert_with(|| {
+            let bar = multi.add(ProgressBar::new(all_bytes));
+            bar.set_style(bar_style.clone());
+            bar.set_prefix(prefix);
+            bar
+        });
+        if fits == self.compact.contains(&line) {
+            if fits {
+                bar.set_style(self.bar_style.clone());
+                self.compact.remove(&line);
+            } else {
+                bar.set_style(self.compact_style.clone());
+                self.compact.insert(line);
+            }
+        }
+        bar.set_length(all_bytes);
+        bar.set_position(processed_bytes);
+        bar.set_message(message);
+    }
+
+    fn dir_finished(&mut self, line: usize, message: &str) {
+        self.finished.insert(line);
+        self.println(format_aligned(
+            message,
+            &self.workdirs[line],
+            self.longest_folder,
+        ));
+        if let Some(bar) = self.bars.remove(&line) {
+            bar.finish_and_clear();
+            self.multi.remove(&bar);
+        }
+    }
+
+    fn log(&mut self, message: &str) {
+        self.println(message.to_string());
+    }
+
+    fn end(&mut self) {
+        // indicatif leaves the cursor behind the last bar of a directory that never finished,
+        // the summary printed afterwards starts on a new line
+        if !self.bars.is_empty() && !self.multi.is_hidden() {
+            println!();
+        }
+    }
+}
+
+/// Prints the updates as plain lines without escape sequences, for stdout that is not a terminal.
+/// The progress of a directory is printed whenever it reaches the next 10 percent.
+pub struct PlainReporter {
+    /// The directories in the order of their indices
+    workdirs: Vec<String>,
+    /// Number of characters in the name of the longest folder
+    longest_folder: usize,
+    /// The last printed step of 10 percent of every directory
+    printed_steps: HashMap<usize, u64>,
+}
+
+impl PlainReporter {
+    /// Creates a PlainReporter printing to stdout
+    ///
+    /// # Arguments
+    /// * `longest_folder` Number of characters in the name of the longest folder, determines how many spaces are padded
+    pub fn new(longest_folder: usize) -> PlainReporter {
+        PlainReporter {
+            workdirs: Vec::new(),
+            longest_folder,
+            printed_steps: HashMap::new(),
+        }
+    }
+}
+
+impl Reporter for PlainReporter {
+    fn begin(&mut self, workdirs: &[String]) {
+        self.workdirs = workdirs.to_vec();
+    }
+
+    fn progress(
+        &mut self,
+        line: usize,
+        processed_bytes: u64,
+        all_bytes: u64,
+        _bytes_per_sec: f64,
+        _eta: Option<Duration>,
+    ) {
+        let percent = (processed_bytes * 100)
+            .checked_div(all_bytes)
+            .unwrap_or(100);
+        let step = percent / 10;
+
+        if self
+            .printed_steps
+            .get(&line)
+            .is_none_or(|&printed| step > printed)
+        {
+            self.printed_steps.insert(line, step);
+            println!(
+                "{}",
+                format_aligned(
+                    &format!("{}%", percent),
+                    &self.workdirs[line],
+                    self.longest_folder
+                )
+            );
+        }
+    }
+
+    fn dir_finished(&mut self, line: usize, message: &str) {
+        println!(
+            "{}",
+            format_aligned(message, &self.workdirs[line], self.longest_folder)
+        );
+    }
+
+    fn log(&mut self, message: &str) {
+        println!("{}", message);
+    }
+}
+
+/// Writes every update as a single line of JSON and flushes it, so readers receive it immediately.
+/// Once writing failed, the error is printed and nothing more is written.
+pub struct JsonReporter<W: Write> {
+    /// Where the lines are written to, None after an error
+    stream: Option<W>,
+    /// The directories in the order of their indices
+    workdirs: Vec<String>,
+}
+
+impl<W: Write> JsonReporter<W> {
+    /// Creates a JsonReporter
+    ///
+    /// # Arguments
+    /// * `stream` Where the lines are written to
+    pub fn new(stream: W) -> JsonReporter<W> {
+        JsonReporter {
+            stream: Some(stream),
+            workdirs: Vec::new(),
+        }
+    }
+
+    /// Writes a line of JSON
+    ///
+    /// # Arguments
+    /// * `json` The JSON value of the line
+    fn write(&mut self, json: serde_json::Value) {
+        let written = match self.stream {
+            Some(ref mut stream) => writeln!(stream, "{}", json).and_then(|()| stream.flush()),
+            None => return,
+        };
+        if let Err(e) = written {
+            eprintln!("Error writing to progress stream: {}", e);
+            self.stream = None;
+        }
+    }
+}
+
+impl<W: Write + Send> Reporter for JsonReporter<W> {
+    fn begin(&mut self, workdirs: &[String]) {
+        self.workdirs = workdirs.to_vec();
+    }
+
+    fn progress(
+        &mut self,
+        line: usize,
+        processed_bytes: u64,
+        all_bytes: u64,
+        _bytes_per_sec: f64,
+        _eta: Option<Duration>,
+    ) {
+        let json = serde_json::json!({
+            "dir": self.workdirs[line],
+            "processed": processed_bytes,
+            "total": all_bytes,
+        });
+        self.write(json);
+    }
+
+    fn dir_finished(&mut self, line: usize, message: &str) {
+        let json = serde_json::json!({
+            "dir": self.workdirs[line],
+            "message": message,
+        });
+        self.write(json);
+    }
+
+    fn log(&mut self, message: &str) {
+        self.write(serde_json::json!({ "log": message }));
+    }
+}
+
+/// Writes a line of JSON for every file update mode hashed and flushes it, eg
+/// `{"hash":"<hash>","path":"./2015/a.jpg","size":1024}`. The path is the one of the directory of the file joined
+/// with its path in the _algorithm_sum.txt. Once writing failed, the error is printed and nothing more is written.
+pub struct JsonHashReporter<W: Write> {
+    /// Where the lines are written to, None after an error
+    stream: Option<W>,
+}
+
+impl<W: Write> JsonHashReporter<W> {
+    /// Creates a JsonHashReporter
+    ///
+    /// # Arguments
+    /// * `stream` Where the lines are written to
+    pub fn new(stream: W) -> JsonHashReporter<W> {
+        JsonHashReporter {
+            stream: Some(stream),
+        }
+    }
+}
+
+impl<W: Write + Send> Reporter for JsonHashReporter<W> {
+    fn file_checked(&mut self, _line: usize, event: &FileEvent) {
+        let hash = match (&event.outcome, &event.hash) {
+            (FileOutcome::Hashed, Some(hash)) => hash,
+            _ => return,
+        };
+        let json = serde_json::json!({
+            "path": super::util::normalize_path(&event.directory.join(&event.path)).to_string_lossy(),
+            "hash": hash,
+            "size": event.bytes,
+        });
+        let written = match self.stream {
+            Some(ref mut stream) => writeln!(stream, "{}", json).and_then(|()| stream.flush()),
+            None => return,
+        };
+        if let Err(e) = written {
+            eprintln!("Error writing hashes: {}", e);
+            self.stream = None;
+        }
+    }
+}
+
+/// Prints only the files that changed, are missing, could not be hashed or whose metadata changed, one line per file,
+/// eg `./2015/a.jpg: changed`. Used by the quiet option, which keeps cron jobs silent while nothing is wrong.
+pub struct QuietReporter;
+
+impl Reporter for QuietReporter {
+    fn file_checked(&mut self, _line: usize, event: &FileEvent) {
+        let path = super::util::normalize_path(&event.directory.join(&event.path));
+        match event.outcome {
+            FileOutcome::Hashed | FileOutcome::Ok => {}
+            FileOutcome::Changed => println!("{}: changed", path.display()),
+            FileOutcome::MetadataChanged => println!("{}: metadata changed", path.display()),
+            FileOutcome::Missing => println!("{}: missing", path.display()),
+            FileOutcome::Failed(ref reason) => println!("{}: failed, {}", path.display(), reason),
+        }
+    }
+}
+
+/// Shows the number of finished directories in the title of the terminal window.
+/// The previous title is saved when the first directory starts and restored when it is dropped.
+struct WindowTitle {
+    /// Number of directories
+    total: usize,
+    /// Indices of the finished directories
+    finished: HashSet<usize>,
+}
+
+impl WindowTitle {
+    /// Creates a WindowTitle, the title is not changed before begin
+    fn new() -> WindowTitle {
+        WindowTitle {
+            total: 0,
+            finished: HashSet::new(),
+        }
+    }
+
+    /// Writes the title to the terminal
+    fn show(&self) {
+        write_terminal(&format!(
+            "\x1b]0;{}\x07",
+            format_title(self.finished.len(), self.total)
+        ));
+    }
+}
+
+impl Reporter for WindowTitle {
+    fn begin(&mut self, workdirs: &[String]) {
+        // terminals without a stack of titles ignore this, the title is cleared on exit then
+        write_terminal("\x1b[22;0t");
+        TITLE_SHOWN.store(true, Ordering::SeqCst);
+        self.total = workdirs.len();
+        self.show();
+    }
+
+    fn dir_finished(&mut self, line: usize, _message: &str) {
+        if self.finished.insert(line) {
+            self.show();
+        }
+    }
+}
+
+impl Drop for WindowTitle {
+    fn drop(&mut self) {
+        restore_title();
+    }
+}
+
+/// Formats the title of the terminal window, eg "arkhash: 63% (412/650 dirs)"
+///
+/// # Arguments
+/// * `finished` Number of finished directories
+/// * `total` Number of directories
+pub fn format_title(finished: usize, total: usize) -> String {
+    let percent = (finished * 100).checked_div(total).unwrap_or(100);
+    format!("arkhash: {}% ({}/{} dirs)", percent, finished, total)
+}
+
+/// Clears the title of the terminal window and restores the one before, if the title showed the progress
+pub fn restore_title() {
+    if TITLE_SHOWN.swap(false, Ordering::SeqCst) {
+        write_terminal("\x1b]0;\x07\x1b[23;0t");
+    }
+}
+
+/// Writes an escape sequence to stdout at once, so it is not torn apart by lines of other threads
+///
+/// # Arguments
+/// * `sequence` The escape sequence
+fn write_terminal(sequence: &str) {
+    let mut stdout = io::stdout().lock();
+    let _ = stdout
+        .write_all(sequence.as_bytes())
+        .and_then(|()| stdout.flush());
+}
+
+/// Measures the throughput of a single directory as a moving average,
+/// so the displayed rate does not jitter between small and large files.
+#[derive(Debug, Clone)]
+pub struct Throughput {
+    /// The moment the directory started
+    start: Instant,
+    /// The moment of the last update
+    last_update: Instant,
+    /// Processed bytes at the last update
+    last_bytes: u64,
+    /// The smoothed throughput in bytes per second
+    bytes_per_sec: f64,
+}
+
+impl Throughput {
+    /// Starts measuring the throughput now
+    pub fn new() -> Throughput {
+        let now = Instant::now();
+        Throughput {
+            start: now,
+            last_update: now,
+            last_bytes: 0,
+            bytes_per_sec: 0.0,
+        }
+    }
+
+    /// Adds a sample and returns the smoothed throughput in bytes per second.
+    /// Newer samples weigh more, the weight of a sample decays exponentially with its age.
+    ///
+    /// # Arguments
+    /// * `processed_bytes` Number of bytes processed since the start
+    pub fn update(&mut self, processed_bytes: u64) -> f64 {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_update).as_secs_f64();
+        if elapsed <= 0.0 {
+            return self.bytes_per_sec;
+        }
+
+        let current = processed_bytes.saturating_sub(self.last_bytes) as f64 / elapsed;
+        if self.last_update == self.start {
+            self.bytes_per_sec = current;
+        } else {
+            let weight = 1.0 - (-elapsed / SMOOTHING_SECS).exp();
+            self.bytes_per_sec += weight * (current - self.bytes_per_sec);
+        }
+
+        self.last_update = now;
+        self.last_bytes = processed_bytes;
+        self.bytes_per_sec
+    }
+
+    /// Returns the estimated time until all bytes are processed, None as long as nothing was processed
+    ///
+    /// # Arguments
+    /// * `processed_bytes` Number of bytes processed since the start
+    /// * `all_bytes` Number of bytes to process in total
+    pub fn eta(&self, processed_bytes: u64, all_bytes: u64) -> Option<Duration> {
+        if self.bytes_per_sec <= 0.0 {
+            return None;
+        }
+
+        let remaining = all_bytes.saturating_sub(processed_bytes) as f64;
+        Some(Duration::from_secs_f64(remaining / self.bytes_per_sec))
+    }
+}
+
+impl Default for Throughput {
+    fn default() -> Throughput {
+        Throughput::new()
+    }
+}
+
+/// Formats throughput and estimated time remaining, eg "12.50 MiB/s ETA 3 minutes"
+///
+/// # Arguments
+/// * `bytes_per_sec` The throughput in bytes per second
+/// * `eta` The estimated time remaining, if known
+/// * `byte_format` How the throughput is printed
+pub fn format_throughput(
+    bytes_per_sec: f64,
+    eta: Option<Duration>,
+    byte_format: ByteFormat,
+) -> String {
+    let throughput = byte_format.format(bytes_per_sec as u64);
+    match eta {
+        Some(eta) => format!("{}/s ETA {}", throughput, HumanDuration(eta)),
+        None => format!("{}/s ETA unknown", throughput),
+    }
+}
+
+/// Indicates that the escape sequences drawing the progress bars and the title can be written to stdout:
+/// it is a terminal, TERM names one with cursor control, and its size is known and not empty.
+/// On Windows, TERM is usually not set, only a TERM naming a dumb terminal rules the bars out there.
+/// If anything is unclear, the plain lines are printed instead of breaking the display.
+///
+/// # Arguments
+/// * `is_terminal` Whether or not stdout is a terminal
+/// * `term` The value of the TERM environment variable, None if it is not set
+/// * `size` Rows and columns of the terminal, None if they could not be queried
+pub fn supports_bars(is_terminal: bool, term: Option<&str>, size: Option<(u16, u16)>) -> bool {
+    let dumb = match term {
+        Some(term) => term.is_empty() || term == "dumb",
+        None => !cfg!(windows),
+    };
+    let sized = matches!(size, Some((rows, columns)) if rows > 0 && columns > 0);
+    is_terminal && !dumb && sized
+}
+
+/// Indicates that stdout can draw the progress bars, see supports_bars
+fn stdout_supports_bars() -> bool {
+    let term = env::var("TERM").ok();
+    supports_bars(
+        io::stdout().is_terminal(),
+        term.as_deref(),
+        Term::stdout().size_checked(),
+    )
+}
+
+/// Formats the processed and total bytes of a progress bar, eg "1.5 MiB/4.0 MiB"
+///
+/// # Arguments
+/// * `processed_bytes` Bytes processed so far
+/// * `all_bytes` Bytes to process, None if unknown
+/// * `byte_format` How the bytes are printed
+fn format_amount(processed_bytes: u64, all_bytes: Option<u64>, byte_format: ByteFormat) -> String {
+    format!(
+        "{}/{}",
+        byte_format.format(processed_bytes),
+        byte_format.format(all_bytes.unwrap_or(0))
+    )
+}
+
+/// Indicates that a bar of at least MIN_BAR_WIDTH characters fits on a line next to the text of BAR_TEMPLATE.
+/// If the width of the terminal is unknown, indicatif falls back to a fixed width and the bar is always drawn.
+///
+/// # Arguments
+/// * `terminal_width` Number of columns of the terminal, None if unknown
+/// * `text_width` Number of characters of prefix, amount and message
+pub fn bar_fits(terminal_width: Option<usize>, text_width: usize) -> bool {
+    match terminal_width {
+        Some(width) => text_width + BAR_DECORATION_WIDTH + MIN_BAR_WIDTH <= width,
+        None => true,
+    }
+}
+
+/// Prefixes a message with the directory, padding it with spaces in order to align it with
+/// messages of other directories, using longest_folder as an indicator of needed padding.
+///
+/// # Arguments
+/// * `message` String to print
+/// * `workdir` The directory the message is about
+/// * `longest_folder` length of the name of the longest folder in the current workset
+pub fn format_aligned(message: &str, workdir: &str, longest_folder: usize) -> String {
+    let padding = longest_folder.saturating_sub(workdir.len());
+    format!("{}: {} {}", workdir, " ".repeat(padding), message)
+}
@@ -0,0 +1,156 @@
+//! This module implements the prune mode
+
+extern crate log;
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use self::log::{error, info};
+
+use super::blocks::BlockManifest;
+use super::manifest::Manifest;
+use super::util::Options;
+
+/// The result of pruning the _algorithm_sum.txt files of a single directory
+#[derive(Debug, Clone, Default)]
+pub struct DirectoryPrune {
+    /// Path to the pruned directory
+    pub path: PathBuf,
+    /// Paths to the files whose lines were removed, or would have been removed in a dry run
+    pub pruned: Vec<PathBuf>,
+    /// Descriptions of _algorithm_sum.txt files that could not be read or written
+    pub failed: Vec<String>,
+}
+
+/// The result of pruning some directories
+#[derive(Debug, Clone, Default)]
+pub struct PruneReport {
+    /// The directories in which lines were removed or whose _algorithm_sum.txt could not be pruned
+    pub directories: Vec<DirectoryPrune>,
+}
+
+impl PruneReport {
+    /// Returns the number of lines that were removed, or would have been removed in a dry run
+    pub fn pruned_entries(&self) -> usize {
+        self.directories
+            .iter()
+            .map(|directory| directory.pruned.len())
+            .sum()
+    }
+}
+
+/// Removes the lines of files that no longer exist from the _algorithm_sum.txt files of some directories.
+/// Only the _algorithm_sum.txt of each directory itself is changed, it is rewritten as a whole.
+/// With opts.detect_algorithm, every _algorithm_sum.txt of a directory is pruned.
+/// Info messages and errors are emitted through the log crate according to the loglevel, nothing is emitted in
+/// loglevel quiet. In a dry run, the lines are only listed in the report and nothing is written.
+///
+/// # Arguments
+///
+/// * `opts` An Options object containing information about the program behavior
+///
+/// # Returns
+/// A PruneReport describing the outcome for every directory.
+pub fn prune(opts: &Options) -> PruneReport {
+    let directories: Vec<PathBuf> = if opts.subdir_mode {
+        opts.folders
+            .iter()
+            .flat_map(|folder| {
+                super::util::find_subdirectories(
+                    folder,
+                    opts.max_depth,
+                    opts.hidden,
+                    Vec::new(),
+                    &opts.exclude,
+                )
+            })
+            .collect()
+    } else {
+        opts.folders.clone()
+    };
+
+    PruneReport {
+        directories: directories
+            .iter()
+            .map(|directory| prune_directory(directory, opts))
+            .filter(|directory| !directory.pruned.is_empty() || !directory.failed.is_empty())
+            .collect(),
+    }
+}
+
+/// Removes the lines of files that no longer exist from the _algorithm_sum.txt files of a directory
+///
+/// # Arguments
+///
+/// * `path` The path to the directory that is going to be pruned
+/// * `opts` An Options object containing the algorithm and whether or not this is a dry run
+pub(crate) fn prune_directory(path: &Path, opts: &Options) -> DirectoryPrune {
+    let mut report = DirectoryPrune {
+        path: path.to_path_buf(),
+        ..DirectoryPrune::default()
+    };
+
+    let algorithms = if opts.detect_algorithm {
+        Manifest::find_algorithms(path)
+    } else if Manifest::find(path, opts.algorithm).is_some() {
+        vec![opts.algorithm]
+    } else {
+        Vec::new()
+    };
+
+    for algorithm in algorithms {
+        let opts = Options {
+            algorithm,
+            ..opts.clone()
+        };
+
+        let result = Manifest::read(path, &opts).and_then(|mut manifest| {
+            // a file that can not be examined is kept, only files that are certainly gone are removed
+            let removed = manifest.retain(|entry| {
+                !matches!(
+                    fs::symlink_metadata(path.join(&entry.path)),
+                    Err(ref e) if e.kind() == io::ErrorKind::NotFound
+                )
+            });
+
+            if !removed.is_empty() && !opts.dry_run {
+                manifest.write(path, &opts)?;
+
+                let mut block_manifest = BlockManifest::read(path, algorithm)?;
+                if !block_manifest.is_empty() {
+                    block_manifest.retain(|file_path| manifest.get(file_path).is_some());
+                    block_manifest.write(path, algorithm)?;
+                }
+            }
+            Ok(removed)
+        });
+
+        match result {
+            Ok(removed) => {
+                if opts.loglevel_info() {
+                    let action = if opts.dry_run {
+                        "would remove"
+                    } else {
+                        "removed"
+                    };
+                    for entry in &removed {
+                        info!("{}: {} {}", path.display(), action, entry.path.display());
+                    }
+                }
+                report
+                    .pruned
+                    .extend(removed.into_iter().map(|entry| entry.path));
+            }
+            Err(e) => {
+                let message = format!("could not prune {}sum.txt: {}", algorithm, e);
+                if !opts.loglevel_quiet() {
+                    error!("{}: {}", path.display(), message);
+                }
+                report.failed.push(message);
+            }
+        }
+    }
+
+    report
+}
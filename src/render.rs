@@ -0,0 +1,404 @@
+//! This module prints the reports the modes of the library return, according to the loglevel and the format
+//! of the Options. The library itself only emits messages through the log crate, printing the outcome of a run
+//! is left to the arkhash binary.
+
+use arkhash::check::format_lines;
+use arkhash::util::{self, Options, OutputFormat};
+use arkhash::{
+    CheckReport, CompareReport, DuplicateGroup, ManifestCheck, ManifestDiff, PruneReport,
+    ResetReport, StatusReport, UpdateReport, VerifyReport,
+};
+use log::info;
+
+/// Prints the outcome of update mode.
+/// With opts.quiet, the summary is only printed if anything failed.
+/// In JSON format, only the duplicates are printed as a single JSON object, with emit_jsonl nothing is printed,
+/// so stdout only contains the JSON lines of the hashed files.
+///
+/// # Arguments
+///
+/// * `report` The UpdateReport to print
+/// * `opts` An Options object containing the loglevel and format
+pub fn update(report: &UpdateReport, opts: &Options) {
+    if opts.find_duplicates && !opts.dry_run {
+        duplicates(report, opts);
+    }
+
+    if opts.dry_run {
+        if !opts.loglevel_quiet() {
+            println!(
+                "Would hash {} files ({}) in {} directories",
+                report.hashed_files(),
+                opts.byte_format.format(report.hashed_bytes()),
+                report.directories.len()
+            );
+        }
+    } else if opts.format == OutputFormat::Json || opts.emit_jsonl {
+        // stdout only contains the duplicates or the JSON lines of the hashed files
+    } else if opts.loglevel_info() {
+        info!("{}", report.summary(opts.byte_format));
+    } else if opts.loglevel_progress() || (opts.quiet && !report.is_ok()) {
+        println!("{}", report.summary(opts.byte_format));
+    }
+}
+
+/// Prints the groups of duplicates and the bytes they waste, unless in loglevel quiet.
+/// In JSON format, a single JSON object is printed.
+///
+/// # Arguments
+///
+/// * `report` The UpdateReport containing the groups of duplicates
+/// * `opts` An Options object containing the loglevel and format
+fn duplicates(report: &UpdateReport, opts: &Options) {
+    if opts.format == OutputFormat::Json {
+        let groups: Vec<serde_json::Value> = report
+            .duplicates
+            .iter()
+            .map(DuplicateGroup::to_json)
+            .collect();
+        println!(
+            "{}",
+            serde_json::json!({
+                "duplicates": groups,
+                "wasted_bytes": report.wasted_bytes(),
+            })
+        );
+        return;
+    }
+    if opts.loglevel_quiet() {
+        return;
+    }
+
+    for group in &report.duplicates {
+        println!(
+            "{} files of {} with hash {}, {} wasted:",
+            group.paths.len(),
+            opts.byte_format.format(group.size),
+            group.hash,
+            opts.byte_format.format(group.wasted_bytes())
+        );
+        for path in &group.paths {
+            println!("    {}", path.display());
+        }
+    }
+    println!(
+        "Found {} groups of duplicates, {} wasted",
+        report.duplicates.len(),
+        opts.byte_format.format(report.wasted_bytes())
+    );
+}
+
+/// Prints the outcome of verify mode, a summary of all directories.
+/// With opts.quiet, the summary is only printed if anything failed.
+/// In JSON format a JSON object is printed for every directory, followed by a summary object.
+/// In a dry run, the directories that would be verified are printed together with their size instead.
+///
+/// # Arguments
+///
+/// * `report` The VerifyReport to print
+/// * `opts` An Options object containing the loglevel and format
+pub fn verify(report: &VerifyReport, opts: &Options) {
+    if opts.dry_run && opts.repair_from.is_none() {
+        verify_plan(report, opts);
+        return;
+    }
+
+    if opts.format == OutputFormat::Json {
+        for directory in &report.directories {
+            println!("{}", directory.to_json());
+        }
+        println!("{}", report.summary_json());
+    } else if opts.loglevel_info() {
+        info!("{}", report.summary(opts.byte_format));
+    } else if opts.loglevel_progress() || (opts.quiet && report.exit_code() != 0) {
+        println!("{}", report.summary(opts.byte_format));
+    }
+}
+
+/// Prints the directories a dry run of verify mode would verify, together with the number of bytes of the files
+/// listed in them, and the total of all directories.
+/// In JSON format a JSON object is printed for every directory, followed by a summary object.
+///
+/// # Arguments
+///
+/// * `report` The VerifyReport of the dry run
+/// * `opts` An Options object containing the loglevel and format
+fn verify_plan(report: &VerifyReport, opts: &Options) {
+    for directory in &report.directories {
+        if opts.format == OutputFormat::Json {
+            println!(
+                "{}",
+                serde_json::json!({
+                    "path": directory.path.to_string_lossy(),
+                    "bytes": directory.bytes,
+                })
+            );
+        } else if opts.loglevel_info() {
+            info!(
+                "Would verify Directory {} ({})",
+                directory.path.display(),
+                opts.byte_format.format(directory.bytes)
+            );
+        }
+    }
+
+    if opts.format == OutputFormat::Json {
+        println!(
+            "{}",
+            serde_json::json!({
+                "summary": true,
+                "dry_run": true,
+                "directories": report.directories.len(),
+                "bytes": report.bytes(),
+            })
+        );
+    } else if !opts.loglevel_quiet() {
+        println!(
+            "Would verify {} directories ({})",
+            report.directories.len(),
+            opts.byte_format.format(report.bytes())
+        );
+    }
+}
+
+/// Prints the problems found in every _algorithm_sum.txt by check manifest mode and a summary,
+/// nothing is printed in loglevel quiet
+///
+/// # Arguments
+///
+/// * `report` The CheckReport to print
+/// * `opts` An Options object containing the loglevel and whether or not the duplicates were to be removed
+pub fn check(report: &CheckReport, opts: &Options) {
+    if opts.loglevel_quiet() {
+        return;
+    }
+
+    for check in &report.manifests {
+        manifest_problems(check);
+
+        if !opts.dedupe || check.duplicates.is_empty() {
+            continue;
+        }
+        if !check.malformed_lines.is_empty() {
+            println!(
+                "{}: duplicate lines not removed, the file has improperly formatted lines",
+                check.path.display()
+            );
+        } else if opts.dry_run {
+            println!(
+                "{}: would remove {} duplicate lines",
+                check.path.display(),
+                check.duplicate_lines()
+            );
+        } else if check.deduplicated {
+            println!(
+                "{}: removed {} duplicate lines",
+                check.path.display(),
+                check.duplicate_lines()
+            );
+        }
+    }
+
+    println!(
+        "Checked {} hashsum files: {} paths listed more than once, {} of them with different hashes, {} improperly formatted lines",
+        report.manifests.len(),
+        report.duplicate_paths(),
+        report.conflicting_paths(),
+        report.malformed_lines()
+    );
+    if !opts.dedupe && report.duplicate_paths() > 0 {
+        println!("Run with --dedupe to keep only the last line of every path");
+    }
+}
+
+/// Prints the malformed lines and duplicate paths of an _algorithm_sum.txt
+///
+/// # Arguments
+///
+/// * `check` The problems found in the _algorithm_sum.txt
+fn manifest_problems(check: &ManifestCheck) {
+    for line in &check.malformed_lines {
+        println!(
+            "{}: line {} is improperly formatted",
+            check.path.display(),
+            line
+        );
+    }
+
+    for duplicate in &check.duplicates {
+        if duplicate.conflicting {
+            println!(
+                "{}: {} is listed with different hashes on lines {}, the last one is used",
+                check.path.display(),
+                duplicate.path.display(),
+                format_lines(&duplicate.lines)
+            );
+        } else {
+            println!(
+                "{}: {} is listed on lines {}",
+                check.path.display(),
+                duplicate.path.display(),
+                format_lines(&duplicate.lines)
+            );
+        }
+    }
+}
+
+/// Prints the outcome of compare mode. Nothing is printed in loglevel quiet, identical files are only printed
+/// in loglevel debug and in JSON format a single JSON object is printed.
+///
+/// # Arguments
+///
+/// * `report` The CompareReport to print
+/// * `opts` An Options object containing the two directories, the loglevel and format
+pub fn compare(report: &CompareReport, opts: &Options) {
+    if opts.format == OutputFormat::Json {
+        println!("{}", report.to_json());
+        return;
+    }
+    if opts.loglevel_quiet() {
+        return;
+    }
+
+    let (first, second) = (opts.folders[0].display(), opts.folders[1].display());
+    if opts.loglevel_debug() {
+        for path in &report.identical {
+            println!("identical: {}", path.display());
+        }
+    }
+    for path in &report.differing {
+        println!("differs: {}", path.display());
+    }
+    for path in &report.only_in_first {
+        println!("only in {}: {}", first, path.display());
+    }
+    for path in &report.only_in_second {
+        println!("only in {}: {}", second, path.display());
+    }
+    println!(
+        "{} identical, {} differ, {} only in {}, {} only in {}",
+        report.identical.len(),
+        report.differing.len(),
+        report.only_in_first.len(),
+        first,
+        report.only_in_second.len(),
+        second
+    );
+}
+
+/// Prints the differences between two _algorithm_sum.txt files found by diff mode.
+/// Nothing is printed in loglevel quiet, in JSON format a single JSON object is printed.
+///
+/// # Arguments
+///
+/// * `diff` The ManifestDiff to print
+/// * `opts` An Options object containing the loglevel and format
+pub fn diff(diff: &ManifestDiff, opts: &Options) {
+    if opts.format == OutputFormat::Json {
+        println!("{}", diff.to_json());
+        return;
+    }
+    if opts.loglevel_quiet() {
+        return;
+    }
+
+    for (label, paths) in &[
+        ("changed", &diff.changed),
+        ("added", &diff.added),
+        ("removed", &diff.removed),
+    ] {
+        for path in paths.iter() {
+            println!("{:<8} {}", label, path.display());
+        }
+    }
+    println!("{}", diff.summary());
+}
+
+/// Prints the directories of a StatusReport and the bad files of every directory that needs attention.
+/// Nothing is printed in loglevel quiet, in JSON format a single JSON object is printed.
+///
+/// # Arguments
+///
+/// * `report` The StatusReport to print
+/// * `opts` An Options object containing the loglevel and format
+pub fn status(report: &StatusReport, opts: &Options) {
+    if opts.format == OutputFormat::Json {
+        println!("{}", report.to_json());
+        return;
+    }
+    if opts.loglevel_quiet() {
+        return;
+    }
+
+    for dir in &report.known_good {
+        println!("known good   {}", dir.display());
+    }
+    for dir in &report.to_check {
+        println!("to check     {}", dir.display());
+    }
+
+    for bad_files in &report.bad_files {
+        match bad_files.directory {
+            Some(ref directory) => println!(
+                "{}: {} bad files ({})",
+                directory.display(),
+                bad_files.files.len(),
+                bad_files.list.display()
+            ),
+            None => println!(
+                "{}: {} bad files",
+                bad_files.list.display(),
+                bad_files.files.len()
+            ),
+        }
+        for file in &bad_files.files {
+            println!("    {}", file.display());
+        }
+    }
+
+    println!("{}", report.summary());
+}
+
+/// Prints the number of lines prune mode removed, nothing is printed in loglevel quiet
+///
+/// # Arguments
+///
+/// * `report` The PruneReport to print
+/// * `opts` An Options object containing the loglevel and whether or not this is a dry run
+pub fn prune(report: &PruneReport, opts: &Options) {
+    if opts.loglevel_quiet() {
+        return;
+    }
+
+    let action = if opts.dry_run {
+        "Would remove"
+    } else {
+        "Removed"
+    };
+    println!(
+        "{} {} lines of files that no longer exist",
+        action,
+        report.pruned_entries()
+    );
+}
+
+/// Prints every file reset mode removed, nothing is printed in loglevel quiet
+///
+/// # Arguments
+///
+/// * `report` The ResetReport to print
+/// * `opts` An Options object containing the loglevel and whether or not this is a dry run
+pub fn reset(report: &ResetReport, opts: &Options) {
+    if opts.loglevel_quiet() {
+        return;
+    }
+
+    let action = if opts.dry_run {
+        "Would remove"
+    } else {
+        "Removed"
+    };
+    for path in &report.removed {
+        println!("[{}] {} {}", util::timestamp(opts), action, path.display());
+    }
+}
@@ -0,0 +1,257 @@
+//! This module implements restoring the files that failed verification from a second copy of the folders.
+//!
+//! With the repair_from option, verify mode looks up every file of a directory that changed or is missing in the
+//! mirror, at the same path relative to the verified folder. The copy in the mirror is only used if its hash is the
+//! one listed in the _algorithm_sum.txt. It is copied to a temporary file next to the file, which is hashed again
+//! and only then renamed over the file, so good data is never replaced by bad data.
+//! The recorded modification time is restored, so the next update does not hash the file again.
+
+extern crate log;
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::time::{Duration, UNIX_EPOCH};
+
+use self::log::{info, warn};
+
+use super::manifest::{FileStamp, Manifest};
+use super::progress::ProgressEvent;
+use super::util::Options;
+use super::verify::DirectoryReport;
+
+/// Restores the files of a directory that changed or are missing from their copies in opts.repair_from.
+/// Restored files are moved from the failed and missing files of the report to its repaired files.
+/// Files that can not be restored are printed and stay failed.
+/// In a dry run, the copies in the mirror are checked, but nothing is copied. The files that would be restored
+/// are listed as repaired and stay failed.
+///
+/// # Arguments
+///
+/// * `workdir` Path to the verified directory
+/// * `opts` An Options object containing the mirror, the algorithm and whether or not this is a dry run
+/// * `report` The DirectoryReport of the directory
+/// * `progress` Sender to the printer thread, only present if progress is displayed, streamed or reported
+pub(crate) fn repair_directory(
+    workdir: &Path,
+    opts: &Options,
+    report: &mut DirectoryReport,
+    progress: Option<&Sender<ProgressEvent>>,
+) {
+    let mirror = match opts.repair_from {
+        Some(ref mirror) => mirror,
+        None => return,
+    };
+    if report.failed.is_empty() && report.missing.is_empty() {
+        return;
+    }
+
+    let manifest = match Manifest::read(workdir, opts) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            let message = format!("could not read {}sum.txt: {}", opts.algorithm, e);
+            print_message(workdir, &message, opts, progress);
+            return;
+        }
+    };
+    let mirror_directory = match mirror_directory(workdir, mirror, opts) {
+        Some(mirror_directory) => mirror_directory,
+        None => {
+            let message = format!(
+                "not restored from {}, the directory is not inside one of the verified folders",
+                mirror.display()
+            );
+            print_message(workdir, &message, opts, progress);
+            return;
+        }
+    };
+
+    let mut repaired = Vec::new();
+    for file in report.failed.iter().chain(report.missing.iter()) {
+        let entry = match manifest.get(file) {
+            Some(entry) => entry,
+            None => continue,
+        };
+        let source = mirror_directory.join(&entry.path);
+        match restore_file(
+            &workdir.join(&entry.path),
+            &source,
+            &entry.hash,
+            entry.stamp.as_ref(),
+            opts,
+        ) {
+            Ok(()) => {
+                let message = if opts.dry_run {
+                    format!("would restore {} from {}", file.display(), source.display())
+                } else {
+                    format!("restored {} from {}", file.display(), source.display())
+                };
+                print_message(workdir, &message, opts, progress);
+                repaired.push(file.clone());
+            }
+            Err(e) => {
+                let message = format!(
+                    "could not restore {} from {}: {}",
+                    file.display(),
+                    source.display(),
+                    e
+                );
+                print_message(workdir, &message, opts, progress);
+            }
+        }
+    }
+
+    if !opts.dry_run {
+        report.failed.retain(|file| !repaired.contains(file));
+        report.missing.retain(|file| !repaired.contains(file));
+    }
+    report.repaired = repaired;
+}
+
+/// Returns the copy of a verified directory in the mirror: the mirror joined with the path of the directory
+/// relative to the folder of opts.folders it is in. None if it is in none of them.
+///
+/// # Arguments
+///
+/// * `workdir` Path to the verified directory
+/// * `mirror` Path to the mirror
+/// * `opts` An Options object containing the verified folders
+fn mirror_directory(workdir: &Path, mirror: &Path, opts: &Options) -> Option<PathBuf> {
+    // the innermost folder wins if the folders are nested
+    opts.folders
+        .iter()
+        .filter_map(|folder| workdir.strip_prefix(folder).ok())
+        .min_by_key(|relative| relative.components().count())
+        .map(|relative| mirror.join(relative))
+}
+
+/// Restores a file from its copy in the mirror, if the copy has the recorded hash.
+/// The copy is written to a temporary file next to the file, which is synced to disk, hashed again and only then
+/// renamed over the file. Its modification time is set to the recorded one. In a dry run, only the copy in the mirror
+/// is hashed.
+///
+/// # Arguments
+///
+/// * `target` Path to the file that is restored
+/// * `source` Path to the copy of the file in the mirror
+/// * `hash` The hash recorded for the file
+/// * `stamp` The size and modification time recorded for the file
+/// * `opts` An Options object containing the algorithm and whether or not this is a dry run
+fn restore_file(
+    target: &Path,
+    source: &Path,
+    hash: &str,
+    stamp: Option<&FileStamp>,
+    opts: &Options,
+) -> io::Result<()> {
+    if !fs::symlink_metadata(source)?.is_file() {
+        return Err(invalid_data("the copy in the mirror is not a regular file"));
+    }
+    if !hash_of(source, opts)?.eq_ignore_ascii_case(hash) {
+        return Err(invalid_data(
+            "the copy in the mirror does not have the listed hash either",
+        ));
+    }
+    if opts.dry_run {
+        return Ok(());
+    }
+
+    let name = target.file_name().unwrap_or_default().to_string_lossy();
+    let temp_path = target.with_file_name(format!(".{}.arkhash-repair.tmp", name));
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let result = copy_verified(source, &temp_path, hash, stamp, opts)
+        .and_then(|()| fs::rename(&temp_path, target));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Copies the copy in the mirror to the temporary file and checks that the temporary file has the recorded hash
+///
+/// # Arguments
+///
+/// * `source` Path to the copy of the file in the mirror
+/// * `temp_path` Path to the temporary file next to the restored file
+/// * `hash` The hash recorded for the file
+/// * `stamp` The size and modification time recorded for the file
+/// * `opts` An Options object containing the algorithm
+fn copy_verified(
+    source: &Path,
+    temp_path: &Path,
+    hash: &str,
+    stamp: Option<&FileStamp>,
+    opts: &Options,
+) -> io::Result<()> {
+    fs::copy(source, temp_path)?;
+    let file = fs::OpenOptions::new().write(true).open(temp_path)?;
+    if let Some(stamp) = stamp {
+        let mtime = UNIX_EPOCH
+            + Duration::from_secs(stamp.mtime_secs)
+            + Duration::from_nanos(stamp.mtime_nanos.into());
+        file.set_modified(mtime)?;
+    }
+    file.sync_all()?;
+
+    if hash_of(temp_path, opts)?.eq_ignore_ascii_case(hash) {
+        Ok(())
+    } else {
+        Err(invalid_data(
+            "the copied file does not have the listed hash, the file was left untouched",
+        ))
+    }
+}
+
+/// Hashes a file with opts.algorithm
+///
+/// # Arguments
+///
+/// * `path` Path to the file
+/// * `opts` An Options object containing the algorithm
+fn hash_of(path: &Path, opts: &Options) -> io::Result<String> {
+    let hashline = super::util::calculate_hash(path.to_path_buf(), Path::new(""), opts)
+        .map_err(|e| io::Error::new(e.io_error().kind(), e.io_error().to_string()))?;
+    let hashline_re = super::util::regex_from_opts(opts).map_err(invalid_data)?;
+    super::util::parse_hashline(&hashline_re, &hashline)
+        .map(|(hash, _)| hash)
+        .ok_or_else(|| invalid_data("invalid hashline"))
+}
+
+/// Returns an InvalidData error with the given message
+///
+/// # Arguments
+///
+/// * `message` Description of the error
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(message: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Emits a message about the repair of a directory through the log crate, nothing is emitted in loglevel quiet.
+/// With progress bars, it is passed to the printer thread instead, which prints it above them.
+///
+/// # Arguments
+///
+/// * `workdir` Path to the verified directory
+/// * `message` The message
+/// * `opts` An Options object containing the loglevel
+/// * `progress` Sender to the printer thread, only present if progress is displayed, streamed or reported
+fn print_message(
+    workdir: &Path,
+    message: &str,
+    opts: &Options,
+    progress: Option<&Sender<ProgressEvent>>,
+) {
+    match progress {
+        Some(progress) if opts.loglevel_progress() => {
+            let now = super::util::timestamp(opts);
+            let message = format!("[{}] {}: {}", now, workdir.display(), message);
+            progress.send(ProgressEvent::Log(message)).unwrap()
+        }
+        _ if opts.loglevel_info() => info!("{}: {}", workdir.display(), message),
+        _ if !opts.loglevel_quiet() => warn!("{}: {}", workdir.display(), message),
+        _ => {}
+    }
+}
@@ -0,0 +1,93 @@
+//! This module implements the reset mode
+
+extern crate chrono;
+extern crate log;
+extern crate regex;
+
+use std::fs;
+use std::path::PathBuf;
+
+use self::log::error;
+use self::regex::Regex;
+
+/// The result of removing the state files of verify mode
+#[derive(Debug, Clone, Default)]
+pub struct ResetReport {
+    /// The files that were removed, or would have been removed in a dry run
+    pub removed: Vec<PathBuf>,
+    /// Descriptions of files that could not be removed
+    pub failed: Vec<String>,
+}
+
+/// Removes the state files verify mode writes to opts.state_dir:
+/// the known_good and to_check lists of the current month and the lists of bad files of every directory.
+/// The lists of previous months and the _algorithm_sum.txt files are never touched.
+/// Files that can not be removed are emitted as errors through the log crate unless in loglevel quiet.
+///
+/// # Arguments
+///
+/// * `opts` An Options object containing the loglevel and whether or not this is a dry run
+///
+/// # Returns
+/// A ResetReport listing the removed files.
+pub fn reset(opts: &super::util::Options) -> ResetReport {
+    let mut report = ResetReport::default();
+
+    for path in find_state_files(opts) {
+        let result = if opts.dry_run {
+            Ok(())
+        } else {
+            fs::remove_file(&path)
+        };
+
+        match result {
+            Ok(()) => report.removed.push(path),
+            Err(e) => {
+                let message = format!("could not remove {}: {}", path.display(), e);
+                if !opts.loglevel_quiet() {
+                    error!("{}", message);
+                }
+                report.failed.push(message);
+            }
+        }
+    }
+
+    report
+}
+
+/// Returns the state files of verify mode in opts.state_dir, sorted by name:
+/// the known_good and to_check lists of the current month and the lists of bad files of every directory
+///
+/// # Arguments
+/// * `opts` Options object containing the state directory and the template for the names of the lists
+pub(crate) fn find_state_files(opts: &super::util::Options) -> Vec<PathBuf> {
+    let (known_good_path, to_check_path) = super::verify::state_paths(opts, &chrono::Local::now());
+    // the lists of directories of previous months are kept
+    let monthly_list_re = Regex::new(&format!(
+        "^{}$",
+        regex::escape(&opts.state_template)
+            .replace(r"\{list\}", "(known_good|to_check)")
+            .replace(r"\{month\}", r"\d{1,2}")
+            .replace(r"\{year\}", r"\d{4}")
+    ))
+    .unwrap();
+    let bad_files_re = Regex::new(r"^to_check_.+\.txt$").unwrap();
+
+    let mut state_files = Vec::new();
+    if let Ok(dir_entries) = fs::read_dir(&opts.state_dir) {
+        for entry in dir_entries.filter_map(Result::ok) {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            let is_current_list = entry.path() == known_good_path || entry.path() == to_check_path;
+            let is_bad_files_list =
+                bad_files_re.is_match(&name) && !monthly_list_re.is_match(&name);
+
+            if (is_current_list || is_bad_files_list) && entry.path().is_file() {
+                state_files.push(entry.path());
+            }
+        }
+    }
+
+    state_files.sort();
+    state_files
+}
@@ -0,0 +1,198 @@
+//! This module implements signing the _algorithm_sum.txt files with ed25519.
+//!
+//! The signature of an _algorithm_sum.txt is detached, it is stored as hexadecimal characters in the sidecar
+//! _algorithm_sum.txt.sig. It covers the bytes of the file as they are stored, a compressed _algorithm_sum.txt.gz
+//! is signed without decompressing it. Keys are stored as the hexadecimal characters of their 32 bytes:
+//! the secret key in the key file and the public key in the key file with the extension .pub appended.
+
+extern crate ed25519_dalek;
+extern crate hex;
+extern crate rand;
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use self::ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use self::rand::rngs::OsRng;
+
+/// Returns the path to the detached signature of an _algorithm_sum.txt or _algorithm_sum.txt.gz, eg sha1sum.txt.sig
+///
+/// # Arguments
+///
+/// * `path` Path to the _algorithm_sum.txt or _algorithm_sum.txt.gz
+pub fn signature_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".sig");
+    path.with_file_name(name)
+}
+
+/// Returns the path to the public key belonging to a secret key file, the key file with .pub appended
+///
+/// # Arguments
+///
+/// * `key_file` Path to the secret key
+pub fn public_key_path(key_file: &Path) -> PathBuf {
+    let mut name = key_file.as_os_str().to_os_string();
+    name.push(".pub");
+    PathBuf::from(name)
+}
+
+/// Generates a new key pair and writes the secret key to key_file and the public key to key_file.pub.
+/// Existing files are never overwritten. On unix, the secret key is only readable by its owner.
+///
+/// # Arguments
+///
+/// * `key_file` Path the secret key is written to
+pub fn generate(key_file: &Path) -> io::Result<()> {
+    let signing_key = SigningKey::generate(&mut OsRng);
+    let public_key_file = public_key_path(key_file);
+    if public_key_file.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", public_key_file.display()),
+        ));
+    }
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut secret = options.open(key_file)?;
+    writeln!(secret, "{}", hex::encode(signing_key.to_bytes()))?;
+    secret.sync_all()?;
+
+    let mut public = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&public_key_file)?;
+    writeln!(
+        public,
+        "{}",
+        hex::encode(signing_key.verifying_key().to_bytes())
+    )?;
+    public.sync_all()
+}
+
+/// Reads the 32 bytes of a key file
+///
+/// # Arguments
+///
+/// * `path` Path to the key file
+fn read_key_bytes(path: &Path) -> io::Result<[u8; 32]> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} is not a key of 64 hexadecimal characters",
+                path.display()
+            ),
+        )
+    };
+    // the kind of the error must not be NotFound, which would be mistaken for a missing _algorithm_sum.txt
+    let data = fs::read_to_string(path).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("could not read key {}: {}", path.display(), e),
+        )
+    })?;
+    let bytes = hex::decode(data.trim()).map_err(|_| invalid())?;
+    let mut key = [0; 32];
+    if bytes.len() != key.len() {
+        return Err(invalid());
+    }
+    key.copy_from_slice(&bytes);
+    Ok(key)
+}
+
+/// Reads the secret key of a key file
+///
+/// # Arguments
+///
+/// * `key_file` Path to the secret key
+pub fn read_signing_key(key_file: &Path) -> io::Result<SigningKey> {
+    read_key_bytes(key_file).map(|bytes| SigningKey::from_bytes(&bytes))
+}
+
+/// Reads the public key of a key file
+///
+/// # Arguments
+///
+/// * `key_file` Path to the public key
+pub fn read_verifying_key(key_file: &Path) -> io::Result<VerifyingKey> {
+    VerifyingKey::from_bytes(&read_key_bytes(key_file)?).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is not a valid public key", key_file.display()),
+        )
+    })
+}
+
+/// Signs an _algorithm_sum.txt and writes the signature to its sidecar, see signature_path
+///
+/// # Arguments
+///
+/// * `path` Path to the _algorithm_sum.txt
+/// * `key_file` Path to the secret key
+pub fn sign(path: &Path, key_file: &Path) -> io::Result<()> {
+    let signing_key = read_signing_key(key_file)?;
+    let signature = signing_key.sign(&fs::read(path)?);
+    let signature_path = signature_path(path);
+    let name = signature_path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let temp_path = signature_path.with_file_name(format!(".{}.tmp", name));
+
+    super::util::write_atomically(&signature_path, &temp_path, |file| {
+        writeln!(file, "{}", hex::encode(signature.to_bytes()))
+    })
+}
+
+/// Checks the signature of an _algorithm_sum.txt with a public key.
+/// Succeeds if the _algorithm_sum.txt does not exist, fails with an InvalidData error if its signature is missing
+/// or does not match.
+///
+/// # Arguments
+///
+/// * `path` Path to the _algorithm_sum.txt
+/// * `key_file` Path to the public key
+pub fn verify(path: &Path, key_file: &Path) -> io::Result<()> {
+    let verifying_key = read_verifying_key(key_file)?;
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    let signature_path = signature_path(path);
+    let invalid = |problem: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "signature {} {}",
+                signature_path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy(),
+                problem
+            ),
+        )
+    };
+    let signature = match fs::read_to_string(&signature_path) {
+        Ok(signature) => signature,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Err(invalid("is missing")),
+        Err(e) => return Err(e),
+    };
+    let signature = hex::decode(signature.trim())
+        .ok()
+        .and_then(|bytes| Signature::from_slice(&bytes).ok())
+        .ok_or_else(|| invalid("is malformed"))?;
+
+    verifying_key
+        .verify(&data, &signature)
+        .map_err(|_| invalid("does not match"))
+}
@@ -0,0 +1,143 @@
+//! This module implements the status mode, which summarizes the state files of verify mode
+
+extern crate chrono;
+extern crate serde_json;
+
+use std::path::PathBuf;
+
+/// The files of a directory that changed, are missing or could not be read, as recorded by verify mode
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BadFiles {
+    /// Path to the list of bad files in the state directory
+    pub list: PathBuf,
+    /// The directory the list belongs to, None if it is not in the to_check list of the current month
+    pub directory: Option<PathBuf>,
+    /// Paths to the bad files, in the order they were recorded
+    pub files: Vec<PathBuf>,
+}
+
+impl BadFiles {
+    /// Returns the list as a JSON object
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "list": self.list.to_string_lossy(),
+            "dir": self.directory.as_ref().map(|directory| directory.to_string_lossy()),
+            "files": self
+                .files
+                .iter()
+                .map(|file| file.to_string_lossy().to_string())
+                .collect::<Vec<_>>(),
+        })
+    }
+}
+
+/// The state of verify mode in the current month
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StatusReport {
+    /// Directories that were verified and correct this month, sorted
+    pub known_good: Vec<PathBuf>,
+    /// Directories that were verified and failed this month, sorted
+    pub to_check: Vec<PathBuf>,
+    /// The lists of bad files of every directory, sorted by the path of the list
+    pub bad_files: Vec<BadFiles>,
+}
+
+impl StatusReport {
+    /// Returns the number of directories that need attention: the ones in the to_check list
+    /// and the ones that only have a list of bad files, like directories verified outside of subdir mode
+    pub fn needs_attention(&self) -> usize {
+        self.to_check.len()
+            + self
+                .bad_files
+                .iter()
+                .filter(|bad_files| bad_files.directory.is_none())
+                .count()
+    }
+
+    /// Returns the number of bad files of all directories
+    pub fn bad_file_count(&self) -> usize {
+        self.bad_files
+            .iter()
+            .map(|bad_files| bad_files.files.len())
+            .sum()
+    }
+
+    /// Returns a human readable summary, eg "3 directories verified OK, 1 need attention, 2 bad files"
+    pub fn summary(&self) -> String {
+        format!(
+            "{} directories verified OK, {} need attention, {} bad files",
+            self.known_good.len(),
+            self.needs_attention(),
+            self.bad_file_count()
+        )
+    }
+
+    /// Returns the report as a JSON object
+    pub fn to_json(&self) -> serde_json::Value {
+        let paths = |dirs: &[PathBuf]| -> Vec<String> {
+            dirs.iter()
+                .map(|dir| dir.to_string_lossy().to_string())
+                .collect()
+        };
+
+        serde_json::json!({
+            "ok": self.needs_attention() == 0,
+            "known_good": paths(&self.known_good),
+            "to_check": paths(&self.to_check),
+            "needs_attention": self.needs_attention(),
+            "bad_files": self
+                .bad_files
+                .iter()
+                .map(BadFiles::to_json)
+                .collect::<Vec<_>>(),
+        })
+    }
+}
+
+/// Reads the state files verify mode wrote to opts.state_dir:
+/// the known_good and to_check lists of the current month and the lists of bad files of every directory.
+/// Nothing is hashed, written or printed.
+///
+/// # Arguments
+///
+/// * `opts` An Options object containing the state directory and the template for the names of the lists
+///
+/// # Returns
+/// A StatusReport describing the state.
+pub fn status(opts: &super::util::Options) -> StatusReport {
+    let (known_good_path, to_check_path) = super::verify::state_paths(opts, &chrono::Local::now());
+    let read_list = |path: &PathBuf| {
+        let mut dirs = super::util::read_paths_from_file(path);
+        dirs.retain(|dir| !dir.as_os_str().is_empty());
+        dirs.sort();
+        dirs.dedup();
+        dirs
+    };
+
+    let mut report = StatusReport {
+        known_good: read_list(&known_good_path),
+        to_check: read_list(&to_check_path),
+        ..StatusReport::default()
+    };
+
+    for list in super::reset::find_state_files(opts) {
+        if list == known_good_path || list == to_check_path {
+            continue;
+        }
+
+        let directory = report
+            .to_check
+            .iter()
+            .find(|dir| super::verify::bad_files_path(dir, opts) == list)
+            .cloned();
+        let mut files = super::util::read_paths_from_file(&list);
+        files.retain(|file| !file.as_os_str().is_empty());
+        report.bad_files.push(BadFiles {
+            list,
+            directory,
+            files,
+        });
+    }
+
+    report
+}
@@ -1,155 +1,313 @@
 //! This module implements the update functionality
 
-extern crate chrono;
 extern crate crossbeam_deque;
-extern crate num_cpus;
+extern crate log;
+extern crate regex;
+extern crate serde_json;
 
-use std::fs::{self, OpenOptions};
-use std::io::{BufReader, Write};
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::thread;
 
-use self::chrono::DateTime;
+use self::log::{debug, error, info};
+use self::regex::Regex;
+
+use super::blocks::{BlockManifest, BlockRequest};
+use super::manifest::{FileMetadata, FileStamp, Manifest, ManifestEntry};
+use super::progress::{self, FileOutcome, Printer, ProgressEvent, Throughput};
+use super::util::{ByteFormat, ManifestFormat, Options, Symlinks};
 
 use self::crossbeam_deque::Injector;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc::channel;
+use std::sync::mpsc::{channel, Sender};
+use std::time::{Duration, Instant};
+
+/// The result of updating the _algorithm_sum.txt of a single directory
+#[derive(Debug, Clone, Default)]
+pub struct DirectoryUpdate {
+    /// Path to the updated directory
+    pub path: PathBuf,
+    /// Paths to the files that were hashed during this update, or that would be hashed in a dry run
+    pub hashed: Vec<PathBuf>,
+    /// Number of bytes of the hashed files
+    pub bytes: u64,
+    /// Descriptions of files that could not be hashed
+    pub failed: Vec<String>,
+    /// Whether or not the update was interrupted by Ctrl-C before every new or modified file was hashed
+    pub interrupted: bool,
+    /// Whether or not the directory was skipped with opts.trust_mtime, because its _algorithm_sum.txt is newer
+    /// than all of its files
+    pub skipped: bool,
+    /// The lines of the _algorithm_sum.txt after the update, only collected to find duplicates
+    pub entries: Vec<ManifestEntry>,
+    /// Time it took to update the directory
+    pub duration: Duration,
+}
 
-/// Updates the _algorithm_sum.txt files of some directories
-///
-/// # Arguments
-///
-/// * `opts` An Options object containing information about the program behavior
-pub fn update_directories(opts: super::util::Options) {
-    if !opts.subdir_mode {
-        let mut worker_handles = Vec::new();
-        let q = Arc::new(Injector::new());
-        let producer_finished = Arc::new(AtomicBool::new(false));
-        let num_threads = match opts.num_threads {
-            0 => num_cpus::get(),
-            _ => opts.num_threads,
-        };
+impl DirectoryUpdate {
+    /// Returns the message that replaces the progress bar of the directory once it is updated
+    pub(crate) fn progress_message(&self) -> String {
+        let elapsed = super::util::format_duration(self.duration);
+        if self.interrupted {
+            format!("interrupted after {}", elapsed)
+        } else if self.skipped {
+            "skipped: unchanged".to_string()
+        } else if self.failed.is_empty() {
+            format!("updated: OK in {}", elapsed)
+        } else {
+            format!("updated: FAILED in {}", elapsed)
+        }
+    }
+}
 
-        let opts = Arc::new(opts);
-        let workdir = PathBuf::from(&opts.folder);
-        let myq = Arc::clone(&q);
+/// The result of updating some directories
+#[derive(Debug, Clone, Default)]
+pub struct UpdateReport {
+    /// The directories that were updated during this run
+    pub directories: Vec<DirectoryUpdate>,
+    /// Groups of files with identical content, only found with opts.find_duplicates
+    pub duplicates: Vec<DuplicateGroup>,
+    /// Wall-clock time of the whole run
+    pub duration: Duration,
+}
 
-        let handle = thread::spawn(move || {
-            update_hashsums(&workdir, opts, myq);
-        });
+/// Files with the same hash in the updated directories
+#[derive(Debug, Clone, Default)]
+pub struct DuplicateGroup {
+    /// The hash all files share
+    pub hash: String,
+    /// Size of each file in bytes
+    pub size: u64,
+    /// Paths to the files, sorted
+    pub paths: Vec<PathBuf>,
+}
 
-        super::util::execute_workers(
-            num_threads,
-            Arc::clone(&q),
-            Arc::clone(&producer_finished),
-            &mut worker_handles,
-        );
+impl DuplicateGroup {
+    /// Returns the number of bytes that could be saved by keeping only one of the files
+    pub fn wasted_bytes(&self) -> u64 {
+        self.size * (self.paths.len() as u64 - 1)
+    }
 
-        handle.join().unwrap();
+    /// Returns a JSON object describing the group
+    pub fn to_json(&self) -> serde_json::Value {
+        let paths: Vec<String> = self
+            .paths
+            .iter()
+            .map(|path| path.to_string_lossy().to_string())
+            .collect();
+
+        serde_json::json!({
+            "hash": self.hash,
+            "size": self.size,
+            "wasted_bytes": self.wasted_bytes(),
+            "paths": paths,
+        })
+    }
+}
 
-        producer_finished.store(true, Ordering::Relaxed);
+impl UpdateReport {
+    /// Indicates that no file of any directory failed
+    pub fn is_ok(&self) -> bool {
+        self.directories
+            .iter()
+            .all(|directory| directory.failed.is_empty())
+    }
 
-        for handle in worker_handles {
-            handle.join().unwrap();
-        }
-    } else {
-        let dirs_to_process = gather_directories_to_process(&opts);
+    /// Returns the number of files that were hashed, or that would be hashed in a dry run
+    pub fn hashed_files(&self) -> usize {
+        self.directories
+            .iter()
+            .map(|directory| directory.hashed.len())
+            .sum()
+    }
 
-        execute_threads_subdir(opts, dirs_to_process)
+    /// Returns the number of bytes of the files that were hashed, or that would be hashed in a dry run
+    pub fn hashed_bytes(&self) -> u64 {
+        self.directories
+            .iter()
+            .map(|directory| directory.bytes)
+            .sum()
+    }
+
+    /// Returns a line summarizing all directories,
+    /// eg "Updated 412 directories, 0 failed, 1.80 GiB hashed in 27m03s"
+    ///
+    /// # Arguments
+    /// * `byte_format` How the number of hashed bytes is printed
+    pub fn summary(&self, byte_format: ByteFormat) -> String {
+        format!(
+            "Updated {} directories, {} failed, {} hashed in {}",
+            self.directories.len(),
+            self.directories
+                .iter()
+                .filter(|directory| !directory.failed.is_empty())
+                .count(),
+            byte_format.format(self.hashed_bytes()),
+            super::util::format_duration(self.duration)
+        )
+    }
+
+    /// Returns the number of bytes that could be saved by keeping only one file of every group of duplicates
+    pub fn wasted_bytes(&self) -> u64 {
+        self.duplicates
+            .iter()
+            .map(DuplicateGroup::wasted_bytes)
+            .sum()
     }
 }
 
-/// Reads all directories in the working directory.
-/// Ignores all directories listed in .arkignore
+/// Updates the _algorithm_sum.txt files of some directories.
+/// Info messages and errors are emitted through the log crate according to the loglevel, the summary is left to
+/// the caller, see UpdateReport::summary.
+/// In a dry run, the files that would be hashed are only counted and no _algorithm_sum.txt is written.
+/// With opts.find_duplicates, files with identical hashes in all updated directories are gathered in the report.
+/// Files in opts.folders are updated in the _algorithm_sum.txt they belong to, the nearest one in their directory
+/// or above, without looking at the other files of its directory.
 ///
 /// # Arguments
-/// * `opts` Options object containing the working directory
-fn gather_directories_to_process(opts: &super::util::Options) -> Vec<PathBuf> {
-    let dir_entries = fs::read_dir(&opts.folder).unwrap();
-    let to_ignore = read_to_ignore(&opts);
-
-    if opts.loglevel_debug() {
-        println!("Dirs to ignore: {:?}", to_ignore);
-    }
-
-    if opts.loglevel_info() && !to_ignore.is_empty() {
-        let now: DateTime<chrono::Local> = chrono::Local::now();
-        for ignored_dir in to_ignore.iter().by_ref() {
-            println!(
-                "[{}] Ignoring Directory {}",
-                now,
-                ignored_dir.to_str().unwrap()
-            );
-        }
-    }
+///
+/// * `opts` An Options object containing information about the program behavior
+///
+/// # Returns
+/// An UpdateReport describing the outcome for every directory.
+pub fn update(opts: &super::util::Options) -> UpdateReport {
+    let start = Instant::now();
+    let opts = opts.clone();
+    let (files, folders): (Vec<&PathBuf>, Vec<&PathBuf>) = opts
+        .folders
+        .iter()
+        .partition(|folder| super::util::is_single_file(folder));
+    let files: Vec<PathBuf> = files.into_iter().cloned().collect();
+    let (file_groups, ungrouped) = super::edit::group_by_manifest(&files, &opts);
+
+    let dirs_to_process: Box<dyn Iterator<Item = PathBuf> + Send> = if opts.subdir_mode {
+        Box::new(gather_directories_to_process(&folders, &opts))
+    } else {
+        Box::new(folders.into_iter().cloned())
+    };
 
-    let mut dirs_to_process = Vec::new();
-    for entry in dir_entries {
-        let entry = entry.unwrap();
-        let metadata = entry.metadata().unwrap();
+    let dirs_to_process = super::util::schedule_directories(dirs_to_process, &opts)
+        .map(|directory| (directory, None))
+        .chain(
+            file_groups
+                .into_iter()
+                .map(|(workdir, files)| (workdir, Some(files))),
+        );
 
-        if metadata.is_dir() && !to_ignore.contains(&entry.path()) {
-            dirs_to_process.push(entry.path());
+    let mut report = execute_threads(&opts, Box::new(dirs_to_process));
+    for (file, message) in ungrouped {
+        if !opts.loglevel_quiet() || opts.quiet {
+            error!("{}: {}", file.display(), message);
         }
+        report.directories.push(DirectoryUpdate {
+            path: file,
+            failed: vec![message],
+            ..DirectoryUpdate::default()
+        });
+    }
+
+    if opts.find_duplicates && !opts.dry_run {
+        report.duplicates = find_duplicates(&mut report.directories);
     }
 
-    dirs_to_process
+    report.duration = start.elapsed();
+    report
 }
 
-/// Reads the .arkignore file and returns a Vector of directories that should be ignored when updating hashes.
+/// Finds all directories in the folders, descending opts.max_depth levels, while they are processed.
+/// The directories are read in parallel, see discover_subdirectories.
+/// Ignores all directories listed in the .arkignore of each folder
 ///
 /// # Arguments
-/// * `opts` Options object containing the working directory
-fn read_to_ignore(opts: &super::util::Options) -> Vec<PathBuf> {
-    let to_ignore =
-        super::util::read_paths_from_file(&format!("{}{}", &opts.folder, "/.arkignore"));
-    let mut to_ignore_prepended = Vec::new();
-
-    for path in to_ignore {
-        if !path.to_str().unwrap().starts_with("./") {
-            let new_path = PathBuf::from(format!("./{}", path.to_str().unwrap()));
-            to_ignore_prepended.push(new_path);
-        } else {
-            to_ignore_prepended.push(path);
+/// * `folders` The folders whose subdirectories are updated
+/// * `opts` Options object containing the maximum depth
+fn gather_directories_to_process(
+    folders: &[&PathBuf],
+    opts: &super::util::Options,
+) -> std::sync::mpsc::IntoIter<PathBuf> {
+    let mut folders_to_ignore = Vec::new();
+    for folder in folders {
+        let to_ignore = read_to_ignore(folder);
+
+        if opts.loglevel_debug() {
+            debug!("Dirs to ignore: {:?}", to_ignore);
         }
+
+        if opts.loglevel_info() && !to_ignore.is_empty() {
+            for ignored_dir in to_ignore.iter().by_ref() {
+                info!("Ignoring Directory {}", ignored_dir.display());
+            }
+        }
+
+        folders_to_ignore.push((folder.to_path_buf(), to_ignore));
     }
 
-    to_ignore_prepended
+    super::util::discover_subdirectories(folders_to_ignore, opts, |_| true)
+}
+
+/// Reads the .arkignore file and returns a Vector of directories that should be ignored when updating hashes.
+///
+/// # Arguments
+/// * `folder` The folder containing the .arkignore file
+fn read_to_ignore(folder: &Path) -> Vec<PathBuf> {
+    let to_ignore = super::util::read_paths_from_file(folder.join(".arkignore"));
+
+    // entries are relative to the folder, like the paths returned by read_dir
+    to_ignore
+        .iter()
+        .map(|path| folder.join(path.strip_prefix(".").unwrap_or(path)))
+        .collect()
 }
 
-/// Starts a thread for every directory in dirs_to_process as a HashTask producer.
-/// Launches as many worker threads as opts.num_threads or number of logical cpus.
+/// Processes opts.parallel_directories directories of dirs_to_process at a time, each as a HashTask producer.
+/// Launches as many worker threads as opts.threads describes.
+/// The directories are updated as soon as they are found, unless progress is reported,
+/// which needs to know every directory up front.
 ///
 /// # Arguments
 /// * `opts` Options object
-/// * `dirs_to_process` Vector of directory paths that have to be updated
-fn execute_threads_subdir(opts: super::util::Options, dirs_to_process: Vec<PathBuf>) {
-    let mut producer_handles = Vec::new();
+/// * `dirs_to_process` The directories that have to be updated, they are updated as soon as they are found.
+///   Only the given files are updated in directories that come with a list of files.
+///
+/// # Returns
+/// An UpdateReport describing the outcome for every directory.
+fn execute_threads<'a>(
+    opts: &super::util::Options,
+    dirs_to_process: Box<dyn Iterator<Item = (PathBuf, Option<Vec<PathBuf>>)> + Send + 'a>,
+) -> UpdateReport {
+    // a dry run hashes nothing, so there is no progress to report
+    let (printer, dirs_to_process) = if Printer::is_needed(opts) && !opts.dry_run {
+        let dirs_to_process: Vec<(PathBuf, Option<Vec<PathBuf>>)> = dirs_to_process.collect();
+        let longest_folder = dirs_to_process
+            .iter()
+            .map(|(dir, _)| dir.to_string_lossy().len())
+            .max()
+            .unwrap_or(0);
+
+        if opts.loglevel_progress() {
+            super::util::terminal_noecho();
+        }
+        let workdirs = dirs_to_process
+            .iter()
+            .map(|(dir, _)| dir.to_string_lossy().to_string())
+            .collect();
+        let printer = Printer::start(workdirs, longest_folder, opts);
+        let dirs_to_process: Box<dyn Iterator<Item = (PathBuf, Option<Vec<PathBuf>>)> + Send + 'a> =
+            Box::new(dirs_to_process.into_iter());
+
+        (Some(printer), dirs_to_process)
+    } else {
+        (None, dirs_to_process)
+    };
+
     let mut worker_handles = Vec::new();
-    let opts = Arc::new(opts);
+    let opts = Arc::new(opts.clone());
     let q = Arc::new(Injector::new());
     let producer_finished = Arc::new(AtomicBool::new(false));
-    let num_threads = match opts.num_threads {
-        0 => num_cpus::get(),
-        _ => opts.num_threads,
-    };
-
-    for entry in dirs_to_process {
-        if opts.loglevel_info() {
-            let now: DateTime<chrono::Local> = chrono::Local::now();
-            println!("[{}] Updating Directory {}", now, entry.to_str().unwrap());
-        }
-
-        let opts = Arc::clone(&opts);
-        let myq = Arc::clone(&q);
-
-        let handle = thread::spawn(move || {
-            update_hashsums(&entry, opts, myq);
-        });
-
-        producer_handles.push(handle);
-    }
+    let num_threads = opts.threads.count();
+    let num_producers = opts.parallel_directories.count();
+    super::util::log_thread_count(num_threads, &opts);
 
     super::util::execute_workers(
         num_threads,
@@ -158,93 +316,601 @@ fn execute_threads_subdir(opts: super::util::Options, dirs_to_process: Vec<PathB
         &mut worker_handles,
     );
 
-    for handle in producer_handles {
-        handle.join().unwrap();
-    }
+    let progress = printer.as_ref().map(Printer::sender);
+    let mut report = UpdateReport {
+        directories: super::util::execute_producers(
+            dirs_to_process.enumerate(),
+            num_producers,
+            |(line, (entry, files)): (usize, (PathBuf, Option<Vec<PathBuf>>))| {
+                if opts.loglevel_info() {
+                    info!("Updating Directory {}", entry.display());
+                }
+                if let Some(ref progress) = progress {
+                    progress.send(ProgressEvent::Started { line }).unwrap();
+                }
+
+                let update = update_hashsums(
+                    &entry,
+                    files.as_deref(),
+                    Arc::clone(&opts),
+                    line,
+                    progress.as_ref(),
+                    Arc::clone(&q),
+                );
+                if let Some(ref progress) = progress {
+                    progress
+                        .send(ProgressEvent::Message {
+                            line,
+                            message: update.progress_message(),
+                        })
+                        .unwrap();
+                }
+                update
+            },
+        ),
+        ..UpdateReport::default()
+    };
+    report.directories.sort_by(|a, b| a.path.cmp(&b.path));
+    // the printer finishes once every sender is dropped
+    drop(progress);
 
     producer_finished.store(true, Ordering::Relaxed);
 
     for handle in worker_handles {
         handle.join().unwrap();
     }
+
+    if let Some(printer) = printer {
+        printer.finish();
+        if opts.loglevel_progress() {
+            super::util::terminal_echo();
+        }
+    }
+
+    report
 }
 
-/// Updates the _algorithm_sum.txt in a directory
+/// The _algorithm_sum.txt of one of opts.extra_algorithms, updated in the same pass as the one of opts.algorithm
+struct ExtraManifest {
+    /// The Options using the algorithm of the manifest
+    opts: Options,
+    /// The manifest as it was read
+    manifest: Manifest,
+    /// Matches the hashlines of the algorithm
+    hashline_re: Regex,
+    /// The entries of the files hashed in this update
+    hashed_entries: Vec<ManifestEntry>,
+}
+
+/// Updates the _algorithm_sum.txt in a directory.
+/// Only files that are new or whose size or modification time differ from the recorded ones are hashed.
+/// With opts.extra_algorithms, the _algorithm_sum.txt of every algorithm is updated and every file is read only once.
+/// A file is hashed with all algorithms if it is out of date in any of their _algorithm_sum.txt.
+/// With opts.trust_mtime, the directory is skipped without looking at the stamps of its files
+/// if its _algorithm_sum.txt is newer than all of them.
+/// The _algorithm_sum.txt is rewritten as a whole afterwards.
+/// With opts.block_size, the blocks of the hashed files are hashed as well and written to the _algorithm_sum.blocks.txt.
+/// Files that already have block hashes keep them up to date with their recorded block size.
+/// With opts.check_metadata, the permissions, owner and group of every file are recorded as well,
+/// files that are up to date get their current ones without being hashed again.
+/// In a dry run, these files are only listed and nothing is hashed or written.
+/// The sizes of the files to hash are summed up before hashing starts, so their progress can be reported.
 ///
 /// # Arguments
 ///
 /// * `path` The path to the directory that is going to be updated
+/// * `files` The files that are updated, relative to path. None updates every file in the directory.
 /// * `opts` An Options object containing information about the program behavior
+/// * `line` The index of path in the directories of the printer thread. Only used with progress.
+/// * `progress` Sender to the printer thread, only present if progress is displayed, streamed or reported
 /// * `myq` An Injector queue that is used to push the generated hashtasks to the workers and receive the results
+///
+/// # Returns
+/// A DirectoryUpdate describing which files were hashed.
 fn update_hashsums(
-    path: &PathBuf,
+    path: &Path,
+    files: Option<&[PathBuf]>,
     opts: Arc<super::util::Options>,
+    line: usize,
+    progress: Option<&Sender<ProgressEvent>>,
     myq: Arc<Injector<super::util::HashTask>>,
-) {
-    if dir_is_empty(path) {
-        return;
-    }
+) -> DirectoryUpdate {
+    let start = Instant::now();
+    let mut report = DirectoryUpdate {
+        path: path.to_path_buf(),
+        ..DirectoryUpdate::default()
+    };
 
-    let dirwalker = super::util::DirWalker::new(&path, opts.subdir_mode);
-    let reader = BufReader::new(dirwalker);
+    if files.is_none() && dir_is_empty(path, &opts) {
+        return report;
+    }
+    if opts.xattr && !super::xattrs::is_supported(path) && opts.loglevel_info() {
+        info!(
+            "{}: extended attributes are not supported, using {}sum.txt",
+            path.display(),
+            opts.algorithm
+        );
+    }
 
-    let filter = super::filter::Filter::new(reader, path.to_str().unwrap(), &opts);
+    let mut manifest = match Manifest::read_or_new(path, &opts) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            let message = format!("could not read {}sum.txt: {}", opts.algorithm, e);
+            print_error(path, &message, &opts, progress);
+            report.failed.push(message);
+            return report;
+        }
+    };
+    if files.is_none() && opts.trust_mtime && super::util::manifest_is_newest(path, &opts) {
+        if opts.loglevel_info() {
+            info!(
+                "Directory {} unchanged since its {}sum.txt was written, skipping",
+                path.display(),
+                opts.algorithm
+            );
+        }
+        if opts.find_duplicates {
+            report.entries = manifest.entries().to_vec();
+        }
+        report.skipped = true;
+        return report;
+    }
+    let mut extras = Vec::new();
+    for &algorithm in &opts.extra_algorithms {
+        let extra_opts = opts.with_algorithm(algorithm);
+        match Manifest::read_or_new(path, &extra_opts) {
+            Ok(manifest) => extras.push(ExtraManifest {
+                hashline_re: super::util::regex_from_opts(&extra_opts).unwrap(),
+                opts: extra_opts,
+                manifest,
+                hashed_entries: Vec::new(),
+            }),
+            Err(e) => {
+                let message = format!("could not read {}sum.txt: {}", algorithm, e);
+                print_error(path, &message, &opts, progress);
+                report.failed.push(message);
+                return report;
+            }
+        }
+    }
+    let mut block_manifest = match BlockManifest::read(path, opts.algorithm) {
+        Ok(block_manifest) => block_manifest,
+        Err(e) => {
+            let message = format!("could not read {}sum.blocks.txt: {}", opts.algorithm, e);
+            print_error(path, &message, &opts, progress);
+            report.failed.push(message);
+            return report;
+        }
+    };
+    let hashline_re = super::util::regex_from_opts(&opts).unwrap();
 
     let (sender, receiver) = channel();
+    let (block_sender, block_receiver) = channel();
+    let mut stamps = HashMap::new();
+    let mut file_metadata = HashMap::new();
+    let mut hashed_entries = Vec::new();
+    let mut tasks = Vec::new();
+    let mut all_bytes: u64 = 0;
+
+    let lines: Box<dyn Iterator<Item = PathBuf>> = match files {
+        Some(files) => Box::new(files.iter().cloned()),
+        None => super::util::walk_files(path, true, &opts),
+    };
 
-    if let Ok(filter) = filter {
-        let mut filepath = path.clone();
-        filepath.push(format!("{}sum.txt", opts.algorithm));
-        let file = OpenOptions::new().create(true).append(true).open(filepath);
-
-        if let Ok(mut file) = file {
-            for line in filter {
-                let task = super::util::HashTask {
-                    path: line,
-                    workdir: PathBuf::from(path),
-                    opts: Arc::clone(&opts),
-                    cmp: String::new(),
-                    result_chan: sender.clone(),
-                };
+    for line in lines {
+        if Manifest::is_own_file(&line) {
+            continue;
+        }
+
+        // with hash-link, a changed link target is noticed by the stamp of the link itself
+        let metadata = if opts.symlinks == Symlinks::HashLink {
+            fs::symlink_metadata(path.join(&line))
+        } else {
+            fs::metadata(path.join(&line))
+        };
+        let metadata = metadata.ok();
+        let stamp = metadata.as_ref().and_then(FileStamp::from_metadata);
+        let current_metadata = if opts.check_metadata {
+            metadata.as_ref().and_then(FileMetadata::from_metadata)
+        } else {
+            None
+        };
 
-                myq.push(task);
+        let recorded = manifest.get(&line);
+        if is_up_to_date(recorded, &stamp, &opts)
+            && extras
+                .iter()
+                .all(|extra| is_up_to_date(extra.manifest.get(&line), &stamp, &opts))
+        {
+            if opts.check_metadata {
+                refresh_metadata(&mut manifest, &line, current_metadata, path, &opts);
+                for extra in &mut extras {
+                    refresh_metadata(
+                        &mut extra.manifest,
+                        &line,
+                        current_metadata,
+                        path,
+                        &extra.opts,
+                    );
+                }
+            }
+            continue;
+        }
+
+        if opts.dry_run {
+            let bytes = metadata.map_or(0, |metadata| metadata.len());
+            if opts.loglevel_info() {
+                info!(
+                    "{}: would hash {} ({})",
+                    path.display(),
+                    line.display(),
+                    opts.byte_format.format(bytes)
+                );
             }
+            report.hashed.push(line);
+            report.bytes += bytes;
+            continue;
+        }
+
+        let task = super::util::HashTask {
+            path: line.clone(),
+            workdir: PathBuf::from(path),
+            opts: Arc::clone(&opts),
+            cmp: match recorded {
+                Some(entry) if entry.stamp.is_none() => entry.hash.clone(),
+                _ => String::new(),
+            },
+            result_chan: sender.clone(),
+            blocks: opts
+                .block_size
+                .or_else(|| block_manifest.get(&line).map(|hashes| hashes.block_size))
+                .map(|block_size| BlockRequest {
+                    block_size,
+                    result_chan: block_sender.clone(),
+                }),
+            cancelled: None,
+            read_bytes: None,
+        };
+        all_bytes += stamp.as_ref().map_or(0, |stamp| stamp.size);
+        file_metadata.insert(line.clone(), current_metadata);
+        stamps.insert(line, stamp);
+        tasks.push(task);
+    }
+
+    drop(sender);
+    drop(block_sender);
+
+    if opts.dry_run {
+        if opts.loglevel_info() {
+            info!(
+                "Directory {}: {} files ({}) would be hashed",
+                path.display(),
+                report.hashed.len(),
+                opts.byte_format.format(report.bytes)
+            );
+        }
+        return report;
+    }
+
+    let mut processed_bytes: u64 = 0;
+    let mut throughput = Throughput::new();
+    if let Some(progress) = progress {
+        progress
+            .send(ProgressEvent::Progress {
+                line,
+                processed_bytes: 0,
+                all_bytes,
+                bytes_per_sec: 0.0,
+                eta: None,
+            })
+            .unwrap();
+    }
 
-            drop(sender);
+    let mut unfinished = tasks.len();
+    for task in tasks {
+        myq.push(task);
+    }
 
-            for task_result in receiver {
-                if let Ok((hashline, _)) = task_result {
-                    if let Err(e) = write!(file, "{}", hashline) {
-                        eprintln!("Error writing to file: {}", e);
+    // tasks that are dropped after an interrupt send no result
+    for task_result in receiver {
+        unfinished -= 1;
+        match task_result {
+            Ok((hashlines, cmp)) => {
+                let hashline = &hashlines[0];
+                let (hash, file_path) = match super::util::parse_hashline(&hashline_re, hashline) {
+                    Some(parsed) => parsed,
+                    None => continue,
+                };
+                let bytes = stamp_size(&stamps, &file_path);
+                processed_bytes += bytes;
+
+                if !cmp.is_empty() && cmp != hash {
+                    // a legacy line whose file changed: keep it so the next verify reports the file
+                    let message = format!(
+                        "{}: hash differs from the recorded one, keeping the recorded hash. Please verify this directory.",
+                        file_path.display()
+                    );
+                    print_error(path, &message, &opts, progress);
+                    report.failed.push(message);
+                    progress::send_file(
+                        progress,
+                        line,
+                        path,
+                        &file_path,
+                        bytes,
+                        FileOutcome::Changed,
+                        None,
+                    );
+                } else {
+                    let stamp = stamps.remove(&file_path).unwrap_or(None);
+                    let metadata = file_metadata.remove(&file_path).unwrap_or(None);
+                    report.bytes += stamp.as_ref().map_or(0, |stamp| stamp.size);
+                    for (extra, hashline) in extras.iter_mut().zip(&hashlines[1..]) {
+                        if let Some((hash, path)) =
+                            super::util::parse_hashline(&extra.hashline_re, hashline)
+                        {
+                            extra.hashed_entries.push(ManifestEntry {
+                                hash,
+                                path,
+                                stamp: stamp.clone(),
+                                metadata,
+                            });
+                        }
                     }
+                    progress::send_file(
+                        progress,
+                        line,
+                        path,
+                        &file_path,
+                        bytes,
+                        FileOutcome::Hashed,
+                        Some(&hash),
+                    );
+                    hashed_entries.push(ManifestEntry {
+                        hash,
+                        path: file_path,
+                        stamp,
+                        metadata,
+                    });
 
                     if opts.loglevel_info() {
-                        let now: DateTime<chrono::Local> = chrono::Local::now();
-                        print!("[{}] {}: {}", now, path.to_str().unwrap(), hashline);
+                        for hashline in &hashlines {
+                            info!("{}: {}", path.display(), hashline.trim_end());
+                        }
                     }
-                } else {
-                    let now: DateTime<chrono::Local> = chrono::Local::now();
-                    eprintln!(
-                        "[{}] {}: {}",
-                        now,
-                        path.to_str().unwrap(),
-                        "could not hash file!"
-                    );
                 }
             }
+            Err(e) => {
+                processed_bytes += stamp_size(&stamps, e.path());
+                print_error(
+                    path,
+                    &format!("could not hash file! {}", e),
+                    &opts,
+                    progress,
+                );
+                report.failed.push(e.to_string());
+                progress::send_file(
+                    progress,
+                    line,
+                    path,
+                    e.path(),
+                    0,
+                    FileOutcome::from_error(&e),
+                    None,
+                );
+            }
+        }
+
+        if let Some(progress) = progress {
+            let bytes_per_sec = throughput.update(processed_bytes);
+            progress
+                .send(ProgressEvent::Progress {
+                    line,
+                    processed_bytes,
+                    all_bytes,
+                    bytes_per_sec,
+                    eta: throughput.eta(processed_bytes, all_bytes),
+                })
+                .unwrap();
+        }
+    }
+
+    // the workers finish in arbitrary order, new lines are appended sorted by path
+    hashed_entries.sort_by(|a, b| a.path.cmp(&b.path));
+    for entry in hashed_entries {
+        report.hashed.push(entry.path.clone());
+        manifest.upsert(entry);
+    }
+    for extra in &mut extras {
+        extra.hashed_entries.sort_by(|a, b| a.path.cmp(&b.path));
+        for entry in extra.hashed_entries.drain(..) {
+            extra.manifest.upsert(entry);
+        }
+    }
+    report.failed.sort();
+    report.interrupted = unfinished > 0;
+
+    // a file whose hash was kept because it differs keeps its previous block hashes as well
+    let hashed: HashSet<&PathBuf> = report.hashed.iter().collect();
+    for hashes in block_receiver.try_iter() {
+        if hashed.contains(&hashes.path) {
+            block_manifest.insert(hashes);
+        }
+    }
+    block_manifest.retain(|file_path| manifest.get(file_path).is_some());
+    if let Err(e) = block_manifest.write(path, opts.algorithm) {
+        error!("Error writing to file: {}", e);
+    }
+
+    if opts.find_duplicates {
+        report.entries = manifest.entries().to_vec();
+    }
+
+    // after an interrupt, the files hashed so far are recorded and the rest is hashed on the next update
+    if let Err(e) = manifest.write(path, &opts) {
+        error!("Error writing to file: {}", e);
+    }
+    for extra in &extras {
+        if let Err(e) = extra.manifest.write(path, &extra.opts) {
+            error!("Error writing to file: {}", e);
         }
     }
 
+    report.duration = start.elapsed();
     if opts.loglevel_info() {
-        let now: DateTime<chrono::Local> = chrono::Local::now();
-        println!("[{}] Directory {} Updated", now, path.to_str().unwrap());
+        let elapsed = super::util::format_duration(report.duration);
+        if report.interrupted {
+            info!(
+                "Directory {} interrupted after {}, {} files were not hashed",
+                path.display(),
+                elapsed,
+                unfinished
+            );
+        } else {
+            info!("Directory {} Updated in {}", path.display(), elapsed);
+        }
+    }
+
+    report
+}
+
+/// Reports an error that occurred while updating a directory through the log crate.
+/// With progress bars, it is passed to the printer thread instead, which prints it above them.
+///
+/// # Arguments
+///
+/// * `path` The path to the directory that is updated
+/// * `message` Description of the error
+/// * `opts` An Options object containing the loglevel
+/// * `progress` Sender to the printer thread, only present if progress is displayed, streamed or reported
+fn print_error(
+    path: &Path,
+    message: &str,
+    opts: &super::util::Options,
+    progress: Option<&Sender<ProgressEvent>>,
+) {
+    match progress {
+        Some(progress) if opts.loglevel_progress() => {
+            let now = super::util::timestamp(opts);
+            let message = format!("[{}] {}: {}", now, path.display(), message);
+            progress.send(ProgressEvent::Log(message)).unwrap()
+        }
+        _ if !opts.loglevel_quiet() => error!("{}: {}", path.display(), message),
+        _ => {}
+    }
+}
+
+/// Returns whether the recorded entry of a file is up to date, so the file does not need to be hashed
+///
+/// # Arguments
+///
+/// * `recorded` The entry of the file in the _algorithm_sum.txt, if it is listed
+/// * `stamp` The current size and modification time of the file
+/// * `opts` An Options object containing the format of the _algorithm_sum.txt
+fn is_up_to_date(
+    recorded: Option<&ManifestEntry>,
+    stamp: &Option<FileStamp>,
+    opts: &Options,
+) -> bool {
+    match recorded {
+        // lines without a stamp have unknown modification time and must be rehashed
+        Some(entry) if entry.stamp.is_some() => entry.stamp == *stamp,
+        // BSD style and plain lines have no stamp, files listed in them are only hashed again by verify
+        Some(_) => opts.manifest_format != ManifestFormat::Gnu,
+        None => false,
     }
 }
 
-fn dir_is_empty(path: &PathBuf) -> bool {
-    let mut dirwalker = super::util::DirWalker::new(&path, false);
-    match dirwalker.next() {
-        Some(_) => false,
-        None => true,
+/// Records the current permissions, owner and group of a file whose entry is up to date, without hashing it again.
+/// Entries without a stamp have no room for them. A change of the recorded ones is logged in loglevel info.
+///
+/// # Arguments
+///
+/// * `manifest` The _algorithm_sum.txt listing the file
+/// * `file_path` Path to the file, relative to the updated directory
+/// * `metadata` The current permissions, owner and group of the file
+/// * `path` The path to the directory that is updated
+/// * `opts` An Options object containing the algorithm and the loglevel
+fn refresh_metadata(
+    manifest: &mut Manifest,
+    file_path: &Path,
+    metadata: Option<FileMetadata>,
+    path: &Path,
+    opts: &Options,
+) {
+    let entry = match manifest.get(file_path) {
+        Some(entry) if entry.stamp.is_some() && entry.metadata != metadata => entry.clone(),
+        _ => return,
+    };
+    if let (Some(recorded), Some(current), true) = (entry.metadata, metadata, opts.loglevel_info())
+    {
+        info!(
+            "{}: metadata of {} changed in {}sum.txt: {}",
+            path.display(),
+            file_path.display(),
+            opts.algorithm,
+            recorded.describe_change(&current)
+        );
     }
+    manifest.upsert(ManifestEntry { metadata, ..entry });
+}
+
+/// Returns the size of a file to hash as it was when its hash task was created, 0 if it is unknown
+///
+/// # Arguments
+///
+/// * `stamps` The stamps of the files that are hashed by their path
+/// * `file_path` Path to the file, relative to the updated directory
+fn stamp_size(stamps: &HashMap<PathBuf, Option<FileStamp>>, file_path: &Path) -> u64 {
+    stamps
+        .get(file_path)
+        .and_then(Option::as_ref)
+        .map_or(0, |stamp| stamp.size)
+}
+
+/// Groups the files of the updated directories by their hash and returns the groups of at least two files.
+/// The collected entries of the directories are consumed. Files that no longer exist and empty files are left out.
+///
+/// # Arguments
+///
+/// * `directories` The updated directories together with the entries of their _algorithm_sum.txt
+fn find_duplicates(directories: &mut [DirectoryUpdate]) -> Vec<DuplicateGroup> {
+    let mut by_hash: HashMap<(String, u64), Vec<PathBuf>> = HashMap::new();
+
+    for directory in directories.iter_mut() {
+        for entry in directory.entries.drain(..) {
+            let path = directory
+                .path
+                .join(entry.path.strip_prefix(".").unwrap_or(&entry.path));
+            let size = match fs::metadata(&path) {
+                Ok(metadata) if metadata.len() > 0 => metadata.len(),
+                _ => continue,
+            };
+            by_hash
+                .entry((entry.hash.to_lowercase(), size))
+                .or_default()
+                .push(path);
+        }
+    }
+
+    let mut groups: Vec<DuplicateGroup> = by_hash
+        .into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .map(|((hash, size), mut paths)| {
+            paths.sort();
+            DuplicateGroup { hash, size, paths }
+        })
+        .collect();
+    // the groups wasting the most space first
+    groups.sort_by(|a, b| {
+        b.wasted_bytes()
+            .cmp(&a.wasted_bytes())
+            .then_with(|| a.paths.cmp(&b.paths))
+    });
+
+    groups
+}
+
+fn dir_is_empty(path: &Path, opts: &Options) -> bool {
+    super::util::walk_files(path, false, opts).next().is_none()
 }
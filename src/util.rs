@@ -1,12 +1,14 @@
 //! This module describes a set of utilities that will be used throughout the other modules
 
+extern crate chrono;
+extern crate console;
 extern crate crossbeam_deque;
-extern crate digest;
-extern crate hex;
-extern crate md5;
+extern crate ctrlc;
+extern crate ignore;
+extern crate log;
+extern crate memmap2;
+extern crate rayon;
 extern crate regex;
-extern crate sha1;
-extern crate sha2;
 
 #[cfg(unix)]
 extern crate termios;
@@ -14,30 +16,315 @@ extern crate termios;
 #[cfg(windows)]
 extern crate winapi;
 
+use self::log::{debug, info, trace, warn};
+use self::rayon::iter::{ParallelBridge, ParallelIterator};
 use self::regex::Regex;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::{HashSet, VecDeque};
 use std::fs::{self, OpenOptions};
-use std::io::{self, BufRead, BufReader, Error, Read};
-use std::path::PathBuf;
+use std::io::{self, BufRead, BufReader, Error, IsTerminal, Read};
+use std::path::{Component, Path, PathBuf};
 
-use self::digest::{Digest, DynDigest};
-use self::md5::Md5;
-use self::sha1::Sha1;
-use self::sha2::{Sha224, Sha256, Sha384, Sha512};
+use super::hasher::Hasher;
 
 use self::crossbeam_deque::{Injector, Steal};
-use std::sync::atomic::{AtomicBool, Ordering};
+use super::blocks::{BlockHasher, BlockHashes, BlockRequest};
+use super::ignore::Ignores;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::mpsc::Sender;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
 use std::fmt;
 
+use super::progress::{FileCallback, FileEvent, ProgressStream, Reporter, SharedReporter};
+
 /// The mode the program will operate in
 #[derive(Debug, Clone)]
 pub enum Mode {
     Filter,
     Update,
     Verify,
+    Reset,
+    Status,
+    Prune,
+    CheckManifest,
+    Add,
+    Remove,
+    Diff,
+    Compare,
+    Stdin,
+    Keygen,
+    Watch,
+}
+
+/// The hashing algorithms the program is able to use.
+/// Crc32 and Adler32 are checksums that only detect accidental corruption, they are not cryptographic.
+/// Custom algorithms are added by library users with `hasher::register`, they are named by their name.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Algorithm {
+    Sha1,
+    Md5,
+    Sha224,
+    Sha256,
+    Sha384,
+    Sha512,
+    Sha3_256,
+    Sha3_512,
+    Crc32,
+    Adler32,
+    Custom(&'static str),
+}
+
+impl Algorithm {
+    /// Every built-in algorithm
+    pub const ALL: [Algorithm; 10] = [
+        Algorithm::Sha1,
+        Algorithm::Md5,
+        Algorithm::Sha224,
+        Algorithm::Sha256,
+        Algorithm::Sha384,
+        Algorithm::Sha512,
+        Algorithm::Sha3_256,
+        Algorithm::Sha3_512,
+        Algorithm::Crc32,
+        Algorithm::Adler32,
+    ];
+
+    /// Returns every registered algorithm, the built-in ones first
+    pub fn all() -> Vec<Algorithm> {
+        super::hasher::registrations()
+            .iter()
+            .map(|registration| registration.algorithm)
+            .collect()
+    }
+
+    /// Returns the algorithm with the given name, ignoring case
+    ///
+    /// # Arguments
+    ///
+    /// * `name` Name of the algorithm, eg "sha1"
+    pub fn from_name(name: &str) -> Option<Algorithm> {
+        super::hasher::find(name).map(|registration| registration.algorithm)
+    }
+
+    /// Returns the algorithms of a list of names separated by commas, eg "md5,sha256", without duplicates.
+    /// None if a name is unknown or the list is empty.
+    pub fn from_names(names: &str) -> Option<Vec<Algorithm>> {
+        let mut algorithms = Vec::new();
+        for name in names.split(',') {
+            let algorithm = Algorithm::from_name(name.trim())?;
+            if !algorithms.contains(&algorithm) {
+                algorithms.push(algorithm);
+            }
+        }
+        Some(algorithms)
+    }
+
+    /// Returns the names of every registered algorithm separated by commas, eg for usage messages
+    pub fn available() -> String {
+        let names: Vec<&str> = super::hasher::registrations()
+            .iter()
+            .map(|registration| registration.name)
+            .collect();
+        names.join(", ")
+    }
+
+    /// Returns the tag of the algorithm in BSD style lines, eg SHA1
+    pub fn tag(self) -> &'static str {
+        super::hasher::registration(self).tag
+    }
+
+    /// Returns the name of the algorithm, which is also the prefix of the _algorithm_sum.txt file
+    pub fn name(self) -> &'static str {
+        super::hasher::registration(self).name
+    }
+
+    /// Returns the number of hexadecimal characters of a hashsum calculated with this algorithm
+    pub fn hash_length(self) -> usize {
+        super::hasher::registration(self).hash_length
+    }
+
+    /// Returns a new hasher for this algorithm
+    pub fn hasher(self) -> Box<dyn Hasher> {
+        (super::hasher::registration(self).constructor)()
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// The format of the output of the program
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OutputFormat {
+    /// Human readable text according to the loglevel
+    Text,
+    /// One JSON object per line for every verified directory and a final summary
+    Json,
+}
+
+/// The format of the lines written to the _algorithm_sum.txt
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ManifestFormat {
+    /// `<hash> <size> <mtime>  <path>`, the format of the coreutils extended by size and modification time
+    Gnu,
+    /// `SHA1 (<path>) = <hash>`, the format the coreutils write with --tag
+    Bsd,
+    /// `<hash>  <path>`, the plain format of the coreutils that `sha1sum -c` understands
+    Plain,
+}
+
+/// How numbers of bytes are printed in logs, progress bars and summaries
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ByteFormat {
+    /// Powers of 1024, eg `1.80 GiB`
+    Binary,
+    /// Powers of 1000, eg `1.93 GB`
+    Si,
+    /// The exact number, eg `1932735283 bytes`, for scripts
+    Raw,
+}
+
+impl ByteFormat {
+    /// Formats a number of bytes.
+    /// Numbers below the first unit are printed exactly, larger ones with two decimals of the largest fitting unit.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` The number of bytes
+    pub fn format(self, bytes: u64) -> String {
+        let (base, units) = match self {
+            ByteFormat::Binary => (1024.0, ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"]),
+            ByteFormat::Si => (1000.0, ["kB", "MB", "GB", "TB", "PB", "EB"]),
+            ByteFormat::Raw => return format!("{} bytes", bytes),
+        };
+
+        if (bytes as f64) < base {
+            return format!("{} B", bytes);
+        }
+
+        let mut value = bytes as f64 / base;
+        let mut unit = 0;
+        // switch units before rounding would print eg 1024.00 KiB
+        while value >= base - 0.005 && unit < units.len() - 1 {
+            value /= base;
+            unit += 1;
+        }
+        format!("{:.2} {}", value, units[unit])
+    }
+}
+
+/// Formats a number of bytes with binary units, eg `1.80 GiB`
+///
+/// # Arguments
+///
+/// * `bytes` The number of bytes
+pub fn format_bytes(bytes: u64) -> String {
+    ByteFormat::Binary.format(bytes)
+}
+
+/// Formats a duration compactly for completion messages, eg `4.2s`, `3m12s` or `1h05m00s`
+///
+/// # Arguments
+///
+/// * `duration` The duration
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs < 60 {
+        // tenths are truncated, so 59.96 seconds are not printed as 60.0s
+        return format!("{}.{}s", secs, duration.subsec_millis() / 100);
+    }
+
+    let (hours, minutes, secs) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if hours > 0 {
+        format!("{}h{:02}m{:02}s", hours, minutes, secs)
+    } else {
+        format!("{}m{:02}s", minutes, secs)
+    }
+}
+
+/// The order in which directories are processed
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Schedule {
+    /// In the order the directories are found
+    Discovery,
+    /// The largest directories first, which requires reading the metadata of every file up front
+    Size,
+}
+
+/// How symbolic links inside a directory are handled
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Symlinks {
+    /// Symbolic links are ignored
+    Skip,
+    /// The target of a symbolic link is hashed, linked directories are descended into once
+    Follow,
+    /// The path a symbolic link points to is hashed instead of the target
+    HashLink,
+}
+
+/// Whether or not hidden files and directories, whose names start with a dot, are processed
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Hidden {
+    /// Hidden files are hashed and hidden directories are descended into
+    Include,
+    /// Hidden files and everything below hidden directories are skipped
+    Exclude,
+}
+
+impl Hidden {
+    /// Indicates that a file or directory with this path is skipped
+    ///
+    /// # Arguments
+    ///
+    /// * `path` Path to the file or directory
+    pub fn excludes(self, path: &Path) -> bool {
+        self == Hidden::Exclude
+            && path
+                .file_name()
+                .is_some_and(|name| path_to_bytes(Path::new(name)).starts_with(b"."))
+    }
+}
+
+/// How the paths of the files are written to the _algorithm_sum.txt
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PathStyle {
+    /// Relative to the directory of the _algorithm_sum.txt, eg ./pictures/1.jpg, so the directory can be moved
+    Relative,
+    /// Absolute, eg /mnt/archive/pictures/1.jpg, so the _algorithm_sum.txt can be used from any working directory
+    Absolute,
+}
+
+/// Which part of the files listed in an _algorithm_sum.txt verify mode checks
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Sample {
+    /// This percentage of the files of every directory, at least one file
+    Percent(f64),
+    /// At most this many files of every directory
+    Count(usize),
+}
+
+impl Sample {
+    /// Returns the number of files of a directory that are checked
+    ///
+    /// # Arguments
+    ///
+    /// * `listed` Number of files listed in the _algorithm_sum.txt of the directory
+    pub fn size(self, listed: usize) -> usize {
+        match self {
+            Sample::Percent(percent) => {
+                let size = (listed as f64 * percent / 100.0).ceil() as usize;
+                size.clamp(listed.min(1), listed)
+            }
+            Sample::Count(count) => count.min(listed),
+        }
+    }
 }
 
 /// The level of detail the program will be logging
@@ -47,35 +334,147 @@ pub enum LogLevel {
     Info,
     Progress,
     Debug,
+    Trace,
+}
+
+impl LogLevel {
+    /// Returns the loglevel of a number of -v flags, or of -q flags for a negative number:
+    /// -v is info, -vv debug and -vvv trace, -q is progress and -qq quiet.
+    /// Progress lies between quiet and info, it replaces the info messages by bars.
+    ///
+    /// # Arguments
+    /// * `verbosity` Number of times -v was given minus the number of times -q was given
+    pub fn from_verbosity(verbosity: i32) -> LogLevel {
+        match verbosity {
+            i32::MIN..=-2 => LogLevel::Quiet,
+            -1 => LogLevel::Progress,
+            0 | 1 => LogLevel::Info,
+            2 => LogLevel::Debug,
+            _ => LogLevel::Trace,
+        }
+    }
+
+    /// Returns the filter of the log crate that corresponds to the loglevel.
+    /// Info messages are replaced by progress bars in the progress loglevel.
+    pub fn level_filter(&self) -> log::LevelFilter {
+        match *self {
+            LogLevel::Quiet => log::LevelFilter::Off,
+            LogLevel::Info => log::LevelFilter::Info,
+            LogLevel::Progress => log::LevelFilter::Warn,
+            LogLevel::Debug => log::LevelFilter::Debug,
+            LogLevel::Trace => log::LevelFilter::Trace,
+        }
+    }
+}
+
+/// The number of worker threads that hash files
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Threads {
+    /// As many worker threads as the system can run in parallel
+    Auto,
+    /// A fixed number of worker threads
+    Fixed(usize),
+}
+
+impl Threads {
+    /// Returns the number of threads given on the command line: auto or 0 for Auto, a positive number for Fixed.
+    /// None if it is neither.
+    ///
+    /// # Arguments
+    ///
+    /// * `arg` The argument, eg "auto" or "8"
+    pub fn from_arg(arg: &str) -> Option<Threads> {
+        let arg = arg.trim();
+        if arg.eq_ignore_ascii_case("auto") {
+            return Some(Threads::Auto);
+        }
+        match arg.parse().ok()? {
+            0 => Some(Threads::Auto),
+            n => Some(Threads::Fixed(n)),
+        }
+    }
+
+    /// Returns the number of worker threads to start
+    pub fn count(self) -> usize {
+        match self {
+            Threads::Auto => std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1),
+            Threads::Fixed(n) => n,
+        }
+    }
 }
 
 /// A structure that defines everything needed to hash a requested file and return the result
 pub struct HashTask {
     /// Path to the file that should be hashed
-    pub path: String,
+    pub path: PathBuf,
     /// The desired working directory of the worker thread
     pub workdir: PathBuf,
     /// A reference to an Options struct containing various parameters
     pub opts: Arc<Options>,
     /// A string containing the hash that the file should match
     pub cmp: String,
-    /// A channel to return the calculated hash and cmp to the task generator
-    pub result_chan: Sender<Result<(String, String), HashError>>,
+    /// A channel to return the calculated hashlines and cmp to the task generator
+    pub result_chan: Sender<HashResult>,
+    /// Whether and how the blocks of the file are hashed as well
+    pub blocks: Option<BlockRequest>,
+    /// Set once the task generator stopped waiting for the result, the file is then not hashed
+    pub cancelled: Option<Arc<AtomicBool>>,
+    /// Counts the bytes read of the file while it is hashed, so the task generator can tell a file that is read
+    /// slowly from a read that is stuck. Shared by the tasks of a directory.
+    pub read_bytes: Option<Arc<AtomicU64>>,
 }
 
+/// The result of a HashTask: the calculated hashlines, one for every algorithm of Options::algorithms in that order,
+/// and the cmp of the task, or the reason why hashing failed
+pub type HashResult = Result<(Vec<String>, String), HashError>;
+
 /// An error that occurs when a file cannot be hashed
 #[derive(Debug)]
 pub struct HashError {
     source: io::Error,
-    path: String,
+    path: PathBuf,
+}
+
+impl HashError {
+    /// Returns the path to the file that could not be hashed
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Indicates that the file does not exist
+    pub fn is_not_found(&self) -> bool {
+        self.source.kind() == io::ErrorKind::NotFound
+    }
+
+    /// Returns the error that occurred while opening or reading the file
+    pub fn io_error(&self) -> &io::Error {
+        &self.source
+    }
 }
 
 impl fmt::Display for HashError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}: {}", self.source.to_string(), self.path)
+        write!(f, "{}: {}", self.source, self.path.display())
     }
 }
 
+/// The default number of bytes read from a file at a time while it is hashed
+pub const DEFAULT_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// The default name of the known_good and to_check lists of verify mode
+pub const DEFAULT_STATE_TEMPLATE: &str = "{list}_{month}_{year}.txt";
+
+/// The name of the known_good and to_check lists of verify mode if they are not partitioned by month
+pub const SINGLE_STATE_TEMPLATE: &str = "{list}.txt";
+
+/// The default strftime pattern of the timestamps in front of log lines
+pub const DEFAULT_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// The strftime pattern of RFC 3339 timestamps, selected with `--time-format rfc3339`
+pub const RFC3339_TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.3f%:z";
+
 /// A single structure that gets constructed by commandline arguments and describes the behavior of the program
 #[derive(Debug, Clone)]
 pub struct Options {
@@ -86,17 +485,190 @@ pub struct Options {
     /// My name
     pub program_name: String,
     /// The hashing algorithm to use
-    pub algorithm: String,
+    pub algorithm: Algorithm,
+    /// Further algorithms that files are hashed with in the same pass, each with its own _algorithm_sum.txt
+    pub extra_algorithms: Vec<Algorithm>,
+    /// Whether or not verify mode uses the algorithm of the _algorithm_sum.txt it finds in a directory
+    pub detect_algorithm: bool,
     /// Whether or not it will be operated on a single folder or every subfolder
     pub subdir_mode: bool,
     /// The mode the program will operate in
     pub mode: Mode,
     /// The level of detail the program will be logging
     pub log_level: LogLevel,
-    /// Maximum number of threads to spawn
-    pub num_threads: usize,
-    /// The folder to operate on
-    pub folder: String,
+    /// Whether or not to print only the files that failed and a summary if anything failed, overrides log_level
+    pub quiet: bool,
+    /// Whether or not update mode prints a line of JSON with path, hash and size to stdout for every hashed file
+    pub emit_jsonl: bool,
+    /// Number of worker threads hashing files, shared by all directories
+    pub threads: Threads,
+    /// Number of directories that are processed at the same time in subdir mode
+    pub parallel_directories: Threads,
+    /// The order in which directories are processed
+    pub schedule: Schedule,
+    /// How symbolic links inside a directory are handled
+    pub symlinks: Symlinks,
+    /// Whether or not hidden files and directories are processed
+    pub hidden: Hidden,
+    /// Whether or not files are found with the walker of the ignore crate, which respects .gitignore files
+    pub respect_gitignore: bool,
+    /// Patterns excluding the files and directories whose names match them, in addition to the .arkhashignore files
+    pub exclude: Vec<String>,
+    /// Whether or not directories whose _algorithm_sum.txt is newer than all of their files are not hashed
+    pub trust_mtime: bool,
+    /// Whether or not hashes are stored in extended attributes of the files instead of the _algorithm_sum.txt
+    pub xattr: bool,
+    /// Whether or not the _algorithm_sum.txt is written compressed with gzip, as _algorithm_sum.txt.gz
+    pub compress: bool,
+    /// Whether or not the checksum of the _algorithm_sum.txt is written to the sidecar _algorithm_sum.txt.chk
+    pub manifest_checksum: bool,
+    /// The secret key the _algorithm_sum.txt is signed with, or the key file generated in keygen mode
+    pub sign_key: Option<PathBuf>,
+    /// The public key the signature of the _algorithm_sum.txt is checked with before it is read
+    pub verify_key: Option<PathBuf>,
+    /// Whether or not verify mode skips files whose extended attribute holds the recorded hash,
+    /// if their size and modification time did not change
+    pub trust_xattr: bool,
+    /// Whether or not update mode records the permissions, owner and group of every file
+    /// and verify mode reports files where they changed. Only supported on unix.
+    pub check_metadata: bool,
+    /// A second copy of the folders, verify mode restores the files that changed or are missing from it
+    pub repair_from: Option<PathBuf>,
+    /// Number of directory levels to descend in subdir mode
+    pub max_depth: usize,
+    /// Whether or not to draw progress bars even if stdout is not a terminal
+    pub force_progress: bool,
+    /// Whether or not to show the overall progress in the title of the terminal window
+    pub title: bool,
+    /// Called for every file update and verify mode hashed or checked, only used by library users
+    pub on_file: Option<FileCallback>,
+    /// Presents the progress of update and verify mode besides the built-in output, only used by library users
+    pub reporter: Option<SharedReporter>,
+    /// The format of the output
+    pub format: OutputFormat,
+    /// How numbers of bytes are printed
+    pub byte_format: ByteFormat,
+    /// File the messages of the loglevel are appended to in addition to stdout, only used by the binary
+    pub log_file: Option<PathBuf>,
+    /// The strftime pattern of the timestamps in front of log lines
+    pub time_format: String,
+    /// Where JSON progress events are written to, independent of the loglevel
+    pub progress_stream: Option<ProgressStream>,
+    /// Whether or not paths and the lines of the _algorithm_sum.txt are terminated by NUL instead of newline
+    pub null_separated: bool,
+    /// The format of the lines written to the _algorithm_sum.txt, every format is read
+    pub manifest_format: ManifestFormat,
+    /// How the paths of the files are written to the _algorithm_sum.txt, both are read
+    pub paths: PathStyle,
+    /// A regex replacing the pattern the lines of an _algorithm_sum.txt are read with,
+    /// see manifest::manifest_pattern
+    pub manifest_pattern: Option<String>,
+    /// Whether or not verify mode processes directories that are already known good or known bad this month
+    pub force: bool,
+    /// Whether or not verify mode only processes the directories in the to_check list of this month
+    pub recheck: bool,
+    /// Whether or not to only print what would be changed, without changing anything
+    pub dry_run: bool,
+    /// Whether or not update mode reports files with identical hashes
+    pub find_duplicates: bool,
+    /// Whether or not check manifest mode removes duplicate lines from the _algorithm_sum.txt files
+    pub dedupe: bool,
+    /// Number of bytes read from a file at a time while it is hashed
+    pub buffer_size: usize,
+    /// Files of at least this many bytes are memory mapped instead of read while they are hashed
+    pub mmap_threshold: Option<u64>,
+    /// Number of bytes of the blocks update mode hashes on their own, None hashes no blocks
+    pub block_size: Option<u64>,
+    /// Which part of the listed files verify mode checks, None checks every file
+    pub sample: Option<Sample>,
+    /// Seed of the random selection of the sample, None picks a random seed
+    pub seed: Option<u64>,
+    /// Whether or not verify mode stops at the first file that failed
+    pub fail_fast: bool,
+    /// Number of bytes all worker threads together read per second at most, None reads as fast as possible
+    pub max_rate: Option<u64>,
+    /// Time verify mode waits for the next file of a directory to finish hashing before giving up on it,
+    /// None waits forever
+    pub timeout: Option<Duration>,
+    /// Time watch mode waits after the last change before it updates the _algorithm_sum.txt files
+    pub debounce: Duration,
+    /// Whether or not update and verify mode read the files and directories to process from stdin
+    pub from_stdin: bool,
+    /// Whether or not to wait for another process holding the lock of the state directory instead of exiting
+    pub wait: bool,
+    /// The directory the known_good and to_check files of verify mode are written to
+    pub state_dir: PathBuf,
+    /// The name of the known_good and to_check lists, {list}, {month} and {year} are replaced
+    pub state_template: String,
+    /// The folders to operate on, filter mode only uses the first one
+    pub folders: Vec<PathBuf>,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            help: false,
+            version_info: false,
+            program_name: "arkhash".to_string(),
+            algorithm: Algorithm::Sha1,
+            detect_algorithm: true,
+            extra_algorithms: Vec::new(),
+            subdir_mode: false,
+            mode: Mode::Filter,
+            log_level: LogLevel::Info,
+            quiet: false,
+            emit_jsonl: false,
+            threads: Threads::Auto,
+            parallel_directories: Threads::Auto,
+            schedule: Schedule::Discovery,
+            symlinks: Symlinks::Skip,
+            hidden: Hidden::Exclude,
+            respect_gitignore: false,
+            exclude: Vec::new(),
+            trust_mtime: false,
+            xattr: false,
+            compress: false,
+            manifest_checksum: false,
+            sign_key: None,
+            verify_key: None,
+            trust_xattr: false,
+            check_metadata: false,
+            repair_from: None,
+            max_depth: 1,
+            force_progress: false,
+            title: false,
+            on_file: None,
+            reporter: None,
+            format: OutputFormat::Text,
+            byte_format: ByteFormat::Binary,
+            log_file: None,
+            time_format: DEFAULT_TIME_FORMAT.to_string(),
+            progress_stream: None,
+            null_separated: false,
+            manifest_format: ManifestFormat::Gnu,
+            paths: PathStyle::Relative,
+            manifest_pattern: None,
+            force: false,
+            recheck: false,
+            dry_run: false,
+            find_duplicates: false,
+            dedupe: false,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            mmap_threshold: None,
+            block_size: None,
+            sample: None,
+            seed: None,
+            fail_fast: false,
+            max_rate: None,
+            timeout: None,
+            debounce: Duration::from_secs(2),
+            from_stdin: false,
+            wait: false,
+            state_dir: PathBuf::from("."),
+            state_template: DEFAULT_STATE_TEMPLATE.to_string(),
+            folders: vec![PathBuf::from(".")],
+        }
+    }
 }
 
 impl Options {
@@ -107,19 +679,15 @@ impl Options {
     /// * `args` - A vec of Strings containing all commandline parameters
     pub fn new(args: Vec<String>) -> Options {
         let mut opts = Options {
-            help: false,
-            version_info: false,
             program_name: args[0].to_string(),
-            algorithm: "sha1".to_string(),
-            subdir_mode: false,
-            mode: Mode::Filter,
-            log_level: LogLevel::Info,
-            num_threads: 0,
-            folder: ".".to_string(),
+            ..Options::default()
         };
 
         // prepare Strings for parsing
         let args = prepare_args(args[1..].to_vec());
+        let mut folders = Vec::new();
+        // replaces the loglevel once every argument is parsed, so the order of --loglevel does not matter
+        let mut verbosity = 0;
 
         // loop through every argument, except the name
         for i in 0..args.len() {
@@ -129,18 +697,57 @@ impl Options {
             if arg.starts_with('-') {
                 match arg.as_ref() {
                     "-a" | "--algo" | "--algorithm" => {
-                        opts.algorithm = args[i + 1].clone().to_lowercase()
+                        opts.detect_algorithm = false;
+                        let mut algorithms = args
+                            .get(i + 1)
+                            .and_then(|names| Algorithm::from_names(names))
+                            .unwrap_or_else(|| {
+                                panic!(
+                                    "Usage: {} -a ALGORITHM[,ALGORITHM...] (available: {})",
+                                    opts.program_name,
+                                    Algorithm::available()
+                                )
+                            });
+                        opts.algorithm = algorithms.remove(0);
+                        opts.extra_algorithms = algorithms;
                     }
                     "-s" | "--subdir" | "--subdirs" | "--subdirectories" => opts.subdir_mode = true,
                     "-u" | "--update" => opts.mode = Mode::Update,
-                    "-v" | "--verify" => opts.mode = Mode::Verify,
+                    "-c" | "--verify" => opts.mode = Mode::Verify,
+                    "--reset" => opts.mode = Mode::Reset,
+                    "--status" => opts.mode = Mode::Status,
+                    "--prune" => opts.mode = Mode::Prune,
+                    "--check-manifest" | "--check_manifest" => opts.mode = Mode::CheckManifest,
+                    "--add" => opts.mode = Mode::Add,
+                    "--remove" => opts.mode = Mode::Remove,
+                    "--diff" => opts.mode = Mode::Diff,
+                    "--compare" => opts.mode = Mode::Compare,
+                    "--watch" => opts.mode = Mode::Watch,
+                    "-" | "--stdin" => opts.mode = Mode::Stdin,
+                    "-n" | "--dry-run" | "--dry_run" => opts.dry_run = true,
+                    "--find-duplicates" | "--find_duplicates" => opts.find_duplicates = true,
+                    "--dedupe" => opts.dedupe = true,
+                    "--fail-fast" | "--fail_fast" => opts.fail_fast = true,
+                    "--from-stdin" | "--from_stdin" => opts.from_stdin = true,
+                    "--wait" => opts.wait = true,
+                    // handled by config::with_config_and_env before the arguments are parsed
+                    "--no-config" => {}
+                    "--respect-gitignore" | "--respect_gitignore" => opts.respect_gitignore = true,
+                    "--trust-mtime" | "--trust_mtime" => opts.trust_mtime = true,
+                    "--xattr" => opts.xattr = true,
+                    "--compress" => opts.compress = true,
+                    "--manifest-checksum" | "--manifest_checksum" => opts.manifest_checksum = true,
+                    "--trust-xattr" | "--trust_xattr" => opts.trust_xattr = true,
+                    "--check-metadata" | "--check_metadata" => opts.check_metadata = true,
+                    "--si" => opts.byte_format = ByteFormat::Si,
+                    "--machine" => opts.byte_format = ByteFormat::Raw,
                     "--loglevel" | "--log_level" | "--log-level" => {
                         opts.log_level = {
                             match args
                                 .get(i + 1)
                                 .unwrap_or_else(|| {
                                     panic!(
-                                        "Usage: {} {} quiet/info/debug",
+                                        "Usage: {} {} quiet/progress/info/debug/trace",
                                         opts.program_name, args[i]
                                     )
                                 })
@@ -150,21 +757,311 @@ impl Options {
                                 "info" | "1" => LogLevel::Info,
                                 "progress" => LogLevel::Progress,
                                 "debug" | "2" => LogLevel::Debug,
+                                "trace" | "3" => LogLevel::Trace,
                                 _ => LogLevel::Info,
                             }
                         }
                     }
-                    "--quiet" => opts.log_level = LogLevel::Quiet,
+                    "--quiet" => opts.quiet = true,
+                    "--emit-jsonl" | "--emit_jsonl" => opts.emit_jsonl = true,
+                    "-v" | "--verbose" => verbosity += 1,
+                    "-q" => verbosity -= 1,
+                    "--force-progress" | "--force_progress" => opts.force_progress = true,
+                    "--title" => opts.title = true,
+                    "-0" | "--null" => opts.null_separated = true,
+                    "--force" => opts.force = true,
+                    "--recheck" => {
+                        opts.mode = Mode::Verify;
+                        opts.recheck = true;
+                    }
+                    "--tag" => opts.manifest_format = ManifestFormat::Bsd,
+                    "--compat-format" | "--compat_format" => {
+                        opts.manifest_format = ManifestFormat::Plain
+                    }
+                    "--progress-fd" | "--progress_fd" => {
+                        opts.progress_stream = Some(ProgressStream::Fd(
+                            args.get(i + 1)
+                                .and_then(|fd| fd.trim().parse().ok())
+                                .unwrap_or_else(|| {
+                                    panic!("Usage: {} --progress-fd FD", opts.program_name)
+                                }),
+                        ))
+                    }
+                    "--progress-file" | "--progress_file" => {
+                        opts.progress_stream = Some(ProgressStream::File(PathBuf::from(
+                            args.get(i + 1).unwrap_or_else(|| {
+                                panic!("Usage: {} --progress-file PATH", opts.program_name)
+                            }),
+                        )))
+                    }
+                    "--format" => {
+                        opts.format = match args.get(i + 1).map(String::as_str) {
+                            Some("text") => OutputFormat::Text,
+                            Some("json") => OutputFormat::Json,
+                            _ => panic!("Usage: {} --format text/json", opts.program_name),
+                        }
+                    }
                     "-T" | "--threads" => {
-                        opts.num_threads = args
+                        opts.threads = args
+                            .get(i + 1)
+                            .and_then(|threads| Threads::from_arg(threads))
+                            .unwrap_or_else(|| {
+                                panic!("Usage: {} -T auto/NUMBER_OF_MAX_THREADS", opts.program_name)
+                            })
+                    }
+                    "-D" | "--parallel-directories" | "--parallel_directories" => {
+                        opts.parallel_directories = args
+                            .get(i + 1)
+                            .and_then(|directories| Threads::from_arg(directories))
+                            .unwrap_or_else(|| {
+                                panic!("Usage: {} -D auto/NUMBER_OF_DIRECTORIES", opts.program_name)
+                            })
+                    }
+                    "--schedule" => {
+                        opts.schedule = match args.get(i + 1).map(String::as_str) {
+                            Some("discovery") => Schedule::Discovery,
+                            Some("size") => Schedule::Size,
+                            _ => panic!("Usage: {} --schedule discovery/size", opts.program_name),
+                        }
+                    }
+                    "--symlinks" => {
+                        opts.symlinks = match args.get(i + 1).map(String::as_str) {
+                            Some("skip") => Symlinks::Skip,
+                            Some("follow") => Symlinks::Follow,
+                            Some("hash-link") | Some("hash_link") => Symlinks::HashLink,
+                            _ => panic!(
+                                "Usage: {} --symlinks skip/follow/hash-link",
+                                opts.program_name
+                            ),
+                        }
+                    }
+                    "--paths" => {
+                        opts.paths = match args.get(i + 1).map(String::as_str) {
+                            Some("relative") => PathStyle::Relative,
+                            Some("absolute") => PathStyle::Absolute,
+                            _ => panic!("Usage: {} --paths relative/absolute", opts.program_name),
+                        }
+                    }
+                    "--hidden" => {
+                        opts.hidden = match args.get(i + 1).map(String::as_str) {
+                            Some("include") => Hidden::Include,
+                            Some("exclude") => Hidden::Exclude,
+                            _ => panic!("Usage: {} --hidden include/exclude", opts.program_name),
+                        }
+                    }
+                    "--exclude" => {
+                        // the last --exclude replaces the patterns of earlier ones, of the environment and the file
+                        opts.exclude = args
+                            .get(i + 1)
+                            .map(|patterns| {
+                                patterns
+                                    .split(',')
+                                    .filter(|pattern| !pattern.is_empty())
+                                    .map(String::from)
+                                    .collect::<Vec<String>>()
+                            })
+                            .filter(|patterns| {
+                                patterns.iter().all(|pattern| {
+                                    !pattern.starts_with('!')
+                                        && !pattern.trim_end_matches('/').contains('/')
+                                })
+                            })
+                            .unwrap_or_else(|| {
+                                panic!(
+                                    "Usage: {} --exclude PATTERN[,PATTERN...] (names without slashes or a leading !)",
+                                    opts.program_name
+                                )
+                            })
+                    }
+                    "--buffer-size" | "--buffer_size" => {
+                        opts.buffer_size = args
+                            .get(i + 1)
+                            .and_then(|size| parse_size(size))
+                            .filter(|&size| size > 0)
+                            .unwrap_or_else(|| {
+                                panic!(
+                                    "Usage: {} --buffer-size SIZE (eg 65536, 64K, 4M)",
+                                    opts.program_name
+                                )
+                            }) as usize
+                    }
+                    "--mmap-threshold" | "--mmap_threshold" => {
+                        opts.mmap_threshold = Some(
+                            args.get(i + 1)
+                                .and_then(|size| parse_size(size))
+                                .unwrap_or_else(|| {
+                                    panic!(
+                                        "Usage: {} --mmap-threshold SIZE (eg 64M, 1G)",
+                                        opts.program_name
+                                    )
+                                }),
+                        )
+                    }
+                    "--block-size" | "--block_size" => {
+                        opts.block_size = Some(
+                            args.get(i + 1)
+                                .and_then(|size| parse_size(size))
+                                .filter(|&size| size > 0)
+                                .unwrap_or_else(|| {
+                                    panic!(
+                                        "Usage: {} --block-size SIZE (eg 64M)",
+                                        opts.program_name
+                                    )
+                                }),
+                        )
+                    }
+                    "--sample" => {
+                        opts.sample = Some(Sample::Percent(
+                            args.get(i + 1)
+                                .and_then(|percent| percent.trim_end_matches('%').parse().ok())
+                                .filter(|&percent: &f64| percent > 0.0 && percent <= 100.0)
+                                .unwrap_or_else(|| {
+                                    panic!(
+                                        "Usage: {} --sample PERCENT (eg 5 or 0.5%)",
+                                        opts.program_name
+                                    )
+                                }),
+                        ))
+                    }
+                    "--sample-count" | "--sample_count" => {
+                        opts.sample = Some(Sample::Count(
+                            args.get(i + 1)
+                                .and_then(|count| count.parse().ok())
+                                .filter(|&count| count > 0)
+                                .unwrap_or_else(|| {
+                                    panic!("Usage: {} --sample-count N", opts.program_name)
+                                }),
+                        ))
+                    }
+                    "--seed" => {
+                        opts.seed = Some(
+                            args.get(i + 1)
+                                .and_then(|seed| seed.parse().ok())
+                                .unwrap_or_else(|| {
+                                    panic!("Usage: {} --seed SEED", opts.program_name)
+                                }),
+                        )
+                    }
+                    "--max-rate" | "--max_rate" => {
+                        opts.max_rate = Some(
+                            args.get(i + 1)
+                                .and_then(|rate| parse_size(rate))
+                                .filter(|&rate| rate > 0)
+                                .unwrap_or_else(|| {
+                                    panic!(
+                                        "Usage: {} --max-rate BYTES_PER_SECOND (eg 50M)",
+                                        opts.program_name
+                                    )
+                                }),
+                        )
+                    }
+                    "--timeout" => {
+                        opts.timeout = Some(Duration::from_secs(
+                            args.get(i + 1)
+                                .and_then(|seconds| seconds.parse().ok())
+                                .filter(|&seconds| seconds > 0)
+                                .unwrap_or_else(|| {
+                                    panic!("Usage: {} --timeout SECONDS", opts.program_name)
+                                }),
+                        ))
+                    }
+                    "--debounce" => {
+                        opts.debounce = Duration::from_secs(
+                            args.get(i + 1)
+                                .and_then(|seconds| seconds.parse().ok())
+                                .unwrap_or_else(|| {
+                                    panic!("Usage: {} --debounce SECONDS", opts.program_name)
+                                }),
+                        )
+                    }
+                    "--manifest-pattern" | "--manifest_pattern" => {
+                        opts.manifest_pattern = Some(
+                            args.get(i + 1)
+                                .unwrap_or_else(|| {
+                                    panic!("Usage: {} --manifest-pattern REGEX", opts.program_name)
+                                })
+                                .clone(),
+                        );
+                        if let Err(e) = super::manifest::manifest_regex_from_opts(&opts) {
+                            panic!(
+                                "Usage: {} --manifest-pattern REGEX ({})",
+                                opts.program_name, e
+                            )
+                        }
+                    }
+                    "--sign" => {
+                        opts.sign_key = Some(PathBuf::from(args.get(i + 1).unwrap_or_else(|| {
+                            panic!("Usage: {} --sign KEYFILE", opts.program_name)
+                        })))
+                    }
+                    "--repair-from" | "--repair_from" => {
+                        opts.repair_from =
+                            Some(PathBuf::from(args.get(i + 1).unwrap_or_else(|| {
+                                panic!("Usage: {} --repair-from DIR", opts.program_name)
+                            })))
+                    }
+                    "--verify-sig" | "--verify_sig" => {
+                        opts.verify_key =
+                            Some(PathBuf::from(args.get(i + 1).unwrap_or_else(|| {
+                                panic!("Usage: {} --verify-sig PUBKEY", opts.program_name)
+                            })))
+                    }
+                    "--keygen" => {
+                        opts.mode = Mode::Keygen;
+                        opts.sign_key = Some(PathBuf::from(args.get(i + 1).unwrap_or_else(|| {
+                            panic!("Usage: {} --keygen KEYFILE", opts.program_name)
+                        })))
+                    }
+                    "--log-file" | "--log_file" => {
+                        opts.log_file = Some(PathBuf::from(args.get(i + 1).unwrap_or_else(|| {
+                            panic!("Usage: {} --log-file PATH", opts.program_name)
+                        })))
+                    }
+                    "--time-format" | "--time_format" => {
+                        let format = args.get(i + 1).unwrap_or_else(|| {
+                            panic!("Usage: {} --time-format FORMAT", opts.program_name)
+                        });
+                        opts.time_format = match format.as_str() {
+                            "rfc3339" => RFC3339_TIME_FORMAT.to_string(),
+                            format if is_valid_time_format(format) => format.to_string(),
+                            _ => panic!(
+                                "Usage: {} --time-format FORMAT (rfc3339 or a strftime pattern)",
+                                opts.program_name
+                            ),
+                        }
+                    }
+                    "--state-dir" | "--state_dir" => {
+                        opts.state_dir = PathBuf::from(args.get(i + 1).unwrap_or_else(|| {
+                            panic!("Usage: {} --state-dir DIRECTORY", opts.program_name)
+                        }))
+                    }
+                    "--no-monthly-state" | "--no_monthly_state" => {
+                        opts.state_template = SINGLE_STATE_TEMPLATE.to_string()
+                    }
+                    "--state-template" | "--state_template" => {
+                        opts.state_template = args
+                            .get(i + 1)
+                            .filter(|template| template.contains("{list}"))
+                            .unwrap_or_else(|| {
+                                panic!(
+                                    "Usage: {} --state-template TEMPLATE (must contain {{list}})",
+                                    opts.program_name
+                                )
+                            })
+                            .to_string()
+                    }
+                    "--max-depth" | "--max_depth" => {
+                        opts.max_depth = args
                             .get(i + 1)
                             .unwrap_or_else(|| {
-                                panic!("Usage: {} -T NUMBER_OF_MAX_THREADS", opts.program_name)
+                                panic!("Usage: {} --max-depth DEPTH", opts.program_name)
                             })
                             .trim()
                             .parse()
-                            .unwrap_or_else(|_| {
-                                panic!("Usage: {} -T NUMBER_OF_MAX_THREADS", opts.program_name)
+                            .ok()
+                            .filter(|&depth| depth > 0)
+                            .unwrap_or_else(|| {
+                                panic!("Usage: {} --max-depth DEPTH", opts.program_name)
                             })
                     }
                     "-h" | "--help" => opts.help = true,
@@ -172,240 +1069,1968 @@ impl Options {
                     _ => opts.help = true,
                 }
             } else {
-                // if a String does not start with - and the String before it is none of the below, it is the folder to operate on
+                // if a String does not start with - and the String before it is none of the below, it is a folder to operate on
                 match args[i - 1].as_ref() {
-                    "--loglevel" | "--log_level" | "--log-level" | "-a" | "--algo"
-                    | "--algorithm" | "-T" | "--threads" => {}
-                    _ => opts.folder = arg.clone(),
+                    "--loglevel"
+                    | "--log_level"
+                    | "--log-level"
+                    | "-a"
+                    | "--algo"
+                    | "--algorithm"
+                    | "-T"
+                    | "--threads"
+                    | "-D"
+                    | "--parallel-directories"
+                    | "--parallel_directories"
+                    | "--schedule"
+                    | "--symlinks"
+                    | "--buffer-size"
+                    | "--buffer_size"
+                    | "--mmap-threshold"
+                    | "--mmap_threshold"
+                    | "--block-size"
+                    | "--block_size"
+                    | "--sample"
+                    | "--sample-count"
+                    | "--sample_count"
+                    | "--seed"
+                    | "--max-rate"
+                    | "--max_rate"
+                    | "--timeout"
+                    | "--debounce"
+                    | "--hidden"
+                    | "--exclude"
+                    | "--paths"
+                    | "--manifest-pattern"
+                    | "--manifest_pattern"
+                    | "--sign"
+                    | "--verify-sig"
+                    | "--verify_sig"
+                    | "--repair-from"
+                    | "--repair_from"
+                    | "--keygen"
+                    | "--log-file"
+                    | "--log_file"
+                    | "--time-format"
+                    | "--time_format"
+                    | "--state-dir"
+                    | "--state_dir"
+                    | "--state-template"
+                    | "--state_template"
+                    | "--max-depth"
+                    | "--max_depth"
+                    | "--format"
+                    | "--progress-fd"
+                    | "--progress_fd"
+                    | "--progress-file"
+                    | "--progress_file" => {}
+                    _ => folders.push(PathBuf::from(arg)),
                 }
             }
         }
 
+        if !folders.is_empty() {
+            opts.folders = folders;
+        }
+        if verbosity != 0 {
+            opts.log_level = LogLevel::from_verbosity(verbosity);
+        }
+
         opts
     }
 
-    /// Indicates that the program is in the debug loglevel
+    /// Returns an OptionsBuilder starting from the default Options
+    pub fn builder() -> OptionsBuilder {
+        OptionsBuilder::default()
+    }
+
+    /// Returns opts.algorithm followed by opts.extra_algorithms
+    pub fn algorithms(&self) -> Vec<Algorithm> {
+        let mut algorithms = vec![self.algorithm];
+        algorithms.extend(&self.extra_algorithms);
+        algorithms
+    }
+
+    /// Returns a copy of the options that only uses one algorithm, eg to read or write its _algorithm_sum.txt
+    ///
+    /// # Arguments
+    ///
+    /// * `algorithm` The algorithm of the copy
+    pub fn with_algorithm(&self, algorithm: Algorithm) -> Options {
+        Options {
+            algorithm,
+            extra_algorithms: Vec::new(),
+            ..self.clone()
+        }
+    }
+
+    /// Indicates that the program is in the quiet loglevel or only prints the files that failed
+    pub fn loglevel_quiet(&self) -> bool {
+        self.quiet || self.log_level == LogLevel::Quiet
+    }
+
+    /// Indicates that the program is in the trace loglevel.
+    /// Always false in JSON format, so stdout stays valid JSON.
+    pub fn loglevel_trace(&self) -> bool {
+        self.format == OutputFormat::Text && !self.quiet && self.log_level == LogLevel::Trace
+    }
+
+    /// Indicates that the program is at least in the debug loglevel.
+    /// Always false in JSON format, so stdout stays valid JSON.
     pub fn loglevel_debug(&self) -> bool {
-        self.log_level == LogLevel::Debug
+        self.format == OutputFormat::Text
+            && !self.quiet
+            && (self.log_level == LogLevel::Debug || self.log_level == LogLevel::Trace)
     }
 
-    /// Indicates that the program is at least in the info loglevel
+    /// Indicates that the program is at least in the info loglevel.
+    /// Always false in JSON format, so stdout stays valid JSON.
     pub fn loglevel_info(&self) -> bool {
-        self.log_level == LogLevel::Debug || self.log_level == LogLevel::Info
+        self.format == OutputFormat::Text
+            && !self.quiet
+            && matches!(
+                self.log_level,
+                LogLevel::Info | LogLevel::Debug | LogLevel::Trace
+            )
     }
 
-    /// Indicates that the program is in the progress loglevel
+    /// Indicates that the program is in the progress loglevel.
+    /// Always false in JSON format, so stdout stays valid JSON.
     pub fn loglevel_progress(&self) -> bool {
-        self.log_level == LogLevel::Progress
+        self.format == OutputFormat::Text && !self.quiet && self.log_level == LogLevel::Progress
     }
 }
 
-/// Prepares a vec of Strings for parsing options
-///
-/// A new vec gets returned that contains more Strings than the original, because two rules get applied:
-/// * If a String starts with a single -, but it has more than 2 characters, the parameters get split
-///   into single Strings with a leading -
-/// * If a String contains a =, the = will get cut and the prefix and suffix will be split into two Strings
-/// This is necessary for the match statement in Options::new to work correctly
+/// Constructs an Options object for library use, validating the settings on build
 ///
-/// # Arguments
+/// ```
+/// use arkhash::{Mode, Options, Threads};
 ///
-/// * `args` A vec of Strings containing all commandline parameters
-fn prepare_args(args: Vec<String>) -> Vec<String> {
-    let mut prepared_args = Vec::with_capacity(args.len());
+/// let opts = Options::builder()
+///     .mode(Mode::Verify)
+///     .algorithm_name("sha256")
+///     .threads(Threads::Fixed(2))
+///     .folder("/mnt/archive")
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct OptionsBuilder {
+    /// The Options under construction
+    opts: Options,
+    /// An algorithm name that is validated on build
+    algorithm_name: Option<String>,
+}
 
-    for arg in args {
-        if !arg.contains('=') {
-            if arg.contains('-') && !arg.contains("--") && arg.len() > 2 {
-                let characters = &arg[1..];
-                for char in characters.chars() {
-                    let single_arg = format!("-{}", char);
-                    prepared_args.push(single_arg);
-                }
-            } else {
-                prepared_args.push(arg);
-            }
-        } else {
-            let position = arg.find('=').unwrap();
-            let prefix = arg[0..position].to_string();
-            let suffix = arg[position + 1..].to_string();
-            prepared_args.push(prefix);
-            prepared_args.push(suffix);
+impl OptionsBuilder {
+    /// Sets the hashing algorithm, verify mode no longer detects it
+    pub fn algorithm(mut self, algorithm: Algorithm) -> OptionsBuilder {
+        self.opts.algorithm = algorithm;
+        self.opts.extra_algorithms = Vec::new();
+        self.opts.detect_algorithm = false;
+        self.algorithm_name = None;
+        self
+    }
+
+    /// Sets the hashing algorithms, files are hashed with all of them in one pass.
+    /// Verify mode no longer detects the algorithm. An empty slice keeps the current algorithm.
+    pub fn algorithms(mut self, algorithms: &[Algorithm]) -> OptionsBuilder {
+        if let Some((&algorithm, extra)) = algorithms.split_first() {
+            self.opts.algorithm = algorithm;
+            self.opts.extra_algorithms = extra.to_vec();
+        }
+        self.opts.detect_algorithm = false;
+        self.algorithm_name = None;
+        self
+    }
+
+    /// Sets the hashing algorithm by its name, eg "sha256", or several separated by commas, eg "md5,sha256".
+    /// Unknown names are rejected by build.
+    /// Verify mode no longer detects the algorithm.
+    pub fn algorithm_name(mut self, name: &str) -> OptionsBuilder {
+        self.opts.detect_algorithm = false;
+        self.algorithm_name = Some(name.to_string());
+        self
+    }
+
+    /// Sets the mode the program will operate in
+    pub fn mode(mut self, mode: Mode) -> OptionsBuilder {
+        self.opts.mode = mode;
+        self
+    }
+
+    /// Sets whether every subdirectory of the folder is processed as a unit
+    pub fn subdir_mode(mut self, subdir_mode: bool) -> OptionsBuilder {
+        self.opts.subdir_mode = subdir_mode;
+        self
+    }
+
+    /// Sets the number of directory levels to descend in subdir mode
+    pub fn max_depth(mut self, max_depth: usize) -> OptionsBuilder {
+        self.opts.max_depth = max_depth;
+        self
+    }
+
+    /// Sets the level of detail the program will be logging
+    pub fn log_level(mut self, log_level: LogLevel) -> OptionsBuilder {
+        self.opts.log_level = log_level;
+        self
+    }
+
+    /// Sets whether or not to print only the files that failed and a summary if anything failed,
+    /// regardless of the loglevel
+    pub fn quiet(mut self, quiet: bool) -> OptionsBuilder {
+        self.opts.quiet = quiet;
+        self
+    }
+
+    /// Sets whether or not update mode prints a line of JSON with path, hash and size to stdout for every hashed file
+    pub fn emit_jsonl(mut self, emit_jsonl: bool) -> OptionsBuilder {
+        self.opts.emit_jsonl = emit_jsonl;
+        self
+    }
+
+    /// Sets the number of worker threads
+    pub fn threads(mut self, threads: Threads) -> OptionsBuilder {
+        self.opts.threads = threads;
+        self
+    }
+
+    /// Sets the number of directories that are processed at the same time in subdir mode
+    pub fn parallel_directories(mut self, parallel_directories: Threads) -> OptionsBuilder {
+        self.opts.parallel_directories = parallel_directories;
+        self
+    }
+
+    /// Sets the order in which directories are processed
+    pub fn schedule(mut self, schedule: Schedule) -> OptionsBuilder {
+        self.opts.schedule = schedule;
+        self
+    }
+
+    /// Sets how symbolic links inside a directory are handled
+    pub fn symlinks(mut self, symlinks: Symlinks) -> OptionsBuilder {
+        self.opts.symlinks = symlinks;
+        self
+    }
+
+    /// Sets whether or not hidden files and directories are processed
+    pub fn hidden(mut self, hidden: Hidden) -> OptionsBuilder {
+        self.opts.hidden = hidden;
+        self
+    }
+
+    /// Sets whether or not files are found with the walker of the ignore crate, which respects .gitignore files
+    pub fn respect_gitignore(mut self, respect_gitignore: bool) -> OptionsBuilder {
+        self.opts.respect_gitignore = respect_gitignore;
+        self
+    }
+
+    /// Sets the patterns excluding the files and directories whose names match them
+    pub fn exclude(mut self, exclude: Vec<String>) -> OptionsBuilder {
+        self.opts.exclude = exclude;
+        self
+    }
+
+    /// Sets whether or not directories whose _algorithm_sum.txt is newer than all of their files are not hashed
+    pub fn trust_mtime(mut self, trust_mtime: bool) -> OptionsBuilder {
+        self.opts.trust_mtime = trust_mtime;
+        self
+    }
+
+    /// Sets whether or not hashes are stored in extended attributes of the files instead of the _algorithm_sum.txt
+    pub fn xattr(mut self, xattr: bool) -> OptionsBuilder {
+        self.opts.xattr = xattr;
+        self
+    }
+
+    /// Sets whether or not the _algorithm_sum.txt is written compressed with gzip, as _algorithm_sum.txt.gz
+    pub fn compress(mut self, compress: bool) -> OptionsBuilder {
+        self.opts.compress = compress;
+        self
+    }
+
+    /// Sets whether or not the checksum of the _algorithm_sum.txt is written to the sidecar _algorithm_sum.txt.chk
+    pub fn manifest_checksum(mut self, manifest_checksum: bool) -> OptionsBuilder {
+        self.opts.manifest_checksum = manifest_checksum;
+        self
+    }
+
+    /// Sets the secret key the _algorithm_sum.txt is signed with
+    pub fn sign_key(mut self, key_file: &str) -> OptionsBuilder {
+        self.opts.sign_key = Some(PathBuf::from(key_file));
+        self
+    }
+
+    /// Sets the public key the signature of the _algorithm_sum.txt is checked with before it is read
+    pub fn verify_key(mut self, key_file: &str) -> OptionsBuilder {
+        self.opts.verify_key = Some(PathBuf::from(key_file));
+        self
+    }
+
+    /// Sets whether or not verify mode skips files whose extended attribute holds the recorded hash,
+    /// if their size and modification time did not change
+    pub fn trust_xattr(mut self, trust_xattr: bool) -> OptionsBuilder {
+        self.opts.trust_xattr = trust_xattr;
+        self
+    }
+
+    /// Sets whether or not update mode records the permissions, owner and group of every file
+    /// and verify mode reports files where they changed
+    pub fn check_metadata(mut self, check_metadata: bool) -> OptionsBuilder {
+        self.opts.check_metadata = check_metadata;
+        self
+    }
+
+    /// Sets the second copy of the folders verify mode restores the files that changed or are missing from
+    pub fn repair_from(mut self, mirror: &str) -> OptionsBuilder {
+        self.opts.repair_from = Some(PathBuf::from(mirror));
+        self
+    }
+
+    /// Sets whether or not to draw progress bars even if stdout is not a terminal
+    pub fn force_progress(mut self, force_progress: bool) -> OptionsBuilder {
+        self.opts.force_progress = force_progress;
+        self
+    }
+
+    /// Sets whether or not to show the overall progress in the title of the terminal window
+    pub fn title(mut self, title: bool) -> OptionsBuilder {
+        self.opts.title = title;
+        self
+    }
+
+    /// Sets a function that is called for every file update and verify mode hashed or checked,
+    /// eg to drive the progress display of a GUI
+    pub fn on_file<F: FnMut(&FileEvent) + Send + 'static>(mut self, on_file: F) -> OptionsBuilder {
+        self.opts.on_file = Some(FileCallback::new(on_file));
+        self
+    }
+
+    /// Sets a reporter that is passed the progress of update and verify mode besides the built-in output,
+    /// set the loglevel to quiet to replace it
+    pub fn reporter<R: Reporter + 'static>(mut self, reporter: R) -> OptionsBuilder {
+        self.opts.reporter = Some(SharedReporter::new(reporter));
+        self
+    }
+
+    /// Sets the format of the output
+    pub fn format(mut self, format: OutputFormat) -> OptionsBuilder {
+        self.opts.format = format;
+        self
+    }
+
+    /// Sets a regex replacing the pattern the lines of an _algorithm_sum.txt are read with.
+    /// It has to contain the named groups hash and path, see manifest::manifest_pattern.
+    pub fn manifest_pattern(mut self, pattern: &str) -> OptionsBuilder {
+        self.opts.manifest_pattern = Some(pattern.to_string());
+        self
+    }
+
+    /// Sets how numbers of bytes are printed
+    pub fn byte_format(mut self, byte_format: ByteFormat) -> OptionsBuilder {
+        self.opts.byte_format = byte_format;
+        self
+    }
+
+    /// Sets where JSON progress events are written to
+    pub fn progress_stream(mut self, progress_stream: Option<ProgressStream>) -> OptionsBuilder {
+        self.opts.progress_stream = progress_stream;
+        self
+    }
+
+    /// Sets whether or not paths and the lines of the _algorithm_sum.txt are terminated by NUL
+    pub fn null_separated(mut self, null_separated: bool) -> OptionsBuilder {
+        self.opts.null_separated = null_separated;
+        self
+    }
+
+    /// Sets the format of the lines written to the _algorithm_sum.txt
+    pub fn manifest_format(mut self, manifest_format: ManifestFormat) -> OptionsBuilder {
+        self.opts.manifest_format = manifest_format;
+        self
+    }
+
+    /// Sets how the paths of the files are written to the _algorithm_sum.txt
+    pub fn paths(mut self, paths: PathStyle) -> OptionsBuilder {
+        self.opts.paths = paths;
+        self
+    }
+
+    /// Sets whether or not verify mode processes directories that are already known good or known bad this month
+    pub fn force(mut self, force: bool) -> OptionsBuilder {
+        self.opts.force = force;
+        self
+    }
+
+    /// Sets whether or not verify mode only processes the directories in the to_check list of this month
+    pub fn recheck(mut self, recheck: bool) -> OptionsBuilder {
+        self.opts.recheck = recheck;
+        self
+    }
+
+    /// Sets whether or not to only print what would be changed, without changing anything
+    pub fn dry_run(mut self, dry_run: bool) -> OptionsBuilder {
+        self.opts.dry_run = dry_run;
+        self
+    }
+
+    /// Sets whether or not update mode reports files with identical hashes
+    pub fn find_duplicates(mut self, find_duplicates: bool) -> OptionsBuilder {
+        self.opts.find_duplicates = find_duplicates;
+        self
+    }
+
+    /// Sets whether or not check manifest mode removes duplicate lines from the _algorithm_sum.txt files
+    pub fn dedupe(mut self, dedupe: bool) -> OptionsBuilder {
+        self.opts.dedupe = dedupe;
+        self
+    }
+
+    /// Sets the number of bytes read from a file at a time while it is hashed
+    pub fn buffer_size(mut self, buffer_size: usize) -> OptionsBuilder {
+        self.opts.buffer_size = buffer_size;
+        self
+    }
+
+    /// Sets the size from which on files are memory mapped instead of read while they are hashed, None never maps files
+    pub fn mmap_threshold(mut self, mmap_threshold: Option<u64>) -> OptionsBuilder {
+        self.opts.mmap_threshold = mmap_threshold;
+        self
+    }
+
+    /// Sets the number of bytes of the blocks update mode hashes on their own, None hashes no blocks
+    pub fn block_size(mut self, block_size: Option<u64>) -> OptionsBuilder {
+        self.opts.block_size = block_size;
+        self
+    }
+
+    /// Sets which part of the listed files verify mode checks, None checks every file
+    pub fn sample(mut self, sample: Option<Sample>) -> OptionsBuilder {
+        self.opts.sample = sample;
+        self
+    }
+
+    /// Sets the seed of the random selection of the sample, None picks a random seed
+    pub fn seed(mut self, seed: Option<u64>) -> OptionsBuilder {
+        self.opts.seed = seed;
+        self
+    }
+
+    /// Sets whether or not verify mode stops at the first file that failed
+    pub fn fail_fast(mut self, fail_fast: bool) -> OptionsBuilder {
+        self.opts.fail_fast = fail_fast;
+        self
+    }
+
+    /// Sets the number of bytes all worker threads together read per second at most, None reads as fast as possible
+    pub fn max_rate(mut self, max_rate: Option<u64>) -> OptionsBuilder {
+        self.opts.max_rate = max_rate;
+        self
+    }
+
+    /// Sets the time verify mode waits for the next file of a directory to finish hashing before giving up on it,
+    /// None waits forever
+    pub fn timeout(mut self, timeout: Option<Duration>) -> OptionsBuilder {
+        self.opts.timeout = timeout;
+        self
+    }
+
+    /// Sets the time watch mode waits after the last change before it updates the _algorithm_sum.txt files
+    pub fn debounce(mut self, debounce: Duration) -> OptionsBuilder {
+        self.opts.debounce = debounce;
+        self
+    }
+
+    /// Sets whether or not to wait for another process holding the lock of the state directory instead of exiting
+    pub fn wait(mut self, wait: bool) -> OptionsBuilder {
+        self.opts.wait = wait;
+        self
+    }
+
+    /// Sets the directory the known_good and to_check files of verify mode are written to
+    pub fn state_dir(mut self, state_dir: &str) -> OptionsBuilder {
+        self.opts.state_dir = PathBuf::from(state_dir);
+        self
+    }
+
+    /// Sets the strftime pattern of the timestamps in front of log lines
+    pub fn time_format(mut self, time_format: &str) -> OptionsBuilder {
+        self.opts.time_format = time_format.to_string();
+        self
+    }
+
+    /// Sets the name of the known_good and to_check lists, {list}, {month} and {year} are replaced
+    pub fn state_template(mut self, state_template: &str) -> OptionsBuilder {
+        self.opts.state_template = state_template.to_string();
+        self
+    }
+
+    /// Sets the folder to operate on
+    pub fn folder(mut self, folder: &str) -> OptionsBuilder {
+        self.opts.folders = vec![PathBuf::from(folder)];
+        self
+    }
+
+    /// Sets the folders to operate on
+    pub fn folders(mut self, folders: &[&str]) -> OptionsBuilder {
+        self.opts.folders = folders.iter().map(PathBuf::from).collect();
+        self
+    }
+
+    /// Validates the settings and returns the Options
+    ///
+    /// # Returns
+    /// An error message describing the first invalid setting
+    pub fn build(self) -> Result<Options, String> {
+        let mut opts = self.opts;
+
+        if let Some(name) = self.algorithm_name {
+            let mut algorithms = Algorithm::from_names(&name).ok_or_else(|| {
+                format!(
+                    "unknown algorithm {} (available: {})",
+                    name,
+                    Algorithm::available()
+                )
+            })?;
+            opts.algorithm = algorithms.remove(0);
+            opts.extra_algorithms = algorithms;
+        }
+        if opts.threads == Threads::Fixed(0) {
+            return Err("the number of threads must be greater than 0".to_string());
+        }
+        if opts.parallel_directories == Threads::Fixed(0) {
+            return Err("the number of parallel directories must be greater than 0".to_string());
+        }
+        match opts.sample {
+            Some(Sample::Percent(percent)) if !(percent > 0.0 && percent <= 100.0) => {
+                return Err("the sample must be more than 0 and at most 100 percent".to_string());
+            }
+            Some(Sample::Count(0)) => {
+                return Err("the sample must contain at least 1 file".to_string());
+            }
+            _ => {}
+        }
+        if opts.max_rate == Some(0) {
+            return Err("the maximum rate must be greater than 0".to_string());
+        }
+        if opts.block_size == Some(0) {
+            return Err("the block size must be greater than 0".to_string());
+        }
+        if opts.buffer_size == 0 {
+            return Err("the buffer size must be greater than 0".to_string());
+        }
+        if opts.max_depth == 0 {
+            return Err("the maximum depth must be greater than 0".to_string());
+        }
+        super::manifest::manifest_regex_from_opts(&opts)?;
+        if opts.folders.is_empty() {
+            return Err("at least one folder is required".to_string());
+        }
+        if opts
+            .folders
+            .iter()
+            .any(|folder| folder.as_os_str().is_empty())
+        {
+            return Err("the folder must not be empty".to_string());
+        }
+        if !opts.state_template.contains("{list}") {
+            return Err("the state template must contain {list}".to_string());
+        }
+
+        Ok(opts)
+    }
+}
+
+/// Prepares a vec of Strings for parsing options
+///
+/// A new vec gets returned that contains more Strings than the original, because two rules get applied:
+/// * If a String starts with a single -, but it has more than 2 characters, the parameters get split
+///   into single Strings with a leading -
+/// * If a String contains a =, the = will get cut and the prefix and suffix will be split into two Strings
+///
+/// This is necessary for the match statement in Options::new to work correctly
+///
+/// # Arguments
+///
+/// * `args` A vec of Strings containing all commandline parameters
+fn prepare_args(args: Vec<String>) -> Vec<String> {
+    let mut prepared_args = Vec::with_capacity(args.len());
+
+    for arg in args {
+        if !arg.contains('=') {
+            if arg.starts_with('-') && !arg.starts_with("--") && arg.len() > 2 {
+                let characters = &arg[1..];
+                for char in characters.chars() {
+                    let single_arg = format!("-{}", char);
+                    prepared_args.push(single_arg);
+                }
+            } else {
+                prepared_args.push(arg);
+            }
+        } else {
+            let position = arg.find('=').unwrap();
+            let prefix = arg[0..position].to_string();
+            let suffix = arg[position + 1..].to_string();
+            prepared_args.push(prefix);
+            prepared_args.push(suffix);
         }
     }
 
     prepared_args
 }
 
-/// Creates a regex that identifies hashsum and path from a hashsum line.
+/// Returns the pattern of regex_from_opts, which identifies hashsum and path from a hashsum line in GNU or BSD style.
+/// Leading whitespace and the binary mode indicator `*` of the coreutils before the path are accepted,
+/// size and modification time of a line in the extended format are skipped.
+/// Further spaces after the two characters separating hashsum and path belong to the path, like in the coreutils.
+///
+/// # Arguments
+/// * `opts` Options object that contains the desired algorithm
+pub fn hashline_pattern(opts: &Options) -> String {
+    format!(
+        r"^\s*(?P<escaped>\\)?(?:(?P<hash>[[:xdigit:]]{{{length}}})(?: \d+ \d+\.\d{{9}})?\s[\s*](?P<path>.*)|{tag} \((?P<bsd_path>.*)\) = (?P<bsd_hash>[[:xdigit:]]{{{length}}}))$",
+        length = opts.algorithm.hash_length(),
+        tag = regex::escape(opts.algorithm.tag())
+    )
+}
+
+/// Creates a regex that identifies hashsum and path from a hashsum line in GNU or BSD style,
+/// see hashline_pattern. The path of an escaped line still has to be unescaped, see parse_hashline.
+///
+/// # Arguments
+/// * `opts` Options object that contains the desired algorithm
+pub fn regex_from_opts(opts: &Options) -> Result<Regex, String> {
+    Regex::new(&hashline_pattern(opts)).map_err(|e| format!("invalid hashline pattern: {}", e))
+}
+
+/// Splits a hashsum line into hashsum and path, unescaping the path if necessary.
+/// Returns None if the line is not a valid hashsum line.
+///
+/// # Arguments
+/// * `re` A regex created by regex_from_opts
+/// * `line` The hashsum line, with or without the trailing newline or CRLF
+pub fn parse_hashline(re: &Regex, line: &str) -> Option<(String, PathBuf)> {
+    let line = line.trim_end_matches('\n').trim_end_matches('\r');
+    let captures = re.captures(line)?;
+    // GNU style lines fill the groups hash and path, BSD style lines bsd_hash and bsd_path
+    let (hash, path) = match (captures.name("hash"), captures.name("path")) {
+        (Some(hash), Some(path)) => (hash.as_str(), path.as_str()),
+        _ => (
+            captures.name("bsd_hash")?.as_str(),
+            captures.name("bsd_path")?.as_str(),
+        ),
+    };
+
+    let path = if captures.name("escaped").is_some() {
+        super::manifest::unescape_path(path)?
+    } else {
+        PathBuf::from(path)
+    };
+
+    Some((hash.to_string(), path))
+}
+
+/// Imitate _algorithm_sum with the path of a file to get the hashsum.
+/// Symbolic links are handled according to opts.symlinks, skipped links cannot be hashed.
+///
+/// # Arguments
+///
+/// * `path` Path to the file to be hashed, relative to the workdir
+/// * `workdir` Path to the wanted working directory
+/// * `opts` A reference to an Options object containing information about the program behavior
+///
+/// # Returns
+///
+/// A String containing the output of the _algorithm_sum command.
+pub fn calculate_hash(
+    path: PathBuf,
+    workdir: &Path,
+    opts: &super::util::Options,
+) -> Result<String, HashError> {
+    calculate_hash_with_blocks(path, workdir, None, opts).map(|(hashline, _)| hashline)
+}
+
+/// Like calculate_hash, but hashes the blocks of the file in the same pass if a block size is given.
+/// The data hashed for a symbolic link with opts.symlinks set to hash-link is hashed in blocks as well.
+///
+/// # Arguments
+///
+/// * `path` Path to the file to be hashed, relative to the workdir
+/// * `workdir` Path to the wanted working directory
+/// * `block_size` Number of bytes of every block, None hashes no blocks
+/// * `opts` A reference to an Options object containing information about the program behavior
+///
+/// # Returns
+///
+/// The output of the _algorithm_sum command and the hashes of the blocks, empty without a block size.
+pub fn calculate_hash_with_blocks(
+    path: PathBuf,
+    workdir: &Path,
+    block_size: Option<u64>,
+    opts: &super::util::Options,
+) -> Result<(String, Vec<String>), HashError> {
+    calculate_hashes(path, workdir, &[opts.algorithm], block_size, opts)
+        .map(|(mut hashlines, blocks)| (hashlines.remove(0), blocks))
+}
+
+/// Like calculate_hash_with_blocks, but feeds the contents of the file to the hashers of several algorithms,
+/// so the file is only read once. The blocks are hashed with the first algorithm.
+///
+/// # Arguments
+///
+/// * `path` Path to the file to be hashed, relative to the workdir
+/// * `workdir` Path to the wanted working directory
+/// * `algorithms` The algorithms to hash the file with, at least one
+/// * `block_size` Number of bytes of every block, None hashes no blocks
+/// * `opts` A reference to an Options object containing information about the program behavior
+///
+/// # Returns
+///
+/// The output of the _algorithm_sum command of every algorithm in the given order and the hashes of the blocks.
+pub fn calculate_hashes(
+    path: PathBuf,
+    workdir: &Path,
+    algorithms: &[Algorithm],
+    block_size: Option<u64>,
+    opts: &super::util::Options,
+) -> Result<(Vec<String>, Vec<String>), HashError> {
+    if opts.loglevel_trace() {
+        trace!("{}: hashing {}", workdir.display(), path.display());
+    }
+    let mut hashers: Vec<Box<dyn Hasher>> = algorithms
+        .iter()
+        .map(|algorithm| algorithm.hasher())
+        .collect();
+    let mut blocks = block_size.map(|block_size| BlockHasher::new(algorithms[0], block_size));
+
+    // opening the file follows symbolic links, so they are only looked for if they are not followed
+    let link_target = match opts.symlinks {
+        Symlinks::Follow => None,
+        Symlinks::Skip | Symlinks::HashLink => fs::read_link(workdir.join(&path)).ok(),
+    };
+
+    if let Some(link_target) = link_target {
+        if opts.symlinks == Symlinks::Skip {
+            let source =
+                io::Error::other("is a symbolic link, choose how to handle it with --symlinks");
+            return Err(HashError { source, path });
+        }
+        let target = path_to_bytes(&link_target);
+        for hasher in hashers.iter_mut() {
+            hasher.update(&target);
+        }
+        if let Some(blocks) = blocks.as_mut() {
+            blocks.input(&target);
+        }
+    } else {
+        let result = fs::File::open(workdir.join(&path))
+            .and_then(|mut file| hash_file(&mut file, &mut hashers, blocks.as_mut(), opts));
+        if let Err(e) = result {
+            return Err(HashError { source: e, path });
+        }
+    }
+
+    let hashlines = hashers
+        .into_iter()
+        .zip(algorithms)
+        .map(|(hasher, &algorithm)| {
+            let entry = super::manifest::ManifestEntry {
+                hash: hasher.finalize(),
+                path: path.clone(),
+                stamp: None,
+                metadata: None,
+            };
+            match opts.manifest_format {
+                ManifestFormat::Gnu | ManifestFormat::Plain => entry.to_line(),
+                ManifestFormat::Bsd => entry.to_tagged_line(algorithm),
+            }
+        })
+        .collect();
+
+    Ok((hashlines, blocks.map_or_else(Vec::new, BlockHasher::finish)))
+}
+
+thread_local! {
+    /// The buffer calculate_hash reads files into, every worker thread reuses its buffer for all of its files
+    static READ_BUFFER: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+    /// The counter of HashTask::read_bytes of the task the worker thread is hashing, see count_read_bytes
+    static READ_BYTES: RefCell<Option<Arc<AtomicU64>>> = const { RefCell::new(None) };
+}
+
+/// Feeds the contents of a file to hashers, reading opts.buffer_size bytes at a time.
+/// Files of at least opts.mmap_threshold bytes are memory mapped and fed to the hashers at once instead.
+/// Reading is throttled to opts.max_rate.
+///
+/// # Arguments
+///
+/// * `file` The opened file
+/// * `hashers` The hashers of the algorithms
+/// * `blocks` The hasher of the blocks of the file, if they are hashed as well
+/// * `opts` Options object containing the buffer size and the threshold for memory mapping
+fn hash_file(
+    file: &mut fs::File,
+    hashers: &mut [Box<dyn Hasher>],
+    mut blocks: Option<&mut BlockHasher>,
+    opts: &Options,
+) -> io::Result<()> {
+    let mut feed = |data: &[u8]| {
+        for hasher in hashers.iter_mut() {
+            hasher.update(data);
+        }
+        if let Some(blocks) = blocks.as_mut() {
+            blocks.input(data);
+        }
+        count_read_bytes(data.len());
+    };
+
+    if let Some(threshold) = opts.mmap_threshold {
+        let len = file.metadata()?.len();
+        // empty files can not be mapped on every platform
+        if len >= threshold && len > 0 {
+            // the mapping must not outlive the file, which is opened by this function's caller.
+            // A file truncated by another process while it is mapped makes the program crash with SIGBUS.
+            let map = unsafe { memmap2::Mmap::map(&*file)? };
+            // the pages of the mapping are only read once they are hashed, so they are throttled
+            // and counted for the timeout in chunks
+            let chunk_size = if opts.max_rate.is_some() || opts.timeout.is_some() {
+                opts.buffer_size
+            } else {
+                map.len()
+            };
+            for chunk in map.chunks(chunk_size) {
+                throttle(chunk.len(), opts);
+                feed(chunk);
+            }
+            return Ok(());
+        }
+    }
+
+    read_chunks(file, &mut feed, opts)
+}
+
+/// Adds bytes that were read to the counter of the task the worker thread is hashing, if it has one
+///
+/// # Arguments
+///
+/// * `len` Number of bytes that were read
+fn count_read_bytes(len: usize) {
+    READ_BYTES.with(|read_bytes| {
+        if let Some(ref read_bytes) = *read_bytes.borrow() {
+            read_bytes.fetch_add(len as u64, Ordering::Relaxed);
+        }
+    });
+}
+
+/// Feeds everything a reader returns to a closure, reading opts.buffer_size bytes at a time.
+/// Reading is throttled to opts.max_rate.
+///
+/// # Arguments
+///
+/// * `reader` The reader, eg an opened file
+/// * `feed` The closure receiving the chunks that were read
+/// * `opts` Options object containing the buffer size
+fn read_chunks(
+    reader: &mut dyn Read,
+    feed: &mut dyn FnMut(&[u8]),
+    opts: &Options,
+) -> io::Result<()> {
+    READ_BUFFER.with(|buffer| {
+        let mut buffer = buffer.borrow_mut();
+        buffer.resize(opts.buffer_size, 0);
+
+        loop {
+            match reader.read(&mut buffer) {
+                Ok(0) => return Ok(()),
+                Ok(n) => {
+                    throttle(n, opts);
+                    feed(&buffer[..n]);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    })
+}
+
+/// Imitate _algorithm_sum with the path - to hash everything a reader returns, eg stdin.
+///
+/// # Arguments
+///
+/// * `reader` The reader whose data is hashed until it ends
+/// * `opts` A reference to an Options object containing the algorithms, the manifest format and the buffer size
+///
+/// # Returns
+///
+/// The output of the _algorithm_sum command of every algorithm of Options::algorithms, the path is written as -.
+pub fn calculate_hashes_of_reader(
+    reader: &mut dyn Read,
+    opts: &Options,
+) -> io::Result<Vec<String>> {
+    let algorithms = opts.algorithms();
+    let mut hashers: Vec<Box<dyn Hasher>> = algorithms
+        .iter()
+        .map(|algorithm| algorithm.hasher())
+        .collect();
+    read_chunks(
+        reader,
+        &mut |data| {
+            for hasher in hashers.iter_mut() {
+                hasher.update(data);
+            }
+        },
+        opts,
+    )?;
+
+    Ok(hashers
+        .into_iter()
+        .zip(algorithms)
+        .map(|(hasher, algorithm)| {
+            let entry = super::manifest::ManifestEntry {
+                hash: hasher.finalize(),
+                path: PathBuf::from("-"),
+                stamp: None,
+                metadata: None,
+            };
+            match opts.manifest_format {
+                ManifestFormat::Gnu | ManifestFormat::Plain => entry.to_plain_line(),
+                ManifestFormat::Bsd => entry.to_tagged_line(algorithm),
+            }
+        })
+        .collect())
+}
+
+/// Bytes that may be read without waiting, shared by all worker threads, see throttle
+struct TokenBucket {
+    /// Number of bytes that may be read, negative if threads are waiting for bytes they already took
+    tokens: f64,
+    /// The moment the bucket was last refilled
+    refilled: Instant,
+}
+
+/// The bucket limiting the reads of all worker threads to opts.max_rate
+static RATE_LIMIT: Mutex<Option<TokenBucket>> = Mutex::new(None);
+
+/// Takes bytes from the bucket shared by all worker threads and waits until they are paid for.
+/// The bucket refills at opts.max_rate bytes per second and holds at most one second worth of bytes.
+/// A thread that takes more bytes than the bucket holds waits for them, threads taking bytes after it wait longer.
+/// Does nothing without opts.max_rate.
+///
+/// # Arguments
+///
+/// * `bytes` Number of bytes that were or are about to be read
+/// * `opts` Options object containing the maximum rate
+fn throttle(bytes: usize, opts: &Options) {
+    let rate = match opts.max_rate {
+        Some(rate) => rate as f64,
+        None => return,
+    };
+
+    let wait = {
+        let mut bucket = RATE_LIMIT.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let bucket = bucket.get_or_insert(TokenBucket {
+            tokens: rate,
+            refilled: now,
+        });
+        let refill = now.duration_since(bucket.refilled).as_secs_f64() * rate;
+        bucket.tokens = (bucket.tokens + refill).min(rate) - bytes as f64;
+        bucket.refilled = now;
+
+        if bucket.tokens < 0.0 {
+            Duration::from_secs_f64(-bucket.tokens / rate)
+        } else {
+            Duration::ZERO
+        }
+    };
+
+    if !wait.is_zero() {
+        std::thread::sleep(wait);
+    }
+}
+
+/// Parses a number of bytes with an optional binary suffix, eg 4096, 64K, 4M or 1G
+///
+/// # Arguments
+///
+/// * `size` The number of bytes as given on the commandline
+pub fn parse_size(size: &str) -> Option<u64> {
+    let size = size.trim();
+    let (number, factor) = match size.chars().last()?.to_ascii_uppercase() {
+        'K' => (&size[..size.len() - 1], 1024),
+        'M' => (&size[..size.len() - 1], 1024 * 1024),
+        'G' => (&size[..size.len() - 1], 1024 * 1024 * 1024),
+        _ => (size, 1),
+    };
+
+    number.parse::<u64>().ok()?.checked_mul(factor)
+}
+
+/// Logs the number of worker threads at info level, and whether it was resolved from the available parallelism.
+///
+/// # Arguments
+///
+/// * `num_threads` Number of worker threads that are started
+/// * `opts` Options object containing the loglevel and the requested number of threads
+pub fn log_thread_count(num_threads: usize, opts: &Options) {
+    if opts.loglevel_info() {
+        match opts.threads {
+            Threads::Auto => info!(
+                "Using {} worker threads (auto, the available parallelism)",
+                num_threads
+            ),
+            Threads::Fixed(_) => info!("Using {} worker threads", num_threads),
+        }
+    }
+}
+
+/// Processes the given directories on a pool of producer threads and waits for them to finish.
+/// Every producer takes the next directory from the iterator once it finished its current one,
+/// so directories are processed while later ones are still being discovered.
+/// Stops taking directories once the program is interrupted or stopped.
+///
+/// # Arguments
+///
+/// * `directories` The directories to process, together with any data the process function needs per directory
+/// * `num_producers` Number of producer threads to start
+/// * `process` Function that processes a single directory
+///
+/// # Returns
+/// The results of all directories, in no particular order
+pub fn execute_producers<I, R, F>(directories: I, num_producers: usize, process: F) -> Vec<R>
+where
+    I: IntoIterator,
+    I::IntoIter: Send,
+    I::Item: Send,
+    R: Send,
+    F: Fn(I::Item) -> R + Sync + Send,
+{
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(num_producers)
+        .build()
+        .unwrap();
+
+    // after an interrupt, no further directories are discovered or started
+    let directories = directories.into_iter().take_while(|_| !stopping());
+    pool.install(|| {
+        directories
+            .par_bridge()
+            .filter(|_| !stopping())
+            .map(process)
+            .collect()
+    })
+}
+
+/// Starts a number of worker threads ready for hashing files.
+/// Once the program is interrupted or stopped, the remaining HashTasks are dropped without hashing them.
+///
+/// # Arguments
+///
+/// * `num_threads` Number of worker threads to start
+/// * `q` Reference to the Injector that will carry the HashTask objects
+/// * `produced_finished` Reference to a central boolean that indicates that no new HashTasks will be pushed into q
+/// * `worker_handles` A mutable reference to a vector of thread handles, in which the handles to the worker threads will be stored
+pub fn execute_workers(
+    num_threads: usize,
+    q: Arc<Injector<super::util::HashTask>>,
+    producer_finished: Arc<AtomicBool>,
+    worker_handles: &mut Vec<JoinHandle<()>>,
+) {
+    for _ in 0..num_threads {
+        let myq = Arc::clone(&q);
+        let myp = Arc::clone(&producer_finished);
+
+        let handle = std::thread::spawn(move || loop {
+            let task = myq.steal();
+
+            match task {
+                Steal::Success(task) => {
+                    // dropping the task closes its result channel, so the producer stops waiting for it
+                    let cancelled = task
+                        .cancelled
+                        .as_ref()
+                        .is_some_and(|cancelled| cancelled.load(Ordering::Relaxed));
+                    if stopping() || cancelled {
+                        continue;
+                    }
+                    let block_size = task.blocks.as_ref().map(|request| request.block_size);
+                    let path = task.path.clone();
+                    READ_BYTES
+                        .with(|read_bytes| *read_bytes.borrow_mut() = task.read_bytes.clone());
+                    match calculate_hashes(
+                        task.path,
+                        &task.workdir,
+                        &task.opts.algorithms(),
+                        block_size,
+                        &task.opts,
+                    ) {
+                        Ok((hashlines, hashes)) => {
+                            // the hashes of the blocks arrive before the hashlines they belong to
+                            if let Some(request) = task.blocks {
+                                let hashes = BlockHashes {
+                                    path,
+                                    block_size: request.block_size,
+                                    hashes,
+                                };
+                                // the producer is gone if it gave up waiting, see HashTask::cancelled
+                                let _ = request.result_chan.send(hashes);
+                            }
+                            let _ = task.result_chan.send(Ok((hashlines, task.cmp)));
+                        }
+                        Err(e) => {
+                            let _ = task.result_chan.send(Err(e));
+                        }
+                    };
+                }
+                Steal::Retry => {
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                }
+                Steal::Empty => {
+                    if myp.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                }
+            }
+        });
+
+        worker_handles.push(handle);
+    }
+}
+
+/// Returns whether or not output may be colored.
+/// Colors are disabled if stdout is not a terminal or the NO_COLOR environment variable is set to a non-empty value.
+pub fn colors_enabled() -> bool {
+    let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    !no_color && io::stdout().is_terminal()
+}
+
+/// Indicates that Ctrl-C was pressed, see install_interrupt_handler
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Returns whether or not the program was interrupted by Ctrl-C.
+/// Files that are being hashed are finished, but no further files or directories are started.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Indicates that a file failed in verify mode with opts.fail_fast set, see stop
+static STOPPED: AtomicBool = AtomicBool::new(false);
+
+/// Stops the program at the first failure. Like after Ctrl-C, the files that are being hashed are finished,
+/// but no further files or directories are started. Unlike Ctrl-C, the program exits like after a complete run.
+/// Returns false if the program was already stopped.
+pub fn stop() -> bool {
+    !STOPPED.swap(true, Ordering::SeqCst)
+}
+
+/// Returns whether or not the program was stopped at the first failure
+pub fn stopped() -> bool {
+    STOPPED.load(Ordering::SeqCst)
+}
+
+/// Clears a previous stop, so a run of a library user is not stopped by a failure of an earlier run
+pub fn reset_stopped() {
+    STOPPED.store(false, Ordering::SeqCst);
+}
+
+/// Returns whether or not no further files and directories are started,
+/// because the program was interrupted by Ctrl-C or stopped at the first failure
+pub fn stopping() -> bool {
+    interrupted() || stopped()
+}
+
+/// Installs a handler for Ctrl-C.
+/// The first Ctrl-C lets the worker threads stop after the files they are hashing,
+/// so the results of every finished directory are still recorded.
+/// The second Ctrl-C restores the terminal and exits immediately.
+///
+/// # Arguments
+///
+/// * `opts` Options object containing the loglevel
+pub fn install_interrupt_handler(opts: &Options) {
+    let quiet = opts.loglevel_quiet();
+
+    let result = ctrlc::set_handler(move || {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            restore_terminal();
+            println!();
+            std::process::exit(130);
+        }
+
+        if !quiet {
+            eprintln!("Interrupted, finishing the files that are being hashed. Press Ctrl-C again to exit immediately.");
+        }
+    });
+
+    if let Err(e) = result {
+        if opts.loglevel_debug() {
+            debug!("Could not install Ctrl-C handler: {}", e);
+        }
+    }
+}
+
+/// Enables echo and shows the cursor again, after they were changed for drawing progress bars.
+/// Restores the title of the terminal window if it showed the progress.
+pub fn restore_terminal() {
+    terminal_echo();
+    super::progress::restore_title();
+    let stdout = console::Term::stdout();
+    if stdout.is_term() {
+        let _ = stdout.show_cursor();
+    }
+}
+
+/// Disables echo on terminal. Does nothing if stdin is not a terminal.
+#[cfg(unix)]
+pub fn terminal_noecho() {
+    if !io::stdin().is_terminal() {
+        return;
+    }
+
+    if let Ok(mut termios_noecho) = termios::Termios::from_fd(0) {
+        termios_noecho.c_lflag &= !termios::ECHO;
+        let _ = termios::tcsetattr(0, termios::TCSANOW, &termios_noecho);
+    }
+}
+
+/// Disables echo on terminal. Does nothing if stdin is not a terminal.
+#[cfg(windows)]
+pub fn terminal_noecho() {
+    if !io::stdin().is_terminal() {
+        return;
+    }
+
+    use self::winapi::shared::minwindef::LPDWORD;
+    use self::winapi::um::consoleapi::{GetConsoleMode, SetConsoleMode};
+    use self::winapi::um::processenv::GetStdHandle;
+    use self::winapi::um::winbase::STD_INPUT_HANDLE;
+    use self::winapi::um::wincon::ENABLE_ECHO_INPUT;
+
+    let handle = unsafe { GetStdHandle(STD_INPUT_HANDLE) };
+
+    let mut mode = 0;
+    // unsafe { GetConsoleMode(handle, &mut mode as LPDWORD) };
+    unsafe { GetConsoleMode(handle, &mut mode as LPDWORD) };
+    unsafe { SetConsoleMode(handle, mode & (!ENABLE_ECHO_INPUT)) };
+}
+
+/// Enables echo on terminal. Does nothing if stdin is not a terminal.
+#[cfg(unix)]
+pub fn terminal_echo() {
+    if !io::stdin().is_terminal() {
+        return;
+    }
+
+    if let Ok(mut termios_echo) = termios::Termios::from_fd(0) {
+        termios_echo.c_lflag |= termios::ECHO;
+        let _ = termios::tcsetattr(0, termios::TCSANOW, &termios_echo);
+    }
+}
+
+/// Enables echo on terminal. Does nothing if stdin is not a terminal.
+#[cfg(windows)]
+pub fn terminal_echo() {
+    if !io::stdin().is_terminal() {
+        return;
+    }
+
+    use self::winapi::shared::minwindef::LPDWORD;
+    use self::winapi::um::consoleapi::{GetConsoleMode, SetConsoleMode};
+    use self::winapi::um::processenv::GetStdHandle;
+    use self::winapi::um::winbase::STD_INPUT_HANDLE;
+    use self::winapi::um::wincon::ENABLE_ECHO_INPUT;
+
+    let handle = unsafe { GetStdHandle(STD_INPUT_HANDLE) };
+
+    let mut mode = 0;
+    unsafe { GetConsoleMode(handle, &mut mode as LPDWORD) };
+    unsafe { SetConsoleMode(handle, mode | ENABLE_ECHO_INPUT) };
+}
+
+/// Reads a list of paths separated by newlines, or by NUL if null_separated is set, as written by find or git ls-files.
+/// Empty lines are skipped, a carriage return at the end of a line is removed.
+///
+/// # Arguments
+///
+/// * `reader` The reader the list is read from, eg stdin
+/// * `null_separated` Whether the paths are separated by NUL instead of newline
+pub fn read_path_list<R: BufRead>(reader: R, null_separated: bool) -> io::Result<Vec<PathBuf>> {
+    let separator = if null_separated { b'\0' } else { b'\n' };
+    let mut paths = Vec::new();
+
+    for line in reader.split(separator) {
+        let mut line = line?;
+        if !null_separated && line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        if !line.is_empty() {
+            paths.push(path_from_bytes(&line));
+        }
+    }
+
+    Ok(paths)
+}
+
+/// Replaces a file atomically: the contents are written to a temporary file next to it,
+/// which is flushed, synced to disk and only then renamed over the file.
+/// If anything fails, the temporary file is removed and the file is left untouched.
+///
+/// # Arguments
+///
+/// * `path` Path to the file that is replaced
+/// * `temp_path` Path to the temporary file, in the same directory
+/// * `write` Writes the contents to the temporary file
+pub fn write_atomically<F>(path: &Path, temp_path: &Path, write: F) -> io::Result<()>
+where
+    F: FnOnce(&mut io::BufWriter<fs::File>) -> io::Result<()>,
+{
+    let result = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(temp_path)
+        .and_then(|file| {
+            let mut file = io::BufWriter::new(file);
+            write(&mut file)?;
+            let file = file.into_inner().map_err(|e| e.into_error())?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(temp_path, path));
+
+    if result.is_err() {
+        let _ = fs::remove_file(temp_path);
+    }
+    result
+}
+
+/// Read paths line by line from a file and return them in a Vector
+///
+/// # Arguments
+///
+/// * `filepath` Path to the file to be read
+pub fn read_paths_from_file<P: AsRef<Path>>(filepath: P) -> Vec<PathBuf> {
+    let mut vec = Vec::new();
+
+    let file = OpenOptions::new().read(true).open(filepath);
+    if let Ok(file) = file {
+        let reader = BufReader::new(file);
+        for mut line in reader.split(b'\n').filter_map(Result::ok) {
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            vec.push(path_from_bytes(&line));
+        }
+    }
+
+    vec
+}
+
+/// Normalizes a path without looking at the filesystem, so symbolic links are not resolved.
+/// `.` components and duplicate separators are removed and `..` removes the component before it.
+/// Relative paths start with ./ like the paths of an _algorithm_sum.txt, eg foo//../bar/./baz.txt becomes ./bar/baz.txt.
+/// `..` components at the start of a relative path are kept, at the start of an absolute path they are dropped.
 ///
 /// # Arguments
-/// * `opts` Options object that contains the desired algorithm
-pub fn regex_from_opts(opts: &Options) -> Result<Regex, &'static str> {
-    match opts.algorithm.as_ref() {
-        "sha1" => Ok(Regex::new(r"([[:xdigit:]]{40})\s\s(.*)$").unwrap()),
-        "md5" => Ok(Regex::new(r"([[:xdigit:]]{32})\s\s(.*)$").unwrap()),
-        "sha224" => Ok(Regex::new(r"([[:xdigit:]]{56})\s\s(.*)$").unwrap()),
-        "sha256" => Ok(Regex::new(r"([[:xdigit:]]{64})\s\s(.*)$").unwrap()),
-        "sha384" => Ok(Regex::new(r"([[:xdigit:]]{96})\s\s(.*)$").unwrap()),
-        "sha512" => Ok(Regex::new(r"([[:xdigit:]]{128})\s\s(.*)$").unwrap()),
-        _ => Err("Could not recognize hashing algorithm"),
+///
+/// * `path` The path to be normalized
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut root = PathBuf::new();
+    let mut parts = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => root.push(component.as_os_str()),
+            Component::CurDir => {}
+            Component::ParentDir => match parts.last() {
+                Some(&Component::Normal(_)) => {
+                    parts.pop();
+                }
+                _ if root.as_os_str().is_empty() => parts.push(component),
+                _ => {}
+            },
+            Component::Normal(_) => parts.push(component),
+        }
     }
+
+    let mut normalized = if root.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        root
+    };
+    normalized.extend(parts);
+    normalized
 }
 
-/// Imitate _algorithm_sum with the path of a file to get the hashsum.
+/// Returns the current local time formatted for the start of a log line, see Options::time_format
 ///
 /// # Arguments
 ///
-/// * `path` Path to the file to be hashed, relative to the workdir
-/// * `workdir` Path to the wanted working directory
-/// * `opts` A reference to an Options object containing information about the program behavior
+/// * `opts` Options object containing the strftime pattern
+pub fn timestamp(opts: &Options) -> String {
+    chrono::Local::now().format(&opts.time_format).to_string()
+}
+
+/// Indicates that a strftime pattern only consists of known specifiers, formatting it cannot fail
 ///
-/// # Returns
+/// # Arguments
 ///
-/// A String containing the output of the _algorithm_sum command.
-pub fn calculate_hash(
-    path: String,
-    workdir: &PathBuf,
-    opts: &super::util::Options,
-) -> Result<String, HashError> {
-    let file = fs::File::open(&format!("{}/{}", workdir.to_str().unwrap(), path));
-    const BUFFER_SIZE: usize = 1024;
-    let mut buffer = [0; BUFFER_SIZE];
-
-    let mut hasher = match opts.algorithm.as_ref() {
-        "sha1" => Box::new(Sha1::new()) as Box<dyn DynDigest>,
-        "md5" => Box::new(Md5::new()) as Box<dyn DynDigest>,
-        "sha224" => Box::new(Sha224::new()) as Box<dyn DynDigest>,
-        "sha256" => Box::new(Sha256::new()) as Box<dyn DynDigest>,
-        "sha384" => Box::new(Sha384::new()) as Box<dyn DynDigest>,
-        "sha512" => Box::new(Sha512::new()) as Box<dyn DynDigest>,
-        _ => panic!("Algorithm not recognized"),
+/// * `format` The strftime pattern
+pub fn is_valid_time_format(format: &str) -> bool {
+    chrono::format::StrftimeItems::new(format).all(|item| item != chrono::format::Item::Error)
+}
+
+/// Returns a path with `/` as separator, as it is written to the _algorithm_sum.txt,
+/// so the file can be read on every platform. Paths on unix are returned unchanged.
+///
+/// # Arguments
+///
+/// * `path` The path to be converted
+#[cfg(unix)]
+pub fn portable_path(path: &Path) -> Cow<'_, Path> {
+    Cow::Borrowed(path)
+}
+
+/// Returns a path with `/` as separator, as it is written to the _algorithm_sum.txt,
+/// so the file can be read on every platform. Backslashes, the separator of Windows, are replaced.
+///
+/// # Arguments
+///
+/// * `path` The path to be converted
+#[cfg(windows)]
+pub fn portable_path(path: &Path) -> Cow<'_, Path> {
+    match path.to_string_lossy() {
+        Cow::Borrowed(text) if !text.contains('\\') => Cow::Borrowed(path),
+        text => Cow::Owned(PathBuf::from(text.replace('\\', "/"))),
+    }
+}
+
+/// Returns the bytes of a path, as they are stored in files written by this program
+///
+/// # Arguments
+///
+/// * `path` The path to be converted
+#[cfg(unix)]
+pub fn path_to_bytes(path: &Path) -> Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt;
+
+    Cow::Borrowed(path.as_os_str().as_bytes())
+}
+
+/// Returns the bytes of a path, as they are stored in files written by this program
+///
+/// # Arguments
+///
+/// * `path` The path to be converted
+#[cfg(windows)]
+pub fn path_to_bytes(path: &Path) -> Cow<'_, [u8]> {
+    match path.to_string_lossy() {
+        Cow::Borrowed(path) => Cow::Borrowed(path.as_bytes()),
+        Cow::Owned(path) => Cow::Owned(path.into_bytes()),
+    }
+}
+
+/// Creates a path from bytes that were returned by path_to_bytes
+///
+/// # Arguments
+///
+/// * `bytes` The bytes of the path
+#[cfg(unix)]
+pub fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    PathBuf::from(OsStr::from_bytes(bytes))
+}
+
+/// Creates a path from bytes that were returned by path_to_bytes
+///
+/// # Arguments
+///
+/// * `bytes` The bytes of the path
+#[cfg(windows)]
+pub fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// Returns true if a path given to update or verify mode is processed as a single file,
+/// which is every path that is not a directory, even if it does not exist
+///
+/// # Arguments
+///
+/// * `path` The path as it was given
+pub fn is_single_file(path: &Path) -> bool {
+    !path.is_dir()
+}
+
+/// Orders the directories that are going to be processed according to opts.schedule.
+/// Only the size schedule needs to know every directory up front, otherwise they are passed on as they are found.
+///
+/// # Arguments
+///
+/// * `directories` The directories in the order they are found
+/// * `opts` Options object containing the schedule
+pub fn schedule_directories<'a, I>(
+    directories: I,
+    opts: &Options,
+) -> Box<dyn Iterator<Item = PathBuf> + Send + 'a>
+where
+    I: Iterator<Item = PathBuf> + Send + 'a,
+{
+    if opts.schedule == Schedule::Size {
+        let mut directories: Vec<PathBuf> = directories.collect();
+        directories.sort_by_cached_key(|directory| Reverse(directory_size(directory, opts)));
+        Box::new(directories.into_iter())
+    } else {
+        Box::new(directories)
+    }
+}
+
+/// Returns the number of bytes of all files in a directory and its subdirectories
+///
+/// # Arguments
+///
+/// * `directory` Path to the directory
+/// * `opts` Options object describing which files inside the directory are processed
+fn directory_size(directory: &Path, opts: &Options) -> u64 {
+    walk_files(directory, false, opts)
+        .filter_map(|file| fs::metadata(file).ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// Indicates that the _algorithm_sum.txt of a directory was written after every file and subdirectory below it
+/// was modified, so the directory presumably did not change since. Modification times can be set at will,
+/// so this is no proof. Files removed from the directory itself are not noticed either,
+/// since writing the _algorithm_sum.txt changes the modification time of the directory.
+/// Returns false if there is no _algorithm_sum.txt or a modification time can not be read.
+///
+/// # Arguments
+///
+/// * `workdir` Path to the directory
+/// * `opts` Options object containing the algorithm and describing which files inside the directory are processed
+pub fn manifest_is_newest(workdir: &Path, opts: &Options) -> bool {
+    let manifest_path = match super::manifest::Manifest::find(workdir, opts.algorithm) {
+        Some(path) => path,
+        None => return false,
+    };
+    let written = match fs::metadata(manifest_path).and_then(|metadata| metadata.modified()) {
+        Ok(written) => written,
+        Err(_) => return false,
     };
 
-    match file {
-        Err(e) => {
-            return Err(HashError {
-                source: e,
-                path: path,
-            })
+    let mut directories = HashSet::new();
+    for file in walk_files(workdir, true, opts) {
+        if super::manifest::Manifest::is_own_file(&file) {
+            continue;
+        }
+
+        // with hash-link, a changed link target is noticed by the modification time of the link itself
+        let metadata = if opts.symlinks == Symlinks::HashLink {
+            fs::symlink_metadata(workdir.join(&file))
+        } else {
+            fs::metadata(workdir.join(&file))
+        };
+        if !metadata
+            .and_then(|metadata| metadata.modified())
+            .is_ok_and(|modified| modified < written)
+        {
+            return false;
         }
-        Ok(mut file) => loop {
-            let n = file.read(&mut buffer).unwrap();
-            hasher.input(&buffer[0..n]);
 
-            if n == 0 || n < BUFFER_SIZE {
+        // adding, removing or renaming a file changes the modification time of its directory
+        for directory in file.ancestors().skip(1) {
+            if directory == Path::new(".") || !directories.insert(directory.to_path_buf()) {
                 break;
             }
-        },
+            let modified =
+                fs::metadata(workdir.join(directory)).and_then(|metadata| metadata.modified());
+            if !modified.is_ok_and(|modified| modified < written) {
+                return false;
+            }
+        }
     }
 
-    Ok(format!("{}  {}\n", hex::encode(hasher.result()), path))
+    true
 }
 
-/// Starts a number of worker threads ready for hashing files.
+/// Returns the paths to all files in a directory and its subdirectories, sorted like DirWalker sorts them.
+/// With opts.respect_gitignore, the files are found by the parallel walker of the ignore crate,
+/// which skips the files excluded by .gitignore and .arkhashignore files, the global gitignore
+/// and .git/info/exclude. Otherwise the files are returned by a DirWalker.
+/// Either way, the files and directories excluded by opts.exclude are skipped.
 ///
 /// # Arguments
 ///
-/// * `num_threads` Number of worker threads to start
-/// * `q` Reference to the Injector that will carry the HashTask objects
-/// * `produced_finished` Reference to a central boolean that indicates that no new HashTasks will be pushed into q
-/// * `worker_handles` A mutable reference to a vector of thread handles, in which the handles to the worker threads will be stored
-pub fn execute_workers(
-    num_threads: usize,
-    q: Arc<Injector<super::util::HashTask>>,
-    producer_finished: Arc<AtomicBool>,
-    worker_handles: &mut Vec<JoinHandle<()>>,
-) {
-    for _ in 0..num_threads {
-        let myq = Arc::clone(&q);
-        let myp = Arc::clone(&producer_finished);
-
-        let handle = std::thread::spawn(move || loop {
-            let task = myq.steal();
+/// * `directory` Path to the directory that should be scanned
+/// * `strip_start_directory` Whether or not the directory is replaced by `.` in the returned paths
+/// * `opts` Options object describing which files inside the directory are processed
+pub fn walk_files(
+    directory: &Path,
+    strip_start_directory: bool,
+    opts: &Options,
+) -> Box<dyn Iterator<Item = PathBuf>> {
+    if !opts.respect_gitignore {
+        return Box::new(DirWalker::new(
+            directory,
+            strip_start_directory,
+            opts.symlinks,
+            opts.hidden,
+            &opts.exclude,
+        ));
+    }
 
-            match task {
-                Steal::Success(task) => {
-                    let hashline = calculate_hash(task.path, &task.workdir, &task.opts);
-                    match hashline {
-                        Ok(hashline) => task.result_chan.send(Ok((hashline, task.cmp))).unwrap(),
-                        Err(e) => task.result_chan.send(Err(e)).unwrap(),
-                    };
-                }
-                Steal::Retry => {
-                    std::thread::sleep(std::time::Duration::from_millis(10));
-                }
-                Steal::Empty => {
-                    if myp.load(Ordering::Relaxed) {
-                        break;
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let symlinks = opts.symlinks;
+    let excludes = Ignores::excludes(&opts.exclude);
+    ignore::WalkBuilder::new(directory)
+        .hidden(opts.hidden == Hidden::Exclude)
+        .follow_links(symlinks == Symlinks::Follow)
+        // archives are rarely git repositories, their .gitignore files apply nonetheless
+        .require_git(false)
+        .add_custom_ignore_filename(super::ignore::IGNORE_FILE_NAME)
+        .filter_entry(move |entry| {
+            let is_dir = entry
+                .file_type()
+                .is_some_and(|file_type| file_type.is_dir());
+            entry.depth() == 0 || !excludes.is_excluded(entry.path(), is_dir)
+        })
+        .threads(opts.threads.count())
+        .build_parallel()
+        .run(|| {
+            let sender = sender.clone();
+            Box::new(move |entry| {
+                if let Ok(entry) = entry {
+                    // with follow_links, the file type is the one of the target of a link
+                    let is_file = entry.file_type().is_some_and(|file_type| {
+                        file_type.is_file()
+                            || (file_type.is_symlink() && symlinks == Symlinks::HashLink)
+                    });
+                    if is_file {
+                        sender.send(entry.into_path()).unwrap();
                     }
-                    std::thread::sleep(std::time::Duration::from_millis(10));
                 }
-            }
+                ignore::WalkState::Continue
+            })
         });
+    drop(sender);
+
+    let mut files: Vec<PathBuf> = receiver
+        .into_iter()
+        .map(|path| match path.strip_prefix(directory) {
+            Ok(relative) if strip_start_directory => Path::new(".").join(relative),
+            _ => path,
+        })
+        .collect();
+    files.sort();
+    Box::new(files.into_iter())
+}
 
-        worker_handles.push(handle);
+/// Returns the directories that are processed as a unit in subdir mode.
+/// Descends max_depth levels below folder and returns every directory on the deepest level,
+/// as well as directories above it that do not contain any further directories.
+/// Symbolic links to directories are not followed.
+/// Hidden directories are skipped together with everything below them unless they are included,
+/// as well as directories excluded by a .arkhashignore.
+/// The directories are read lazily while the returned iterator is advanced.
+///
+/// # Arguments
+///
+/// * `folder` Path to the directory whose subdirectories should be found
+/// * `max_depth` Number of levels to descend, 1 only returns the immediate subdirectories
+/// * `hidden` Whether or not hidden directories are returned and descended into
+/// * `to_ignore` Directories that are skipped together with everything below them
+/// * `exclude` Patterns excluding the directories whose names match them, see Options::exclude
+pub fn find_subdirectories<P: AsRef<Path>>(
+    folder: P,
+    max_depth: usize,
+    hidden: Hidden,
+    to_ignore: Vec<PathBuf>,
+    exclude: &[String],
+) -> Subdirectories {
+    let mut pending = VecDeque::new();
+    let ignores = Ignores::for_directory(folder.as_ref()).excluding(exclude);
+    pending.push_back((folder.as_ref().to_path_buf(), 0, ignores));
+
+    Subdirectories {
+        pending,
+        max_depth,
+        hidden,
+        to_ignore,
     }
 }
 
-/// Disables echo on terminal
-#[cfg(unix)]
-pub fn terminal_noecho() {
-    let mut termios_noecho = termios::Termios::from_fd(0).unwrap();
-    termios_noecho.c_lflag &= !termios::ECHO;
-    termios::tcsetattr(0, termios::TCSANOW, &termios_noecho).unwrap();
+/// An Iterator over the directories that are processed as a unit in subdir mode, see find_subdirectories
+///
+/// The directories are visited level by level, so directories on upper levels are returned first.
+pub struct Subdirectories {
+    /// The directories that still have to be visited, together with their depth below the folder
+    /// and the .arkhashignore files that apply to the directories above them
+    pending: VecDeque<(PathBuf, usize, Ignores)>,
+    /// Number of levels to descend
+    max_depth: usize,
+    /// Whether or not hidden directories are returned and descended into
+    hidden: Hidden,
+    /// Directories that are skipped together with everything below them
+    to_ignore: Vec<PathBuf>,
 }
 
-/// Disables echo on terminal
-#[cfg(windows)]
-pub fn terminal_noecho() {
-    use self::winapi::shared::minwindef::LPDWORD;
-    use self::winapi::um::consoleapi::{GetConsoleMode, SetConsoleMode};
-    use self::winapi::um::processenv::GetStdHandle;
-    use self::winapi::um::winbase::STD_INPUT_HANDLE;
-    use self::winapi::um::wincon::ENABLE_ECHO_INPUT;
+impl Iterator for Subdirectories {
+    type Item = PathBuf;
 
-    let handle = unsafe { GetStdHandle(STD_INPUT_HANDLE) };
+    fn next(&mut self) -> Option<PathBuf> {
+        while let Some((directory, depth, ignores)) = self.pending.pop_front() {
+            if depth == self.max_depth {
+                return Some(directory);
+            }
 
-    let mut mode = 0;
-    // unsafe { GetConsoleMode(handle, &mut mode as LPDWORD) };
-    unsafe { GetConsoleMode(handle, &mut mode as LPDWORD) };
-    unsafe { SetConsoleMode(handle, mode & (!ENABLE_ECHO_INPUT)) };
+            let ignores = ignores.enter(&directory);
+            let (children, _) =
+                child_directories(&directory, self.hidden, &self.to_ignore, &ignores);
+
+            if depth > 0 && children.is_empty() {
+                return Some(directory);
+            }
+            self.pending.extend(
+                children
+                    .into_iter()
+                    .map(|child| (child, depth + 1, ignores.clone())),
+            );
+        }
+
+        None
+    }
 }
 
-/// Read paths line by line from a file and return them in a Vector
+/// Returns the subdirectories of a directory that are descended into in subdir mode, see find_subdirectories
 ///
 /// # Arguments
 ///
-/// * `filepath` Path to the file to be read
-pub fn read_paths_from_file(filepath: &str) -> Vec<PathBuf> {
-    let mut vec = Vec::new();
+/// * `directory` Path to the directory
+/// * `hidden` Whether or not hidden directories are returned
+/// * `to_ignore` Directories that are skipped
+/// * `ignores` The .arkhashignore files that apply to the entries of the directory
+///
+/// # Returns
+/// The subdirectories and the number of files in the directory itself that would be hashed,
+/// which are left out if the directory is not processed as a unit.
+fn child_directories(
+    directory: &Path,
+    hidden: Hidden,
+    to_ignore: &[PathBuf],
+    ignores: &Ignores,
+) -> (Vec<PathBuf>, usize) {
+    let mut children = Vec::new();
+    let mut files = 0;
+    if let Ok(dir_entries) = fs::read_dir(directory) {
+        for entry in dir_entries.filter_map(Result::ok) {
+            if hidden.excludes(&entry.path()) {
+                continue;
+            }
+            // the file type of a DirEntry does not traverse symbolic links
+            // and most filesystems return it without reading the metadata of every entry
+            if let Ok(file_type) = entry.file_type() {
+                if file_type.is_dir() {
+                    if !to_ignore.contains(&entry.path())
+                        && !ignores.is_ignored(&entry.path(), true)
+                    {
+                        children.push(entry.path());
+                    }
+                } else if file_type.is_file()
+                    && !ignores.is_ignored(&entry.path(), false)
+                    && !super::manifest::Manifest::is_own_file(
+                        &Path::new(".").join(entry.file_name()),
+                    )
+                {
+                    files += 1;
+                }
+            }
+        }
+    }
+    (children, files)
+}
 
-    let file = OpenOptions::new().read(true).open(filepath);
-    if let Ok(file) = file {
-        let reader = BufReader::new(file);
-        for line in reader.lines() {
-            if let Ok(line) = line {
-                vec.push(PathBuf::from(line));
+/// Finds the directories that are processed as a unit in subdir mode like find_subdirectories,
+/// but reads the directories on opts.threads threads in the background, so the first directories
+/// can be processed while the others are still being found.
+/// Only the directories accept returns true for are returned, it is called on the discovery threads as well.
+/// The directories are returned in the order they are found, which may differ between runs.
+/// No further directories are read once the returned iterator is dropped or the program is stopping.
+/// The files of a directory between a folder and the deepest level are not processed, unless in loglevel quiet
+/// a warning is emitted for every such directory that contains files.
+///
+/// # Arguments
+///
+/// * `folders` Paths to the directories whose subdirectories should be found,
+///   each with the directories below it that are skipped together with everything below them
+/// * `opts` Options object containing the maximum depth, the policy for hidden directories and the number of threads
+/// * `accept` Function deciding whether a directory that was found is returned
+pub fn discover_subdirectories<F>(
+    folders: Vec<(PathBuf, Vec<PathBuf>)>,
+    opts: &Options,
+    accept: F,
+) -> std::sync::mpsc::IntoIter<PathBuf>
+where
+    F: Fn(&Path) -> bool + Send + Sync + 'static,
+{
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let discovery = Discovery {
+        max_depth: opts.max_depth,
+        hidden: opts.hidden,
+        warn: !opts.loglevel_quiet(),
+        accept,
+        sender,
+        abandoned: AtomicBool::new(false),
+    };
+    let num_threads = opts.threads.count();
+    let exclude = opts.exclude.clone();
+
+    std::thread::spawn(move || {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .build()
+            .unwrap();
+        pool.scope(|scope| {
+            for (folder, to_ignore) in &folders {
+                let ignores = Ignores::for_directory(folder).excluding(&exclude);
+                let discovery = &discovery;
+                scope.spawn(move |scope| {
+                    discovery.visit(scope, folder.clone(), 0, ignores, to_ignore)
+                });
             }
+        });
+    });
+
+    receiver.into_iter()
+}
+
+/// The state shared by the threads of discover_subdirectories
+struct Discovery<F> {
+    /// Number of levels to descend
+    max_depth: usize,
+    /// Whether or not hidden directories are returned and descended into
+    hidden: Hidden,
+    /// Whether or not a warning is emitted for the files left out above the deepest level
+    warn: bool,
+    /// Decides whether a directory that was found is returned
+    accept: F,
+    /// Passes the directories that were found to the returned iterator
+    sender: std::sync::mpsc::Sender<PathBuf>,
+    /// Indicates that the returned iterator was dropped
+    abandoned: AtomicBool,
+}
+
+impl<F: Fn(&Path) -> bool + Sync> Discovery<F> {
+    /// Returns a directory if it is processed as a unit, otherwise reads it and visits its subdirectories
+    /// on the threads of the scope
+    ///
+    /// # Arguments
+    ///
+    /// * `scope` The scope the subdirectories are visited in
+    /// * `directory` Path to the directory
+    /// * `depth` Number of levels the directory is below its folder
+    /// * `ignores` The .arkhashignore files that apply to the directories above the directory
+    /// * `to_ignore` Directories that are skipped together with everything below them
+    fn visit<'s>(
+        &'s self,
+        scope: &rayon::Scope<'s>,
+        directory: PathBuf,
+        depth: usize,
+        ignores: Ignores,
+        to_ignore: &'s [PathBuf],
+    ) {
+        if stopping() || self.abandoned.load(Ordering::SeqCst) {
+            return;
+        }
+        if depth == self.max_depth {
+            return self.found(directory);
+        }
+
+        let ignores = ignores.enter(&directory);
+        let (children, files) = child_directories(&directory, self.hidden, to_ignore, &ignores);
+        if depth > 0 && children.is_empty() {
+            return self.found(directory);
+        }
+        // a directory between the folder and the deepest level is no unit, its own files are not processed
+        if depth > 0 && files > 0 && self.warn {
+            warn!(
+                "{}: {} files are not processed, they are above the deepest level of --max-depth {}",
+                directory.display(),
+                files,
+                self.max_depth
+            );
+        }
+        for child in children {
+            let ignores = ignores.clone();
+            scope.spawn(move |scope| self.visit(scope, child, depth + 1, ignores, to_ignore));
         }
     }
 
-    vec
+    /// Passes a directory that is processed as a unit to the returned iterator if it is accepted
+    ///
+    /// # Arguments
+    ///
+    /// * `directory` Path to the directory
+    fn found(&self, directory: PathBuf) {
+        if (self.accept)(&directory) && self.sender.send(directory).is_err() {
+            self.abandoned.store(true, Ordering::SeqCst);
+        }
+    }
 }
 
-/// An Object that returns Paths to all the files in all folders recursively (like find)
+/// An Object that returns Paths to all the files in all folders recursively (like find).
+/// The paths are returned sorted like Path::cmp sorts them, independent of the filesystem.
 ///
 /// DirWalker implements Iterator and Read for this behavior
 pub struct DirWalker {
-    /// A Buffer for the currently known files
-    current_files: Vec<PathBuf>,
-    /// A Buffer for the directories that have to be scanned recursively
-    current_directories: Vec<PathBuf>,
+    /// A stack of the files and directories that were found but not visited yet, the next one on top.
+    /// Directories are marked with true and scanned recursively once they are taken from the stack.
+    pending: Vec<(PathBuf, bool)>,
     /// A Buffer for the filepath that was only partially read
-    unfinished_read: String,
-    /// Whether or not the first directory should be stripped from the filepath
-    subdir_mode: bool,
+    unfinished_read: Vec<u8>,
+    /// The directory the walk started in
+    start_directory: PathBuf,
+    /// Whether or not the start directory should be stripped from the filepath
+    strip_start_directory: bool,
+    /// How symbolic links are handled
+    symlinks: Symlinks,
+    /// Whether or not hidden files and directories are returned and descended into
+    hidden: Hidden,
+    /// The directories that were already scanned when following symbolic links, to detect loops
+    visited: HashSet<DirectoryId>,
+    /// The .arkhashignore files that apply to the entries of the directory scanned last
+    ignores: Ignores,
+}
+
+/// Identifies a directory regardless of the path it was reached through
+#[cfg(unix)]
+type DirectoryId = (u64, u64);
+
+/// Identifies a directory regardless of the path it was reached through
+#[cfg(windows)]
+type DirectoryId = PathBuf;
+
+/// Returns the device and inode number of a directory
+///
+/// # Arguments
+///
+/// * `directory` Path to the directory
+#[cfg(unix)]
+fn directory_id(directory: &Path) -> Option<DirectoryId> {
+    use std::os::unix::fs::MetadataExt;
+
+    fs::metadata(directory)
+        .ok()
+        .map(|metadata| (metadata.dev(), metadata.ino()))
+}
+
+/// Returns the canonical path of a directory
+///
+/// # Arguments
+///
+/// * `directory` Path to the directory
+#[cfg(windows)]
+fn directory_id(directory: &Path) -> Option<DirectoryId> {
+    fs::canonicalize(directory).ok()
 }
 
 impl DirWalker {
@@ -414,95 +3039,118 @@ impl DirWalker {
     /// # Arguments
     ///
     /// * `start_directory` Path to the directory that should be scanned
-    /// * `subdir_mode` Whether or not the first directory should be stripped from the filepath
-    pub fn new(start_directory: &PathBuf, subdir_mode: bool) -> DirWalker {
+    /// * `strip_start_directory` Whether or not the start directory should be stripped from the filepath
+    /// * `symlinks` How symbolic links are handled, with HashLink they are returned like files
+    /// * `hidden` Whether or not hidden files and directories are returned and descended into,
+    ///   the start directory is scanned even if it is hidden
+    ///
+    /// * `exclude` Patterns excluding the files and directories whose names match them, see Options::exclude
+    ///
+    /// Files and directories excluded by a .arkhashignore in the start directory, below or above it are skipped.
+    /// Like a hidden one, the start directory is scanned even if it is excluded.
+    pub fn new(
+        start_directory: &Path,
+        strip_start_directory: bool,
+        symlinks: Symlinks,
+        hidden: Hidden,
+        exclude: &[String],
+    ) -> DirWalker {
         let mut dirwalker = DirWalker {
-            current_files: Vec::new(),
-            current_directories: Vec::new(),
-            unfinished_read: String::new(),
-            subdir_mode,
+            pending: Vec::new(),
+            unfinished_read: Vec::new(),
+            start_directory: start_directory.to_path_buf(),
+            strip_start_directory,
+            symlinks,
+            hidden,
+            visited: HashSet::new(),
+            ignores: Ignores::for_directory(start_directory).excluding(exclude),
         };
 
-        dirwalker.populate_with_dir(&start_directory);
+        dirwalker.populate_with_dir(start_directory);
 
         dirwalker
     }
 
-    /// Update the DirWalker object by adding all subdirectories and files of directory to the queue
+    /// Update the DirWalker object by adding all subdirectories and files of directory to the stack.
+    /// They are sorted by name, so the paths are returned in the order of Path::cmp on every system.
     ///
     /// # Arguments
     ///
     /// * `directory` Path to the directory that is going to be scanned
-    fn populate_with_dir(&mut self, directory: &PathBuf) {
+    fn populate_with_dir(&mut self, directory: &Path) {
+        // a directory reached a second time through a symbolic link is skipped, which also breaks loops
+        if self.symlinks == Symlinks::Follow {
+            if let Some(id) = directory_id(directory) {
+                if !self.visited.insert(id) {
+                    return;
+                }
+            }
+        }
+
+        // the directories are scanned depth first, so the ignore files of the directories above are kept
+        self.ignores = self.ignores.enter(directory);
         let dir_entries = fs::read_dir(directory);
 
         if let Ok(dir_entries) = dir_entries {
-            let mut files = Vec::new();
-            let mut dirs = Vec::new();
+            let mut entries = Vec::new();
 
             for entry in dir_entries {
                 let entry = entry.unwrap();
-                let metadata = entry.metadata().unwrap();
-
-                if metadata.is_dir() {
-                    dirs.push(entry.path());
+                // hidden directories are never queued, so nothing below them is scanned
+                if self.hidden.excludes(&entry.path()) {
+                    continue;
+                }
+                let mut metadata = entry.metadata().unwrap();
+
+                if metadata.file_type().is_symlink() {
+                    match self.symlinks {
+                        Symlinks::Skip => continue,
+                        Symlinks::HashLink => {
+                            if !self.ignores.is_ignored(&entry.path(), false) {
+                                entries.push((entry.path(), false));
+                            }
+                            continue;
+                        }
+                        // broken links are skipped
+                        Symlinks::Follow => match fs::metadata(entry.path()) {
+                            Ok(target) => metadata = target,
+                            Err(_) => continue,
+                        },
+                    }
                 }
-                if metadata.is_file() {
-                    files.push(entry.path());
+
+                if (metadata.is_dir() || metadata.is_file())
+                    && !self.ignores.is_ignored(&entry.path(), metadata.is_dir())
+                {
+                    entries.push((entry.path(), metadata.is_dir()));
                 }
             }
 
-            self.current_directories.append(&mut dirs);
-            self.current_files.append(&mut files);
+            // read_dir returns the entries in an order that depends on the filesystem,
+            // the smallest name has to end up on top of the stack
+            entries.sort_by(|a, b| b.0.cmp(&a.0));
+            self.pending.append(&mut entries);
         }
     }
-
-    /// Return the position of the first directory seperator in a str containing a path
-    ///
-    /// # Arguments
-    ///
-    /// * `path_string` String containing the path to be searched
-    #[cfg(unix)]
-    fn find_dir_seperator_position(path_string: &str) -> usize {
-        path_string.find('/').unwrap()
-    }
-
-    /// Return the position of the first directory seperator in a str containing a path
-    ///
-    /// # Arguments
-    ///
-    /// * `path_string` String containing the path to be searched
-    #[cfg(windows)]
-    fn find_dir_seperator_position(path_string: &str) -> usize {
-        path_string.find('\\').unwrap()
-    }
 }
 
 impl Iterator for DirWalker {
     type Item = PathBuf;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if !self.current_files.is_empty() {
-            let filepath = self.current_files.pop().unwrap();
-
-            if self.subdir_mode {
-                let path_string = filepath.to_string_lossy().to_string();
-                let path_string = &path_string[2..];
-                let position = DirWalker::find_dir_seperator_position(path_string);
-                let path_string = format!(".{}", path_string[position..].to_string());
-                let filepath = PathBuf::from(path_string);
-                return Some(filepath);
+        while let Some((path, is_dir)) = self.pending.pop() {
+            if is_dir {
+                self.populate_with_dir(&path);
+                continue;
             }
 
-            return Some(filepath);
-        }
-
-        if !self.current_directories.is_empty() {
-            let dirpath = self.current_directories.pop().unwrap();
-
-            self.populate_with_dir(&dirpath);
+            if self.strip_start_directory {
+                if let Ok(relative) = path.strip_prefix(&self.start_directory) {
+                    return Some(Path::new(".").join(relative));
+                }
+            }
 
-            return self.next();
+            return Some(path);
         }
 
         None
@@ -511,47 +3159,20 @@ impl Iterator for DirWalker {
 
 impl Read for DirWalker {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
-        let mut i = 0;
-
-        if !self.unfinished_read.is_empty() {
-            loop {
-                if i >= buf.len() || i >= self.unfinished_read.len() {
-                    break;
+        if self.unfinished_read.is_empty() {
+            match self.next() {
+                None => return Ok(0),
+                Some(path) => {
+                    self.unfinished_read = path_to_bytes(&path).into_owned();
+                    self.unfinished_read.push(b'\n');
                 }
-
-                buf[i] = self.unfinished_read.as_bytes()[i];
-                i += 1;
-            }
-
-            if i < self.unfinished_read.len() {
-                self.unfinished_read = self.unfinished_read[0..i].to_string();
             }
-
-            return Ok(i);
         }
 
-        let path: Option<PathBuf> = self.next();
-
-        match path {
-            None => Ok(0),
-            Some(path) => {
-                let path_str = format!("{}\n", path.to_str().unwrap());
-
-                loop {
-                    if i >= buf.len() || i >= path_str.len() {
-                        break;
-                    }
-
-                    buf[i] = path_str.as_bytes()[i];
-                    i += 1;
-                }
+        let n = buf.len().min(self.unfinished_read.len());
+        buf[..n].copy_from_slice(&self.unfinished_read[..n]);
+        self.unfinished_read.drain(..n);
 
-                if i < path_str.len() {
-                    self.unfinished_read = path_str[0..i].to_string();
-                }
-
-                Ok(i)
-            }
-        }
+        Ok(n)
     }
 }
@@ -2,252 +2,910 @@
 
 extern crate chrono;
 extern crate crossbeam_deque;
-extern crate num_cpus;
+extern crate log;
+extern crate rand;
 extern crate regex;
+extern crate serde_json;
 
-use std::borrow::Borrow;
+use std::collections::{HashMap, HashSet};
 use std::fs::{self, OpenOptions};
-use std::io::{self, BufRead, BufReader, Write};
-use std::path::PathBuf;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
 use self::chrono::{DateTime, Datelike};
+use self::log::{debug, error, info};
+use self::regex::Regex;
 
 use self::crossbeam_deque::Injector;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+
+use super::blocks::{BlockHashes, BlockManifest, BlockRequest, ChangedBlocks};
+use super::ignore::IgnoreCache;
+use super::manifest::{FileMetadata, FileStamp, Manifest, ManifestEntry};
+use super::progress::{self, FileOutcome, Printer, ProgressEvent, Throughput};
+
+use self::rand::rngs::StdRng;
+use self::rand::SeedableRng;
+use super::util::{ByteFormat, HashError, HashResult, Symlinks};
+
+/// Time waited for the next file before the bytes read of a directory are looked at again, see next_result
+const ACTIVITY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The result of verifying a single directory
+#[derive(Debug, Clone, Default)]
+pub struct DirectoryReport {
+    /// Path to the verified directory
+    pub path: PathBuf,
+    /// Paths to files that changed unexpectedly
+    pub failed: Vec<PathBuf>,
+    /// Paths to files that are listed in the _algorithm_sum.txt, but do not exist
+    pub missing: Vec<PathBuf>,
+    /// Files that exist, but could not be read, so it is unknown whether they changed
+    pub unreadable: Vec<UnreadableFile>,
+    /// Paths to files whose content is correct, but whose permissions, owner or group differ from the recorded ones.
+    /// Only checked with opts.check_metadata.
+    pub metadata_changed: Vec<PathBuf>,
+    /// Paths to files that were restored from opts.repair_from, or that would be restored in a dry run.
+    /// Restored files are no longer listed as failed or missing, in a dry run they still are.
+    pub repaired: Vec<PathBuf>,
+    /// Numbers of the lines of the _algorithm_sum.txt that could not be read, eg because their hash is truncated
+    pub malformed_lines: Vec<usize>,
+    /// The blocks that changed of the changed files that have block hashes
+    pub changed_blocks: Vec<ChangedBlocks>,
+    /// Number of files listed in the _algorithm_sum.txt
+    pub listed_files: usize,
+    /// Number of files that were checked if only a sample of the listed files was checked
+    pub sampled_files: Option<usize>,
+    /// Number of files that were not hashed with opts.trust_xattr, because their extended attribute holds
+    /// the recorded hash and their size and modification time did not change
+    pub trusted_files: usize,
+    /// Number of bytes that were hashed, or that would be hashed in a dry run
+    pub bytes: u64,
+    /// Time it took to verify the directory
+    pub duration: Duration,
+    /// Whether or not the verification was interrupted by Ctrl-C or stopped by a failure elsewhere
+    /// before every file was hashed
+    pub interrupted: bool,
+    /// Whether or not verification gave up on the directory, because nothing was read of its files within opts.timeout
+    pub timed_out: bool,
+    /// Whether or not the directory was passed without hashing with opts.trust_mtime,
+    /// because its _algorithm_sum.txt is newer than all of its files
+    pub trusted: bool,
+    /// Why the directory could not be verified at all, eg because its _algorithm_sum.txt could not be read
+    pub error: Option<String>,
+}
 
-use super::util::HashError;
+impl DirectoryReport {
+    /// Indicates that every file listed in the _algorithm_sum.txt was hashed and correct
+    pub fn is_ok(&self) -> bool {
+        !self.has_failures() && !self.interrupted
+    }
 
-/// Verifies the integrity of some directories
-///
-/// # Arguments
-///
-/// * `opts` An Options object containing information about the program behavior
-///
-/// # Returns
-/// The exit code the program should return.
-pub fn verify_directories(opts: super::util::Options) -> i32 {
-    let now = chrono::Local::now();
-    let known_good_path = format!("known_good_{}_{}.txt", now.month(), now.year());
-    let to_check_path = format!("to_check_{}_{}.txt", now.month(), now.year());
+    /// Indicates that a file or its metadata changed unexpectedly, could not be read or is missing, that a line of the
+    /// _algorithm_sum.txt is malformed, or that the directory timed out or could not be verified at all
+    pub fn has_failures(&self) -> bool {
+        !self.failed.is_empty()
+            || !self.missing.is_empty()
+            || !self.unreadable.is_empty()
+            || !self.metadata_changed.is_empty()
+            || !self.malformed_lines.is_empty()
+            || self.timed_out
+            || self.error.is_some()
+    }
+
+    /// Returns the message that replaces the progress bar of the directory once it is verified
+    ///
+    /// # Arguments
+    /// * `opts` The Options object determining whether or not a sample was checked
+    pub(crate) fn progress_message(&self, opts: &super::util::Options) -> String {
+        let elapsed = super::util::format_duration(self.duration);
+        if self.trusted {
+            "trusted: unchanged".to_string()
+        } else if self.error.is_some() {
+            "checked: ERROR".to_string()
+        } else if self.timed_out {
+            format!("timed out after {}", elapsed)
+        } else if self.has_failures() {
+            format!("checked: FAILED in {}", elapsed)
+        } else if self.interrupted {
+            format!("interrupted after {}", elapsed)
+        } else if opts.sample.is_some() {
+            format!("sample checked: OK in {}", elapsed)
+        } else {
+            format!("checked: OK in {}", elapsed)
+        }
+    }
 
-    if !opts.subdir_mode {
-        // execute in directory
+    /// Returns the report as a JSON object
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "dir": self.path.to_string_lossy(),
+            "ok": self.is_ok(),
+            "failed": paths_to_json(&self.failed),
+            "missing": paths_to_json(&self.missing),
+            "unreadable": self
+                .unreadable
+                .iter()
+                .map(UnreadableFile::to_json)
+                .collect::<Vec<_>>(),
+            "metadata_changed": paths_to_json(&self.metadata_changed),
+            "repaired": paths_to_json(&self.repaired),
+            "malformed_lines": self.malformed_lines,
+            "changed_blocks": self
+                .changed_blocks
+                .iter()
+                .map(ChangedBlocks::to_json)
+                .collect::<Vec<_>>(),
+            "listed_files": self.listed_files,
+            "sampled_files": self.sampled_files,
+            "trusted_files": self.trusted_files,
+            "bytes": self.bytes,
+            "duration_ms": self.duration.as_millis() as u64,
+            "interrupted": self.interrupted,
+            "timed_out": self.timed_out,
+            "trusted": self.trusted,
+            "error": self.error,
+        })
+    }
+}
 
-        if opts.loglevel_progress() {
-            super::util::terminal_noecho();
-            println!();
-        }
-        let mut worker_handles = Vec::new();
-        let q = Arc::new(Injector::new());
-        let producer_finished = Arc::new(AtomicBool::new(false));
-        let num_threads = match opts.num_threads {
-            0 => num_cpus::get(),
-            _ => opts.num_threads,
+/// A file that could not be read during verification
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnreadableFile {
+    /// Path to the file
+    pub path: PathBuf,
+    /// Why the file could not be read
+    pub reason: UnreadableReason,
+}
+
+/// The reason a file could not be read
+#[derive(Debug, Clone, PartialEq)]
+pub enum UnreadableReason {
+    /// The permissions of the file do not allow reading it
+    PermissionDenied,
+    /// Any other error while opening or reading the file, with its description
+    IoError(String),
+}
+
+impl UnreadableFile {
+    /// Classifies the error of a file that could not be hashed
+    ///
+    /// # Arguments
+    /// * `error` The error returned for the file
+    pub fn from_error(error: &HashError) -> UnreadableFile {
+        let reason = match error.io_error().kind() {
+            io::ErrorKind::PermissionDenied => UnreadableReason::PermissionDenied,
+            _ => UnreadableReason::IoError(error.io_error().to_string()),
         };
 
-        let opts = Arc::new(opts);
-        let cloned_opts = Arc::clone(&opts);
-        let workdir = PathBuf::from(&opts.folder);
-        let myq = Arc::clone(&q);
-        let (tx, rx) = channel();
+        UnreadableFile {
+            path: error.path().to_path_buf(),
+            reason,
+        }
+    }
 
-        let handle = thread::spawn(move || {
-            verify_directory(
-                &workdir,
-                Arc::new(known_good_path),
-                Arc::new(to_check_path),
-                cloned_opts,
-                1,
-                0,
-                myq,
-                tx,
+    /// Returns the file as a JSON object
+    pub fn to_json(&self) -> serde_json::Value {
+        match &self.reason {
+            UnreadableReason::PermissionDenied => serde_json::json!({
+                "path": self.path.to_string_lossy(),
+                "reason": "permission_denied",
+            }),
+            UnreadableReason::IoError(message) => serde_json::json!({
+                "path": self.path.to_string_lossy(),
+                "reason": "io_error",
+                "message": message,
+            }),
+        }
+    }
+}
+
+/// The result of verifying some directories
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    /// The directories that were verified during this run
+    pub directories: Vec<DirectoryReport>,
+    /// Directories that were skipped, because they were already marked known good in subdir mode
+    pub known_good: Vec<PathBuf>,
+    /// Directories that were skipped, because they were already marked known bad in subdir mode
+    pub known_bad: Vec<PathBuf>,
+    /// Wall-clock time of the whole run
+    pub duration: Duration,
+    /// The seed the samples were selected with if only samples of the listed files were checked
+    pub seed: Option<u64>,
+    /// Whether or not the run was stopped at the first failure, leaving files unchecked
+    pub stopped: bool,
+}
+
+impl VerifyReport {
+    /// Returns the number of bytes hashed in all directories
+    pub fn bytes(&self) -> u64 {
+        self.directories.iter().map(|d| d.bytes).sum()
+    }
+
+    /// Returns the number of directories that failed during this run
+    pub fn failed_directories(&self) -> usize {
+        self.directories.iter().filter(|d| d.has_failures()).count()
+    }
+
+    /// Returns the number of files that could not be read in all directories
+    pub fn unreadable_files(&self) -> usize {
+        self.directories.iter().map(|d| d.unreadable.len()).sum()
+    }
+
+    /// Returns the number of files whose content is correct, but whose metadata changed in all directories
+    pub fn metadata_changed_files(&self) -> usize {
+        self.directories
+            .iter()
+            .map(|d| d.metadata_changed.len())
+            .sum()
+    }
+
+    /// Returns the number of files that were restored from the mirror in all directories,
+    /// or that would be restored in a dry run
+    pub fn repaired_files(&self) -> usize {
+        self.directories.iter().map(|d| d.repaired.len()).sum()
+    }
+
+    /// Returns the number of directories whose verification was interrupted without finding a failure
+    pub fn interrupted_directories(&self) -> usize {
+        self.directories
+            .iter()
+            .filter(|d| d.interrupted && !d.has_failures())
+            .count()
+    }
+
+    /// Returns the number of directories that could not be verified at all
+    pub fn errored_directories(&self) -> usize {
+        self.directories
+            .iter()
+            .filter(|d| d.error.is_some())
+            .count()
+    }
+
+    /// Returns the number of directories verification gave up on, because nothing was read of their files in time
+    pub fn timed_out_directories(&self) -> usize {
+        self.directories.iter().filter(|d| d.timed_out).count()
+    }
+
+    /// Returns the number of files that were checked in all directories and the number of listed files
+    /// if only samples of the listed files were checked
+    pub fn sampled_files(&self) -> Option<(usize, usize)> {
+        self.seed?;
+        let sampled = self
+            .directories
+            .iter()
+            .map(|d| d.sampled_files.unwrap_or(d.listed_files))
+            .sum();
+        let listed = self.directories.iter().map(|d| d.listed_files).sum();
+        Some((sampled, listed))
+    }
+
+    /// Returns a line summarizing all directories,
+    /// eg "Checked 412 directories, 409 OK, 3 failed, 1.80 TiB hashed in 27 minutes"
+    ///
+    /// # Arguments
+    /// * `byte_format` How the number of hashed bytes is printed
+    pub fn summary(&self, byte_format: ByteFormat) -> String {
+        let failed = self.failed_directories();
+        let interrupted = self.interrupted_directories();
+        let mut summary = format!(
+            "Checked {} directories, {} OK, {} failed",
+            self.directories.len(),
+            self.directories.len() - failed - interrupted,
+            failed
+        );
+
+        if interrupted > 0 {
+            summary = format!("{}, {} interrupted", summary, interrupted);
+        }
+
+        let timed_out = self.timed_out_directories();
+        if timed_out > 0 {
+            summary = format!("{}, {} timed out", summary, timed_out);
+        }
+
+        let errored = self.errored_directories();
+        if errored > 0 {
+            summary = format!("{}, {} could not be verified", summary, errored);
+        }
+
+        if self.stopped {
+            summary = format!("{}, stopped at the first failure", summary);
+        }
+
+        let unreadable = self.unreadable_files();
+        if unreadable > 0 {
+            summary = format!("{}, {} files could not be read", summary, unreadable);
+        }
+
+        let metadata_changed = self.metadata_changed_files();
+        if metadata_changed > 0 {
+            summary = format!(
+                "{}, metadata of {} files changed",
+                summary, metadata_changed
             );
-        });
+        }
 
-        super::util::execute_workers(
-            num_threads,
-            Arc::clone(&q),
-            Arc::clone(&producer_finished),
-            &mut worker_handles,
+        let repaired = self.repaired_files();
+        if repaired > 0 {
+            summary = format!("{}, {} files restored from the mirror", summary, repaired);
+        }
+
+        summary = format!(
+            "{}, {} hashed in {}",
+            summary,
+            byte_format.format(self.bytes()),
+            super::util::format_duration(self.duration)
         );
 
-        handle.join().unwrap();
+        if let (Some((sampled, listed)), Some(seed)) = (self.sampled_files(), self.seed) {
+            summary = format!(
+                "{}, partial check of {} of {} listed files (--seed {})",
+                summary, sampled, listed, seed
+            );
+        }
+
+        if !self.known_good.is_empty() || !self.known_bad.is_empty() {
+            summary = format!(
+                "{}, skipped {} already known good and {} already known bad",
+                summary,
+                self.known_good.len(),
+                self.known_bad.len()
+            );
+        }
 
-        producer_finished.store(true, Ordering::Relaxed);
+        summary
+    }
 
-        for handle in worker_handles {
-            handle.join().unwrap();
+    /// Returns a JSON object summarizing all directories
+    pub fn summary_json(&self) -> serde_json::Value {
+        let paths = |dirs: &[PathBuf]| -> Vec<String> {
+            dirs.iter()
+                .map(|dir| dir.to_string_lossy().to_string())
+                .collect()
+        };
+
+        serde_json::json!({
+            "summary": true,
+            "ok": self.exit_code() == 0,
+            "directories": self.directories.len(),
+            "failed_directories": self.failed_directories(),
+            "interrupted_directories": self.interrupted_directories(),
+            "timed_out_directories": self.timed_out_directories(),
+            "errored_directories": self.errored_directories(),
+            "unreadable_files": self.unreadable_files(),
+            "metadata_changed_files": self.metadata_changed_files(),
+            "repaired_files": self.repaired_files(),
+            "known_good": paths(&self.known_good),
+            "known_bad": paths(&self.known_bad),
+            "bytes": self.bytes(),
+            "sampled_files": self.sampled_files().map(|(sampled, _)| sampled),
+            "listed_files": self.sampled_files().map(|(_, listed)| listed),
+            "seed": self.seed,
+            "stopped": self.stopped,
+            "duration_ms": self.duration.as_millis() as u64,
+        })
+    }
+
+    /// Returns the exit code the program should return.
+    /// 1 if a directory failed or was interrupted during this run, 2 if directories were already marked known bad, 0 otherwise.
+    pub fn exit_code(&self) -> i32 {
+        if self.directories.iter().any(|directory| !directory.is_ok()) {
+            1
+        } else if !self.known_bad.is_empty() {
+            2
+        } else {
+            0
         }
+    }
+}
 
-        let mut exit_code = 0;
+/// Verifies the integrity of some directories.
+/// Info messages and errors are emitted through the log crate according to the loglevel, nothing is emitted
+/// in loglevel quiet. Rendering the report is left to the caller, see VerifyReport::summary and
+/// DirectoryReport::to_json.
+/// In a dry run, the report lists the directories that would be verified together with the number of bytes
+/// that would be hashed, nothing is hashed and the lists of checked directories are left untouched.
+/// With opts.repair_from, the files that changed or are missing are restored from their copy in the mirror,
+/// see repair::repair_directory. A directory whose files were all restored counts as good. In a dry run with
+/// opts.repair_from, the directories are verified and the files that would be restored are reported,
+/// but nothing is written.
+/// With opts.sample, only a random sample of the files of every directory is checked. The sample is selected
+/// with opts.seed or a random seed, which is reported so the same sample can be checked again.
+/// Directories whose sample is correct are not recorded as known good.
+/// With opts.fail_fast, the run stops at the first file that changed, is missing or can not be read.
+/// Files that are being hashed are finished and the results gathered so far are reported.
+/// With opts.timeout, a directory is given up on and reported as timed out if nothing of its files is read in time.
+/// With opts.trust_mtime, a directory whose _algorithm_sum.txt is newer than all of its files is passed
+/// without hashing and not recorded as known good.
+/// With opts.recheck, only the directories in the to_check list of this month are verified, instead of opts.folders.
+/// Directories that are correct now move to the known_good list, the others stay in the to_check list.
+///
+/// # Arguments
+///
+/// * `opts` An Options object containing information about the program behavior
+///
+/// # Returns
+/// A VerifyReport describing the outcome for every directory.
+pub fn verify(opts: &super::util::Options) -> VerifyReport {
+    let start = Instant::now();
+    let mut opts = opts.clone();
+    if opts.sample.is_some() && opts.seed.is_none() {
+        opts.seed = Some(rand::random());
+    }
+    super::util::reset_stopped();
+    let mut report = verify_directories(opts.clone());
+    report.duration = start.elapsed();
+    report.stopped = super::util::stopped();
+    if opts.sample.is_some() {
+        report.seed = opts.seed;
+    }
 
-        for code in rx {
-            if code != 0 {
-                exit_code = code;
-            }
+    report
+}
+
+/// Verifies the integrity of the folders or their subdirectories, depending on subdir mode
+///
+/// # Arguments
+///
+/// * `opts` An Options object containing information about the program behavior
+fn verify_directories(mut opts: super::util::Options) -> VerifyReport {
+    // the month of the lists is determined once, a run spanning midnight keeps writing to the same lists
+    let started: DateTime<chrono::Local> = chrono::Local::now();
+    let (known_good_path, to_check_path) = state_paths(&opts, &started);
+    // the rechecked directories are recorded in the lists like in subdir mode
+    let rechecked = if opts.recheck {
+        opts.subdir_mode = true;
+        start_recheck(&opts, &to_check_path)
+    } else {
+        Vec::new()
+    };
+    // nothing is written in a dry run
+    if !opts.dry_run {
+        if let Err(e) = fs::create_dir_all(&opts.state_dir) {
+            error!(
+                "Error creating directory {}: {}",
+                opts.state_dir.display(),
+                e
+            );
+        }
+    }
+
+    let (known_good, known_bad) = if opts.recheck {
+        (Vec::new(), Vec::new())
+    } else if opts.subdir_mode && opts.force {
+        if !opts.dry_run {
+            reset_already_checked(&opts, &known_good_path, &to_check_path);
         }
+        (Vec::new(), Vec::new())
+    } else if opts.subdir_mode {
+        read_already_checked(&opts, &known_good_path, &to_check_path)
+    } else {
+        (Vec::new(), Vec::new())
+    };
 
-        exit_code
+    // the folders are not looked at, the directories of the to_check list are rechecked instead
+    let folders = if opts.recheck {
+        &[][..]
     } else {
-        // iterate over subdirs and spawn verify_directory threads
-        execute_threads_subdir(
-            opts,
-            known_good_path,
-            to_check_path,
-        )
+        &opts.folders[..]
+    };
+    let (files, folders): (Vec<&PathBuf>, Vec<&PathBuf>) = folders
+        .iter()
+        .partition(|folder| super::util::is_single_file(folder));
+    let files: Vec<PathBuf> = files.into_iter().cloned().collect();
+    let (file_groups, ungrouped) = super::edit::group_by_manifest(&files, &opts);
+
+    let mut directories = {
+        let dirs_to_process: Box<dyn Iterator<Item = PathBuf> + Send> = if opts.recheck {
+            Box::new(rechecked.into_iter())
+        } else if opts.subdir_mode {
+            Box::new(discover_directories(
+                &opts,
+                folders,
+                &known_good,
+                &known_bad,
+            ))
+        } else {
+            Box::new(folders.into_iter().map(PathBuf::from))
+        };
+        let dirs_to_process = super::util::schedule_directories(dirs_to_process, &opts)
+            .map(|directory| (directory, None))
+            .chain(
+                file_groups
+                    .into_iter()
+                    .map(|(workdir, files)| (workdir, Some(files))),
+            );
+
+        if opts.dry_run && opts.repair_from.is_none() {
+            plan_directories(&opts, dirs_to_process)
+        } else {
+            execute_threads(
+                &opts,
+                Box::new(dirs_to_process),
+                &known_good,
+                &known_bad,
+                known_good_path.clone(),
+                to_check_path.clone(),
+            )
+        }
+    };
+    if opts.recheck && !opts.dry_run {
+        finish_recheck(&opts, &known_good_path, &to_check_path, &directories);
+    }
+
+    for (file, message) in ungrouped {
+        if !opts.loglevel_quiet() {
+            error!("{}: {}", file.display(), message);
+        }
+        stop_if_fail_fast(&opts);
+        directories.push(DirectoryReport {
+            path: file,
+            error: Some(message),
+            ..DirectoryReport::default()
+        });
+    }
+
+    VerifyReport {
+        directories,
+        known_good,
+        known_bad,
+        ..VerifyReport::default()
     }
 }
 
-/// Reads all directories in the working directory and compares them with already checked directories.
-/// Ignores directories that don't contain an _algorithm_sum.txt file.
-/// Logs information about known good and known bad directories in info and progress levels.
-/// Returns unchecked directories and the number of characters in the name of the directory with the longest name.
-/// Also returns a flag indicating if there exist known bad directories.
+/// Returns the paths to the lists of directories that were checked in the month of date,
+/// the known_good list and the to_check list.
 ///
 /// # Arguments
-/// * `opts` Options object containing the working directory
+/// * `opts` Options object containing the state directory and the template for the names of the lists
+/// * `date` The date whose month and year are inserted into the template
+pub(crate) fn state_paths(
+    opts: &super::util::Options,
+    date: &DateTime<chrono::Local>,
+) -> (PathBuf, PathBuf) {
+    let state_path = |list: &str| {
+        let name = opts
+            .state_template
+            .replace("{list}", list)
+            .replace("{month}", &date.month().to_string())
+            .replace("{year}", &date.year().to_string());
+        opts.state_dir.join(name)
+    };
+
+    (state_path("known_good"), state_path("to_check"))
+}
+
+/// Returns the path to the list of the files of a directory that changed, are missing or could not be read
+///
+/// # Arguments
+/// * `workdir` Path to the directory
+/// * `opts` Options object containing the state directory
+pub(crate) fn bad_files_path(workdir: &Path, opts: &super::util::Options) -> PathBuf {
+    let to_check_dir = &*workdir.to_string_lossy();
+    let to_check_dir = to_check_dir
+        .strip_prefix("./")
+        .or_else(|| to_check_dir.strip_prefix(".\\"))
+        .unwrap_or(to_check_dir);
+    // nested directories in subdir mode must not create subdirectories for their list of bad files
+    let to_check_dir = to_check_dir.replace(['/', '\\'], "_");
+    opts.state_dir
+        .join(format!("to_check_{}.txt", to_check_dir))
+}
+
+/// Reads the directories that were already checked and logs them in info level.
+/// Returns the directories that are already known good and known bad.
+///
+/// # Arguments
+/// * `opts` Options object containing the loglevel
 /// * `known_good_path` Path to the text file containing all checked and good directories
 /// * `to_check_path` Path to the text file containing all checked and bad directories
-fn gather_directories_to_process(
+fn read_already_checked(
     opts: &super::util::Options,
-    known_good_path: &String,
-    to_check_path: &String,
-) -> (Vec<PathBuf>, usize, bool) {
+    known_good_path: &Path,
+    to_check_path: &Path,
+) -> (Vec<PathBuf>, Vec<PathBuf>) {
     // read every line from known_good_path and to_check_path to vec
-    let already_checked_good = super::util::read_paths_from_file(&known_good_path);
-    let already_checked_bad = super::util::read_paths_from_file(&to_check_path);
+    let already_checked_good = super::util::read_paths_from_file(known_good_path);
+    let already_checked_bad = super::util::read_paths_from_file(to_check_path);
     if opts.loglevel_debug() {
-        println!("Already checked subdirs: known good: {:?}, known bad: {:?}", already_checked_good, already_checked_bad);
+        debug!(
+            "Already checked subdirs: known good: {:?}, known bad: {:?}",
+            already_checked_good, already_checked_bad
+        );
     }
 
     if opts.loglevel_info() {
-        let now: DateTime<chrono::Local> = chrono::Local::now();
         for dir in already_checked_good.iter().as_ref() {
-            println!(
-                "[{}] Directory {} already marked known good",
-                now,
-                dir.to_str().unwrap()
-            );
+            info!("Directory {} already marked known good", dir.display());
         }
         for dir in already_checked_bad.iter().as_ref() {
-            println!(
-                "[{}] Directory {} already marked known bad",
-                now,
-                dir.to_str().unwrap()
-            );
+            info!("Directory {} already marked known bad", dir.display());
         }
     }
 
-    let dir_entries = fs::read_dir(&opts.folder).unwrap();
-    let mut dirs_to_process = Vec::new();
-    let mut longest_folder = 0;
+    (already_checked_good, already_checked_bad)
+}
 
-    for entry in dir_entries {
-        let entry = entry.unwrap();
-        let metadata = entry.metadata().unwrap();
+/// Reads the directories of the to_check list for recheck mode.
+/// The list and the lists of bad files stay in place while the directories are rechecked, see finish_recheck.
+///
+/// # Arguments
+/// * `opts` Options object containing the loglevel
+/// * `to_check_path` Path to the text file containing all checked and bad directories
+fn start_recheck(opts: &super::util::Options, to_check_path: &Path) -> Vec<PathBuf> {
+    let mut directories = super::util::read_paths_from_file(to_check_path);
+    directories.retain(|directory| !directory.as_os_str().is_empty());
+    directories.sort();
+    directories.dedup();
 
-        if metadata.is_dir() {
-            if !(already_checked_good.contains(&entry.path()) || already_checked_bad.contains(&entry.path())) {
-                let sum_txt_path = fs::metadata(format!(
-                    "{}/{}sum.txt",
-                    entry.path().to_str().unwrap(),
-                    &opts.algorithm
-                ));
-                if let Ok(path) = sum_txt_path {
-                    if path.is_file() {
-                        dirs_to_process.push(entry.path());
-                    }
-                }
-            }
+    if opts.loglevel_info() {
+        info!(
+            "Rechecking {} directories from {}",
+            directories.len(),
+            to_check_path.display()
+        );
+    }
+    directories
+}
+
+/// Moves the directories of recheck mode that were verified completely and correct from the to_check list
+/// to the known_good list and removes their lists of bad files. The to_check list is replaced atomically,
+/// so the directories that still need attention are never lost, even if the program is killed.
+/// Directories that failed again, were interrupted, trusted or only sampled stay in the to_check list.
+///
+/// # Arguments
+/// * `opts` Options object containing the state directory
+/// * `known_good_path` Path to the text file containing all checked and good directories
+/// * `to_check_path` Path to the text file containing all checked and bad directories
+/// * `directories` The DirectoryReports of the directories that were verified
+fn finish_recheck(
+    opts: &super::util::Options,
+    known_good_path: &Path,
+    to_check_path: &Path,
+    directories: &[DirectoryReport],
+) {
+    let good: HashSet<&Path> = directories
+        .iter()
+        .filter(|report| report.is_ok() && !report.trusted && report.sampled_files.is_none())
+        .map(|report| report.path.as_path())
+        .collect();
+    if good.is_empty() {
+        return;
+    }
+
+    let remaining: Vec<PathBuf> = super::util::read_paths_from_file(to_check_path)
+        .into_iter()
+        .filter(|directory| !good.contains(directory.as_path()))
+        .collect();
+    let result = if remaining.is_empty() {
+        fs::remove_file(to_check_path)
+    } else {
+        let name = to_check_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let temp_path = to_check_path.with_file_name(format!(".{}.tmp", name));
+        super::util::write_atomically(to_check_path, &temp_path, |file| {
+            remaining
+                .iter()
+                .try_for_each(|directory| write_path_line(file, directory))
+        })
+    };
+    if let Err(e) = result {
+        error!("Error writing to file {}: {}", to_check_path.display(), e);
+        return;
+    }
 
-            let len = entry.path().to_str().unwrap().len();
-            if len > longest_folder {
-                longest_folder = len;
+    for directory in good {
+        let moved = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(known_good_path)
+            .and_then(|mut file| write_path_line(&mut file, directory));
+        if let Err(e) = moved {
+            error!("Error writing to file {}: {}", known_good_path.display(), e);
+        }
+        match fs::remove_file(bad_files_path(directory, opts)) {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => error!(
+                "Error removing file {}: {}",
+                bad_files_path(directory, opts).display(),
+                e
+            ),
+        }
+    }
+}
+
+/// Removes the lists of directories that were already checked, so every directory is verified again
+/// and the lists only contain the results of this run.
+///
+/// # Arguments
+/// * `opts` Options object containing the loglevel
+/// * `known_good_path` Path to the text file containing all checked and good directories
+/// * `to_check_path` Path to the text file containing all checked and bad directories
+fn reset_already_checked(
+    opts: &super::util::Options,
+    known_good_path: &Path,
+    to_check_path: &Path,
+) {
+    for list_path in [known_good_path, to_check_path] {
+        match fs::remove_file(list_path) {
+            Ok(()) => {
+                if opts.loglevel_info() {
+                    info!("Removed {}, verifying every directory", list_path.display());
+                }
             }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => error!("Error removing file {}: {}", list_path.display(), e),
         }
     }
-    
-    if opts.loglevel_progress() {
-        for dir in already_checked_good {
-            println!();
-            print_message_aligned(1, "already known good", dir.to_str().unwrap(), longest_folder).unwrap();
+}
+
+/// Finds the directories in the folders, descending opts.max_depth levels,
+/// that contain an _algorithm_sum.txt file and were not checked already, while they are verified.
+/// The directories are read and checked in parallel, see discover_subdirectories.
+///
+/// # Arguments
+/// * `opts` Options object containing the maximum depth
+/// * `folders` The folders whose subdirectories are verified
+/// * `known_good` Directories that are already known good
+/// * `known_bad` Directories that are already known bad
+fn discover_directories(
+    opts: &super::util::Options,
+    folders: Vec<&PathBuf>,
+    known_good: &[PathBuf],
+    known_bad: &[PathBuf],
+) -> impl Iterator<Item = PathBuf> + Send {
+    let already_checked: HashSet<PathBuf> = known_good.iter().chain(known_bad).cloned().collect();
+    let folders = folders
+        .into_iter()
+        .map(|folder| (folder.clone(), Vec::new()))
+        .collect();
+    let xattr = opts.xattr;
+    let algorithms = if opts.detect_algorithm {
+        None
+    } else {
+        Some(opts.algorithms())
+    };
+
+    super::util::discover_subdirectories(folders, opts, move |entry| {
+        if already_checked.contains(entry) {
+            return false;
         }
-        for dir in already_checked_bad.iter().by_ref() {
-            println!();
-            print_message_aligned(1, "already known BAD", dir.to_str().unwrap(), longest_folder).unwrap();
+        // every directory may contain files with hashes in their extended attributes
+        if xattr {
+            return true;
         }
-    }
+        match algorithms {
+            None => !Manifest::find_algorithms(entry).is_empty(),
+            Some(ref algorithms) => algorithms
+                .iter()
+                .any(|&algorithm| Manifest::find(entry, algorithm).is_some()),
+        }
+    })
+}
+
+/// Returns the directories that would be verified, each with the number of bytes of the files that would be checked
+///
+/// # Arguments
+/// * `opts` Options object containing the algorithm
+/// * `directories` The directories that would be verified, with the files to verify if only some are
+fn plan_directories(
+    opts: &super::util::Options,
+    directories: impl Iterator<Item = (PathBuf, Option<Vec<PathBuf>>)>,
+) -> Vec<DirectoryReport> {
+    let opts = Arc::new(opts.clone());
+    directories
+        .map(|(directory, files)| {
+            // errors are reported when the directory is verified
+            let dir_opts = detect_algorithm(&directory, Arc::clone(&opts));
+            let bytes = Manifest::read(&directory, &dir_opts)
+                .map(|manifest| {
+                    let entries =
+                        select_entries(&directory, manifest.entries(), files.as_deref(), &dir_opts);
+                    count_bytes(&directory, &entries)
+                })
+                .unwrap_or(0);
+            DirectoryReport {
+                path: directory,
+                bytes,
+                ..DirectoryReport::default()
+            }
+        })
+        .collect()
+}
 
-    (dirs_to_process, longest_folder, already_checked_bad.is_empty())
+/// Passes the directories that are already known good and known bad to the printer thread,
+/// which prints them aligned with the progress bars.
+///
+/// # Arguments
+/// * `progress` Sender to the printer thread
+/// * `known_good` Directories that are already known good
+/// * `known_bad` Directories that are already known bad
+/// * `longest_folder` Number of characters in the name of the directory with the longest name
+fn report_already_checked(
+    progress: &Sender<ProgressEvent>,
+    known_good: &[PathBuf],
+    known_bad: &[PathBuf],
+    longest_folder: usize,
+) {
+    let lines = known_good
+        .iter()
+        .map(|dir| ("already known good", dir))
+        .chain(known_bad.iter().map(|dir| ("already known BAD", dir)));
+    for (message, dir) in lines {
+        let line = progress::format_aligned(message, &dir.to_string_lossy(), longest_folder);
+        progress.send(ProgressEvent::Log(line)).unwrap();
+    }
 }
 
-/// Starts a thread for every directory in dirs_to_process and launches them all at once.
+/// Verifies opts.parallel_directories directories of dirs_to_process at a time.
+/// The directories are verified as soon as they are found, unless progress is reported,
+/// which needs to know every directory up front.
 /// Waits for them to finish.
 ///
 /// # Arguments
 /// * `opts` Options object
+/// * `dirs_to_process` The directories that have to be verified, with the files to verify if only some are
+/// * `known_good` Directories that are already known good, only printed in loglevel progress
+/// * `known_bad` Directories that are already known bad, only printed in loglevel progress
 /// * `known_good_path` Path to the text file containing all checked and good directories
 /// * `to_check_path` Path to the text file containing all checked and bad directories
 ///
 /// # Returns
-/// The exit code the program should return.
-fn execute_threads_subdir(
-    opts: super::util::Options,
-    known_good_path: String,
-    to_check_path: String,
-) -> i32 {
-    let (dirs_to_process, longest_folder, known_bad_empty) =
-        gather_directories_to_process(&opts, &known_good_path, &to_check_path);
-
-    if opts.loglevel_progress() {
-        super::util::terminal_noecho();
-        for _ in 0..dirs_to_process.len() {
-            println!();
+/// A DirectoryReport for every directory.
+fn execute_threads<'a>(
+    opts: &super::util::Options,
+    dirs_to_process: Box<dyn Iterator<Item = (PathBuf, Option<Vec<PathBuf>>)> + Send + 'a>,
+    known_good: &[PathBuf],
+    known_bad: &[PathBuf],
+    known_good_path: PathBuf,
+    to_check_path: PathBuf,
+) -> Vec<DirectoryReport> {
+    let (printer, dirs_to_process) = if Printer::is_needed(opts) {
+        let dirs_to_process: Vec<(PathBuf, Option<Vec<PathBuf>>)> = dirs_to_process.collect();
+        let longest_folder = dirs_to_process
+            .iter()
+            .map(|(dir, _)| dir)
+            .chain(known_good)
+            .chain(known_bad)
+            .map(|dir| dir.to_string_lossy().len())
+            .max()
+            .unwrap_or(0);
+
+        let workdirs = dirs_to_process
+            .iter()
+            .map(|(dir, _)| dir.to_string_lossy().to_string())
+            .collect();
+        let printer = Printer::start(workdirs, longest_folder, opts);
+        if opts.loglevel_progress() {
+            report_already_checked(&printer.sender(), known_good, known_bad, longest_folder);
+            super::util::terminal_noecho();
         }
-    }
+        let dirs_to_process: Box<dyn Iterator<Item = (PathBuf, Option<Vec<PathBuf>>)> + Send + 'a> =
+            Box::new(dirs_to_process.into_iter());
+
+        (Some(printer), dirs_to_process)
+    } else {
+        (None, dirs_to_process)
+    };
 
-    let mut producer_handles = Vec::new();
     let mut worker_handles = Vec::new();
-    let mut print_line = 1;
-    let opts = Arc::new(opts);
-    let known_good_path = Arc::new(known_good_path);
-    let to_check_path = Arc::new(to_check_path);
+    let opts = Arc::new(opts.clone());
+    let (state, state_writer) = start_state_writer(known_good_path, to_check_path);
     let q = Arc::new(Injector::new());
     let producer_finished = Arc::new(AtomicBool::new(false));
-    let num_threads = match opts.num_threads {
-        0 => num_cpus::get(),
-        _ => opts.num_threads,
-    };
+    let num_threads = opts.threads.count();
+    let num_producers = opts.parallel_directories.count();
+    super::util::log_thread_count(num_threads, &opts);
     let (tx, rx) = channel();
-    let mut exit_code = if known_bad_empty { 0 } else { 2 };
-
-    for entry in dirs_to_process {
-        let opts = Arc::clone(&opts);
-        let myq = Arc::clone(&q);
-        let known_good_path = Arc::clone(&known_good_path);
-        let to_check_path = Arc::clone(&to_check_path);
-        let tx = tx.clone();
-
-        let handle = thread::spawn(move || {
-            verify_directory(
-                &entry,
-                known_good_path,
-                to_check_path,
-                opts,
-                print_line,
-                longest_folder,
-                myq,
-                tx,
-            );
-        });
-
-        producer_handles.push(handle);
-
-        print_line += 1;
-    }
 
     super::util::execute_workers(
         num_threads,
@@ -256,163 +914,525 @@ fn execute_threads_subdir(
         &mut worker_handles,
     );
 
-    for handle in producer_handles {
-        handle.join().unwrap();
-    }
+    let progress = printer.as_ref().map(Printer::sender);
+    super::util::execute_producers(
+        dirs_to_process.enumerate(),
+        num_producers,
+        |(line, (entry, files)): (usize, (PathBuf, Option<Vec<PathBuf>>))| {
+            verify_directory(
+                &entry,
+                files.as_deref(),
+                state.clone(),
+                Arc::clone(&opts),
+                line,
+                progress.clone(),
+                Arc::clone(&q),
+                tx.clone(),
+            );
+        },
+    );
+    // the printer and the state writer finish once every sender is dropped
+    drop(progress);
+    drop(state);
+    state_writer.join().unwrap();
 
     producer_finished.store(true, Ordering::Relaxed);
 
+    drop(tx);
+    let directories: Vec<DirectoryReport> = rx.iter().collect();
+
+    let timed_out = directories.iter().any(|directory| directory.timed_out);
     for handle in worker_handles {
+        // a worker stuck on a file of a directory that timed out is left behind and ends with the process,
+        // see next_result
+        if timed_out && !handle.is_finished() {
+            continue;
+        }
         handle.join().unwrap();
     }
 
-    drop(tx);
-    for code in rx {
-        if code != 0 {
-            exit_code = code;
-        }
+    if let Some(printer) = printer {
+        printer.finish();
+    }
+
+    directories
+}
+
+/// Verifies the integrity of a directory
+///
+/// # Arguments
+///
+/// * `workdir` Path to the directory that should be verified
+/// * `files` The files of workdir to verify, every listed file if None
+/// * `state` Sender to the state writer, which records workdir as known good or to check in subdir mode
+/// * `opts` An Options object containing information about the program behavior
+/// * `line` The index of workdir in the directories of the printer thread. Only used with progress.
+/// * `progress` Sender to the printer thread, only present if progress is displayed, streamed or reported
+/// * `tx` Sender for sending the DirectoryReport of workdir.
+#[allow(clippy::too_many_arguments)]
+fn verify_directory(
+    workdir: &Path,
+    files: Option<&[PathBuf]>,
+    state: Sender<StateLine>,
+    mut opts: Arc<super::util::Options>,
+    line: usize,
+    progress: Option<Sender<ProgressEvent>>,
+    myq: Arc<Injector<super::util::HashTask>>,
+    tx: Sender<DirectoryReport>,
+) {
+    if opts.loglevel_info() {
+        info!("Verifying Directory {}", workdir.display());
+    }
+    if let Some(ref progress) = progress {
+        progress.send(ProgressEvent::Started { line }).unwrap();
+    }
+
+    let start = Instant::now();
+    let mut report = DirectoryReport {
+        path: workdir.to_path_buf(),
+        ..DirectoryReport::default()
+    };
+
+    opts = detect_algorithm(workdir, opts);
+    if opts.xattr && !super::xattrs::is_supported(workdir) && opts.loglevel_info() {
+        info!(
+            "{}: extended attributes are not supported, using {}sum.txt",
+            workdir.display(),
+            opts.algorithm
+        );
+    }
+    let mut success = if files.is_none()
+        && opts.trust_mtime
+        && super::util::manifest_is_newest(workdir, &opts)
+    {
+        report.trusted = true;
+        Ok(())
+    } else if let Some(ref progress) = progress {
+        verify_directory_with_progressbar(workdir, files, &opts, line, progress, &mut report, myq)
+    } else {
+        verify_directory_oneshot(workdir, files, &opts, &mut report, myq)
+    };
+
+    if let Some(ref message) = report.error {
+        match progress {
+            Some(ref progress) if opts.loglevel_progress() => {
+                let now = super::util::timestamp(&opts);
+                let message = format!("[{}] {}: {}", now, workdir.display(), message);
+                progress.send(ProgressEvent::Log(message)).unwrap()
+            }
+            _ if !opts.loglevel_quiet() || opts.quiet => {
+                error!("{}: {}", workdir.display(), message)
+            }
+            _ => {}
+        }
+        stop_if_fail_fast(&opts);
+    }
+
+    if opts.repair_from.is_some() && !report.interrupted {
+        super::repair::repair_directory(workdir, &opts, &mut report, progress.as_ref());
+        if !report.has_failures() {
+            success = Ok(());
+        }
+    }
+
+    // the workers finish in arbitrary order
+    report.failed.sort();
+    report.missing.sort();
+    report.metadata_changed.sort();
+    report.unreadable.sort_by(|a, b| a.path.cmp(&b.path));
+    report.duration = start.elapsed();
+
+    if let Some(progress) = progress {
+        progress
+            .send(ProgressEvent::Message {
+                line,
+                message: report.progress_message(&opts),
+            })
+            .unwrap();
+    }
+
+    if success.is_ok() && report.interrupted {
+        // not every file was hashed, the directory is neither known good nor known bad
+        inform_directory_interrupted(workdir, report.duration, Arc::clone(&opts));
+    } else if success.is_ok() && report.trusted {
+        // nothing was hashed, the directory is neither known good nor known bad
+        inform_directory_trusted(workdir, Arc::clone(&opts));
+    } else if success.is_ok() && report.sampled_files.is_none() {
+        // every file from _algorithm_sum.txt was correct
+        inform_directory_good(workdir, &state, report.duration, Arc::clone(&opts));
+    } else if success.is_ok() {
+        // a correct sample does not make the directory known good
+        inform_directory_sample_good(workdir, &report, Arc::clone(&opts));
+    } else {
+        // some files from _algorithm_sum.txt were INCORRECT
+        let failed_paths: Vec<PathBuf> = report
+            .failed
+            .iter()
+            .chain(report.missing.iter())
+            .chain(report.unreadable.iter().map(|file| &file.path))
+            .chain(report.metadata_changed.iter())
+            .cloned()
+            .collect();
+        inform_directory_bad(
+            workdir,
+            &state,
+            report.duration,
+            Arc::clone(&opts),
+            &failed_paths,
+        );
+    }
+
+    tx.send(report).unwrap();
+}
+
+/// Records why a directory could not be verified at all
+///
+/// # Arguments
+/// * `report` The DirectoryReport of the directory
+/// * `message` Description of the error
+///
+/// # Returns
+/// The error to return from the verification of the directory
+fn record_error(report: &mut DirectoryReport, message: String) -> io::Error {
+    report.error = Some(message.clone());
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The _algorithm_sum.txt of one of opts.extra_algorithms, checked in the same pass as the one of opts.algorithm
+struct ExtraManifest {
+    /// The Options using the algorithm of the manifest
+    opts: super::util::Options,
+    /// The manifest of the algorithm
+    manifest: Manifest,
+    /// Matches the hashlines of the algorithm
+    hashline_re: Regex,
+}
+
+/// Reads the _algorithm_sum.txt of every algorithm of opts.extra_algorithms
+///
+/// # Arguments
+/// * `workdir` Path to the directory that is verified
+/// * `opts` The Options object containing the algorithms
+/// * `report` The DirectoryReport the error is recorded in if a file can not be read
+fn read_extra_manifests(
+    workdir: &Path,
+    opts: &super::util::Options,
+    report: &mut DirectoryReport,
+) -> Result<Vec<ExtraManifest>, io::Error> {
+    let mut extras = Vec::new();
+    for &algorithm in &opts.extra_algorithms {
+        let extra_opts = opts.with_algorithm(algorithm);
+        let hashline_re = match super::util::regex_from_opts(&extra_opts) {
+            Ok(re) => re,
+            Err(e) => return Err(record_error(report, e)),
+        };
+        let manifest = match Manifest::read(workdir, &extra_opts) {
+            Ok(manifest) => manifest,
+            Err(e) => return Err(record_manifest_error(report, e, &extra_opts)),
+        };
+        extras.push(ExtraManifest {
+            opts: extra_opts,
+            manifest,
+            hashline_re,
+        });
+    }
+    Ok(extras)
+}
+
+/// Returns the entries of the _algorithm_sum.txt of opts.algorithm, followed by the files
+/// that are only listed in the _algorithm_sum.txt of one of opts.extra_algorithms.
+/// The hash of these files is left empty, as there is nothing to compare with the hash of opts.algorithm.
+///
+/// # Arguments
+/// * `manifest` The _algorithm_sum.txt of opts.algorithm
+/// * `extras` The _algorithm_sum.txt of opts.extra_algorithms
+fn listed_entries(manifest: &Manifest, extras: &[ExtraManifest]) -> Vec<ManifestEntry> {
+    let mut entries = manifest.entries().to_vec();
+    let mut listed: HashSet<PathBuf> = entries.iter().map(|entry| entry.path.clone()).collect();
+    for extra in extras {
+        for entry in extra.manifest.entries() {
+            if listed.insert(entry.path.clone()) {
+                entries.push(ManifestEntry {
+                    hash: String::new(),
+                    ..entry.clone()
+                });
+            }
+        }
+    }
+    entries
+}
+
+/// Compares the hashlines calculated for a file with the hashes recorded in the _algorithm_sum.txt of every algorithm
+///
+/// # Arguments
+/// * `hashlines` The calculated hashlines, one for every algorithm of Options::algorithms
+/// * `cmp` The hash recorded for opts.algorithm, empty if the file is not listed in its _algorithm_sum.txt
+/// * `file_path_re` Matches the hashlines of opts.algorithm
+/// * `extras` The _algorithm_sum.txt of opts.extra_algorithms
+///
+/// # Returns
+/// The path of the file and the hashlines that differ from the recorded hashes, None if the hashlines can not be parsed
+fn changed_hashlines<'a>(
+    hashlines: &'a [String],
+    cmp: &str,
+    file_path_re: &Regex,
+    extras: &[ExtraManifest],
+) -> Option<(PathBuf, Vec<&'a str>)> {
+    let (hash, path) = super::util::parse_hashline(file_path_re, &hashlines[0])?;
+    let mut changed = Vec::new();
+    if !cmp.is_empty() && hash != cmp {
+        changed.push(hashlines[0].trim_end_matches('\n'));
+    }
+
+    for (extra, hashline) in extras.iter().zip(&hashlines[1..]) {
+        let recorded = super::util::parse_hashline(&extra.hashline_re, hashline)
+            .and_then(|(hash, _)| Some((hash, extra.manifest.get(&path)?)));
+        if let Some((hash, entry)) = recorded {
+            if hash != entry.hash {
+                changed.push(hashline.trim_end_matches('\n'));
+            }
+        }
+    }
+
+    Some((path, changed))
+}
+
+/// Records that the _algorithm_sum.txt of a directory could not be read, see record_error
+///
+/// # Arguments
+/// * `report` The DirectoryReport of the directory
+/// * `e` The error reading the _algorithm_sum.txt
+/// * `opts` The Options object containing the algorithm
+fn record_manifest_error(
+    report: &mut DirectoryReport,
+    e: io::Error,
+    opts: &super::util::Options,
+) -> io::Error {
+    record_error(
+        report,
+        format!("could not read {}sum.txt: {}", opts.algorithm, e),
+    )
+}
+
+/// Record workdir as to check, print FAILED if in loglevel info or above
+/// and append all paths to unexpectedly changed files to to_check_workdir.txt.
+/// A rechecked directory is still in the to_check list, its to_check_workdir.txt is replaced atomically
+/// by the paths found in the recheck.
+///
+/// # Arguments
+/// * `workdir` Path to the directory that was just checked
+/// * `state` Sender to the state writer
+/// * `duration` Time it took to check the directory
+/// * `opts` The Options object determining subdir_mode and loglevel
+/// * `failed_paths` Vector of paths to files that have changed
+fn inform_directory_bad(
+    workdir: &Path,
+    state: &Sender<StateLine>,
+    duration: Duration,
+    opts: Arc<super::util::Options>,
+    failed_paths: &[PathBuf],
+) {
+    if opts.loglevel_info() {
+        info!(
+            "Directory {} checked: FAILED in {}",
+            workdir.display(),
+            super::util::format_duration(duration)
+        );
+    }
+    // nothing is written in a dry run
+    if opts.dry_run {
+        return;
+    }
+    if opts.subdir_mode && !opts.recheck {
+        state
+            .send(StateLine::ToCheck(workdir.to_path_buf()))
+            .unwrap();
+    }
+    let bad_hashlines_filepath = bad_files_path(workdir, &opts);
+    if opts.loglevel_debug() {
+        debug!("Filepath for Bad Files: {:?}", bad_hashlines_filepath);
+    }
+    if opts.recheck {
+        let name = bad_hashlines_filepath
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let temp_path = bad_hashlines_filepath.with_file_name(format!(".{}.tmp", name));
+        let result = super::util::write_atomically(&bad_hashlines_filepath, &temp_path, |file| {
+            failed_paths
+                .iter()
+                .try_for_each(|path| write_path_line(file, path))
+        });
+        if let Err(e) = result {
+            error!(
+                "Error writing to file {}: {}",
+                bad_hashlines_filepath.display(),
+                e
+            );
+        }
+        return;
+    }
+    let mut bad_hashlines_file = match OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&bad_hashlines_filepath)
+    {
+        Ok(file) => file,
+        Err(e) => {
+            error!("Could not open {}: {}", bad_hashlines_filepath.display(), e);
+            return;
+        }
+    };
+    for path in failed_paths {
+        if let Err(e) = write_path_line(&mut bad_hashlines_file, path) {
+            error!("Error writing to file: {}", e);
+        }
+    }
+}
+
+/// Record workdir as known good and print OK if in loglevel info or above.
+///
+/// # Arguments
+/// * `workdir` Path to the directory that was just checked
+/// * `state` Sender to the state writer
+/// * `duration` Time it took to check the directory
+/// * `opts` The Options object determining subdir_mode and loglevel
+fn inform_directory_good(
+    workdir: &Path,
+    state: &Sender<StateLine>,
+    duration: Duration,
+    opts: Arc<super::util::Options>,
+) {
+    // rechecked directories are moved to the known_good list by finish_recheck
+    if opts.subdir_mode && !opts.dry_run && !opts.recheck {
+        state
+            .send(StateLine::KnownGood(workdir.to_path_buf()))
+            .unwrap();
+    }
+
+    if opts.loglevel_info() {
+        info!(
+            "{}: checked: OK in {}",
+            workdir.display(),
+            super::util::format_duration(duration)
+        );
     }
-
-    exit_code
 }
 
-/// Verifies the integrity of a directory
+/// Print that the sample of workdir, or the files of it that were given, were correct if in loglevel info or above.
+/// The directory is neither known good nor known bad afterwards.
 ///
 /// # Arguments
-///
-/// * `workdir` Path to the directory that should be verified
-/// * `known_good_path` The file the workdir path gets appended to if the directory is verified to be good
-/// * `to_check_path` The file the workdir path gets appended to if the directory is not verified to be good
-/// * `opts` An Options object containing information about the program behavior
-/// * `print_line` The line to print progressbar and messages to. Only used in loglevel progress.
-/// * `longest_folder` Number of characters in the name of the longest folder, determines how many spaces are padded
-/// * `tx` Sender for sending the supposed exit code for the program.
-fn verify_directory(
-    workdir: &PathBuf,
-    known_good_path: Arc<String>,
-    to_check_path: Arc<String>,
+/// * `workdir` Path to the directory that was just checked
+/// * `report` The DirectoryReport containing the number of checked and listed files and the duration
+/// * `opts` The Options object determining the loglevel and whether or not a sample was checked
+fn inform_directory_sample_good(
+    workdir: &Path,
+    report: &DirectoryReport,
     opts: Arc<super::util::Options>,
-    print_line: u32,
-    longest_folder: usize,
-    myq: Arc<Injector<super::util::HashTask>>,
-    tx: Sender<i32>,
 ) {
     if opts.loglevel_info() {
-        let now: DateTime<chrono::Local> = chrono::Local::now();
-        println!(
-            "[{}] Verifying Directory {}",
-            now,
-            workdir.to_str().unwrap()
+        info!(
+            "{}: {}{} of {} files checked: OK in {}",
+            workdir.display(),
+            if opts.sample.is_some() {
+                "sample of "
+            } else {
+                ""
+            },
+            report.sampled_files.unwrap_or_default(),
+            report.listed_files,
+            super::util::format_duration(report.duration)
         );
     }
-
-    let mut failed_paths = Vec::new();
-
-    let success = if opts.loglevel_progress() {
-        verify_directory_with_progressbar(
-            &workdir,
-            &opts,
-            print_line,
-            &mut failed_paths,
-            longest_folder,
-            myq,
-        )
-    } else {
-        verify_directory_oneshot(&workdir, &opts, &mut failed_paths, myq)
-    };
-
-    if success.is_ok() {
-        // every file from _algorithm_sum.txt was correct
-        inform_directory_good(&workdir, known_good_path, opts);
-        tx.send(0).unwrap();
-    } else {
-        // some files from _algorithm_sum.txt were INCORRECT
-        inform_directory_bad(&workdir, to_check_path, opts, &failed_paths);
-        tx.send(1).unwrap();
-    }
 }
 
-/// Append workdir to the text file in to_check_path, print FAILED if in loglevel info or above
-/// and append all paths to unexpectedly changed files to to_check_workdir.txt
+/// Print that workdir was passed without hashing, because its _algorithm_sum.txt is newer than all of its files,
+/// if in loglevel info or above. The directory is neither known good nor known bad afterwards.
 ///
 /// # Arguments
-/// * `workdir` Path to the directory that was just checked
-/// * `to_check_path` Path to the text file containing all checked and bad directories
-/// * `opts` The Options object determining subdir_mode and loglevel
-/// * `failed_paths` Vector of paths to files that have changed
-fn inform_directory_bad(
-    workdir: &PathBuf,
-    to_check_path: Arc<String>,
-    opts: Arc<super::util::Options>,
-    failed_paths: &[String],
-) {
-    if opts.subdir_mode {
-        let to_check_path: &String = to_check_path.borrow();
-
-        let mut to_check_file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(to_check_path)
-            .unwrap();
-        if let Err(e) = writeln!(to_check_file, "{}", workdir.to_str().unwrap()) {
-            eprintln!("Error writing to file: {}", e);
-        }
-    }
+/// * `workdir` Path to the directory that was just passed
+/// * `opts` The Options object determining the loglevel and the algorithm
+fn inform_directory_trusted(workdir: &Path, opts: Arc<super::util::Options>) {
     if opts.loglevel_info() {
-        let now = chrono::Local::now();
-        println!(
-            "[{}] Directory {} checked: FAILED",
-            now,
-            workdir.to_str().unwrap()
+        info!(
+            "{}: unchanged since its {}sum.txt was written, trusted: OK",
+            workdir.display(),
+            opts.algorithm
         );
     }
-    let mut to_check_dir = workdir.to_str().unwrap();
-    if to_check_dir.len() > 2 {
-        to_check_dir = &to_check_dir[2..];
+}
+
+/// Determines the algorithms of a directory from the names of its _algorithm_sum.txt files.
+/// If opts.detect_algorithm is set, every _algorithm_sum.txt is checked, otherwise the ones of the chosen algorithms
+/// that exist. Directories without any of these keep the chosen algorithms.
+///
+/// # Arguments
+/// * `workdir` Path to the directory that is going to be verified
+/// * `opts` The Options object containing the chosen algorithms
+///
+/// # Returns
+/// The Options object for the directory, the first algorithm found becomes opts.algorithm and the others
+/// opts.extra_algorithms.
+fn detect_algorithm(workdir: &Path, opts: Arc<super::util::Options>) -> Arc<super::util::Options> {
+    let mut algorithms = Manifest::find_algorithms(workdir);
+    if !opts.detect_algorithm {
+        let chosen = opts.algorithms();
+        algorithms = chosen
+            .into_iter()
+            .filter(|algorithm| algorithms.contains(algorithm))
+            .collect();
+    }
+    if algorithms.is_empty() || algorithms == opts.algorithms() {
+        return opts;
     }
-    let bad_hashlines_filepath = format!("to_check_{}.txt", to_check_dir);
+
     if opts.loglevel_debug() {
-        println!("Filepath for Bad Files: {:?}", bad_hashlines_filepath);
+        let names: Vec<&str> = algorithms
+            .iter()
+            .map(|algorithm| algorithm.name())
+            .collect();
+        debug!(
+            "{}: detected algorithms {}",
+            workdir.display(),
+            names.join(", ")
+        );
     }
-    let mut bad_hashlines_file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(bad_hashlines_filepath)
-        .unwrap();
-    for line in failed_paths {
-        if let Err(e) = writeln!(bad_hashlines_file, "{}", line) {
-            eprintln!("Error writing to file: {}", e);
-        }
+    Arc::new(super::util::Options {
+        algorithm: algorithms[0],
+        extra_algorithms: algorithms[1..].to_vec(),
+        ..(*opts).clone()
+    })
+}
+
+/// Stops the run if opts.fail_fast is set, after a file failed
+///
+/// # Arguments
+/// * `opts` The Options object determining whether or not to stop
+fn stop_if_fail_fast(opts: &super::util::Options) {
+    // stop returns false for every failure after the first one, which is only announced once
+    if opts.fail_fast && super::util::stop() && opts.loglevel_info() {
+        info!("Stopping at the first failure");
     }
 }
 
-/// Append workdir to the text file in known_good_path and print OK if in loglevel info or above.
+/// Print that the verification of workdir was interrupted if in loglevel info or above.
 ///
 /// # Arguments
-/// * `workdir` Path to the directory that was just checked
-/// * `known_good_path` Path to the text file containing all checked and good directories
-/// * `opts` The Options object determining subdir_mode and loglevel
-fn inform_directory_good(
-    workdir: &PathBuf,
-    known_good_path: Arc<String>,
+/// * `workdir` Path to the directory that was interrupted
+/// * `duration` Time until the directory was interrupted
+/// * `opts` The Options object determining the loglevel
+fn inform_directory_interrupted(
+    workdir: &Path,
+    duration: Duration,
     opts: Arc<super::util::Options>,
 ) {
-    if opts.subdir_mode {
-        let known_good_path: &String = known_good_path.borrow();
-
-        let mut known_good_file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(known_good_path)
-            .unwrap();
-        if let Err(e) = writeln!(known_good_file, "{}", workdir.to_str().unwrap()) {
-            eprintln!("Error writing to file: {}", e);
-        }
-    }
-
     if opts.loglevel_info() {
-        let now = chrono::Local::now();
-        println!("[{}] {}: checked: OK", now, workdir.to_str().unwrap());
+        info!(
+            "{}: interrupted after {}",
+            workdir.display(),
+            super::util::format_duration(duration)
+        );
     }
 }
 
@@ -421,82 +1441,134 @@ fn inform_directory_good(
 /// # Arguments
 ///
 /// * `workdir` Path to the directory that should be verified
+/// * `files` The files of workdir to verify, every listed file if None
 /// * `opts` An Options object containing information about the program behavior
-/// * `failed_paths` Reference to a Vector of Paths to files that have changed unexpectedly
+/// * `report` The DirectoryReport that changed, missing, unreadable and hashed files are recorded in
 fn verify_directory_oneshot(
-    workdir: &PathBuf,
+    workdir: &Path,
+    files: Option<&[PathBuf]>,
     opts: &Arc<super::util::Options>,
-    failed_paths: &mut Vec<String>,
+    report: &mut DirectoryReport,
     myq: Arc<Injector<super::util::HashTask>>,
 ) -> Result<(), io::Error> {
-    let file_path_re = match super::util::regex_from_opts(&opts) {
+    let file_path_re = match super::util::regex_from_opts(opts) {
         Ok(re) => re,
-        Err(e) => panic!(e),
+        Err(e) => return Err(record_error(report, e)),
     };
     let mut success = true;
 
-    let file = match OpenOptions::new()
-        .read(true)
-        .append(true)
-        .create(true)
-        .open(format!(
-            "{}/{}sum.txt",
-            workdir.to_str().unwrap(),
-            opts.algorithm
-        )) {
-        Ok(f) => f,
-        Err(e) => panic!(e),
+    let manifest = match Manifest::read(workdir, opts) {
+        Ok(manifest) => manifest,
+        Err(e) => return Err(record_manifest_error(report, e, opts)),
     };
 
     let (sender, receiver) = channel();
 
-    for line in BufReader::new(file).lines() {
-        if let Ok(line) = line {
-            if let Some(captures) = file_path_re.captures(&line) {
-                let hash = &captures[1];
-                let path = &captures[2];
-
-                let task = super::util::HashTask {
-                    path: String::from(path),
-                    workdir: PathBuf::from(workdir),
-                    opts: Arc::clone(&opts),
-                    cmp: String::from(hash),
-                    result_chan: sender.clone(),
-                };
-
-                myq.push(task);
-            }
-        }
+    let extras = read_extra_manifests(workdir, opts, report)?;
+
+    let block_manifest = read_block_manifest(workdir, opts);
+    let (block_sender, block_receiver) = channel();
+    let mut changed = Vec::new();
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let read_bytes = Arc::new(AtomicU64::new(0));
+
+    let listed = listed_entries(&manifest, &extras);
+    let entries = select_entries(workdir, &listed, files, opts);
+    report.listed_files = listed.len();
+    if opts.sample.is_some() || files.is_some() {
+        report.sampled_files = Some(entries.len());
+    }
+    success &= record_malformed_lines(&manifest, opts, report);
+    record_duplicates(&manifest, opts, report);
+    for extra in &extras {
+        success &= record_malformed_lines(&extra.manifest, &extra.opts, report);
+        record_duplicates(&extra.manifest, &extra.opts, report);
+    }
+    if let Some(files) = files {
+        success &= record_unlisted_files(files, &entries, opts, report);
+    }
+    let checked = skip_trusted_files(workdir, &entries, opts, report);
+    let recorded_metadata = recorded_metadata(&checked, opts);
+
+    for entry in &checked {
+        let task = super::util::HashTask {
+            path: entry.path.clone(),
+            workdir: PathBuf::from(workdir),
+            opts: Arc::clone(opts),
+            cmp: entry.hash.clone(),
+            result_chan: sender.clone(),
+            blocks: block_manifest.get(&entry.path).map(|hashes| BlockRequest {
+                block_size: hashes.block_size,
+                result_chan: block_sender.clone(),
+            }),
+            cancelled: Some(Arc::clone(&cancelled)),
+            read_bytes: Some(Arc::clone(&read_bytes)),
+        };
+
+        myq.push(task);
     }
 
     drop(sender);
+    drop(block_sender);
 
-    for task_result in receiver {
+    // tasks that are dropped after an interrupt send no result
+    let mut unfinished = checked.len();
+    while let Some(task_result) = next_result(&receiver, &read_bytes, opts, report, None) {
+        unfinished -= 1;
         match task_result {
-            Ok((mut hashline, cmp)) => {
-                hashline.pop();
-                if let Some(new_captures) = file_path_re.captures(&hashline) {
-                    let new_hash = &new_captures[1];
-                    if new_hash != cmp {
+            Ok((hashlines, cmp)) => {
+                if let Some((new_path, changed_lines)) =
+                    changed_hashlines(&hashlines, &cmp, &file_path_re, &extras)
+                {
+                    if !changed_lines.is_empty() {
                         if opts.loglevel_info() {
-                            let now: DateTime<chrono::Local> = chrono::Local::now();
-                            println!("[{}] {}: {}", now, workdir.to_str().unwrap(), hashline);
+                            for hashline in changed_lines {
+                                info!("{}: {}", workdir.display(), hashline);
+                            }
                         }
-                        failed_paths.push(String::from(&new_captures[2]));
+                        report.failed.push(new_path.clone());
+                        changed.push(new_path.clone());
+                        stop_if_fail_fast(opts);
                         success = false;
+                    } else if metadata_changed(workdir, &new_path, &recorded_metadata, opts, report)
+                    {
+                        success = false;
+                    }
+
+                    if let Ok(metadata) = fs::metadata(workdir.join(&new_path)) {
+                        report.bytes += metadata.len();
                     }
                 }
             }
             Err(e) => {
-                let now: DateTime<chrono::Local> = chrono::Local::now();
-                eprintln!("[{}] {}: {}", now, workdir.to_str().unwrap(), e);
+                if !opts.loglevel_quiet() {
+                    error!("{}: {}", workdir.display(), e);
+                }
 
-                failed_paths.push(e.to_string());
+                if e.is_not_found() {
+                    report.missing.push(e.path().to_path_buf());
+                } else {
+                    report.unreadable.push(UnreadableFile::from_error(&e));
+                }
+                stop_if_fail_fast(opts);
                 success = false;
             }
         }
     }
 
+    report.interrupted = unfinished > 0 && !report.timed_out;
+    success &= !report.timed_out;
+    // a worker may still be stuck on a file, the files that are left are not hashed anymore
+    cancelled.store(true, Ordering::Relaxed);
+    compare_blocks(
+        workdir,
+        opts,
+        &block_manifest,
+        &changed,
+        block_receiver,
+        report,
+    );
+
     if success {
         Ok(())
     } else {
@@ -507,125 +1579,191 @@ fn verify_directory_oneshot(
     }
 }
 
-/// Verifies the integrity of a directory and printing a progressbar along the way
+/// Verifies the integrity of a directory and reporting its progress to the printer thread along the way
 ///
 /// # Arguments
 ///
 /// * `workdir` Path to the directory that should be verified
+/// * `files` The files of workdir to verify, every listed file if None
 /// * `opts` An Options object containing information about the program behavior
-/// * `print_line` Number of lines to scroll up before printing the progressbar
-/// * `failed_paths` Reference to a Vector of Paths to files that have changed unexpectedly
-/// * `longest_folder` Number of characters in the name of the longest folder
+/// * `line` The index of workdir in the directories of the printer thread
+/// * `progress` Sender to the printer thread
+/// * `report` The DirectoryReport that changed, missing, unreadable and hashed files are recorded in
+#[allow(clippy::too_many_arguments)]
 fn verify_directory_with_progressbar(
-    workdir: &PathBuf,
+    workdir: &Path,
+    files: Option<&[PathBuf]>,
     opts: &Arc<super::util::Options>,
-    print_line: u32,
-    failed_paths: &mut Vec<String>,
-    longest_folder: usize,
+    line: usize,
+    progress: &Sender<ProgressEvent>,
+    report: &mut DirectoryReport,
     myq: Arc<Injector<super::util::HashTask>>,
 ) -> Result<(), io::Error> {
     let mut processed_bytes: u64 = 0;
-    let file_path_re = match super::util::regex_from_opts(&opts) {
-        Ok(re) => Arc::new(re),
-        Err(e) => panic!(e),
+    let file_path_re = match super::util::regex_from_opts(opts) {
+        Ok(re) => re,
+        Err(e) => return Err(record_error(report, e)),
     };
-    let all_bytes = count_bytes_from_txt(workdir, opts, &file_path_re);
-    let workdir_str = workdir.to_str().unwrap();
-    let workdir_updater = String::from(workdir_str);
-    let file_path_re_updater = Arc::clone(&file_path_re);
-    let (tx_result, rx_result): (
-        Sender<Result<(String, String), HashError>>,
-        Receiver<Result<(String, String), HashError>>,
-    ) = channel();
-    let (tx_paths, rx_paths) = channel();
-
-    print_progress(
-        all_bytes,
-        processed_bytes,
-        print_line,
-        workdir_str,
-        longest_folder,
-    )?;
-
-    let file = match OpenOptions::new()
-        .read(true)
-        .append(true)
-        .create(true)
-        .open(format!(
-            "{}/{}sum.txt",
-            workdir.to_str().unwrap(),
-            opts.algorithm
-        )) {
-        Ok(f) => f,
-        Err(e) => panic!(e),
+    let manifest = match Manifest::read(workdir, opts) {
+        Ok(manifest) => manifest,
+        Err(e) => return Err(record_manifest_error(report, e, opts)),
     };
+    let extras = read_extra_manifests(workdir, opts, report)?;
+
+    let listed = listed_entries(&manifest, &extras);
+    let entries = select_entries(workdir, &listed, files, opts);
+    let checked = skip_trusted_files(workdir, &entries, opts, report);
+    let recorded_metadata = recorded_metadata(&checked, opts);
+    let all_bytes = count_bytes(workdir, &checked);
+    let mut throughput = Throughput::new();
+    let (sender, receiver): (Sender<HashResult>, Receiver<HashResult>) = channel();
+
+    progress
+        .send(ProgressEvent::Progress {
+            line,
+            processed_bytes,
+            all_bytes,
+            bytes_per_sec: 0.0,
+            eta: None,
+        })
+        .unwrap();
 
-    let updater_handle = std::thread::spawn(move || {
-        for task_result in rx_result {
-            match task_result {
-                Ok((mut hashline, cmp)) => {
-                    hashline.pop();
-                    if let Some(new_captures) = file_path_re_updater.captures(&hashline) {
-                        let new_hash = &new_captures[1];
-                        if new_hash != cmp {
-                            tx_paths.send(String::from(&new_captures[2])).unwrap();
-                        }
+    let block_manifest = read_block_manifest(workdir, opts);
+    let (block_sender, block_receiver) = channel();
+    let mut changed = Vec::new();
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let read_bytes = Arc::new(AtomicU64::new(0));
+
+    report.listed_files = listed.len();
+    if opts.sample.is_some() || files.is_some() {
+        report.sampled_files = Some(entries.len());
+    }
+    record_malformed_lines(&manifest, opts, report);
+    record_duplicates(&manifest, opts, report);
+    for extra in &extras {
+        record_malformed_lines(&extra.manifest, &extra.opts, report);
+        record_duplicates(&extra.manifest, &extra.opts, report);
+    }
+    if let Some(files) = files {
+        record_unlisted_files(files, &entries, opts, report);
+    }
 
-                        let metadata =
-                            fs::metadata(format!("{}/{}", workdir_updater, &new_captures[2]));
-                        if let Ok(metadata) = metadata {
-                            processed_bytes += metadata.len();
+    for entry in &checked {
+        let task = super::util::HashTask {
+            path: entry.path.clone(),
+            workdir: PathBuf::from(workdir),
+            opts: Arc::clone(opts),
+            cmp: entry.hash.clone(),
+            result_chan: sender.clone(),
+            blocks: block_manifest.get(&entry.path).map(|hashes| BlockRequest {
+                block_size: hashes.block_size,
+                result_chan: block_sender.clone(),
+            }),
+            cancelled: Some(Arc::clone(&cancelled)),
+            read_bytes: Some(Arc::clone(&read_bytes)),
+        };
+
+        myq.push(task);
+    }
+
+    drop(sender);
+    drop(block_sender);
+
+    // tasks that are dropped after an interrupt send no result
+    let mut unfinished = checked.len();
+    while let Some(task_result) = next_result(&receiver, &read_bytes, opts, report, Some(progress))
+    {
+        unfinished -= 1;
+        match task_result {
+            Ok((hashlines, cmp)) => {
+                if let Some((new_path, changed_lines)) =
+                    changed_hashlines(&hashlines, &cmp, &file_path_re, &extras)
+                {
+                    let outcome = if !changed_lines.is_empty() {
+                        FileOutcome::Changed
+                    } else if metadata_changed(workdir, &new_path, &recorded_metadata, opts, report)
+                    {
+                        FileOutcome::MetadataChanged
+                    } else {
+                        FileOutcome::Ok
+                    };
+                    if !changed_lines.is_empty() {
+                        if opts.loglevel_info() {
+                            for hashline in changed_lines {
+                                info!("{}: {}", workdir.display(), hashline);
+                            }
                         }
+                        report.failed.push(new_path.clone());
+                        changed.push(new_path.clone());
+                        stop_if_fail_fast(opts);
                     }
 
-                    print_progress(
-                        all_bytes,
+                    let bytes =
+                        fs::metadata(workdir.join(&new_path)).map_or(0, |metadata| metadata.len());
+                    processed_bytes += bytes;
+                    progress::send_file(
+                        Some(progress),
+                        line,
+                        workdir,
+                        &new_path,
+                        bytes,
+                        outcome,
+                        None,
+                    );
+                }
+
+                let bytes_per_sec = throughput.update(processed_bytes);
+                progress
+                    .send(ProgressEvent::Progress {
+                        line,
                         processed_bytes,
-                        print_line,
-                        &workdir_updater,
-                        longest_folder,
-                    )
+                        all_bytes,
+                        bytes_per_sec,
+                        eta: throughput.eta(processed_bytes, all_bytes),
+                    })
                     .unwrap();
+            }
+            Err(e) => {
+                if opts.loglevel_progress() {
+                    let now = super::util::timestamp(opts);
+                    let message = format!("[{}] {}: {}", now, workdir.display(), e);
+                    progress.send(ProgressEvent::Log(message)).unwrap();
+                } else if !opts.loglevel_quiet() {
+                    error!("{}: {}", workdir.display(), e);
                 }
-                Err(e) => {
-                    tx_paths.send(e.to_string()).unwrap();
+                if e.is_not_found() {
+                    report.missing.push(e.path().to_path_buf());
+                } else {
+                    report.unreadable.push(UnreadableFile::from_error(&e));
                 }
+                let outcome = FileOutcome::from_error(&e);
+                progress::send_file(Some(progress), line, workdir, e.path(), 0, outcome, None);
+                stop_if_fail_fast(opts);
             }
         }
-    });
-
-    for line in BufReader::new(file).lines() {
-        if let Ok(line) = line {
-            if let Some(captures) = file_path_re.captures(&line) {
-                let hash = &captures[1];
-                let path = &captures[2];
-
-                let task = super::util::HashTask {
-                    path: String::from(path),
-                    workdir: PathBuf::from(workdir),
-                    opts: Arc::clone(&opts),
-                    cmp: String::from(hash),
-                    result_chan: tx_result.clone(),
-                };
-
-                myq.push(task);
-            }
-        }
-    }
-
-    drop(tx_result);
-
-    for path in rx_paths {
-        failed_paths.push(path);
     }
 
-    updater_handle.join().unwrap();
+    report.bytes = processed_bytes;
+    report.interrupted = unfinished > 0 && !report.timed_out;
+    // a worker may still be stuck on a file, the files that are left are not hashed anymore
+    cancelled.store(true, Ordering::Relaxed);
+    compare_blocks(
+        workdir,
+        opts,
+        &block_manifest,
+        &changed,
+        block_receiver,
+        report,
+    );
 
-    if failed_paths.is_empty() {
-        print_message_aligned(print_line, "checked: OK", workdir_str, longest_folder)?;
+    if report.timed_out {
+        Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "No file finished in time",
+        ))
+    } else if !report.has_failures() {
         Ok(())
     } else {
-        print_message_aligned(print_line, "checked: FAILED", workdir_str, longest_folder)?;
         Err(io::Error::new(
             io::ErrorKind::InvalidData,
             "Some files changed unexpectedly",
@@ -633,118 +1771,500 @@ fn verify_directory_with_progressbar(
     }
 }
 
-/// Reads all files from an _algorithm_sum.txt and accumulates all bytes
+/// Waits for the result of the next file of a directory.
+/// With opts.timeout, verification gives up on the directory if neither a file finished nor any byte of its files
+/// was read in time, which is printed and recorded in the report. A large file that is read slowly keeps the
+/// directory going, only a read that makes no progress at all runs into the timeout.
+/// The worker thread stuck on such a read can not be stopped. It is left behind, so every directory given up on
+/// this way leaves the remaining directories with one worker thread less until the read returns.
 ///
 /// # Arguments
-/// * `workdir` PathBuf to the current working directory with an _algorithm_sum.txt inside
-/// * `opts` The Options object containing the chosen algorithm
-/// * `file_path_re` Regex used to extrapolate the filepath from the line containing filepath and hash
-fn count_bytes_from_txt(
-    workdir: &PathBuf,
-    opts: &Arc<super::util::Options>,
-    file_path_re: &regex::Regex,
-) -> u64 {
-    let mut all_bytes = 0;
-
-    let file = match OpenOptions::new()
-        .read(true)
-        .append(true)
-        .create(true)
-        .open(format!(
-            "{}/{}sum.txt",
-            workdir.to_str().unwrap(),
-            opts.algorithm
-        )) {
-        Ok(f) => f,
-        Err(e) => panic!(e),
+/// * `receiver` Receiver of the results of the files of the directory
+/// * `read_bytes` Counter of the bytes the worker threads read of the files of the directory
+/// * `opts` The Options object containing the timeout and the loglevel
+/// * `report` The DirectoryReport of the directory
+/// * `progress` Sender to the printer thread, only present in loglevel progress
+///
+/// # Returns
+/// The next result, None if every result was received or the directory timed out
+fn next_result(
+    receiver: &Receiver<HashResult>,
+    read_bytes: &AtomicU64,
+    opts: &super::util::Options,
+    report: &mut DirectoryReport,
+    progress: Option<&Sender<ProgressEvent>>,
+) -> Option<HashResult> {
+    let timeout = match opts.timeout {
+        Some(timeout) => timeout,
+        None => return receiver.recv().ok(),
     };
 
-    for line in BufReader::new(file).lines() {
-        if let Ok(line) = line {
-            if let Some(captures) = file_path_re.captures(&line) {
-                let path = &captures[2];
-                let metadata = fs::metadata(format!("{}/{}", workdir.to_str().unwrap(), path));
-                if let Ok(metadata) = metadata {
-                    all_bytes += metadata.len();
+    let mut last_read_bytes = read_bytes.load(Ordering::Relaxed);
+    let mut last_activity = Instant::now();
+    loop {
+        match receiver.recv_timeout(ACTIVITY_INTERVAL.min(timeout)) {
+            Ok(task_result) => return Some(task_result),
+            Err(RecvTimeoutError::Disconnected) => return None,
+            Err(RecvTimeoutError::Timeout) => {
+                let current_read_bytes = read_bytes.load(Ordering::Relaxed);
+                if current_read_bytes != last_read_bytes {
+                    last_read_bytes = current_read_bytes;
+                    last_activity = Instant::now();
+                } else if last_activity.elapsed() >= timeout {
+                    break;
                 }
             }
         }
     }
 
-    all_bytes
+    let message = format!(
+        "{}: nothing was read within {} seconds, giving up on the directory",
+        report.path.display(),
+        timeout.as_secs()
+    );
+    match progress {
+        Some(progress) => {
+            let now = super::util::timestamp(opts);
+            let message = format!("[{}] {}", now, message);
+            progress.send(ProgressEvent::Log(message)).unwrap()
+        }
+        None if !opts.loglevel_quiet() => error!("{}", message),
+        None => {}
+    }
+    report.timed_out = true;
+    stop_if_fail_fast(opts);
+    None
 }
 
-/// Produce a String containing workdir, progress percentage and progress bar, then printing it with print_message
+/// Reads the _algorithm_sum.blocks.txt of a directory.
+/// Without it, changed files are reported without their changed blocks,
+/// so the reason it could not be read is only printed in loglevel debug.
 ///
 /// # Arguments
-/// * `all_bytes` Number of bytes in this working directory that are listed in _algorithm_sum.txt
-/// * `processed_bytes` Number of already processed bytes
-/// * `line` Number of lines to scroll up before printing the message
-/// * `workdir` String containing the current working directory, which is printed before the message
-/// * `longest_folder` Number of characters in the name of the longest folder, determines how many spaces are padded
-fn print_progress(
-    all_bytes: u64,
-    processed_bytes: u64,
-    line: u32,
-    workdir: &str,
-    longest_folder: usize,
-) -> Result<(), io::Error> {
-    let progress = processed_bytes as f64 / all_bytes as f64;
-    let mut message = format!("{:05.2}% ", progress * 100.0);
+/// * `workdir` Path to the verified directory
+/// * `opts` The Options object determining the algorithm and the loglevel
+fn read_block_manifest(workdir: &Path, opts: &super::util::Options) -> BlockManifest {
+    match BlockManifest::read(workdir, opts.algorithm) {
+        Ok(block_manifest) => block_manifest,
+        Err(e) => {
+            if opts.loglevel_debug() {
+                debug!(
+                    "{}: could not read {}sum.blocks.txt: {}",
+                    workdir.display(),
+                    opts.algorithm,
+                    e
+                );
+            }
+            BlockManifest::default()
+        }
+    }
+}
 
-    let progress_bar = 60.0 * progress;
-    for i in 0..60 {
-        if (f64::from(i)) < progress_bar {
-            message = format!("{}#", message);
-        } else {
-            message = format!("{}_", message);
+/// Compares the hashes of the blocks of changed files to the recorded ones
+/// and records which blocks changed in the report. Files without block hashes are left out.
+///
+/// # Arguments
+/// * `workdir` Path to the verified directory
+/// * `opts` The Options object determining the loglevel
+/// * `recorded` The block hashes from the _algorithm_sum.blocks.txt
+/// * `changed` Paths to the files whose hash changed
+/// * `current` Receiver of the block hashes calculated during this verification
+/// * `report` The DirectoryReport the changed blocks are recorded in
+fn compare_blocks(
+    workdir: &Path,
+    opts: &super::util::Options,
+    recorded: &BlockManifest,
+    changed: &[PathBuf],
+    current: Receiver<BlockHashes>,
+    report: &mut DirectoryReport,
+) {
+    if changed.is_empty() {
+        return;
+    }
+
+    let current: HashMap<PathBuf, BlockHashes> = current
+        .try_iter()
+        .map(|hashes| (hashes.path.clone(), hashes))
+        .collect();
+
+    for path in changed {
+        let (recorded, current) = match (recorded.get(path), current.get(path)) {
+            (Some(recorded), Some(current)) => (recorded, current),
+            _ => continue,
+        };
+
+        let blocks = ChangedBlocks {
+            path: path.to_string_lossy().to_string(),
+            block_size: recorded.block_size,
+            blocks: recorded.hashes.len().max(current.hashes.len()) as u64,
+            ranges: recorded.differing_blocks(current),
+        };
+        if opts.loglevel_info() {
+            info!(
+                "{}: {}: {}",
+                workdir.display(),
+                blocks.path,
+                blocks.describe()
+            );
         }
+        report.changed_blocks.push(blocks);
     }
 
-    print_message_aligned(line, &message, workdir, longest_folder)
+    report.changed_blocks.sort_by(|a, b| a.path.cmp(&b.path));
 }
 
-/// Print a message N lines above the current cursor.
-/// Cursor position is saved and restored after this operation.
-/// The line is cleared before printing.
+/// Returns the entries to check without the ones of the files that are trusted with opts.trust_xattr.
+/// A file is trusted if the extended attribute of opts.algorithm holds the hash of its entry
+/// and the size and modification time stored with it are still the ones of the file.
+/// The number of trusted files is recorded in the report.
 ///
 /// # Arguments
-/// * `line` Number of lines to scroll up before printing the message
-/// * `message` String to print
-/// * `workdir` String containing the current working directory, which is printed before the message
-fn print_message(line: u32, message: &str, workdir: &str) -> Result<(), io::Error> {
-    let stdout = io::stdout();
-    let mut handle = stdout.lock();
-    handle.write_all(b"\x1b[s")?;
-    write!(handle, "\x1b[{}A\x1b[2K", line)?;
-    write!(handle, "{}: {}", workdir, message)?;
-    handle.write_all(b"\x1b[u")?;
-    io::stdout().flush()
+/// * `workdir` Path to the directory of the _algorithm_sum.txt
+/// * `entries` The selected entries
+/// * `opts` The Options object containing the algorithm and whether or not to trust extended attributes
+/// * `report` The DirectoryReport the number of trusted files is recorded in
+fn skip_trusted_files<'a>(
+    workdir: &Path,
+    entries: &[&'a ManifestEntry],
+    opts: &super::util::Options,
+    report: &mut DirectoryReport,
+) -> Vec<&'a ManifestEntry> {
+    if !opts.trust_xattr {
+        return entries.to_vec();
+    }
+
+    let mut checked = entries.to_vec();
+    checked.retain(|entry| {
+        let path = workdir.join(&entry.path);
+        let stored = match super::xattrs::read(&path, opts.algorithm) {
+            Ok(Some(stored)) => stored,
+            _ => return true,
+        };
+        let stamp = fs::metadata(&path)
+            .ok()
+            .and_then(|metadata| FileStamp::from_metadata(&metadata));
+        stored.0 != entry.hash || stamp.as_ref() != Some(&stored.1)
+    });
+    report.trusted_files = entries.len() - checked.len();
+
+    if report.trusted_files > 0 && opts.loglevel_info() {
+        info!(
+            "{}: {} files trusted from their extended attributes",
+            workdir.display(),
+            report.trusted_files
+        );
+    }
+    checked
 }
 
-/// Print a message N lines above the current cursor.
-/// Cursor position is saved and restored after this operation.
-/// The line is cleared before printing.
-/// The message gets padded to the left with spaces in order to align it with
-/// other messages on other lines, using longest_folder as an indicator of needed padding.
+/// Returns the permissions, owner and group recorded for the checked files by their path,
+/// nothing without opts.check_metadata
 ///
 /// # Arguments
-/// * `line` Number of lines to scroll up before printing the message
-/// * `message` String to print
-/// * `workdir` String containing the current working directory, which is printed before the message
-/// * `longest_folder` length of the name of the longest folder in the current workset
-fn print_message_aligned(
-    line: u32,
-    message: &str,
-    workdir: &str,
-    longest_folder: usize,
-) -> Result<(), io::Error> {
-    let mut padding = String::new();
-    let mut i = workdir.len();
-    while i < longest_folder {
-        padding = format!("{} ", padding);
-        i += 1;
-    }
-    let to_print = &format!("{} {}", padding, message);
-    print_message(line, to_print, workdir)
+/// * `entries` The entries of the files that are checked
+/// * `opts` The Options object determining whether or not the metadata is checked
+fn recorded_metadata<'a>(
+    entries: &[&'a ManifestEntry],
+    opts: &super::util::Options,
+) -> HashMap<&'a Path, FileMetadata> {
+    if !opts.check_metadata {
+        return HashMap::new();
+    }
+    entries
+        .iter()
+        .filter_map(|entry| Some((entry.path.as_path(), entry.metadata?)))
+        .collect()
+}
+
+/// Compares the permissions, owner and group of a file whose content is correct with the recorded ones.
+/// A change is logged in loglevel info and recorded in the report. Files without recorded metadata
+/// and platforms without unix permissions are never reported.
+///
+/// # Arguments
+/// * `workdir` Path to the verified directory
+/// * `file_path` Path to the file, relative to workdir
+/// * `recorded` The recorded metadata of the checked files, see recorded_metadata
+/// * `opts` The Options object containing the symlink policy and the loglevel
+/// * `report` The DirectoryReport the file is recorded in
+///
+/// # Returns
+/// Whether or not the metadata of the file changed
+fn metadata_changed(
+    workdir: &Path,
+    file_path: &Path,
+    recorded: &HashMap<&Path, FileMetadata>,
+    opts: &super::util::Options,
+    report: &mut DirectoryReport,
+) -> bool {
+    let recorded = match recorded.get(file_path) {
+        Some(recorded) => recorded,
+        None => return false,
+    };
+    let metadata = if opts.symlinks == Symlinks::HashLink {
+        fs::symlink_metadata(workdir.join(file_path))
+    } else {
+        fs::metadata(workdir.join(file_path))
+    };
+    let current = match metadata.ok().as_ref().and_then(FileMetadata::from_metadata) {
+        Some(current) => current,
+        None => return false,
+    };
+    if current == *recorded {
+        return false;
+    }
+
+    if opts.loglevel_info() {
+        info!(
+            "{}: {}: metadata changed, {}",
+            workdir.display(),
+            file_path.display(),
+            recorded.describe_change(&current)
+        );
+    }
+    report.metadata_changed.push(file_path.to_path_buf());
+    stop_if_fail_fast(opts);
+    true
+}
+
+/// Sums up the sizes of the files of some entries of an _algorithm_sum.txt, skipping files that do not exist
+///
+/// # Arguments
+/// * `workdir` Path to the directory of the _algorithm_sum.txt
+/// * `entries` The entries whose files are checked
+fn count_bytes(workdir: &Path, entries: &[&ManifestEntry]) -> u64 {
+    entries
+        .iter()
+        .filter_map(|entry| fs::metadata(workdir.join(&entry.path)).ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// Selects the entries of an _algorithm_sum.txt that are checked.
+/// If files are given, these are the entries of the files, otherwise the entries selected by sample_entries
+/// among the ones whose files are not excluded by a .arkhashignore.
+///
+/// # Arguments
+/// * `workdir` Path to the directory of the _algorithm_sum.txt
+/// * `entries` The entries of the _algorithm_sum.txt
+/// * `files` The files to check, relative to workdir
+/// * `opts` The Options object containing the sample and the seed
+fn select_entries<'a>(
+    workdir: &Path,
+    entries: &'a [ManifestEntry],
+    files: Option<&[PathBuf]>,
+    opts: &super::util::Options,
+) -> Vec<&'a ManifestEntry> {
+    match files {
+        Some(files) => entries
+            .iter()
+            .filter(|entry| files.contains(&entry.path))
+            .collect(),
+        None => {
+            let mut ignores = IgnoreCache::excluding(&opts.exclude);
+            let entries = entries
+                .iter()
+                .filter(|entry| !ignores.is_ignored(workdir, &entry.path))
+                .collect();
+            sample_entries(workdir, entries, opts)
+        }
+    }
+}
+
+/// Records the given files that are not listed in the _algorithm_sum.txt as unreadable
+///
+/// # Arguments
+/// * `files` The files to check, relative to the directory of the _algorithm_sum.txt
+/// * `entries` The selected entries of the _algorithm_sum.txt
+/// * `opts` The Options object containing the algorithm and the loglevel
+/// * `report` The DirectoryReport the files are recorded in
+///
+/// # Returns
+/// Whether or not every file is listed
+fn record_unlisted_files(
+    files: &[PathBuf],
+    entries: &[&ManifestEntry],
+    opts: &super::util::Options,
+    report: &mut DirectoryReport,
+) -> bool {
+    let mut all_listed = true;
+    for file in files {
+        if entries.iter().any(|entry| entry.path == *file) {
+            continue;
+        }
+        let reason = format!("not listed in {}sum.txt", opts.algorithm);
+        if !opts.loglevel_quiet() {
+            error!("{}: {}: {}", report.path.display(), file.display(), reason);
+        }
+        report.unreadable.push(UnreadableFile {
+            path: file.to_path_buf(),
+            reason: UnreadableReason::IoError(reason),
+        });
+        stop_if_fail_fast(opts);
+        all_listed = false;
+    }
+    all_listed
+}
+
+/// Records the lines of the _algorithm_sum.txt that could not be read, so a line with a truncated hash
+/// is reported instead of leaving its file unchecked
+///
+/// # Arguments
+/// * `manifest` The _algorithm_sum.txt of the directory
+/// * `opts` The Options object containing the algorithm and the loglevel
+/// * `report` The DirectoryReport the lines are recorded in
+///
+/// # Returns
+/// Whether or not every line could be read
+fn record_malformed_lines(
+    manifest: &Manifest,
+    opts: &super::util::Options,
+    report: &mut DirectoryReport,
+) -> bool {
+    for &line in manifest.malformed_lines() {
+        if !opts.loglevel_quiet() {
+            error!(
+                "{}: line {} of {}sum.txt is improperly formatted",
+                report.path.display(),
+                line,
+                opts.algorithm
+            );
+        }
+        report.malformed_lines.push(line);
+        stop_if_fail_fast(opts);
+    }
+    manifest.malformed_lines().is_empty()
+}
+
+/// Reports the paths that are listed more than once in an _algorithm_sum.txt.
+/// Such a file is hashed only once and compared with the hash of its last line, so duplicates do not fail the directory.
+/// Duplicates with different hashes are printed as errors, the others are summarized in an info message.
+///
+/// # Arguments
+/// * `manifest` The _algorithm_sum.txt of the directory
+/// * `opts` The Options object containing the algorithm and the loglevel
+/// * `report` The DirectoryReport of the directory
+fn record_duplicates(manifest: &Manifest, opts: &super::util::Options, report: &DirectoryReport) {
+    if manifest.duplicates().is_empty() {
+        return;
+    }
+
+    for duplicate in manifest.duplicates() {
+        if duplicate.conflicting && !opts.loglevel_quiet() {
+            error!(
+                "{}: {} is listed with different hashes on lines {} of {}sum.txt, the last one is used",
+                report.path.display(),
+                duplicate.path.display(),
+                super::check::format_lines(&duplicate.lines),
+                opts.algorithm
+            );
+        }
+    }
+
+    if opts.loglevel_info() {
+        info!(
+            "{}: {} paths are listed more than once in {}sum.txt, arkhash --check-manifest --dedupe removes the duplicate lines",
+            report.path.display(),
+            manifest.duplicates().len(),
+            opts.algorithm
+        );
+    }
+}
+
+/// Selects the entries of an _algorithm_sum.txt that are checked, every entry unless opts.sample is set.
+/// The sample of a directory only depends on opts.seed, the path of the directory and the entries,
+/// so a run with the same seed checks the same files. The entries keep the order of the file.
+///
+/// # Arguments
+/// * `workdir` Path to the directory of the _algorithm_sum.txt
+/// * `entries` The entries of the _algorithm_sum.txt that can be checked
+/// * `opts` The Options object containing the sample and the seed
+fn sample_entries<'a>(
+    workdir: &Path,
+    entries: Vec<&'a ManifestEntry>,
+    opts: &super::util::Options,
+) -> Vec<&'a ManifestEntry> {
+    let sample = match opts.sample {
+        Some(sample) => sample,
+        None => return entries,
+    };
+
+    // FNV-1a, unlike the hasher of the standard library it is guaranteed to stay the same
+    let directory_hash = super::util::path_to_bytes(workdir)
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325u64, |hash, &byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    let mut rng = StdRng::seed_from_u64(opts.seed.unwrap_or_default() ^ directory_hash);
+
+    let mut indices =
+        rand::seq::index::sample(&mut rng, entries.len(), sample.size(entries.len())).into_vec();
+    indices.sort_unstable();
+    indices.into_iter().map(|i| entries[i]).collect()
+}
+
+/// A directory that is appended to one of the lists of checked directories
+enum StateLine {
+    /// The directory is recorded in the known_good list
+    KnownGood(PathBuf),
+    /// The directory is recorded in the to_check list
+    ToCheck(PathBuf),
+}
+
+/// Starts the thread that appends the checked directories to the known_good and to_check lists.
+/// This thread is the only one writing to the lists, so lines of directories that finish at the same time can not interleave.
+///
+/// # Arguments
+/// * `known_good_path` Path to the text file containing all checked and good directories
+/// * `to_check_path` Path to the text file containing all checked and bad directories
+///
+/// # Returns
+/// The Sender for the directories and the handle of the thread, which finishes once every Sender is dropped.
+fn start_state_writer(
+    known_good_path: PathBuf,
+    to_check_path: PathBuf,
+) -> (Sender<StateLine>, JoinHandle<()>) {
+    let (sender, receiver) = channel();
+
+    let handle = std::thread::spawn(move || {
+        for state_line in receiver {
+            let (list_path, dir) = match state_line {
+                StateLine::KnownGood(dir) => (&known_good_path, dir),
+                StateLine::ToCheck(dir) => (&to_check_path, dir),
+            };
+
+            let result = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(list_path)
+                .and_then(|mut file| write_path_line(&mut file, &dir));
+            if let Err(e) = result {
+                error!("Error writing to file {}: {}", list_path.display(), e);
+            }
+        }
+    });
+
+    (sender, handle)
+}
+
+/// Appends a path and a newline to a list of directories or files.
+/// The line is written with a single write, so it is never only partially appended by another line.
+///
+/// # Arguments
+/// * `file` The file containing the list
+/// * `path` The path to be appended
+fn write_path_line<W: Write>(file: &mut W, path: &Path) -> io::Result<()> {
+    let mut line = super::util::path_to_bytes(path).into_owned();
+    line.push(b'\n');
+    file.write_all(&line)
+}
+
+/// Returns paths as JSON strings, paths that are not valid UTF-8 are converted lossily
+///
+/// # Arguments
+/// * `paths` The paths to be converted
+fn paths_to_json(paths: &[PathBuf]) -> Vec<String> {
+    paths
+        .iter()
+        .map(|path| path.to_string_lossy().to_string())
+        .collect()
 }
@@ -0,0 +1,326 @@
+//! This module implements the watch mode, which keeps the _algorithm_sum.txt files up to date while files change.
+//!
+//! The directories are watched recursively with the notify crate. Changes are collected until no further change
+//! happened for opts.debounce, then the batch is processed at once: files that were created or modified are hashed
+//! and their lines added or replaced like in add mode, and the _algorithm_sum.txt files the deleted files belonged to
+//! are pruned. The files update mode skips are skipped here as well: the files of arkhash itself, hidden files,
+//! files excluded by a .arkhashignore or opts.exclude and, with opts.respect_gitignore, by a .gitignore.
+
+extern crate ignore;
+extern crate log;
+extern crate notify;
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use self::ignore::gitignore::{Gitignore, GitignoreBuilder};
+use self::log::{error, info};
+use self::notify::event::{EventKind, ModifyKind};
+use self::notify::{RecursiveMode, Watcher};
+
+use super::ignore::IgnoreCache;
+use super::lock::RunLock;
+use super::manifest::Manifest;
+use super::util::{Hidden, Options, Symlinks};
+
+/// Time waited for the next change before the stop condition is checked again
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The result of watching some directories
+#[derive(Debug, Clone, Default)]
+pub struct WatchReport {
+    /// The files whose lines were added or replaced
+    pub added: Vec<PathBuf>,
+    /// The files whose lines were removed, relative to the directory of their _algorithm_sum.txt
+    pub pruned: Vec<PathBuf>,
+    /// Descriptions of changes that could not be recorded
+    pub failed: Vec<String>,
+}
+
+/// Watches the directories in opts.folders and keeps their _algorithm_sum.txt files up to date until the program
+/// is interrupted, see watch_until. Run update mode first, files that were not hashed before are only added
+/// once they change.
+///
+/// # Arguments
+///
+/// * `opts` An Options object containing the directories and the debounce time
+///
+/// # Returns
+/// A WatchReport listing every recorded change, an error if the directories could not be watched.
+pub fn watch(opts: &Options) -> notify::Result<WatchReport> {
+    watch_until(opts, super::util::interrupted)
+}
+
+/// Watches the directories in opts.folders and keeps their _algorithm_sum.txt files up to date until stop returns
+/// true. Changes that are still pending then are processed before returning.
+/// Messages are emitted through the log crate according to the loglevel, nothing is emitted in loglevel quiet.
+/// In a dry run, the files are only listed and nothing is written.
+///
+/// # Arguments
+///
+/// * `opts` An Options object containing the directories and the debounce time
+/// * `stop` Function that is called regularly and returns true once watching should end
+///
+/// # Returns
+/// A WatchReport listing every recorded change, an error if the directories could not be watched.
+pub fn watch_until<F: Fn() -> bool>(opts: &Options, stop: F) -> notify::Result<WatchReport> {
+    let roots = opts
+        .folders
+        .iter()
+        .map(fs::canonicalize)
+        .collect::<Result<Vec<PathBuf>, _>>()?;
+    let (sender, receiver) = channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    for root in &roots {
+        watcher.watch(root, RecursiveMode::Recursive)?;
+        if opts.loglevel_info() {
+            info!("Watching {}", root.display());
+        }
+    }
+
+    let mut report = WatchReport::default();
+    let mut excludes = Excludes::new(opts);
+    let mut pending = BTreeSet::new();
+    let mut last_change = Instant::now();
+
+    while !stop() {
+        match receiver.recv_timeout(POLL_INTERVAL) {
+            Ok(Ok(event)) => {
+                // reading a file or setting its extended attributes does not change its content
+                if matches!(
+                    event.kind,
+                    EventKind::Access(_) | EventKind::Modify(ModifyKind::Metadata(_))
+                ) {
+                    continue;
+                }
+                for path in event.paths {
+                    let root = roots.iter().find(|root| path.starts_with(root));
+                    if root.is_some_and(|root| !excludes.is_excluded(root, &path)) {
+                        pending.insert(path);
+                        last_change = Instant::now();
+                    }
+                }
+            }
+            Ok(Err(e)) => record_failure(&format!("watch error: {}", e), opts, &mut report),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        if !pending.is_empty() && last_change.elapsed() >= opts.debounce {
+            process(&std::mem::take(&mut pending), opts, &mut report);
+        }
+    }
+
+    if !pending.is_empty() {
+        process(&pending, opts, &mut report);
+    }
+
+    Ok(report)
+}
+
+/// Records a batch of changed paths: existing files are added, the files of new directories as well,
+/// and the _algorithm_sum.txt files the deleted paths belonged to are pruned
+///
+/// # Arguments
+///
+/// * `paths` The changed paths
+/// * `opts` An Options object containing the algorithm and whether or not this is a dry run
+/// * `report` The WatchReport the changes are recorded in
+fn process(paths: &BTreeSet<PathBuf>, opts: &Options, report: &mut WatchReport) {
+    let mut files = Vec::new();
+    let mut prune_directories = BTreeSet::new();
+
+    for path in paths {
+        let metadata = if opts.symlinks == Symlinks::Follow {
+            fs::metadata(path)
+        } else {
+            fs::symlink_metadata(path)
+        };
+        match metadata {
+            Ok(metadata) if metadata.is_dir() => files.extend(
+                super::util::walk_files(path, false, opts).filter(|file| !is_own_file(file)),
+            ),
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                if opts.symlinks == Symlinks::HashLink {
+                    files.push(path.clone());
+                }
+            }
+            Ok(_) => files.push(path.clone()),
+            Err(_) => {
+                if let Some(directory) = manifest_directory(path, opts) {
+                    prune_directories.insert(directory);
+                }
+            }
+        }
+    }
+
+    // a batch of changes is written at once, not in between the writes of another arkhash
+    let _lock = if opts.dry_run {
+        None
+    } else {
+        match RunLock::acquire(opts) {
+            Ok(lock) => Some(lock),
+            Err(e) => {
+                let message = format!("could not lock {}: {}", RunLock::path(opts).display(), e);
+                record_failure(&message, opts, report);
+                None
+            }
+        }
+    };
+
+    if opts.dry_run {
+        if opts.loglevel_info() {
+            for file in &files {
+                info!("would add {}", file.display());
+            }
+        }
+    } else if !files.is_empty() {
+        let edit = super::edit::add_files(&files, opts);
+        report.added.extend(edit.changed);
+        report.failed.extend(edit.failed);
+    }
+
+    for directory in &prune_directories {
+        let prune = super::prune::prune_directory(directory, opts);
+        report.pruned.extend(prune.pruned);
+        report.failed.extend(
+            prune
+                .failed
+                .into_iter()
+                .map(|message| format!("{}: {}", directory.display(), message)),
+        );
+    }
+}
+
+/// Returns the directory of the _algorithm_sum.txt a deleted path belonged to,
+/// the nearest directory above it that has one, None if there is none
+///
+/// # Arguments
+///
+/// * `path` Path to the deleted file or directory
+/// * `opts` An Options object containing the algorithm and whether or not it is detected
+fn manifest_directory(path: &Path, opts: &Options) -> Option<PathBuf> {
+    path.ancestors()
+        .skip(1)
+        .find(|directory| {
+            if opts.detect_algorithm {
+                !Manifest::find_algorithms(directory).is_empty()
+            } else {
+                Manifest::find(directory, opts.algorithm).is_some()
+            }
+        })
+        .map(Path::to_path_buf)
+}
+
+/// Returns whether a file is one of the files of arkhash itself, eg an _algorithm_sum.txt or its temporary file
+///
+/// # Arguments
+///
+/// * `path` Path to the file
+fn is_own_file(path: &Path) -> bool {
+    let name = match path.file_name() {
+        Some(name) => Path::new(".").join(name),
+        None => return false,
+    };
+    Manifest::is_own_file(&name)
+}
+
+/// Records a change that could not be written in the report and emits it as an error
+///
+/// # Arguments
+///
+/// * `message` Description of the failure
+/// * `opts` The Options object containing the loglevel
+/// * `report` The WatchReport the failure is recorded in
+fn record_failure(message: &str, opts: &Options, report: &mut WatchReport) {
+    if !opts.loglevel_quiet() {
+        error!("{}", message);
+    }
+    report.failed.push(message.to_string());
+}
+
+/// Decides which changed paths are skipped, like update mode skips them while walking a directory
+struct Excludes {
+    /// Whether or not hidden files and directories are skipped
+    hidden: Hidden,
+    /// Whether or not .gitignore files are respected
+    respect_gitignore: bool,
+    /// The log file, which changes with every message that is logged
+    log_file: Option<PathBuf>,
+    /// The .arkhashignore files of the watched directories and the patterns of opts.exclude
+    ignores: IgnoreCache,
+}
+
+impl Excludes {
+    /// Creates a new Excludes object
+    ///
+    /// # Arguments
+    ///
+    /// * `opts` An Options object containing the policy for hidden files, the patterns excluding files
+    ///   and whether or not .gitignore is respected
+    fn new(opts: &Options) -> Excludes {
+        Excludes {
+            hidden: opts.hidden,
+            respect_gitignore: opts.respect_gitignore,
+            log_file: opts
+                .log_file
+                .as_ref()
+                .and_then(|log_file| fs::canonicalize(log_file).ok()),
+            ignores: IgnoreCache::excluding(&opts.exclude),
+        }
+    }
+
+    /// Indicates that changes of a path are not recorded
+    ///
+    /// # Arguments
+    ///
+    /// * `root` Path to the watched directory the path is in
+    /// * `path` Path to the changed file or directory
+    fn is_excluded(&mut self, root: &Path, path: &Path) -> bool {
+        let relative = match path.strip_prefix(root) {
+            Ok(relative) => relative,
+            Err(_) => return true,
+        };
+        let is_hidden = relative
+            .components()
+            .any(|component| component.as_os_str().to_string_lossy().starts_with('.'));
+
+        is_own_file(path)
+            || self.log_file.as_deref() == Some(path)
+            || (self.hidden == Hidden::Exclude && is_hidden)
+            || self.ignores.is_ignored(root, relative)
+            || (self.respect_gitignore && is_gitignored(root, path))
+    }
+}
+
+/// Returns whether a path is excluded by the .gitignore files of the directories between root and it,
+/// the global gitignore or .git/info/exclude of root
+///
+/// # Arguments
+///
+/// * `root` Path to the watched directory the path is in
+/// * `path` Path to the file or directory
+fn is_gitignored(root: &Path, path: &Path) -> bool {
+    let is_dir = path.is_dir();
+    let mut exclude = GitignoreBuilder::new(root);
+    exclude.add(root.join(".git").join("info").join("exclude"));
+    let mut gitignores = vec![
+        GitignoreBuilder::new(root).build_global().0,
+        exclude.build().unwrap_or_else(|_| Gitignore::empty()),
+    ];
+    gitignores.extend(
+        path.ancestors()
+            .skip(1)
+            .take_while(|directory| directory.starts_with(root))
+            .map(|directory| Gitignore::new(directory.join(".gitignore")).0),
+    );
+
+    gitignores.iter().any(|gitignore| {
+        gitignore
+            .matched_path_or_any_parents(path, is_dir)
+            .is_ignore()
+    })
+}
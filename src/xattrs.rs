@@ -0,0 +1,157 @@
+//! This module implements storing the hashes of files in their extended attributes instead of an _algorithm_sum.txt.
+//!
+//! The hash of a file is stored in the attribute `user.arkhash.<algorithm>`, eg `user.arkhash.sha256`,
+//! together with the size and the modification time of the file: `<hash> <size> <mtime>`,
+//! the start of a line of the extended format of the _algorithm_sum.txt.
+//! The hash stays attached to the file when it is moved or copied with its attributes.
+//! Filesystems and platforms without extended attributes keep using the _algorithm_sum.txt.
+
+#[cfg(unix)]
+extern crate xattr;
+
+use std::io;
+use std::path::Path;
+
+use super::manifest::FileStamp;
+use super::util::Algorithm;
+
+/// Returns the name of the attribute holding the hash of an algorithm, eg user.arkhash.sha256
+///
+/// # Arguments
+///
+/// * `algorithm` The algorithm of the hash
+pub fn name(algorithm: Algorithm) -> String {
+    format!("user.arkhash.{}", algorithm)
+}
+
+/// Returns whether extended attributes can be stored on the filesystem of a directory
+///
+/// # Arguments
+///
+/// * `workdir` Path to the directory
+#[cfg(unix)]
+pub fn is_supported(workdir: &Path) -> bool {
+    xattr::SUPPORTED_PLATFORM && xattr::get(workdir, name(Algorithm::Sha1)).is_ok()
+}
+
+/// Returns whether extended attributes can be stored on the filesystem of a directory
+///
+/// # Arguments
+///
+/// * `workdir` Path to the directory
+#[cfg(not(unix))]
+pub fn is_supported(_workdir: &Path) -> bool {
+    false
+}
+
+/// Reads the hash of a file and its size and modification time when it was hashed.
+/// Symbolic links are followed.
+///
+/// # Arguments
+///
+/// * `path` Path to the file
+/// * `algorithm` The algorithm of the hash
+///
+/// # Returns
+/// The hash and the stamp, None if the file has no attribute for the algorithm or it is improperly formatted.
+#[cfg(unix)]
+pub fn read(path: &Path, algorithm: Algorithm) -> io::Result<Option<(String, FileStamp)>> {
+    let value = match xattr::get_deref(path, name(algorithm))? {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+
+    Ok(String::from_utf8(value)
+        .ok()
+        .and_then(|value| parse(&value)))
+}
+
+/// Reads the hash of a file and its size and modification time when it was hashed.
+///
+/// # Arguments
+///
+/// * `path` Path to the file
+/// * `algorithm` The algorithm of the hash
+///
+/// # Returns
+/// Always None, extended attributes are not supported on this platform.
+#[cfg(not(unix))]
+pub fn read(_path: &Path, _algorithm: Algorithm) -> io::Result<Option<(String, FileStamp)>> {
+    Ok(None)
+}
+
+/// Stores the hash of a file and its size and modification time when it was hashed.
+/// Symbolic links are followed. Setting an attribute does not change the modification time of the file.
+///
+/// # Arguments
+///
+/// * `path` Path to the file
+/// * `algorithm` The algorithm of the hash
+/// * `hash` The hash of the file
+/// * `stamp` Size and modification time of the file when it was hashed
+#[cfg(unix)]
+pub fn write(path: &Path, algorithm: Algorithm, hash: &str, stamp: &FileStamp) -> io::Result<()> {
+    xattr::set_deref(path, name(algorithm), format(hash, stamp).as_bytes())
+}
+
+/// Stores the hash of a file and its size and modification time when it was hashed.
+///
+/// # Arguments
+///
+/// * `path` Path to the file
+/// * `algorithm` The algorithm of the hash
+/// * `hash` The hash of the file
+/// * `stamp` Size and modification time of the file when it was hashed
+#[cfg(not(unix))]
+pub fn write(
+    _path: &Path,
+    _algorithm: Algorithm,
+    _hash: &str,
+    _stamp: &FileStamp,
+) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "extended attributes are not supported on this platform",
+    ))
+}
+
+/// Formats the value of an attribute, eg `<hash> 1024 1700000000.000000000`
+///
+/// # Arguments
+///
+/// * `hash` The hash of the file
+/// * `stamp` Size and modification time of the file when it was hashed
+pub fn format(hash: &str, stamp: &FileStamp) -> String {
+    format!(
+        "{} {} {}.{:09}",
+        hash, stamp.size, stamp.mtime_secs, stamp.mtime_nanos
+    )
+}
+
+/// Parses the value of an attribute, see format. Returns None if it is improperly formatted.
+///
+/// # Arguments
+///
+/// * `value` The value of the attribute
+pub fn parse(value: &str) -> Option<(String, FileStamp)> {
+    let mut fields = value.split(' ');
+    let hash = fields
+        .next()
+        .filter(|hash| !hash.is_empty() && hash.bytes().all(|c| c.is_ascii_hexdigit()))?;
+    let size = fields.next()?.parse().ok()?;
+    let mut mtime = fields.next()?.splitn(2, '.');
+    let mtime_secs = mtime.next()?.parse().ok()?;
+    let mtime_nanos = mtime.next()?.parse().ok()?;
+    if fields.next().is_some() {
+        return None;
+    }
+
+    Some((
+        hash.to_string(),
+        FileStamp {
+            size,
+            mtime_secs,
+            mtime_nanos,
+        },
+    ))
+}
@@ -1,6 +1,7 @@
 extern crate assert_cli;
 extern crate regex;
 extern crate chrono;
+extern crate serde_json;
 
 #[cfg(windows)]
 extern crate remove_dir_all;
@@ -9,6 +10,7 @@ use assert_cli::*;
 use regex::Regex;
 use chrono::{DateTime, Datelike};
 
+use std::collections::HashSet;
 use std::fs;
 use std::io::prelude::*;
 use std::io::{BufRead, BufReader};
@@ -62,8 +64,10 @@ fn update_test() {
     let hashfile = fs::File::open("testenvironment/sha1sum.txt");
     if let Ok(hashfile) = hashfile {
         let mut i = 0;
-        for _ in BufReader::new(hashfile).lines() {
-            i += 1;
+        for line in BufReader::new(hashfile).lines() {
+            if !is_header(&line.unwrap()) {
+                i += 1;
+            }
         }
 
         if i != 27 {
@@ -86,8 +90,10 @@ fn update_test() {
     let hashfile = fs::File::open("testenvironment/sha1sum.txt");
     if let Ok(hashfile) = hashfile {
         let mut i = 0;
-        for _ in BufReader::new(hashfile).lines() {
-            i += 1;
+        for line in BufReader::new(hashfile).lines() {
+            if !is_header(&line.unwrap()) {
+                i += 1;
+            }
         }
 
         if i != 27 {
@@ -110,8 +116,10 @@ fn update_test() {
     let hashfile = fs::File::open("testenvironment/sha1sum.txt");
     if let Ok(hashfile) = hashfile {
         let mut i = 0;
-        for _ in BufReader::new(hashfile).lines() {
-            i += 1;
+        for line in BufReader::new(hashfile).lines() {
+            if !is_header(&line.unwrap()) {
+                i += 1;
+            }
         }
 
         if i != 28 {
@@ -126,6 +134,149 @@ fn update_test() {
     teardown();
 }
 
+/// Tests that the update mode only rehashes files whose size or modification time changed.
+///
+/// # Steps
+/// * Update checksums for testenvironment
+/// * Replace the database with plain coreutils lines, except for the line of test/little_1
+/// * Change the content and size of test/little_1 and test/little_2
+/// * Update checksums again
+///
+/// # Expected
+/// * arkhash should return without failure
+/// * the database should still contain exactly 27 lines
+/// * the line of test/little_1 should contain the new hash
+/// * the plain line of test/little_2 should be kept, as its recorded hash differs
+/// * every other line should carry size and modification time after the second update
+#[test]
+fn update_incremental_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-u"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let extended_re = Regex::new(r"^([[:xdigit:]]{40}) \d+ \d+\.\d{9}  (.*)$").unwrap();
+    let mut data = String::new();
+    fs::File::open("testenvironment/sha1sum.txt")
+        .unwrap()
+        .read_to_string(&mut data)
+        .unwrap();
+
+    let mut legacy = String::new();
+    for line in hashsum_lines(&data) {
+        let captures = extended_re.captures(line).unwrap();
+        if &captures[2] == "./test/little_1" {
+            legacy.push_str(&format!("{}\n", line));
+        } else {
+            legacy.push_str(&format!("{}  {}\n", &captures[1], &captures[2]));
+        }
+    }
+    fs::File::create("testenvironment/sha1sum.txt")
+        .unwrap()
+        .write_all(legacy.as_bytes())
+        .unwrap();
+
+    let mut f = fs::File::create("testenvironment/test/little_1").unwrap();
+    f.write_all(b"Changed small file").unwrap();
+    let mut f = fs::File::create("testenvironment/test/little_2").unwrap();
+    f.write_all(b"Changed small file").unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-u"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let mut data = String::new();
+    fs::File::open("testenvironment/sha1sum.txt")
+        .unwrap()
+        .read_to_string(&mut data)
+        .unwrap();
+
+    assert_eq!(hashsum_lines(&data).len(), 27);
+    for line in hashsum_lines(&data) {
+        if line.ends_with("./test/little_2") {
+            // sha1 of "Small file"
+            assert_eq!(
+                line,
+                "b85ff052083dea11bc843a9d910edb8219e166da  ./test/little_2"
+            );
+            continue;
+        }
+
+        let captures = extended_re.captures(line).unwrap();
+        if &captures[2] == "./test/little_1" {
+            // sha1 of "Changed small file"
+            assert_eq!(&captures[1], "fea36f7c3b6079b1b95cfd7e0673f1b8f5617d44");
+        }
+    }
+
+    teardown();
+}
+
+/// Tests that the update mode lists every file exactly once in the database.
+///
+/// # Steps
+/// * Update checksums for testenvironment
+/// * Append a duplicate of the first line to the database
+/// * Update checksums twice again
+///
+/// # Expected
+/// * arkhash should return without failure
+/// * the database should contain exactly 27 lines with 27 distinct paths
+#[test]
+fn update_no_duplicates_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-u"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let mut data = String::new();
+    fs::File::open("testenvironment/sha1sum.txt")
+        .unwrap()
+        .read_to_string(&mut data)
+        .unwrap();
+    let duplicate = format!("{}\n", data.lines().next().unwrap());
+    fs::OpenOptions::new()
+        .append(true)
+        .open("testenvironment/sha1sum.txt")
+        .unwrap()
+        .write_all(duplicate.as_bytes())
+        .unwrap();
+
+    for _ in 0..2 {
+        Assert::main_binary()
+            .with_args(&["-u"])
+            .current_dir("testenvironment")
+            .unwrap();
+    }
+
+    let mut data = String::new();
+    fs::File::open("testenvironment/sha1sum.txt")
+        .unwrap()
+        .read_to_string(&mut data)
+        .unwrap();
+
+    let mut paths = HashSet::new();
+    for line in hashsum_lines(&data) {
+        paths.insert(line.split("  ").nth(1).unwrap().to_string());
+    }
+
+    assert_eq!(hashsum_lines(&data).len(), 27);
+    assert_eq!(paths.len(), 27);
+
+    teardown();
+}
+
 /// Tests the verification mode on a clean database.
 ///
 /// # Steps
@@ -148,7 +299,7 @@ fn verify_test() {
         .unwrap();
 
     Assert::main_binary()
-        .with_args(&["-v"])
+        .with_args(&["-c"])
         .current_dir("testenvironment")
         .stdout()
         .doesnt_contain("FAILED")
@@ -191,7 +342,7 @@ fn verify_modified_test() {
     let mut first = true;
     let mut modified = String::new();
     for line in data.split("\n") {
-        if first {
+        if first && !is_header(line) {
             let mut modline = String::from(line);
             modline.remove(0);
             modline.insert(0, '0');
@@ -203,10 +354,10 @@ fn verify_modified_test() {
     }
 
     let mut hashfile = fs::File::create("testenvironment/sha1sum.txt").unwrap();
-    hashfile.write(modified.as_bytes()).unwrap();
+    hashfile.write_all(modified.as_bytes()).unwrap();
 
     Assert::main_binary()
-        .with_args(&["-v"])
+        .with_args(&["-c"])
         .current_dir("testenvironment")
         .stdout()
         .contains("FAILED")
@@ -243,8 +394,10 @@ fn update_subdir_test() {
     let hashfile = fs::File::open("testenvironment/test/sha1sum.txt");
     if let Ok(hashfile) = hashfile {
         let mut i = 0;
-        for _ in BufReader::new(hashfile).lines() {
-            i += 1;
+        for line in BufReader::new(hashfile).lines() {
+            if !is_header(&line.unwrap()) {
+                i += 1;
+            }
         }
 
         if i != 13 {
@@ -262,8 +415,10 @@ fn update_subdir_test() {
     let hashfile = fs::File::open("testenvironment/secondsecond/sha1sum.txt");
     if let Ok(hashfile) = hashfile {
         let mut i = 0;
-        for _ in BufReader::new(hashfile).lines() {
-            i += 1;
+        for line in BufReader::new(hashfile).lines() {
+            if !is_header(&line.unwrap()) {
+                i += 1;
+            }
         }
 
         if i != 14 {
@@ -286,8 +441,10 @@ fn update_subdir_test() {
     let hashfile = fs::File::open("testenvironment/test/sha1sum.txt");
     if let Ok(hashfile) = hashfile {
         let mut i = 0;
-        for _ in BufReader::new(hashfile).lines() {
-            i += 1;
+        for line in BufReader::new(hashfile).lines() {
+            if !is_header(&line.unwrap()) {
+                i += 1;
+            }
         }
 
         if i != 13 {
@@ -305,8 +462,10 @@ fn update_subdir_test() {
     let hashfile = fs::File::open("testenvironment/secondsecond/sha1sum.txt");
     if let Ok(hashfile) = hashfile {
         let mut i = 0;
-        for _ in BufReader::new(hashfile).lines() {
-            i += 1;
+        for line in BufReader::new(hashfile).lines() {
+            if !is_header(&line.unwrap()) {
+                i += 1;
+            }
         }
 
         if i != 14 {
@@ -347,7 +506,7 @@ fn verify_subdir_test() {
         .unwrap();
 
     Assert::main_binary()
-        .with_args(&["-vs"])
+        .with_args(&["-cs"])
         .current_dir("testenvironment")
         .stdout()
         .doesnt_contain("FAILED")
@@ -357,10 +516,8 @@ fn verify_subdir_test() {
     let re = Regex::new(r"known_good.*").unwrap();
     for entry in fs::read_dir("testenvironment").unwrap() {
         let path = entry.unwrap().path();
-        if path.is_file() {
-            if re.is_match(path.to_str().unwrap()) {
-                known_good_found = true;
-            }
+        if path.is_file() && re.is_match(path.to_str().unwrap()) {
+            known_good_found = true;
         }
     }
 
@@ -404,7 +561,7 @@ fn verify_subdir_modified_test() {
     let mut first = true;
     let mut modified = String::new();
     for line in data.split("\n") {
-        if first {
+        if first && !is_header(line) {
             let mut modline = String::from(line);
             modline.remove(0);
             modline.insert(0, '0');
@@ -416,10 +573,10 @@ fn verify_subdir_modified_test() {
     }
 
     let mut hashfile = fs::File::create("testenvironment/test/sha1sum.txt").unwrap();
-    hashfile.write(modified.as_bytes()).unwrap();
+    hashfile.write_all(modified.as_bytes()).unwrap();
 
     Assert::main_binary()
-        .with_args(&["-vs"])
+        .with_args(&["-cs"])
         .current_dir("testenvironment")
         .stdout()
         .contains("FAILED")
@@ -430,10 +587,8 @@ fn verify_subdir_modified_test() {
     let re = Regex::new(r"to_check.*").unwrap();
     for entry in fs::read_dir("testenvironment").unwrap() {
         let path = entry.unwrap().path();
-        if path.is_file() {
-            if re.is_match(path.to_str().unwrap()) {
-                to_check_occurences += 1;
-            }
+        if path.is_file() && re.is_match(path.to_str().unwrap()) {
+            to_check_occurences += 1;
         }
     }
 
@@ -442,150 +597,3814 @@ fn verify_subdir_modified_test() {
     teardown();
 }
 
-/// Tests the exclusion of a folder listed in the ignore file.
+/// Tests the subdir modes on nested directories.
 ///
 /// # Steps
-/// * Create a new directory in testenvironment called ignore
-/// * Create an .arkignore file containing 'ignore'
-/// * Update subdirs for testenvironment
-/// * Check if a database file was created for the ignore folder
+/// * Create the directories deep/first and deep/second containing a file each
+/// * Update subdirs for testenvironment with a maximum depth of 2
+/// * Verify subdirs for testenvironment with a maximum depth of 2, twice
 ///
 /// # Expected
 /// * arkhash should return without failure
-/// * a database file should not be created in the ignore folder
+/// * databases should be created in deep/first, deep/second, test and secondsecond, but not in deep
+/// * databases in deep/first and deep/second should list their file relative to themselves
+/// * the known_good file should contain exactly 4 lines after both verifications
 #[test]
-fn update_subdir_ignore_test() {
+fn subdir_max_depth_test() {
     let _guard = MTX.lock().unwrap();
 
     setup();
-    fs::create_dir("testenvironment/ignore").unwrap();
-    let mut f = fs::File::create("testenvironment/ignore/little_1").unwrap();
-    f.write_all(b"Small file").unwrap();
-    let mut f = fs::File::create("testenvironment/ignore/little_2").unwrap();
-    f.write_all(b"Small file").unwrap();
-    let mut f = fs::File::create("testenvironment/.arkignore").unwrap();
-    f.write_all(b"ignore").unwrap();
+    for dir in &["first", "second"] {
+        fs::create_dir_all(format!("testenvironment/deep/{}", dir)).unwrap();
+        let mut f = fs::File::create(format!("testenvironment/deep/{}/little_1", dir)).unwrap();
+        f.write_all(b"Small file").unwrap();
+    }
 
     // test
     Assert::main_binary()
-        .with_args(&["-us"])
+        .with_args(&["-us", "--max-depth", "2"])
         .current_dir("testenvironment")
-        .stdout()
-        .contains("Ignoring Directory ./ignore")
         .unwrap();
 
-    let mut hashsum_file = false;
-    for entry in fs::read_dir("testenvironment/ignore").unwrap() {
+    assert!(!std::path::Path::new("testenvironment/deep/sha1sum.txt").exists());
+    assert!(std::path::Path::new("testenvironment/test/sha1sum.txt").exists());
+    assert!(std::path::Path::new("testenvironment/secondsecond/sha1sum.txt").exists());
+    for dir in &["first", "second"] {
+        let mut data = String::new();
+        fs::File::open(format!("testenvironment/deep/{}/sha1sum.txt", dir))
+            .unwrap()
+            .read_to_string(&mut data)
+            .unwrap();
+        assert!(data.trim_end().ends_with("  ./little_1"));
+    }
+
+    for _ in 0..2 {
+        Assert::main_binary()
+            .with_args(&["-cs", "--max-depth", "2"])
+            .current_dir("testenvironment")
+            .stdout()
+            .doesnt_contain("FAILED")
+            .unwrap();
+    }
+
+    let re = Regex::new(r"known_good.*").unwrap();
+    for entry in fs::read_dir("testenvironment").unwrap() {
         let path = entry.unwrap().path();
-        if path.is_file() {
-            if path.to_str().unwrap().contains("sha1sum.txt") {
-                hashsum_file = true;
-            }
+        if path.is_file() && re.is_match(path.to_str().unwrap()) {
+            let known_good_file = fs::File::open(path).unwrap();
+            assert_eq!(BufReader::new(known_good_file).lines().count(), 4);
+        }
+    }
+
+    teardown();
+}
+
+/// Tests that subdir mode warns about the files of directories above the deepest level.
+///
+/// # Steps
+/// * Create year/month/album containing a file, a file in year and in year/month and a hidden file in year
+/// * Update subdirs for testenvironment with a maximum depth of 3
+/// * Verify subdirs for testenvironment with a maximum depth of 3 and with --loglevel quiet
+///
+/// # Expected
+/// * the update should hash year/month/album, but write no database in year or year/month
+/// * the update and the first verify should warn that year and year/month have 1 file that is not processed
+/// * the verify in loglevel quiet should not warn
+#[test]
+fn subdir_max_depth_intermediate_files_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+    fs::create_dir_all("testenvironment/year/month/album").unwrap();
+    for file in &[
+        "year/month/album/little_1",
+        "year/month/loose_1",
+        "year/loose_1",
+        "year/.hidden",
+    ] {
+        fs::write(format!("testenvironment/{}", file), "Small file").unwrap();
+    }
+
+    // test
+    let run = |args: &[&str]| {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_arkhash"))
+            .args(args)
+            .current_dir("testenvironment")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stderr).unwrap()
+    };
+
+    let update = run(&["-us", "--max-depth", "3"]);
+    assert!(std::path::Path::new("testenvironment/year/month/album/sha1sum.txt").exists());
+    assert!(!std::path::Path::new("testenvironment/year/sha1sum.txt").exists());
+    assert!(!std::path::Path::new("testenvironment/year/month/sha1sum.txt").exists());
+
+    let verify = run(&["-cs", "--max-depth", "3"]);
+    for stderr in &[update, verify] {
+        for dir in &["year", "year/month"] {
+            let warning = format!(
+                "./{}: 1 files are not processed, they are above the deepest level of --max-depth 3",
+                dir
+            );
+            assert!(stderr.contains(&warning), "{}", stderr);
         }
     }
 
-    assert_ne!(hashsum_file, true);
+    let quiet = run(&["-cs", "--max-depth", "3", "--loglevel", "quiet"]);
+    assert!(!quiet.contains("not processed"), "{}", quiet);
 
     teardown();
 }
 
-/// Tests that the verification is not conducted again when the subdirectories are already known good.
+/// Tests that the database is written in a deterministic order when hashing in parallel.
 ///
 /// # Steps
-/// * Update subdirs for testenvironment
-/// * Verify subdirs for testenvironment
-/// * Verify subdirs for testenvironment again
+/// * Update subdirs for testenvironment with 4 hashing threads, processing one directory at a time
+/// * Read the paths from both generated database files
 ///
 /// # Expected
 /// * arkhash should return without failure
-/// * a file called known_good_month_year.txt should be created, containing exactly 2 lines,
-///   irregardless of how often arkhash is executed
+/// * the paths in both database files should be sorted
 #[test]
-fn known_good_no_repetition_test() {
+fn update_sorted_test() {
     let _guard = MTX.lock().unwrap();
 
     setup();
 
     // test
     Assert::main_binary()
-        .with_args(&["-us"])
+        .with_args(&["-us", "-T", "4", "-D", "1"])
         .current_dir("testenvironment")
         .unwrap();
 
-    for _ in 0..4 {
-        Assert::main_binary()
-            .with_args(&["-vs"])
-            .current_dir("testenvironment")
-            .unwrap();
+    for dir in &["test", "secondsecond"] {
+        let hashfile = fs::File::open(format!("testenvironment/{}/sha1sum.txt", dir)).unwrap();
+        let paths: Vec<String> = BufReader::new(hashfile)
+            .lines()
+            .map(|line| line.unwrap())
+            .filter(|line| !is_header(line))
+            .map(|line| line.split_once("  ").unwrap().1.to_string())
+            .collect();
+        let mut sorted = paths.clone();
+        sorted.sort();
 
-        let re = Regex::new(r"known_good.*").unwrap();
-        for entry in fs::read_dir("testenvironment").unwrap() {
-            let path = entry.unwrap().path();
-            if path.is_file() {
-                if re.is_match(path.to_str().unwrap()) {
-                    let known_good_file = fs::File::open(path);
-                    if let Ok(known_good_file) = known_good_file {
-                        let mut i = 0;
-                        for _ in BufReader::new(known_good_file).lines() {
-                            i += 1;
-                        }
-
-                        if i != 2 {
-                            teardown();
-                            panic!(
-                                "arkhash produced the wrong number of entries in the known_good file. expected: 2, given: {}",
-                                i
-                            );
-                        }
-                    } else {
-                        teardown();
-                        panic!("arkhash did not create the known_good file!");
-                    }
-                }
-            }
+        if paths != sorted {
+            teardown();
+            panic!("database of {} is not sorted: {:?}", dir, paths);
         }
     }
 
     teardown();
 }
 
-/// Tests that a directory which is recently marked as known bad is not analyzed again.
+/// Tests that paths containing special characters are escaped like the coreutils do.
 ///
 /// # Steps
-/// * Update subdirs for testenvironment
-/// * Create a file to_check_month_year.txt which contains secondsecond
-/// * Verify subdirs for testenvironment
+/// * Create files whose names contain a space, a backslash and a newline
+/// * Update checksums for testenvironment
+/// * Verify checksums
 ///
 /// # Expected
-/// * arkhash should fail with exit code 2
-/// * output should contain information that a directory was already in a known bad state
+/// * the line of the file with a space should not be escaped
+/// * the lines of the other files should start with a backslash and contain the escaped path
+/// * verification should not contain the keyword FAILED
+#[cfg(unix)]
 #[test]
-fn to_check_dirs_skipped_test() {
+fn update_escaped_paths_test() {
     let _guard = MTX.lock().unwrap();
 
     setup();
 
+    for name in &["with space", "back\\slash", "new\nline"] {
+        fs::write(format!("testenvironment/test/{}", name), name).unwrap();
+    }
+
     // test
     Assert::main_binary()
-        .with_args(&["-us"])
+        .with_args(&["-u"])
         .current_dir("testenvironment")
         .unwrap();
 
-    let now: DateTime<chrono::Local> = chrono::Local::now();
-    let mut to_check_file = fs::File::create(format!("testenvironment/to_check_{}_{}.txt", now.month(), now.year())).unwrap();
-    to_check_file.write("./secondsecond".as_bytes()).unwrap();
+    let database = fs::read_to_string("testenvironment/sha1sum.txt").unwrap();
+    let expected = [
+        r"^[[:xdigit:]]{40} \d+ \d+\.\d{9}  \./test/with space$",
+        r"^\\[[:xdigit:]]{40} \d+ \d+\.\d{9}  \./test/back\\\\slash$",
+        r"^\\[[:xdigit:]]{40} \d+ \d+\.\d{9}  \./test/new\\nline$",
+    ];
+    for pattern in &expected {
+        let re = Regex::new(pattern).unwrap();
+        if !database.lines().any(|line| re.is_match(line)) {
+            teardown();
+            panic!("no line matching {} in database:\n{}", pattern, database);
+        }
+    }
 
     Assert::main_binary()
-        .with_args(&["-vs"])
+        .with_args(&["-c"])
         .current_dir("testenvironment")
         .stdout()
-        .contains("Directory ./secondsecond already marked known bad")
-        .fails_with(2)
+        .doesnt_contain("FAILED")
+        .unwrap();
+
+    teardown();
+}
+
+/// Tests the NUL separated database format and filter mode.
+///
+/// # Steps
+/// * Create a file whose name contains a newline
+/// * Update checksums for testenvironment with NUL separated lines
+/// * Verify checksums with NUL separated lines
+/// * Filter NUL separated paths of a hashed and an unknown file
+///
+/// # Expected
+/// * the database should contain the unescaped path terminated by NUL
+/// * verification should not contain the keyword FAILED
+/// * only the unknown path should be printed, terminated by NUL
+#[cfg(unix)]
+#[test]
+fn null_separated_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    fs::write("testenvironment/test/new\nline", "new\nline").unwrap();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-u", "-0"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let database = fs::read_to_string("testenvironment/sha1sum.txt").unwrap();
+    let re = Regex::new(r"^[[:xdigit:]]{40} \d+ \d+\.\d{9}  \./test/new\nline$").unwrap();
+    if !database.split_terminator('\0').any(|line| re.is_match(line)) {
+        teardown();
+        panic!("no NUL terminated line for the new file in database:\n{}", database);
+    }
+
+    Assert::main_binary()
+        .with_args(&["-c", "--null"])
+        .current_dir("testenvironment")
+        .stdout()
+        .doesnt_contain("FAILED")
+        .unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_arkhash"))
+        .arg("-0")
+        .current_dir("testenvironment")
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .and_then(|mut child| {
+            child
+                .stdin
+                .take()
+                .unwrap()
+                .write_all(b"./test/new\nline\0./test/unknown\nfile\0")?;
+            child.wait_with_output()
+        })
+        .unwrap();
+
+    teardown();
+
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "./test/unknown\nfile\0");
+}
+
+/// Tests that paths which are not valid UTF-8 are hashed and verified.
+///
+/// # Steps
+/// * Create a file whose name contains a byte that is not valid UTF-8
+/// * Update checksums for testenvironment
+/// * Verify checksums
+///
+/// # Expected
+/// * the line of the file should start with a backslash and contain the byte as \xHH
+/// * verification should not contain the keyword FAILED
+#[cfg(unix)]
+#[test]
+fn update_non_utf8_paths_test() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    let name = OsStr::from_bytes(b"testenvironment/test/caf\xe9");
+    fs::write(name, "latin1").unwrap();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-u"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let database = fs::read_to_string("testenvironment/sha1sum.txt").unwrap();
+    let re = Regex::new(r"^\\[[:xdigit:]]{40} \d+ \d+\.\d{9}  \./test/caf\\xe9$").unwrap();
+    if !database.lines().any(|line| re.is_match(line)) {
+        teardown();
+        panic!("no escaped line for the new file in database:\n{}", database);
+    }
+
+    Assert::main_binary()
+        .with_args(&["-c"])
+        .current_dir("testenvironment")
+        .stdout()
+        .doesnt_contain("FAILED")
         .unwrap();
 
     teardown();
 }
 
+/// Tests that --compat-format writes paths which are not valid UTF-8 verbatim, as the coreutils expect them.
+///
+/// # Steps
+/// * Create a file whose name contains a byte that is not valid UTF-8 and one whose name contains a backslash
+/// * Update checksums for testenvironment with --compat-format
+/// * Verify checksums
+/// * Check the hashsum file with sha1sum -c, if the coreutils are installed
+///
+/// # Expected
+/// * the line of the first file should contain the byte itself instead of \xHH
+/// * the line of the second file should start with a backslash and contain the backslash escaped
+/// * neither verification should fail
+#[cfg(unix)]
+#[test]
+fn compat_format_non_utf8_paths_test() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    fs::write(OsStr::from_bytes(b"testenvironment/test/caf\xe9"), "latin1").unwrap();
+    fs::write("testenvironment/test/back\\slash", "backslash").unwrap();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-u", "--compat-format"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let database = fs::read("testenvironment/sha1sum.txt").unwrap();
+    let lines: Vec<&[u8]> = database.split(|&byte| byte == b'\n').collect();
+    let raw = lines
+        .iter()
+        .any(|line| line.ends_with(b"  ./test/caf\xe9") && !line.starts_with(b"\\"));
+    let re = Regex::new(r"^\\[[:xdigit:]]{40}  \./test/back\\\\slash$").unwrap();
+    let escaped = lines
+        .iter()
+        .any(|line| std::str::from_utf8(line).is_ok_and(|line| re.is_match(line)));
+    if !raw || !escaped {
+        teardown();
+        panic!(
+            "unexpected lines in database:\n{}",
+            String::from_utf8_lossy(&database)
+        );
+    }
+
+    Assert::main_binary()
+        .with_args(&["-c"])
+        .current_dir("testenvironment")
+        .stdout()
+        .doesnt_contain("FAILED")
+        .unwrap();
+
+    let sha1sum = std::process::Command::new("sha1sum")
+        .args(["-c", "sha1sum.txt"])
+        .current_dir("testenvironment")
+        .output();
+
+    teardown();
+
+    if let Ok(output) = sha1sum {
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+}
+
+/// Tests that changed files whose paths are not valid UTF-8 are listed with their exact bytes.
+///
+/// # Steps
+/// * Create a file whose name contains a byte that is not valid UTF-8 in test
+/// * Update checksums for testenvironment in subdir mode
+/// * Change the content of the file
+/// * Verify checksums in subdir mode
+///
+/// # Expected
+/// * the bad files list of test should contain the exact path of the file
+#[cfg(unix)]
+#[test]
+fn verify_non_utf8_bad_files_test() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    let name = OsStr::from_bytes(b"testenvironment/test/f\xff");
+    fs::write(name, "original").unwrap();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-us"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    fs::write(name, "changed").unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-cs"])
+        .current_dir("testenvironment")
+        .fails()
+        .unwrap();
+
+    let bad_files = fs::read("testenvironment/to_check_test.txt").unwrap();
+    if bad_files != b"./f\xff\n" {
+        teardown();
+        panic!("unexpected bad files list: {:?}", String::from_utf8_lossy(&bad_files));
+    }
+
+    teardown();
+}
+
+/// Tests updating and verifying several folders in a single run.
+///
+/// # Steps
+/// * Update checksums for the folders test and secondsecond of testenvironment
+/// * Verify checksums for both folders
+///
+/// # Expected
+/// * both folders should contain a database file, testenvironment should not
+/// * verification should report both directories as OK
+#[test]
+fn multiple_folders_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-u", "test", "secondsecond"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let databases = [
+        fs::metadata("testenvironment/test/sha1sum.txt").is_ok(),
+        fs::metadata("testenvironment/secondsecond/sha1sum.txt").is_ok(),
+        fs::metadata("testenvironment/sha1sum.txt").is_ok(),
+    ];
+    if databases != [true, true, false] {
+        teardown();
+        panic!("unexpected database files: {:?}", databases);
+    }
+
+    Assert::main_binary()
+        .with_args(&["-c", "test", "secondsecond"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("Checked 2 directories, 2 OK, 0 failed")
+        .unwrap();
+
+    teardown();
+}
+
+/// Tests that the largest directories are processed first with the size schedule.
+///
+/// # Steps
+/// * Update subdirs for testenvironment one directory at a time, ordered by size
+///
+/// # Expected
+/// * arkhash should return without failure
+/// * secondsecond, which contains the big file, should be updated before test
+#[test]
+fn schedule_size_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_arkhash"))
+        .args(["-us", "-D", "1", "--schedule=size"])
+        .current_dir("testenvironment")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    teardown();
+
+    assert!(output.status.success());
+    let second = stdout.find("Updating Directory ./secondsecond").unwrap();
+    let test = stdout.find("Updating Directory ./test").unwrap();
+    assert!(second < test, "unexpected order:\n{}", stdout);
+}
+
+/// Tests that directories verified at the same time are recorded in the known_good file without corrupting it.
+///
+/// # Steps
+/// * Create 64 additional subdirectories in testenvironment
+/// * Update subdirs for testenvironment
+/// * Verify subdirs for testenvironment with 16 directories at the same time
+/// * Read the known_good file
+///
+/// # Expected
+/// * arkhash should return without failure
+/// * the known_good file should contain exactly one well-formed line for each of the 66 directories
+#[test]
+fn known_good_concurrent_writes_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    let mut expected = HashSet::new();
+    expected.insert(String::from("./test"));
+    expected.insert(String::from("./secondsecond"));
+    for i in 0..64 {
+        fs::create_dir(format!("testenvironment/dir_{}", i)).unwrap();
+        let mut f = fs::File::create(format!("testenvironment/dir_{}/file", i)).unwrap();
+        f.write_all(format!("File {}", i).as_bytes()).unwrap();
+        expected.insert(format!("./dir_{}", i));
+    }
+
+    Assert::main_binary()
+        .with_args(&["-us"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-cs", "-D", "16", "-T", "4"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let now: DateTime<chrono::Local> = chrono::Local::now();
+    let known_good = fs::read_to_string(format!(
+        "testenvironment/known_good_{}_{}.txt",
+        now.month(),
+        now.year()
+    ))
+    .unwrap();
+
+    let lines: Vec<&str> = known_good.lines().collect();
+    let recorded: HashSet<String> = lines.iter().map(|line| line.to_string()).collect();
+    if !known_good.ends_with('\n') || lines.len() != expected.len() || recorded != expected {
+        teardown();
+        panic!(
+            "known_good file does not contain one line per directory: {:?}",
+            known_good
+        );
+    }
+
+    teardown();
+}
+
+/// Tests the progress loglevel with stdout redirected.
+///
+/// # Steps
+/// * Update subdirs for testenvironment
+/// * Verify subdirs for testenvironment in loglevel progress, capturing stdout
+///
+/// # Expected
+/// * arkhash should return without failure
+/// * the output should not contain escape sequences
+/// * the output should contain plain progress and result lines for both directories
+/// * the output should end with a summary of both directories
+#[test]
+fn progress_not_a_terminal_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-us", "--quiet"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-cs", "--loglevel", "progress"])
+        .current_dir("testenvironment")
+        .stdout()
+        .doesnt_contain("\x1b")
+        .stdout()
+        .contains("./test:          100%")
+        .stdout()
+        .contains("./secondsecond:  checked: OK")
+        .stdout()
+        .contains("Checked 2 directories, 2 OK, 0 failed")
+        .unwrap();
+
+    teardown();
+}
+
+/// Tests the progress loglevel of the update mode with stdout redirected.
+///
+/// # Steps
+/// * Update subdirs for testenvironment in loglevel progress, capturing stdout
+/// * Verify subdirs for testenvironment
+///
+/// # Expected
+/// * arkhash should return without failure both times
+/// * the output of the update should contain plain progress and result lines for both directories
+/// * the written sha1sum.txt files should verify
+#[test]
+fn update_progress_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-us", "--loglevel", "progress"])
+        .current_dir("testenvironment")
+        .stdout()
+        .doesnt_contain("\x1b")
+        .stdout()
+        .contains("./test:          100%")
+        .stdout()
+        .contains("./secondsecond:  updated: OK")
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-cs", "--quiet"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    teardown();
+}
+
+/// Tests the JSON output of the verify mode.
+///
+/// # Steps
+/// * Update subdirs for testenvironment
+/// * Remove a file from testenvironment/test and change a file in testenvironment/secondsecond
+/// * Verify subdirs for testenvironment with JSON output
+///
+/// # Expected
+/// * arkhash should return with exit code 1
+/// * every line of stdout should be a JSON object, one for each directory and a final summary
+/// * the removed file should be reported as missing, the changed file as failed
+#[test]
+fn verify_json_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-us", "--quiet"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    fs::remove_file("testenvironment/test/little_1").unwrap();
+    let mut f = fs::File::create("testenvironment/secondsecond/little_2").unwrap();
+    f.write_all(b"Changed small file").unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_arkhash"))
+        .args(["-cs", "--format", "json"])
+        .current_dir("testenvironment")
+        .output()
+        .unwrap();
+    teardown();
+
+    assert_eq!(output.status.code(), Some(1));
+    let objects: Vec<serde_json::Value> = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(objects.len(), 3);
+
+    for object in &objects[..2] {
+        assert_eq!(object["ok"], false);
+        match object["dir"].as_str().unwrap() {
+            "./test" => {
+                assert_eq!(object["missing"], serde_json::json!(["./little_1"]));
+                assert_eq!(object["failed"], serde_json::json!([]));
+            }
+            "./secondsecond" => {
+                assert_eq!(object["missing"], serde_json::json!([]));
+                assert_eq!(object["failed"], serde_json::json!(["./little_2"]));
+            }
+            dir => panic!("unexpected directory {}", dir),
+        }
+    }
+
+    assert_eq!(objects[2]["summary"], true);
+    assert_eq!(objects[2]["failed_directories"], 2);
+}
+
+/// Tests the JSON progress stream of the verify mode.
+///
+/// # Steps
+/// * Update subdirs for testenvironment
+/// * Verify subdirs for testenvironment with a progress file
+/// * Read the progress file
+///
+/// # Expected
+/// * arkhash should return without failure
+/// * every line of the progress file should be a JSON object
+/// * the last progress event of every directory should report all bytes as processed
+#[test]
+fn verify_progress_file_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-us", "--quiet"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-cs", "--quiet", "--progress-file", "progress.ndjson"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let progress_file = fs::File::open("testenvironment/progress.ndjson").unwrap();
+    let events: Vec<serde_json::Value> = BufReader::new(progress_file)
+        .lines()
+        .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+        .collect();
+    teardown();
+
+    for dir in &["./test", "./secondsecond"] {
+        let last = events
+            .iter()
+            .rev()
+            .find(|event| event["dir"] == *dir && event.get("processed").is_some())
+            .unwrap();
+        assert_eq!(last["processed"], last["total"]);
+        assert!(events
+            .iter()
+            .any(|event| event["dir"] == *dir
+                && event["message"]
+                    .as_str()
+                    .is_some_and(|message| message.starts_with("checked: OK in "))));
+    }
+}
+
+/// Tests the exclusion of a folder listed in the ignore file.
+///
+/// # Steps
+/// * Create a new directory in testenvironment called ignore
+/// * Create an .arkignore file containing 'ignore'
+/// * Update subdirs for testenvironment
+/// * Check if a database file was created for the ignore folder
+///
+/// # Expected
+/// * arkhash should return without failure
+/// * a database file should not be created in the ignore folder
+#[test]
+fn update_subdir_ignore_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+    fs::create_dir("testenvironment/ignore").unwrap();
+    let mut f = fs::File::create("testenvironment/ignore/little_1").unwrap();
+    f.write_all(b"Small file").unwrap();
+    let mut f = fs::File::create("testenvironment/ignore/little_2").unwrap();
+    f.write_all(b"Small file").unwrap();
+    let mut f = fs::File::create("testenvironment/.arkignore").unwrap();
+    f.write_all(b"ignore").unwrap();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-us"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("Ignoring Directory ./ignore")
+        .unwrap();
+
+    let mut hashsum_file = false;
+    for entry in fs::read_dir("testenvironment/ignore").unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_file() && path.to_str().unwrap().contains("sha1sum.txt") {
+            hashsum_file = true;
+        }
+    }
+
+    assert!(!hashsum_file);
+
+    teardown();
+}
+
+/// Tests that the verification is not conducted again when the subdirectories are already known good.
+///
+/// # Steps
+/// * Update subdirs for testenvironment
+/// * Verify subdirs for testenvironment
+/// * Verify subdirs for testenvironment again
+///
+/// # Expected
+/// * arkhash should return without failure
+/// * a file called known_good_month_year.txt should be created, containing exactly 2 lines,
+///   irregardless of how often arkhash is executed
+#[test]
+fn known_good_no_repetition_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-us"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let re = Regex::new(r"known_good.*").unwrap();
+    for _ in 0..4 {
+        Assert::main_binary()
+            .with_args(&["-cs"])
+            .current_dir("testenvironment")
+            .unwrap();
+
+        for entry in fs::read_dir("testenvironment").unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_file() && re.is_match(path.to_str().unwrap()) {
+                let known_good_file = fs::File::open(path);
+                if let Ok(known_good_file) = known_good_file {
+                    let mut i = 0;
+                    for _ in BufReader::new(known_good_file).lines() {
+                        i += 1;
+                    }
+
+                    if i != 2 {
+                        teardown();
+                        panic!(
+                            "arkhash produced the wrong number of entries in the known_good file. expected: 2, given: {}",
+                            i
+                        );
+                    }
+                } else {
+                    teardown();
+                    panic!("arkhash did not create the known_good file!");
+                }
+            }
+        }
+    }
+
+    teardown();
+}
+
+/// Tests that a directory which is recently marked as known bad is not analyzed again.
+///
+/// # Steps
+/// * Update subdirs for testenvironment
+/// * Create a file to_check_month_year.txt which contains secondsecond
+/// * Verify subdirs for testenvironment
+///
+/// # Expected
+/// * arkhash should fail with exit code 2
+/// * output should contain information that a directory was already in a known bad state
+#[test]
+fn to_check_dirs_skipped_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-us"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let now: DateTime<chrono::Local> = chrono::Local::now();
+    let mut to_check_file = fs::File::create(format!("testenvironment/to_check_{}_{}.txt", now.month(), now.year())).unwrap();
+    to_check_file.write_all("./secondsecond".as_bytes()).unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-cs"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("Directory ./secondsecond already marked known bad")
+        .fails_with(2)
+        .unwrap();
+
+    teardown();
+}
+
+/// Tests that --force verifies directories which are already marked known bad and rewrites the lists.
+///
+/// # Steps
+/// * Update subdirs for testenvironment
+/// * Create a file to_check_month_year.txt which contains secondsecond
+/// * Verify subdirs for testenvironment with --force
+///
+/// # Expected
+/// * arkhash should return without failure
+/// * output should not contain information that a directory was already in a known bad state
+/// * the to_check file should be removed and the known_good file should contain both directories
+#[test]
+fn force_verify_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-us"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let now: DateTime<chrono::Local> = chrono::Local::now();
+    let to_check_path = format!("testenvironment/to_check_{}_{}.txt", now.month(), now.year());
+    let mut to_check_file = fs::File::create(&to_check_path).unwrap();
+    to_check_file.write_all("./secondsecond\n".as_bytes()).unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-cs", "--force"])
+        .current_dir("testenvironment")
+        .stdout()
+        .doesnt_contain("already marked known bad")
+        .unwrap();
+
+    let known_good = fs::read_to_string(format!(
+        "testenvironment/known_good_{}_{}.txt",
+        now.month(),
+        now.year()
+    ))
+    .unwrap();
+    let recorded: HashSet<&str> = known_good.lines().collect();
+    let expected: HashSet<&str> = ["./test", "./secondsecond"].iter().cloned().collect();
+    if fs::metadata(&to_check_path).is_ok() || recorded != expected {
+        teardown();
+        panic!("--force did not rewrite the lists: {:?}", known_good);
+    }
+
+    teardown();
+}
+
+/// Tests that reset mode removes the state files of the current month and keeps everything else.
+///
+/// # Steps
+/// * Update subdirs for testenvironment
+/// * Create known_good and to_check files of the current month, a list of bad files and a known_good file of a previous month
+/// * Reset testenvironment with --dry-run
+/// * Reset testenvironment
+///
+/// # Expected
+/// * arkhash should return without failure
+/// * the dry run should list the files without removing them
+/// * the reset should remove the files of the current month and the list of bad files
+/// * the known_good file of the previous month and the sha1sum.txt files should be kept
+#[test]
+fn reset_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-us"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let now: DateTime<chrono::Local> = chrono::Local::now();
+    let removed = [
+        format!("known_good_{}_{}.txt", now.month(), now.year()),
+        format!("to_check_{}_{}.txt", now.month(), now.year()),
+        String::from("to_check_secondsecond.txt"),
+    ];
+    let kept = [
+        String::from("known_good_1_2000.txt"),
+        String::from("test/sha1sum.txt"),
+        String::from("secondsecond/sha1sum.txt"),
+    ];
+    for file in removed.iter().chain(kept[..1].iter()) {
+        let mut f = fs::File::create(format!("testenvironment/{}", file)).unwrap();
+        f.write_all(b"./secondsecond\n").unwrap();
+    }
+
+    Assert::main_binary()
+        .with_args(&["--reset", "--dry-run"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("Would remove ./to_check_secondsecond.txt")
+        .unwrap();
+
+    for file in removed.iter().chain(kept.iter()) {
+        if fs::metadata(format!("testenvironment/{}", file)).is_err() {
+            teardown();
+            panic!("the dry run removed {}", file);
+        }
+    }
+
+    Assert::main_binary()
+        .with_args(&["--reset"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("Removed ./to_check_secondsecond.txt")
+        .unwrap();
+
+    for file in removed.iter() {
+        if fs::metadata(format!("testenvironment/{}", file)).is_ok() {
+            teardown();
+            panic!("reset did not remove {}", file);
+        }
+    }
+    for file in kept.iter() {
+        if fs::metadata(format!("testenvironment/{}", file)).is_err() {
+            teardown();
+            panic!("reset removed {}", file);
+        }
+    }
+
+    teardown();
+}
+
+/// Tests that the state files are written to the state directory with names following the template.
+///
+/// # Steps
+/// * Update subdirs for testenvironment
+/// * Verify subdirs for testenvironment with --state-dir and --state-template
+/// * Verify subdirs for testenvironment again with the same options
+///
+/// # Expected
+/// * arkhash should return without failure
+/// * the known_good list should be created in the state directory with the templated name
+/// * no known_good list should be created in the working directory
+/// * the second verify should skip both directories
+#[test]
+fn state_dir_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-us"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let args = [
+        "-cs",
+        "--state-dir",
+        "state",
+        "--state-template",
+        "{list}-{year}-{month}.txt",
+    ];
+    Assert::main_binary()
+        .with_args(&args)
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let now: DateTime<chrono::Local> = chrono::Local::now();
+    let known_good_path = format!(
+        "testenvironment/state/known_good-{}-{}.txt",
+        now.year(),
+        now.month()
+    );
+    let known_good = fs::read_to_string(&known_good_path).unwrap_or_default();
+    if known_good.lines().count() != 2 {
+        teardown();
+        panic!("arkhash did not write {}: {:?}", known_good_path, known_good);
+    }
+    let re = Regex::new(r"known_good.*").unwrap();
+    for entry in fs::read_dir("testenvironment").unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_file() && re.is_match(path.to_str().unwrap()) {
+            teardown();
+            panic!("arkhash wrote {} to the working directory", path.display());
+        }
+    }
+
+    Assert::main_binary()
+        .with_args(&args)
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("Directory ./secondsecond already marked known good")
+        .unwrap();
+
+    teardown();
+}
+
+/// Tests that --no-monthly-state writes a single known_good list.
+///
+/// # Steps
+/// * Update subdirs for testenvironment
+/// * Verify subdirs for testenvironment with --no-monthly-state
+///
+/// # Expected
+/// * arkhash should return without failure
+/// * the known_good.txt file should contain both directories
+#[test]
+fn no_monthly_state_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-us"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-cs", "--no-monthly-state"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let known_good = fs::read_to_string("testenvironment/known_good.txt").unwrap_or_default();
+    if known_good.lines().count() != 2 {
+        teardown();
+        panic!("arkhash did not write known_good.txt: {:?}", known_good);
+    }
+
+    teardown();
+}
+
+/// Tests writing BSD style lines and reading them without the option.
+///
+/// # Steps
+/// * Update checksums for testenvironment with --tag
+/// * Verify testenvironment without --tag
+/// * Update checksums again with --tag
+///
+/// # Expected
+/// * arkhash should return without failure
+/// * every line of the generated database file should be a BSD style line
+/// * the second update should not hash any file
+#[test]
+fn bsd_format_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-u", "--tag"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let re = Regex::new(r"^SHA1 \(\./.+\) = [0-9a-f]{40}$").unwrap();
+    let hashfile = fs::read_to_string("testenvironment/sha1sum.txt").unwrap();
+    if hashfile.lines().count() != 27 || !hashfile.lines().all(|line| re.is_match(line)) {
+        teardown();
+        panic!("hashfile does not contain BSD style lines: {}", hashfile);
+    }
+
+    Assert::main_binary()
+        .with_args(&["-c"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("checked: OK")
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-u", "--tag"])
+        .current_dir("testenvironment")
+        .stdout()
+        .doesnt_contain("SHA1 (")
+        .unwrap();
+
+    teardown();
+}
+
+/// Tests writing plain lines for the coreutils and reading them without the option.
+///
+/// # Steps
+/// * Update checksums for testenvironment with --compat-format
+/// * Verify testenvironment without --compat-format
+/// * Update checksums again with --compat-format
+///
+/// # Expected
+/// * arkhash should return without failure
+/// * the generated database file should have no header and only plain lines without size and modification time
+/// * the second update should not hash any file
+#[test]
+fn compat_format_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-u", "--compat-format"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let re = Regex::new(r"^[0-9a-f]{40}  \./.+$").unwrap();
+    let hashfile = fs::read_to_string("testenvironment/sha1sum.txt").unwrap();
+    if hashfile.lines().count() != 27 || !hashfile.lines().all(|line| re.is_match(line)) {
+        teardown();
+        panic!("hashfile does not contain plain lines: {}", hashfile);
+    }
+
+    Assert::main_binary()
+        .with_args(&["-c"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("checked: OK")
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-u", "--compat-format"])
+        .current_dir("testenvironment")
+        .stdout()
+        .doesnt_contain("  ./")
+        .unwrap();
+
+    teardown();
+}
+
+/// Tests that verify mode detects the algorithm from the hashsum file.
+///
+/// # Steps
+/// * Update checksums for testenvironment with md5
+/// * Verify testenvironment without an algorithm
+/// * Update checksums for testenvironment with sha1 as well
+/// * Verify testenvironment without an algorithm again
+/// * Change a line of md5sum.txt and verify once more
+///
+/// # Expected
+/// * the first verify should detect md5 and succeed
+/// * the second verify should check both hashsum files and succeed
+/// * the third verify should fail with exit code 1 although sha1sum.txt still matches
+#[test]
+fn detect_algorithm_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-u", "-a", "md5"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-c"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("checked: OK")
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-u"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-c"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("checked: OK")
+        .unwrap();
+
+    let md5sums = fs::read_to_string("testenvironment/md5sum.txt").unwrap();
+    let line = md5sums.lines().find(|line| !is_header(line)).unwrap();
+    let changed = format!("{}{}", "0".repeat(32), &line[32..]);
+    fs::write(
+        "testenvironment/md5sum.txt",
+        md5sums.replacen(line, &changed, 1),
+    )
+    .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-c"])
+        .current_dir("testenvironment")
+        .fails_with(1)
+        .unwrap();
+
+    teardown();
+}
+
+/// Tests that the hashsum files of other algorithms are not hashed themselves.
+///
+/// # Steps
+/// * Update checksums for testenvironment with sha256
+/// * Update checksums for testenvironment with sha1
+/// * Add a file and update checksums for testenvironment with sha256 again
+/// * Verify testenvironment without an algorithm
+///
+/// # Expected
+/// * sha1sum.txt should not list sha256sum.txt
+/// * verify mode should check both hashsum files and succeed although sha256sum.txt changed
+#[test]
+fn other_algorithm_hashsum_file_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-u", "-a", "sha256"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-u"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    fs::write("testenvironment/test/new_file", "New").unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-u", "-a", "sha256"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let sha1sums = fs::read_to_string("testenvironment/sha1sum.txt").unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-c"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("checked: OK")
+        .stdout()
+        .doesnt_contain("FAILED")
+        .unwrap();
+
+    teardown();
+
+    assert!(!sha1sums.contains("sha256sum.txt"), "{}", sha1sums);
+}
+
+/// Tests that files which can not be read are reported apart from changed files.
+///
+/// # Steps
+/// * Update checksums for testenvironment
+/// * Replace a file by a directory of the same name
+/// * Verify testenvironment in JSON format
+///
+/// # Expected
+/// * arkhash should fail with exit code 1
+/// * the file should be reported as unreadable because of an io error, not as failed or missing
+/// * the summary should count the unreadable file
+#[test]
+#[cfg(unix)]
+fn verify_unreadable_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-u"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    fs::remove_file("testenvironment/test/little_1").unwrap();
+    fs::create_dir("testenvironment/test/little_1").unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_arkhash"))
+        .args(["-c", "--format", "json"])
+        .current_dir("testenvironment")
+        .output()
+        .unwrap();
+
+    let objects: Vec<serde_json::Value> = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    teardown();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(objects[0]["failed"], serde_json::json!([]));
+    assert_eq!(objects[0]["missing"], serde_json::json!([]));
+    assert_eq!(objects[0]["unreadable"][0]["path"], "./test/little_1");
+    assert_eq!(objects[0]["unreadable"][0]["reason"], "io_error");
+    assert_eq!(objects[1]["unreadable_files"], 1);
+}
+
+/// Tests a database file with CRLF line endings, as written by editors on Windows.
+///
+/// # Steps
+/// * Update checksums for testenvironment
+/// * Convert the line endings of the database file to CRLF
+/// * Verify testenvironment
+/// * Update checksums again
+///
+/// # Expected
+/// * arkhash should return without failure
+/// * verify should find every file
+/// * the update should not hash any file again, the database file should still contain 27 lines
+#[test]
+fn crlf_manifest_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-u"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let hashfile = fs::read_to_string("testenvironment/sha1sum.txt").unwrap();
+    fs::write("testenvironment/sha1sum.txt", hashfile.replace('\n', "\r\n")).unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-c"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("checked: OK")
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-u"])
+        .current_dir("testenvironment")
+        .stdout()
+        .doesnt_contain("  ./")
+        .unwrap();
+
+    let hashfile = fs::read_to_string("testenvironment/sha1sum.txt").unwrap();
+    if hashsum_lines(&hashfile).len() != 27 {
+        teardown();
+        panic!("hashfile contains duplicated lines: {}", hashfile);
+    }
+
+    teardown();
+}
+
+/// Tests the handling of symbolic links.
+///
+/// # Steps
+/// * Create a symbolic link to a file and a symbolic link to the parent directory, which forms a loop
+/// * Update checksums for testenvironment, once for every symlinks policy
+/// * Verify testenvironment with and without the policy used for the update
+///
+/// # Expected
+/// * by default, the links should not be listed in the database file
+/// * with follow, the linked file should be listed with the hash of its target and the loop should not be descended into
+/// * with hash-link, both links should be listed and the hash of the file link should differ from the hash of its target
+/// * verify should fail if a listed link is skipped
+#[test]
+#[cfg(unix)]
+fn symlinks_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    std::os::unix::fs::symlink("little_1", "testenvironment/test/link_file").unwrap();
+    std::os::unix::fs::symlink("..", "testenvironment/test/loop").unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-u"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let hashfile = fs::read_to_string("testenvironment/sha1sum.txt").unwrap();
+    let skipped = !hashfile.contains("link_file") && !hashfile.contains("loop");
+
+    fs::remove_file("testenvironment/sha1sum.txt").unwrap();
+    Assert::main_binary()
+        .with_args(&["-u", "--symlinks", "follow"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let followed = fs::read_to_string("testenvironment/sha1sum.txt").unwrap();
+    let hash_of = |hashfile: &str, path: &str| {
+        hashfile
+            .lines()
+            .find(|line| line.ends_with(path))
+            .and_then(|line| line.split(' ').next())
+            .map(str::to_string)
+    };
+
+    Assert::main_binary()
+        .with_args(&["-c", "--symlinks", "follow"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("checked: OK")
+        .unwrap();
+
+    fs::remove_file("testenvironment/sha1sum.txt").unwrap();
+    Assert::main_binary()
+        .with_args(&["-u", "--symlinks", "hash-link"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let linked = fs::read_to_string("testenvironment/sha1sum.txt").unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-c", "--symlinks", "hash-link"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("checked: OK")
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-c"])
+        .current_dir("testenvironment")
+        .fails_with(1)
+        .unwrap();
+
+    teardown();
+
+    assert!(skipped, "links were not skipped");
+    assert_eq!(hashsum_lines(&followed).len(), 28);
+    assert!(!followed.contains("./test/loop/"));
+    assert_eq!(
+        hash_of(&followed, "./test/link_file"),
+        hash_of(&followed, "./test/little_1")
+    );
+    assert!(hash_of(&linked, "./test/loop").is_some());
+    assert!(hash_of(&linked, "./test/link_file").is_some());
+    assert_ne!(
+        hash_of(&linked, "./test/link_file"),
+        hash_of(&linked, "./test/little_1")
+    );
+}
+
+/// Tests the handling of hidden files and directories.
+///
+/// # Steps
+/// * Create a hidden file and a hidden directory containing a file
+/// * Update checksums for the subdirectories of testenvironment
+/// * Update checksums for testenvironment
+/// * Update checksums for testenvironment again, including hidden files
+///
+/// # Expected
+/// * the hidden directory should not get a database file in subdir mode
+/// * by default, nothing hidden should be listed in the database file
+/// * with --hidden include, the hidden file and the file in the hidden directory should be listed
+#[test]
+fn hidden_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    fs::write("testenvironment/.hidden_file", "hidden").unwrap();
+    fs::create_dir_all("testenvironment/.git/objects").unwrap();
+    fs::write("testenvironment/.git/objects/object", "object").unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-u", "-s"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let hidden_dir_updated = std::path::Path::new("testenvironment/.git/sha1sum.txt").exists();
+
+    Assert::main_binary()
+        .with_args(&["-u"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let excluded = fs::read_to_string("testenvironment/sha1sum.txt").unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-u", "--hidden", "include"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let included = fs::read_to_string("testenvironment/sha1sum.txt").unwrap();
+
+    teardown();
+
+    assert!(!hidden_dir_updated, "hidden directory was updated in subdir mode");
+    assert!(!excluded.contains("./.hidden_file"));
+    assert!(!excluded.contains("./.git/"));
+    assert!(included.contains("./.hidden_file"));
+    assert!(included.contains("./.git/objects/object"));
+}
+
+/// Tests the dry run of update mode.
+///
+/// # Steps
+/// * Update checksums for testenvironment in a dry run
+/// * Update checksums for testenvironment
+/// * Change a file and update checksums in a dry run again
+///
+/// # Expected
+/// * the first dry run should count every file and not write the database file
+/// * the second dry run should only count the changed file and leave the database file unchanged
+#[test]
+fn update_dry_run_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-u", "--dry-run"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("Would hash 27 files")
+        .unwrap();
+
+    let written = std::path::Path::new("testenvironment/sha1sum.txt").exists();
+
+    Assert::main_binary()
+        .with_args(&["-u"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let hashfile = fs::read_to_string("testenvironment/sha1sum.txt").unwrap();
+    fs::OpenOptions::new()
+        .append(true)
+        .open("testenvironment/test/little_1")
+        .unwrap()
+        .write_all(b"changed")
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-u", "-n"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("would hash ./test/little_1")
+        .stdout()
+        .contains("Would hash 1 files")
+        .unwrap();
+
+    let unchanged = fs::read_to_string("testenvironment/sha1sum.txt").unwrap() == hashfile;
+
+    teardown();
+
+    assert!(!written, "database file was written in a dry run");
+    assert!(unchanged, "database file was changed in a dry run");
+}
+
+/// Tests the dry run of verify mode in subdir mode.
+///
+/// # Steps
+/// * Update checksums for the subdirectories of testenvironment
+/// * Verify the subdirectories of testenvironment
+/// * Add a directory and update its checksums
+/// * Verify the subdirectories of testenvironment in a dry run, once with --force
+///
+/// # Expected
+/// * both dry runs should return without failure
+/// * the first dry run should only list the new directory, the second one every directory
+/// * the known_good list should be unchanged
+#[test]
+fn verify_dry_run_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-u", "-s"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-c", "-s"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let now: DateTime<chrono::Local> = chrono::Local::now();
+    let known_good_path = format!(
+        "testenvironment/known_good_{}_{}.txt",
+        now.month(),
+        now.year()
+    );
+    let known_good = fs::read_to_string(&known_good_path).unwrap();
+
+    fs::create_dir("testenvironment/new").unwrap();
+    fs::write("testenvironment/new/file", "new file").unwrap();
+    Assert::main_binary()
+        .with_args(&["-u", "new"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-c", "-s", "--dry-run"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("Would verify Directory ./new (8 B)")
+        .stdout()
+        .contains("Would verify 1 directories (8 B)")
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-c", "-s", "-n", "--force"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("Would verify Directory ./test")
+        .stdout()
+        .doesnt_contain("Would verify 1 directories")
+        .unwrap();
+
+    let unchanged = fs::read_to_string(&known_good_path).unwrap() == known_good;
+
+    teardown();
+
+    assert!(unchanged, "known_good list was changed in a dry run");
+}
+
+/// Tests the prune mode.
+///
+/// # Steps
+/// * Update checksums for testenvironment
+/// * Delete a file
+/// * Prune the database file in a dry run
+/// * Prune the database file
+/// * Verify testenvironment
+///
+/// # Expected
+/// * the dry run should list the deleted file and leave the database file unchanged
+/// * prune should remove only the line of the deleted file
+/// * verify should return without failure
+#[test]
+fn prune_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-u"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    fs::remove_file("testenvironment/test/little_1").unwrap();
+
+    Assert::main_binary()
+        .with_args(&["--prune", "--dry-run"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("would remove ./test/little_1")
+        .stdout()
+        .contains("Would remove 1 lines")
+        .unwrap();
+
+    let lines_after_dry_run =
+        hashsum_lines(&fs::read_to_string("testenvironment/sha1sum.txt").unwrap()).len();
+
+    Assert::main_binary()
+        .with_args(&["--prune"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("Removed 1 lines")
+        .unwrap();
+
+    let hashfile = fs::read_to_string("testenvironment/sha1sum.txt").unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-c"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("checked: OK")
+        .unwrap();
+
+    teardown();
+
+    assert_eq!(lines_after_dry_run, 27);
+    assert_eq!(hashsum_lines(&hashfile).len(), 26);
+    assert!(!hashfile.contains("./test/little_1\n"));
+}
+
+/// Tests adding and removing the lines of single files.
+///
+/// # Steps
+/// * Update checksums for the subdirectories of testenvironment
+/// * Change a file and add it
+/// * Remove another file
+/// * Verify the subdirectories of testenvironment
+///
+/// # Expected
+/// * add should replace the line of the changed file in the database file of its subdirectory in place
+/// * remove should delete the line of the other file and keep the order of the remaining lines
+/// * verify should return without failure
+#[test]
+fn add_remove_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-u", "-s"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let before: Vec<String> = fs::read_to_string("testenvironment/test/sha1sum.txt")
+        .unwrap()
+        .lines()
+        .map(str::to_string)
+        .collect();
+
+    fs::OpenOptions::new()
+        .append(true)
+        .open("testenvironment/test/little_1")
+        .unwrap()
+        .write_all(b"changed")
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["--add", "test/little_1"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["--remove", "little_2"])
+        .current_dir("testenvironment/test")
+        .unwrap();
+
+    let after: Vec<String> = fs::read_to_string("testenvironment/test/sha1sum.txt")
+        .unwrap()
+        .lines()
+        .map(str::to_string)
+        .collect();
+
+    Assert::main_binary()
+        .with_args(&["-c", "-s", "--no-monthly-state"])
+        .current_dir("testenvironment")
+        .stdout()
+        .doesnt_contain("FAILED")
+        .unwrap();
+
+    teardown();
+
+    let changed = |lines: &[String]| {
+        lines
+            .iter()
+            .position(|line| line.ends_with("  ./little_1"))
+            .map(|i| (i, lines[i].clone()))
+    };
+    let (position_before, line_before) = changed(&before).unwrap();
+    let (position_after, line_after) = changed(&after).unwrap();
+    assert_eq!(position_before, position_after);
+    assert_ne!(line_before, line_after);
+
+    let expected: Vec<&String> = before
+        .iter()
+        .filter(|line| !line.ends_with("  ./little_2") && !line.ends_with("  ./little_1"))
+        .collect();
+    let remaining: Vec<&String> = after
+        .iter()
+        .filter(|line| !line.ends_with("  ./little_1"))
+        .collect();
+    assert_eq!(remaining, expected);
+}
+
+/// Tests the comparison of two database files.
+///
+/// # Steps
+/// * Update checksums for testenvironment and keep the contents of the database file
+/// * Change a file, add a file and delete a file
+/// * Update checksums for testenvironment again, prune it and write the old contents to a copy
+/// * Compare the copy with the database file, in text and in JSON format
+/// * Compare the database file with itself
+///
+/// # Expected
+/// * the differing files should fail with exit code 1 and list the changed, added and removed file
+/// * comparing a file with itself should return without failure
+#[test]
+fn diff_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-u"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let old_hashfile = fs::read("testenvironment/sha1sum.txt").unwrap();
+
+    fs::OpenOptions::new()
+        .append(true)
+        .open("testenvironment/test/little_1")
+        .unwrap()
+        .write_all(b"changed")
+        .unwrap();
+    fs::write("testenvironment/test/new_file", "new file").unwrap();
+    fs::remove_file("testenvironment/test/little_2").unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-u"])
+        .current_dir("testenvironment")
+        .unwrap();
+    Assert::main_binary()
+        .with_args(&["--prune"])
+        .current_dir("testenvironment")
+        .unwrap();
+    fs::write("testenvironment/sha1sum_old.txt", old_hashfile).unwrap();
+
+    Assert::main_binary()
+        .with_args(&["--diff", "sha1sum_old.txt", "sha1sum.txt"])
+        .current_dir("testenvironment")
+        .fails_with(1)
+        .stdout()
+        .contains("changed  ./test/little_1")
+        .stdout()
+        .contains("added    ./test/new_file")
+        .stdout()
+        .contains("removed  ./test/little_2")
+        .stdout()
+        .contains("1 changed, 1 added, 1 removed")
+        .unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_arkhash"))
+        .args(["--diff", "sha1sum_old.txt", "sha1sum.txt", "--format", "json"])
+        .current_dir("testenvironment")
+        .output()
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["--diff", "sha1sum.txt", "sha1sum.txt"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("0 changed, 0 added, 0 removed")
+        .unwrap();
+
+    teardown();
+
+    let diff: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(diff["changed"], serde_json::json!(["./test/little_1"]));
+    assert_eq!(diff["added"], serde_json::json!(["./test/new_file"]));
+    assert_eq!(diff["removed"], serde_json::json!(["./test/little_2"]));
+}
+
+/// Tests the comparison of two directories.
+///
+/// # Steps
+/// * Compare the two directories of testenvironment, which contain the same files except for one
+/// * Change a file, keeping its size, and append to another file
+/// * Compare the directories again, in text and in JSON format
+///
+/// # Expected
+/// * the first comparison should fail with exit code 1 and only list the additional file
+/// * the second comparison should also list both changed files as differing
+#[test]
+fn compare_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["--compare", "test", "secondsecond"])
+        .current_dir("testenvironment")
+        .fails_with(1)
+        .stdout()
+        .contains("only in secondsecond: ./big_1")
+        .stdout()
+        .contains("13 identical, 0 differ, 0 only in test, 1 only in secondsecond")
+        .unwrap();
+
+    fs::write("testenvironment/test/little_1", "Small fila").unwrap();
+    fs::OpenOptions::new()
+        .append(true)
+        .open("testenvironment/test/little_2")
+        .unwrap()
+        .write_all(b"changed")
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["--compare", "test", "secondsecond"])
+        .current_dir("testenvironment")
+        .fails_with(1)
+        .stdout()
+        .contains("differs: ./little_1")
+        .stdout()
+        .contains("11 identical, 2 differ")
+        .unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_arkhash"))
+        .args(["--compare", "test", "secondsecond", "--format", "json"])
+        .current_dir("testenvironment")
+        .output()
+        .unwrap();
+
+    teardown();
+
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        report["differing"],
+        serde_json::json!(["./little_1", "./little_2"])
+    );
+    assert_eq!(report["only_in_second"], serde_json::json!(["./big_1"]));
+    assert_eq!(report["identical"].as_array().unwrap().len(), 11);
+}
+
+/// Tests finding duplicate files during update mode.
+///
+/// # Steps
+/// * Update checksums for the subdirectories of testenvironment, finding duplicates
+/// * Update checksums again with --machine
+/// * Update checksums again in JSON format
+///
+/// # Expected
+/// * the small files and the middle files of both directories should form two groups, the unique file none
+/// * with --machine the sizes are printed as raw numbers of bytes
+/// * the JSON object should list the 18 small files in one group
+#[test]
+fn find_duplicates_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-u", "-s", "--find-duplicates"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("18 files of 10 B with hash")
+        .stdout()
+        .contains("./test/little_1")
+        .stdout()
+        .contains("./secondsecond/little_9")
+        .stdout()
+        .contains("Found 2 groups of duplicates")
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-u", "-s", "--find-duplicates", "--machine"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("18 files of 10 bytes with hash")
+        .stdout()
+        .contains("170 bytes wasted")
+        .unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_arkhash"))
+        .args(["-u", "-s", "--find-duplicates", "--format", "json"])
+        .current_dir("testenvironment")
+        .output()
+        .unwrap();
+
+    teardown();
+
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let groups = report["duplicates"].as_array().unwrap();
+    assert_eq!(groups.len(), 2);
+    let small = groups
+        .iter()
+        .find(|group| group["size"] == 10)
+        .unwrap();
+    assert_eq!(small["paths"].as_array().unwrap().len(), 18);
+    assert_eq!(small["wasted_bytes"], 170);
+}
+
+/// Tests that the buffer size and memory mapping do not change the hashes.
+///
+/// # Steps
+/// 1. Update with the default buffer size
+/// 2. Update again into a fresh sha1sum.txt with a buffer of 4 bytes and memory mapping of all files larger than 10 bytes
+///
+/// # Expected
+/// Both sha1sum.txt files are identical.
+#[test]
+fn buffer_size_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-u"])
+        .current_dir("testenvironment")
+        .unwrap();
+    let default_manifest = fs::read_to_string("testenvironment/sha1sum.txt").unwrap();
+    fs::remove_file("testenvironment/sha1sum.txt").unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-u", "--buffer-size", "4", "--mmap-threshold", "11"])
+        .current_dir("testenvironment")
+        .unwrap();
+    let tuned_manifest = fs::read_to_string("testenvironment/sha1sum.txt").unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-u", "--buffer-size", "0"])
+        .current_dir("testenvironment")
+        .fails()
+        .and()
+        .stderr()
+        .contains("--buffer-size SIZE")
+        .unwrap();
+
+    teardown();
+
+    assert_eq!(hashsum_lines(&default_manifest).len(), 27);
+    assert_eq!(default_manifest, tuned_manifest);
+}
+
+/// Tests that verify mode reports the changed blocks of a file hashed in blocks.
+///
+/// # Steps
+/// 1. Update the directory test with a block size of 100 bytes
+/// 2. Overwrite a byte in the third block of middle_1 without changing its size
+/// 3. Verify the directory in text and in JSON format
+///
+/// # Expected
+/// The sha1sum.blocks.txt has a line for every file, with 6 block hashes for middle_1.
+/// Verify mode reports that block 2 of the 6 blocks of middle_1 differs, which covers bytes 200 to 300.
+#[test]
+fn block_size_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-u", "--block-size", "100"])
+        .current_dir("testenvironment/test")
+        .unwrap();
+    let block_hashes = fs::read_to_string("testenvironment/test/sha1sum.blocks.txt").unwrap();
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .open("testenvironment/test/middle_1")
+        .unwrap();
+    file.seek(std::io::SeekFrom::Start(250)).unwrap();
+    file.write_all(b"X").unwrap();
+    drop(file);
+
+    Assert::main_binary()
+        .with_args(&["-c"])
+        .current_dir("testenvironment/test")
+        .fails()
+        .and()
+        .stdout()
+        .contains("./middle_1: blocks 2 of 6 differ (100 bytes each)")
+        .unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_arkhash"))
+        .args(["-c", "--format", "json"])
+        .current_dir("testenvironment/test")
+        .output()
+        .unwrap();
+
+    teardown();
+
+    assert_eq!(block_hashes.lines().count(), 13);
+    let middle = block_hashes
+        .lines()
+        .find(|line| line.starts_with("./middle_1\t"))
+        .unwrap();
+    assert_eq!(middle.split('\t').count(), 8);
+    assert_eq!(middle.split('\t').nth(1), Some("100"));
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let report: serde_json::Value = serde_json::from_str(stdout.lines().next().unwrap()).unwrap();
+    let changed = &report["changed_blocks"][0];
+    assert_eq!(changed["path"], "./middle_1");
+    assert_eq!(changed["ranges"][0]["bytes"], serde_json::json!([200, 300]));
+}
+
+/// Tests verifying only a sample of the listed files.
+///
+/// # Steps
+/// 1. Update the directories in subdir mode
+/// 2. Verify samples of 3 files of every directory with a fixed seed, in text and twice in JSON format
+///
+/// # Expected
+/// Every directory reports a correct sample of 3 files and the summary names the partial check with its seed.
+/// Both JSON runs hash the same number of bytes. No directory is recorded as known good.
+#[test]
+fn sample_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-u", "-s"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-c", "-s", "--sample-count", "3", "--seed", "42", "--no-monthly-state"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("./test: sample of 3 of 13 files checked: OK")
+        .stdout()
+        .contains("./secondsecond: sample of 3 of 14 files checked: OK")
+        .stdout()
+        .contains("partial check of 6 of 27 listed files (--seed 42)")
+        .unwrap();
+    let known_good = fs::read_to_string("testenvironment/known_good.txt").unwrap_or_default();
+
+    let run = || {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_arkhash"))
+            .args(["-c", "-s", "--sample", "50", "--seed", "7", "--format", "json"])
+            .current_dir("testenvironment")
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let summary: serde_json::Value =
+            serde_json::from_str(stdout.lines().last().unwrap()).unwrap();
+        summary
+    };
+    let first = run();
+    let second = run();
+
+    teardown();
+
+    assert!(known_good.is_empty());
+    assert_eq!(first["sampled_files"], 14);
+    assert_eq!(first["listed_files"], 27);
+    assert_eq!(first["seed"], 7);
+    assert_eq!(first["bytes"], second["bytes"]);
+}
+
+/// Tests stopping verify mode at the first failure.
+///
+/// # Steps
+/// 1. Update the directories in subdir mode
+/// 2. Change every small file in both directories
+/// 3. Verify one directory at a time with --fail-fast, in text and in JSON format
+///
+/// # Expected
+/// Both runs fail. Only the first directory is verified and the summary states that the run was stopped.
+#[test]
+fn fail_fast_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-u", "-s"])
+        .current_dir("testenvironment")
+        .unwrap();
+    for directory in &["test", "secondsecond"] {
+        for i in 1..10 {
+            fs::write(
+                format!("testenvironment/{}/little_{}", directory, i),
+                "Changed file",
+            )
+            .unwrap();
+        }
+    }
+
+    Assert::main_binary()
+        .with_args(&["-c", "-s", "--fail-fast", "-D", "1", "--no-monthly-state"])
+        .current_dir("testenvironment")
+        .fails_with(1)
+        .and()
+        .stdout()
+        .contains("Checked 1 directories, 0 OK, 1 failed")
+        .stdout()
+        .contains("stopped at the first failure")
+        .unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_arkhash"))
+        .args(["-c", "-s", "--fail-fast", "-D", "1", "--force", "--no-monthly-state", "--format", "json"])
+        .current_dir("testenvironment")
+        .output()
+        .unwrap();
+
+    teardown();
+
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let summary: serde_json::Value = serde_json::from_str(stdout.lines().last().unwrap()).unwrap();
+    assert_eq!(summary["directories"], 1);
+    assert_eq!(summary["failed_directories"], 1);
+    assert_eq!(summary["stopped"], true);
+}
+
+/// Tests limiting the rate files are read at.
+///
+/// # Steps
+/// 1. Update the directories in subdir mode
+/// 2. Verify them, reading at most 4 KiB per second
+///
+/// # Expected
+/// Both directories are OK. Reading the 10 KiB of files takes more than a second,
+/// since only the first 4 KiB are read without waiting.
+#[test]
+fn max_rate_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-u", "-s"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let start = std::time::Instant::now();
+    Assert::main_binary()
+        .with_args(&["-c", "-s", "--max-rate", "4K", "--no-monthly-state"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("Checked 2 directories, 2 OK, 0 failed")
+        .unwrap();
+    let elapsed = start.elapsed();
+
+    teardown();
+
+    assert!(elapsed >= std::time::Duration::from_secs(1));
+}
+
+/// Tests processing the files and directories listed on stdin.
+///
+/// # Steps
+/// * Update checksums for a file of test and for secondsecond listed on stdin
+/// * Verify a file listed on stdin after changing another one
+/// * Verify a file listed on stdin that is not in the sha1sum.txt
+///
+/// # Expected
+/// * the sha1sum.txt of test should only contain the listed file, the one of secondsecond every file
+/// * the listed file should be checked without noticing the changed one
+/// * the file that is not listed in the sha1sum.txt should fail the verification
+#[test]
+fn from_stdin_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    let run = |args: &[&str], paths: &[u8]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_arkhash"))
+            .args(args)
+            .current_dir("testenvironment")
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .and_then(|mut child| {
+                child.stdin.take().unwrap().write_all(paths)?;
+                child.wait_with_output()
+            })
+            .unwrap()
+    };
+
+    // test
+    let update = run(&["-u", "--from-stdin"], b"test/little_1\nsecondsecond\n");
+
+    let test_database = fs::read_to_string("testenvironment/test/sha1sum.txt").unwrap();
+    let second_database = fs::read_to_string("testenvironment/secondsecond/sha1sum.txt").unwrap();
+
+    fs::write("testenvironment/test/little_2", "Changed").unwrap();
+    let verify = run(&["-c", "--from-stdin"], b"test/little_1\n");
+    let unlisted = run(&["-c", "--from-stdin"], b"test/little_3\n");
+
+    teardown();
+
+    assert!(update.status.success());
+    assert_eq!(hashsum_lines(&test_database).len(), 1);
+    assert!(test_database.ends_with("  ./little_1\n"));
+    assert_eq!(hashsum_lines(&second_database).len(), 14);
+
+    let verify_stdout = String::from_utf8(verify.stdout).unwrap();
+    assert!(verify.status.success(), "{}", verify_stdout);
+    assert!(verify_stdout.contains("1 of 1 files checked: OK"), "{}", verify_stdout);
+
+    let unlisted_stderr = String::from_utf8(unlisted.stderr).unwrap();
+    assert!(!unlisted.status.success());
+    assert!(unlisted_stderr.contains("./little_3: not listed in sha1sum.txt"), "{}", unlisted_stderr);
+}
+
+/// Tests processing a directory listed on stdin whose path is not valid UTF-8.
+///
+/// # Steps
+/// * Create a directory whose name contains a byte that is not valid UTF-8
+/// * Update checksums for the directory listed NUL separated on stdin
+/// * Verify checksums for the directory listed NUL separated on stdin
+///
+/// # Expected
+/// * the directory should contain a database file listing its file
+/// * verification should report the directory as OK
+#[cfg(unix)]
+#[test]
+fn from_stdin_non_utf8_test() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    let directory = Path::new(OsStr::from_bytes(b"testenvironment/d\xff"));
+    fs::create_dir(directory).unwrap();
+    fs::write(directory.join("file"), "content").unwrap();
+
+    let run = |args: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_arkhash"))
+            .args(args)
+            .current_dir("testenvironment")
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .and_then(|mut child| {
+                child.stdin.take().unwrap().write_all(b"d\xff\0")?;
+                child.wait_with_output()
+            })
+            .unwrap()
+    };
+
+    // test
+    let update = run(&["-u", "--from-stdin", "--null"]);
+    let database = fs::read_to_string(directory.join("sha1sum.txt"));
+    let verify = run(&["-c", "--from-stdin", "--null"]);
+
+    teardown();
+
+    assert!(update.status.success(), "{}", String::from_utf8_lossy(&update.stderr));
+    assert!(database.unwrap().contains("  ./file"));
+    let verify_stdout = String::from_utf8_lossy(&verify.stdout);
+    assert!(verify.status.success(), "{}", verify_stdout);
+    assert!(verify_stdout.contains("1 OK, 0 failed"), "{}", verify_stdout);
+}
+
+/// Tests excluding files with .arkhashignore files.
+///
+/// # Steps
+/// * Create a .arkhashignore in testenvironment and one in test
+/// * Update checksums for testenvironment
+/// * Exclude a file after it was hashed and change it
+/// * Verify checksums
+///
+/// # Expected
+/// * the database file should not list the excluded files, but the ones a later or nested pattern includes
+/// * verification should skip the changed excluded file and not contain the keyword FAILED
+#[test]
+fn ignore_file_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    fs::write(
+        "testenvironment/.arkhashignore",
+        "middle_*\n!middle_2\nsecondsecond/big_1\n",
+    )
+    .unwrap();
+    fs::write("testenvironment/test/.arkhashignore", "little_9\n!middle_3\n").unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-u"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let database = fs::read_to_string("testenvironment/sha1sum.txt").unwrap();
+
+    fs::write("testenvironment/test/.arkhashignore", "little_[19]\n!middle_3\n").unwrap();
+    fs::write("testenvironment/test/little_1", "Changed").unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-c"])
+        .current_dir("testenvironment")
+        .stdout()
+        .doesnt_contain("FAILED")
+        .unwrap();
+
+    teardown();
+
+    let paths: Vec<&str> = hashsum_lines(&database)
+        .into_iter()
+        .map(|line| line.rsplit("  ").next().unwrap())
+        .collect();
+    assert_eq!(paths.len(), 20, "{}", database);
+    assert!(paths.contains(&"./test/little_1"));
+    assert!(!paths.contains(&"./test/little_9"));
+    assert!(paths.contains(&"./secondsecond/little_9"));
+    assert!(paths.contains(&"./test/middle_2"));
+    assert!(paths.contains(&"./test/middle_3"));
+    assert!(!paths.contains(&"./secondsecond/middle_3"));
+    assert!(!paths.contains(&"./secondsecond/big_1"));
+}
+
+/// Tests skipping the files excluded by .gitignore files.
+///
+/// # Steps
+/// * Create a .gitignore in testenvironment
+/// * Update checksums for testenvironment
+/// * Update checksums for testenvironment again, respecting the .gitignore
+///
+/// # Expected
+/// * without --respect-gitignore, every file should be listed in the database file
+/// * with --respect-gitignore, the excluded files should not be listed in the new database file
+#[test]
+fn respect_gitignore_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    fs::write("testenvironment/.gitignore", "middle_*\n!secondsecond/middle_1\n").unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-u"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let all_files = fs::read_to_string("testenvironment/sha1sum.txt").unwrap();
+    fs::remove_file("testenvironment/sha1sum.txt").unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-u", "--respect-gitignore"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let database = fs::read_to_string("testenvironment/sha1sum.txt").unwrap();
+
+    teardown();
+
+    assert_eq!(hashsum_lines(&all_files).len(), 27);
+    assert_eq!(hashsum_lines(&database).len(), 20, "{}", database);
+    assert!(!database.contains("./test/middle_1"));
+    assert!(database.contains("./secondsecond/middle_1"));
+    assert!(database.contains("./secondsecond/big_1"));
+    assert!(!database.contains(".gitignore"));
+}
+
+/// Tests giving up on a directory whose files do not finish hashing in time.
+///
+/// # Steps
+/// * Update checksums for testenvironment
+/// * List a named pipe without a writer in the database file, opening it blocks forever
+/// * Verify checksums with a timeout of 1 second
+///
+/// # Expected
+/// * verification should finish, report the directory as timed out and fail
+#[cfg(unix)]
+#[test]
+fn timeout_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-u"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let status = std::process::Command::new("mkfifo")
+        .arg("testenvironment/test/pipe")
+        .status()
+        .unwrap();
+    assert!(status.success());
+    fs::OpenOptions::new()
+        .append(true)
+        .open("testenvironment/sha1sum.txt")
+        .unwrap()
+        .write_all(b"da39a3ee5e6b4b0d3255bfef95601890afd80709 0 0.000000000  ./test/pipe\n")
+        .unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_arkhash"))
+        .args(["-c", "--timeout", "1"])
+        .current_dir("testenvironment")
+        .output()
+        .unwrap();
+
+    teardown();
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(!output.status.success());
+    assert!(stderr.contains("nothing was read within 1 seconds"), "{}", stderr);
+    assert!(stdout.contains("1 timed out"), "{}", stdout);
+}
+
+/// Tests that a file which is read slowly does not run into the timeout.
+///
+/// # Steps
+/// * Update checksums for testenvironment
+/// * List a named pipe in the database file, a thread writes a byte to it every 300 milliseconds for 3 seconds
+/// * Verify checksums with a timeout of 1 second
+///
+/// # Expected
+/// * verification should wait for the pipe to be read completely, it is reported as changed, not as timed out
+#[cfg(unix)]
+#[test]
+fn timeout_slow_read_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-u"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let status = std::process::Command::new("mkfifo")
+        .arg("testenvironment/test/pipe")
+        .status()
+        .unwrap();
+    assert!(status.success());
+    fs::OpenOptions::new()
+        .append(true)
+        .open("testenvironment/sha1sum.txt")
+        .unwrap()
+        .write_all(b"da39a3ee5e6b4b0d3255bfef95601890afd80709 0 0.000000000  ./test/pipe\n")
+        .unwrap();
+
+    // opening the pipe blocks until arkhash opens it for reading
+    std::thread::spawn(|| {
+        let mut pipe = fs::OpenOptions::new()
+            .write(true)
+            .open("testenvironment/test/pipe")
+            .unwrap();
+        for _ in 0..10 {
+            pipe.write_all(b"a").unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(300));
+        }
+    });
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_arkhash"))
+        .args(["-c", "--timeout", "1"])
+        .current_dir("testenvironment")
+        .output()
+        .unwrap();
+
+    teardown();
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(!output.status.success());
+    assert!(!stderr.contains("nothing was read"), "{}", stderr);
+    assert!(!stdout.contains("timed out"), "{}", stdout);
+    assert!(stdout.contains("1 failed"), "{}", stdout);
+}
+
+/// Tests that the lock file of the state directory is removed when arkhash finishes.
+///
+/// # Steps
+/// * List the files of testenvironment
+/// * Update checksums for testenvironment, then verify them
+/// * List the files of testenvironment again
+///
+/// # Expected
+/// * the only new file is the sha1sum.txt, no .arkhash.lock is left behind
+#[test]
+fn lock_file_removed_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    let list_files = || {
+        let mut names: Vec<String> = fs::read_dir("testenvironment")
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    };
+    let before = list_files();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-u"])
+        .current_dir("testenvironment")
+        .unwrap();
+    let after_update = list_files();
+
+    Assert::main_binary()
+        .with_args(&["-c"])
+        .current_dir("testenvironment")
+        .unwrap();
+    let after_verify = list_files();
+
+    teardown();
+
+    let mut expected = before;
+    expected.push("sha1sum.txt".to_string());
+    expected.sort();
+    assert_eq!(after_update, expected);
+    assert_eq!(after_verify, expected);
+}
+
+/// Indicates that a line of a hashsum file is the header of the extended format
+fn is_header(line: &str) -> bool {
+    line.starts_with("# arkhash manifest v")
+}
+
+/// Returns the first line of the output of the progress loglevel containing the given text,
+/// without the bars cleared at the start of the line
+fn printed_line<'a>(stdout: &'a str, text: &str) -> &'a str {
+    let line = stdout
+        .lines()
+        .find(|line| line.contains(text))
+        .unwrap_or_else(|| panic!("{:?} not found in {:?}", text, stdout));
+    &line[line.rfind("\x1b[2K").map_or(0, |i| i + 4)..]
+}
+
+/// Returns the lines of a hashsum file without the header of the extended format
+fn hashsum_lines(data: &str) -> Vec<&str> {
+    data.lines().filter(|line| !is_header(line)).collect()
+}
+
+/// Tests skipping directories whose database file is newer than all of their files.
+///
+/// # Steps
+/// * Update subdirs for testenvironment
+/// * Verify subdirs for testenvironment with --trust-mtime
+/// * Change a file in testenvironment/secondsecond
+/// * Verify and update subdirs for testenvironment with --trust-mtime
+///
+/// # Expected
+/// * the first verify should pass both directories without hashing
+/// * the second verify should pass testenvironment/test and fail because of the changed file
+/// * the update should skip testenvironment/test and hash the changed file
+#[test]
+fn trust_mtime_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-us", "--quiet"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-cs", "--trust-mtime", "--loglevel", "info"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("./test: unchanged since its sha1sum.txt was written, trusted: OK")
+        .stdout()
+        .contains("./secondsecond: unchanged since its sha1sum.txt was written, trusted: OK")
+        .unwrap();
+
+    fs::write("testenvironment/secondsecond/little_1", "Changed").unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-cs", "--trust-mtime", "--loglevel", "info"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("./test: unchanged since its sha1sum.txt was written, trusted: OK")
+        .stdout()
+        .contains("little_1")
+        .fails_with(1)
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-us", "--trust-mtime", "--loglevel", "info"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("Directory ./test unchanged since its sha1sum.txt was written, skipping")
+        .stdout()
+        .contains("./little_1")
+        .unwrap();
+
+    teardown();
+}
+
+/// Tests that status mode summarizes the state files of verify mode.
+///
+/// # Steps
+/// * Update subdirs for testenvironment
+/// * Change a file in testenvironment/secondsecond
+/// * Verify subdirs for testenvironment
+/// * Print the status of testenvironment as text and as JSON
+///
+/// # Expected
+/// * status mode should return with exit code 1, because a directory needs attention
+/// * testenvironment/test should be known good, testenvironment/secondsecond should be listed with its bad file
+/// * the JSON object should contain the same information
+#[test]
+fn status_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-us", "--quiet"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    fs::write("testenvironment/secondsecond/little_1", "Changed").unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-cs", "--quiet"])
+        .current_dir("testenvironment")
+        .fails_with(1)
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["--status"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("known good   ./test")
+        .stdout()
+        .contains("to check     ./secondsecond")
+        .stdout()
+        .contains("./secondsecond: 1 bad files (./to_check_secondsecond.txt)\n    ./little_1")
+        .stdout()
+        .contains("1 directories verified OK, 1 need attention, 1 bad files")
+        .fails_with(1)
+        .unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_arkhash"))
+        .args(["--status", "--format", "json"])
+        .current_dir("testenvironment")
+        .output()
+        .unwrap();
+
+    teardown();
+
+    assert_eq!(output.status.code(), Some(1));
+    let status: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(status["ok"], false);
+    assert_eq!(status["known_good"], serde_json::json!(["./test"]));
+    assert_eq!(status["to_check"], serde_json::json!(["./secondsecond"]));
+    assert_eq!(status["bad_files"][0]["dir"], "./secondsecond");
+    assert_eq!(status["bad_files"][0]["files"], serde_json::json!(["./little_1"]));
+}
+
+/// Tests that recheck mode verifies the directories of the to_check list again.
+///
+/// # Steps
+/// * Update subdirs for testenvironment
+/// * Change a file in testenvironment/secondsecond
+/// * Verify subdirs for testenvironment
+/// * Recheck testenvironment
+/// * Restore the changed file and recheck testenvironment again
+/// * Print the status of testenvironment
+///
+/// # Expected
+/// * the first recheck should only verify testenvironment/secondsecond and fail, keeping it in the to_check list
+///   with a single list of its bad file
+/// * the second recheck should succeed and move testenvironment/secondsecond to the known_good list
+/// * status mode should report both directories as verified OK
+#[test]
+fn recheck_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-us", "--quiet"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let original = fs::read("testenvironment/secondsecond/little_1").unwrap();
+    fs::write("testenvironment/secondsecond/little_1", "Changed").unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-cs", "--quiet"])
+        .current_dir("testenvironment")
+        .fails_with(1)
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["--recheck", "--loglevel", "info"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("Rechecking 1 directories")
+        .stdout()
+        .doesnt_contain("Verifying Directory ./test")
+        .fails_with(1)
+        .unwrap();
+
+    let now: DateTime<chrono::Local> = chrono::Local::now();
+    let to_check_path = format!("testenvironment/to_check_{}_{}.txt", now.month(), now.year());
+    let to_check = fs::read_to_string(&to_check_path).unwrap();
+    let bad_files = fs::read_to_string("testenvironment/to_check_secondsecond.txt").unwrap();
+
+    fs::write("testenvironment/secondsecond/little_1", original).unwrap();
+
+    Assert::main_binary()
+        .with_args(&["--recheck"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["--status"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("2 directories verified OK, 0 need attention, 0 bad files")
+        .unwrap();
+
+    teardown();
+
+    assert_eq!(to_check, "./secondsecond\n");
+    assert_eq!(bad_files, "./little_1\n");
+}
+
+/// Tests that recheck mode only drops the directories from the to_check list that were rechecked OK.
+///
+/// # Steps
+/// * Update subdirs for testenvironment
+/// * Change a file in testenvironment/test and in testenvironment/secondsecond
+/// * Verify subdirs for testenvironment
+/// * Restore the changed file of testenvironment/test and recheck testenvironment
+///
+/// # Expected
+/// * the to_check list should only contain testenvironment/secondsecond afterwards
+/// * the list of bad files of testenvironment/test should be removed, the one of testenvironment/secondsecond kept
+/// * status mode should report testenvironment/test as verified OK
+#[test]
+fn recheck_partial_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-us", "--quiet"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let original = fs::read("testenvironment/test/little_1").unwrap();
+    fs::write("testenvironment/test/little_1", "Changed").unwrap();
+    fs::write("testenvironment/secondsecond/little_2", "Changed").unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-cs", "--quiet"])
+        .current_dir("testenvironment")
+        .fails_with(1)
+        .unwrap();
+
+    fs::write("testenvironment/test/little_1", original).unwrap();
+
+    Assert::main_binary()
+        .with_args(&["--recheck", "--quiet"])
+        .current_dir("testenvironment")
+        .fails_with(1)
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["--status"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("1 directories verified OK, 1 need attention, 1 bad files")
+        .fails_with(1)
+        .unwrap();
+
+    let now: DateTime<chrono::Local> = chrono::Local::now();
+    let to_check_path = format!("testenvironment/to_check_{}_{}.txt", now.month(), now.year());
+    let to_check = fs::read_to_string(&to_check_path).unwrap();
+    let bad_files = fs::read_to_string("testenvironment/to_check_secondsecond.txt").unwrap();
+    let test_bad_files = fs::metadata("testenvironment/to_check_test.txt").is_ok();
+
+    teardown();
+
+    assert_eq!(to_check, "./secondsecond\n");
+    assert_eq!(bad_files, "./little_2\n");
+    assert!(!test_bad_files);
+}
+
+/// Tests that RUST_LOG filters the messages of the loglevel.
+///
+/// # Steps
+/// * Update subdirs for testenvironment with the messages of update mode turned off by RUST_LOG
+/// * Update subdirs for testenvironment again without RUST_LOG
+///
+/// # Expected
+/// * the first update should not print that it updates the directories, but still the number of worker threads
+/// * the second update should print timestamped messages about both directories
+#[test]
+fn rust_log_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_arkhash"))
+        .args(["-us"])
+        .env("RUST_LOG", "arkhash::update=off")
+        .current_dir("testenvironment")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(output.status.success());
+    assert!(!stdout.contains("Updating Directory"), "{}", stdout);
+    assert!(stdout.contains("worker threads"), "{}", stdout);
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_arkhash"))
+        .args(["-us"])
+        .env_remove("RUST_LOG")
+        .current_dir("testenvironment")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout
+        .lines()
+        .any(|line| line.starts_with('[') && line.ends_with("] Updating Directory ./test")));
+    assert!(stdout.contains("Directory ./secondsecond Updated in "), "{}", stdout);
+
+    teardown();
+}
+
+/// Tests appending the messages of the loglevel to a log file.
+///
+/// # Steps
+/// * Update subdirs for testenvironment with a log file
+/// * Verify subdirs for testenvironment with the same log file
+///
+/// # Expected
+/// * stdout should still contain the messages
+/// * the log file should contain the timestamped messages of both runs
+#[test]
+fn log_file_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-us", "--log-file", "arkhash.log"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("Updating Directory ./test")
+        .unwrap();
+    Assert::main_binary()
+        .with_args(&["-cs", "--log-file", "arkhash.log"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let log = fs::read_to_string("testenvironment/arkhash.log").unwrap();
+    assert!(log.lines().all(|line| line.starts_with('[')), "{}", log);
+    assert!(log.contains("] Updating Directory ./test"), "{}", log);
+    assert!(log.contains("] Directory ./secondsecond Updated in "), "{}", log);
+    assert!(log.contains("] ./secondsecond: checked: OK in "), "{}", log);
+
+    teardown();
+}
+
+/// Tests that a directory whose hashsum file can not be read is reported without stopping the run.
+///
+/// # Steps
+/// * Update subdirs for testenvironment
+/// * Give the sha1sum.txt of testenvironment/test the header of an unsupported version
+/// * Verify subdirs for testenvironment in loglevel progress
+/// * Verify subdirs for testenvironment again in JSON format
+///
+/// # Expected
+/// * both verifications should fail with exit code 1
+/// * testenvironment/test should be reported as not verifiable, testenvironment/secondsecond as OK
+/// * the JSON object of testenvironment/test should contain the error
+#[test]
+fn verify_manifest_error_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-us", "--quiet"])
+        .current_dir("testenvironment")
+        .unwrap();
+    let hashsums = fs::read_to_string("testenvironment/test/sha1sum.txt").unwrap();
+    fs::write(
+        "testenvironment/test/sha1sum.txt",
+        hashsums.replacen("# arkhash manifest v1", "# arkhash manifest v9", 1),
+    )
+    .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-cs", "--loglevel", "progress"])
+        .current_dir("testenvironment")
+        .fails_with(1)
+        .stdout()
+        .contains("./test: could not read sha1sum.txt: manifest version 9 is not supported")
+        .stdout()
+        .contains("./test:          checked: ERROR")
+        .stdout()
+        .contains("./secondsecond:  checked: OK")
+        .stdout()
+        .contains("1 could not be verified")
+        .unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_arkhash"))
+        .args(["-cs", "--force", "--format", "json"])
+        .current_dir("testenvironment")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let objects: Vec<serde_json::Value> = stdout
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let test = objects.iter().find(|object| object["dir"] == "./test").unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(test["ok"], false);
+    assert!(test["error"]
+        .as_str()
+        .unwrap()
+        .starts_with("could not read sha1sum.txt"));
+    assert_eq!(objects.last().unwrap()["errored_directories"], 1);
+
+    teardown();
+}
+
+/// Tests that lines printed while progress bars are drawn do not break the bars.
+///
+/// # Steps
+/// * Update checksums for test
+/// * Verify a listed and an unlisted file of test in loglevel progress with forced progress bars, capturing stdout
+///
+/// # Expected
+/// * arkhash should return with exit code 1
+/// * the message about the unlisted file should be printed to stdout as a line of its own,
+///   after the escape sequence clearing the bars and without escape sequences inside
+/// * the summary should start on a new line after the bars
+#[test]
+fn progress_log_lines_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-u", "--quiet", "test"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_arkhash"))
+        .args([
+            "-c",
+            "--loglevel",
+            "progress",
+            "--force-progress",
+            "--from-stdin",
+        ])
+        .current_dir("testenvironment")
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .and_then(|mut child| {
+            fs::write("testenvironment/test/unlisted", "Not hashed")?;
+            child
+                .stdin
+                .take()
+                .unwrap()
+                .write_all(b"test/little_1\ntest/unlisted\n")?;
+            child.wait_with_output()
+        })
+        .unwrap();
+
+    teardown();
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(output.status.code(), Some(1), "{}", stdout);
+    assert!(!String::from_utf8(output.stderr).unwrap().contains("not listed"));
+
+    let message = printed_line(&stdout, "not listed in sha1sum.txt");
+    assert!(message.starts_with('['), "{:?}", message);
+    assert!(message.ends_with("./unlisted: not listed in sha1sum.txt"), "{:?}", message);
+    assert!(!message.contains('\x1b'), "{:?}", message);
+
+    let summary = printed_line(&stdout, "Checked 1 directories");
+    assert!(summary.starts_with("Checked 1 directories, 0 OK, 1 failed"), "{:?}", stdout);
+}
+
+/// Tests that the bars of finished directories are replaced by their results.
+///
+/// # Steps
+/// * Add a file of 256 KiB to both directories and update subdirs for testenvironment
+/// * Verify subdirs for testenvironment in loglevel progress with forced progress bars, one directory at a time
+///
+/// # Expected
+/// * arkhash should return without failure
+/// * the result of every directory should be printed as a line of its own after the escape sequence clearing the bars
+/// * the bar of the second directory should be drawn in the row of the first one, no bar should be left after the results
+#[test]
+fn progress_finished_rows_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    fs::write("testenvironment/test/large", vec![0u8; 256 * 1024]).unwrap();
+    fs::write("testenvironment/secondsecond/large", vec![0u8; 256 * 1024]).unwrap();
+    Assert::main_binary()
+        .with_args(&["-us", "--quiet"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_arkhash"))
+        .args(["-cs", "-D", "1", "--loglevel", "progress", "--force-progress"])
+        .current_dir("testenvironment")
+        .output()
+        .unwrap();
+
+    teardown();
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(output.status.success(), "{:?}", stdout);
+
+    for dir in &["./test:          ", "./secondsecond:  "] {
+        let result = printed_line(&stdout, &format!("{}checked: OK in ", dir));
+        assert!(result.starts_with(dir), "{:?}", stdout);
+        assert!(!result.contains('\x1b'), "{:?}", result);
+    }
+
+    // every row of the bars is cleared before the next one is drawn, so all bars share one row
+    assert!(!stdout.contains("\x1b[1A"), "{:?}", stdout);
+    let summary = printed_line(&stdout, "Checked 2 directories");
+    assert!(summary.starts_with("Checked 2 directories, 2 OK"), "{:?}", stdout);
+}
+
+/// Tests the progress loglevel where it is unclear whether the terminal can draw progress bars.
+///
+/// # Steps
+/// * Update subdirs for testenvironment
+/// * Verify subdirs for testenvironment in loglevel progress with the title, capturing stdout and stderr,
+///   once with TERM naming a dumb terminal and an empty size and once without TERM
+///
+/// # Expected
+/// * arkhash should return without failure
+/// * neither stdout nor stderr should contain escape sequences, neither for bars nor for the title
+/// * stdout should contain the plain progress and result lines and the summary
+#[test]
+fn progress_unknown_terminal_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-us", "--quiet"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let dumb = std::process::Command::new(env!("CARGO_BIN_EXE_arkhash"))
+        .args(["-cs", "--loglevel", "progress", "--title"])
+        .env("TERM", "dumb")
+        .env("COLUMNS", "0")
+        .env("LINES", "0")
+        .current_dir("testenvironment")
+        .output()
+        .unwrap();
+    let unknown = std::process::Command::new(env!("CARGO_BIN_EXE_arkhash"))
+        .args(["-cs", "--force", "--loglevel", "progress", "--title"])
+        .env_remove("TERM")
+        .current_dir("testenvironment")
+        .output()
+        .unwrap();
+
+    teardown();
+
+    for output in &[dumb, unknown] {
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "{:?}", stdout);
+        assert!(!stdout.contains('\x1b'), "{:?}", stdout);
+        assert!(!stderr.contains('\x1b'), "{:?}", stderr);
+        assert!(stdout.contains("./test:          100%"), "{:?}", stdout);
+        assert!(stdout.contains("./secondsecond:  checked: OK"), "{:?}", stdout);
+        assert!(
+            stdout.contains("Checked 2 directories, 2 OK, 0 failed"),
+            "{:?}",
+            stdout
+        );
+    }
+}
+
+/// Tests hashing stdin like the coreutils do with the path -.
+///
+/// # Steps
+/// * Pipe a text to arkhash with sha256 and the path -
+/// * Pipe it again with --stdin, md5 and sha1 and BSD style lines
+///
+/// # Expected
+/// * the hash of the text should be printed with the path -
+/// * one BSD style line should be printed per algorithm, in the given order
+/// * no hashsum file should be written
+#[test]
+fn stdin_mode_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    let run = |args: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_arkhash"))
+            .args(args)
+            .current_dir("testenvironment")
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .and_then(|mut child| {
+                child.stdin.take().unwrap().write_all(b"abc")?;
+                child.wait_with_output()
+            })
+            .unwrap()
+    };
+
+    // test
+    let gnu = run(&["-a", "sha256", "-"]);
+    let bsd = run(&["--stdin", "-a", "md5,sha1", "--tag"]);
+    let written = fs::read_dir("testenvironment")
+        .unwrap()
+        .filter_map(|entry| entry.ok())
+        .any(|entry| entry.file_name().to_string_lossy().ends_with("sum.txt"));
+
+    teardown();
+
+    assert!(gnu.status.success());
+    assert_eq!(
+        String::from_utf8(gnu.stdout).unwrap(),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  -\n"
+    );
+    assert!(bsd.status.success());
+    assert_eq!(
+        String::from_utf8(bsd.stdout).unwrap(),
+        "MD5 (-) = 900150983cd24fb0d6963f7d28e17f72\nSHA1 (-) = a9993e364706816aba3e25717850c26c9cd0d89d\n"
+    );
+    assert!(!written);
+}
+
+/// Tests the check manifest mode on a database file with duplicate lines.
+///
+/// # Steps
+/// * Update checksums for testenvironment
+/// * Append the first line again, and a line with a different hash followed by the original line for the second file
+/// * Verify testenvironment
+/// * Check the database file, then check it with --dedupe
+/// * Verify and check testenvironment again
+///
+/// # Expected
+/// * verify should pass, reporting the conflicting hashes of the second file
+/// * the check should list both paths and fail, the second one with different hashes
+/// * --dedupe should remove the 3 duplicate lines, keeping the 27 lines of the update
+/// * verify and check should pass afterwards
+#[test]
+fn check_manifest_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-u"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let hashfile = fs::read_to_string("testenvironment/sha1sum.txt").unwrap();
+    let (first, second) = {
+        let lines = hashsum_lines(&hashfile);
+        (lines[0].to_string(), lines[1].to_string())
+    };
+    let changed = format!("{}{}", "0".repeat(40), &second[40..]);
+    let mut file = fs::OpenOptions::new()
+        .append(true)
+        .open("testenvironment/sha1sum.txt")
+        .unwrap();
+    writeln!(file, "{}\n{}\n{}", first, changed, second).unwrap();
+    drop(file);
+
+    Assert::main_binary()
+        .with_args(&["-c"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("checked: OK")
+        .stderr()
+        .contains("is listed with different hashes on lines 3, 30, 31 of sha1sum.txt")
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["--check-manifest"])
+        .current_dir("testenvironment")
+        .fails()
+        .and()
+        .stdout()
+        .contains("is listed with different hashes on lines 3, 30, 31, the last one is used")
+        .stdout()
+        .contains("is listed on lines 2, 29")
+        .stdout()
+        .contains("2 paths listed more than once, 1 of them with different hashes")
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["--check-manifest", "--dedupe"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("removed 3 duplicate lines")
+        .unwrap();
+
+    let deduplicated = fs::read_to_string("testenvironment/sha1sum.txt").unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-c"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("checked: OK")
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["--check-manifest"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("0 paths listed more than once")
+        .unwrap();
+
+    teardown();
+
+    assert_eq!(deduplicated, hashfile);
+}
+
+/// Tests the checksum of the database file.
+///
+/// # Steps
+/// * Update checksums for testenvironment with --manifest-checksum
+/// * Verify testenvironment
+/// * Flip a character of a hash in the database file
+/// * Verify testenvironment
+/// * Update checksums for testenvironment and verify it again
+///
+/// # Expected
+/// * update should write sha1sum.txt.chk containing the checksum of sha1sum.txt
+/// * the first verify should pass
+/// * the second verify should fail, reporting the database file as corrupt
+/// * the database file should not be rewritten by the update, so the last verify should still fail
+#[test]
+fn manifest_checksum_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-u", "--manifest-checksum"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let checksum = fs::read_to_string("testenvironment/sha1sum.txt.chk").unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-c"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("checked: OK")
+        .unwrap();
+
+    let hashfile = fs::read_to_string("testenvironment/sha1sum.txt").unwrap();
+    let line = hashsum_lines(&hashfile)[0];
+    let flipped = if line.starts_with('0') { "1" } else { "0" };
+    let corrupted = hashfile.replacen(line, &format!("{}{}", flipped, &line[1..]), 1);
+    fs::write("testenvironment/sha1sum.txt", &corrupted).unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-c"])
+        .current_dir("testenvironment")
+        .fails()
+        .and()
+        .stdout()
+        .contains("checked: FAILED")
+        .stderr()
+        .contains("manifest corrupt, its checksum does not match sha1sum.txt.chk")
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-u"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let after_update = fs::read_to_string("testenvironment/sha1sum.txt").unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-c"])
+        .current_dir("testenvironment")
+        .fails()
+        .unwrap();
+
+    teardown();
+
+    assert!(checksum.ends_with("  sha1sum.txt\n"));
+    assert_eq!(checksum.len(), 40 + "  sha1sum.txt\n".len());
+    assert_eq!(after_update, corrupted);
+}
+
+/// Tests signing the database file.
+///
+/// # Steps
+/// * Generate a key pair, then try to generate it again
+/// * Update checksums for testenvironment with --sign
+/// * Verify testenvironment with --verify-sig
+/// * Update checksums for testenvironment without --sign
+/// * Verify testenvironment with --verify-sig
+/// * Update checksums for testenvironment with --sign, change a hash in the database file
+/// * Verify testenvironment with --verify-sig
+/// * Remove the database file and its signature, verify testenvironment with --verify-sig
+/// * Update checksums for testenvironment with --sign and --verify-sig, verify it with --verify-sig
+///
+/// # Expected
+/// * the key pair should be written once, the second keygen should fail without overwriting it
+/// * the first verify should pass
+/// * the update without --sign should remove the signature, so the second verify should fail
+/// * the third verify should fail, the signature does not match the changed database file
+/// * the fourth verify should fail, a missing database file can not be told apart from a removed one
+/// * the update should create a new signed database file, which the last verify should pass
+#[test]
+fn signature_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["--keygen", ".key"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("Wrote the secret key to .key and the public key to .key.pub")
+        .unwrap();
+    let key = fs::read_to_string("testenvironment/.key").unwrap();
+
+    Assert::main_binary()
+        .with_args(&["--keygen", ".key"])
+        .current_dir("testenvironment")
+        .fails_with(2)
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-u", "--sign", ".key"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let signature = fs::read_to_string("testenvironment/sha1sum.txt.sig").unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-c", "--verify-sig", ".key.pub"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("checked: OK")
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-u"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-c", "--verify-sig", ".key.pub"])
+        .current_dir("testenvironment")
+        .fails()
+        .and()
+        .stderr()
+        .contains("signature sha1sum.txt.sig is missing")
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-u", "--sign", ".key"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let hashfile = fs::read_to_string("testenvironment/sha1sum.txt").unwrap();
+    let line = hashsum_lines(&hashfile)[0];
+    let flipped = if line.starts_with('0') { "1" } else { "0" };
+    fs::write(
+        "testenvironment/sha1sum.txt",
+        hashfile.replacen(line, &format!("{}{}", flipped, &line[1..]), 1),
+    )
+    .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-c", "--verify-sig", ".key.pub"])
+        .current_dir("testenvironment")
+        .fails()
+        .and()
+        .stderr()
+        .contains("signature sha1sum.txt.sig does not match")
+        .unwrap();
+
+    fs::remove_file("testenvironment/sha1sum.txt").unwrap();
+    fs::remove_file("testenvironment/sha1sum.txt.sig").unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-c", "--verify-sig", ".key.pub"])
+        .current_dir("testenvironment")
+        .fails()
+        .and()
+        .stderr()
+        .contains("sha1sum.txt is missing, its signature can not be verified")
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-u", "--sign", ".key", "--verify-sig", ".key.pub"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-c", "--verify-sig", ".key.pub"])
+        .current_dir("testenvironment")
+        .stdout()
+        .contains("checked: OK")
+        .unwrap();
+
+    teardown();
+
+    assert_eq!(key.trim().len(), 64);
+    assert_eq!(signature.trim().len(), 128);
+}
+
+/// Tests the configuration file arkhash.toml in the working directory.
+///
+/// # Steps
+/// * Write arkhash.toml setting the algorithm to md5
+/// * Update checksums for testenvironment/test
+/// * Update checksums for testenvironment/test with -a sha256
+/// * Update checksums for testenvironment/test with --no-config
+/// * Write arkhash.toml with an unknown setting and update checksums again
+///
+/// # Expected
+/// * the first update should write md5sum.txt, the algorithm of the file
+/// * the second update should write sha256sum.txt, the command line takes precedence
+/// * the third update should write sha1sum.txt, the file is not read
+/// * the last update should fail with exit code 2 and name the unknown setting
+#[test]
+fn config_file_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    fs::write("testenvironment/arkhash.toml", "algorithm = \"md5\"\n").unwrap();
+
+    Assert::main_binary()
+        .with_args(&["-u", "test"])
+        .current_dir("testenvironment")
+        .unwrap();
+    assert!(std::path::Path::new("testenvironment/test/md5sum.txt").exists());
+    assert!(!std::path::Path::new("testenvironment/test/sha1sum.txt").exists());
+
+    Assert::main_binary()
+        .with_args(&["-u", "-a", "sha256", "test"])
+        .current_dir("testenvironment")
+        .unwrap();
+    assert!(std::path::Path::new("testenvironment/test/sha256sum.txt").exists());
+
+    Assert::main_binary()
+        .with_args(&["-u", "--no-config", "test"])
+        .current_dir("testenvironment")
+        .unwrap();
+    assert!(std::path::Path::new("testenvironment/test/sha1sum.txt").exists());
+
+    fs::write("testenvironment/arkhash.toml", "colour = true\n").unwrap();
+    Assert::main_binary()
+        .with_args(&["-u", "test"])
+        .current_dir("testenvironment")
+        .fails_with(2)
+        .and()
+        .stderr()
+        .contains("unknown setting colour")
+        .unwrap();
+
+    teardown();
+}
+
+/// Tests that the patterns of --exclude, ARKHASH_EXCLUDE and the exclude setting of arkhash.toml
+/// replace each other in this order of precedence.
+///
+/// # Steps
+/// * Write a.cfg, b.env, c.cli and cache/d to testenvironment/test and a .arkhashignore including *.cfg again
+/// * Write arkhash.toml excluding *.cfg and cache/ and update checksums for testenvironment/test
+/// * Update checksums again with ARKHASH_EXCLUDE set to *.env
+/// * Update checksums again with ARKHASH_EXCLUDE set to *.env and --exclude *.cli
+///
+/// # Expected
+/// * the first update should hash b.env and c.cli, but neither a.cfg nor cache/d, the .arkhashignore does not include them
+/// * the second update should hash a.cfg, cache/d and c.cli, but not b.env
+/// * the third update should hash a.cfg, cache/d and b.env, but not c.cli
+#[test]
+fn exclude_precedence_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    fs::create_dir("testenvironment/test/cache").unwrap();
+    for file in &["a.cfg", "b.env", "c.cli", "cache/d"] {
+        fs::write(format!("testenvironment/test/{}", file), file).unwrap();
+    }
+    fs::write("testenvironment/test/.arkhashignore", "!*.cfg\n").unwrap();
+    fs::write(
+        "testenvironment/arkhash.toml",
+        "exclude = [\"*.cfg\", \"cache/\"]\n",
+    )
+    .unwrap();
+
+    let update = |env: Option<&str>, args: &[&str]| {
+        let _ = fs::remove_file("testenvironment/test/sha1sum.txt");
+        let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_arkhash"));
+        command.args(["-u", "test"]).args(args);
+        match env {
+            Some(exclude) => command.env("ARKHASH_EXCLUDE", exclude),
+            None => command.env_remove("ARKHASH_EXCLUDE"),
+        };
+        let output = command.current_dir("testenvironment").output().unwrap();
+        assert!(output.status.success());
+        fs::read_to_string("testenvironment/test/sha1sum.txt").unwrap()
+    };
+
+    let hashed = update(None, &[]);
+    assert!(!hashed.contains("a.cfg"), "{}", hashed);
+    assert!(!hashed.contains("cache/d"), "{}", hashed);
+    assert!(hashed.contains("b.env"), "{}", hashed);
+    assert!(hashed.contains("c.cli"), "{}", hashed);
+
+    let hashed = update(Some("*.env"), &[]);
+    assert!(hashed.contains("a.cfg"), "{}", hashed);
+    assert!(hashed.contains("cache/d"), "{}", hashed);
+    assert!(!hashed.contains("b.env"), "{}", hashed);
+    assert!(hashed.contains("c.cli"), "{}", hashed);
+
+    let hashed = update(Some("*.env"), &["--exclude", "*.cli"]);
+    assert!(hashed.contains("a.cfg"), "{}", hashed);
+    assert!(hashed.contains("cache/d"), "{}", hashed);
+    assert!(hashed.contains("b.env"), "{}", hashed);
+    assert!(!hashed.contains("c.cli"), "{}", hashed);
+
+    teardown();
+}
+
+/// Tests that --quiet prints only the files that failed.
+///
+/// # Steps
+/// * Update checksums for testenvironment/test
+/// * Verify testenvironment/test with --quiet and --loglevel info
+/// * Change a file and verify testenvironment/test with --quiet again
+///
+/// # Expected
+/// * the first verify should succeed without printing anything, --quiet overrides the loglevel
+/// * the second verify should fail with exit code 1 and print only the changed file and the summary
+#[test]
+fn quiet_failures_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    Assert::main_binary()
+        .with_args(&["-u", "test"])
+        .current_dir("testenvironment")
+        .unwrap();
+
+    let verify = || {
+        std::process::Command::new(env!("CARGO_BIN_EXE_arkhash"))
+            .args(["-c", "--quiet", "--loglevel", "info", "test"])
+            .current_dir("testenvironment")
+            .output()
+            .unwrap()
+    };
+
+    let output = verify();
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+    assert!(output.stderr.is_empty());
+
+    fs::write("testenvironment/test/little_1", "changed").unwrap();
+    let output = verify();
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0], "./test/little_1: changed");
+    assert!(lines[1].starts_with("Checked 1 directories, 0 OK, 1 failed"));
+
+    teardown();
+}
+
+/// Tests that --emit-jsonl prints a line of JSON for every hashed file.
+///
+/// # Steps
+/// * Update checksums for testenvironment/test with --emit-jsonl and --loglevel progress
+///
+/// # Expected
+/// * stdout holds only JSON lines, one for every line of testenvironment/test/sha1sum.txt
+/// * every JSON line holds the path, hash and size of its file as listed in the sha1sum.txt
+#[test]
+fn emit_jsonl_test() {
+    let _guard = MTX.lock().unwrap();
+
+    setup();
+
+    // test
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_arkhash"))
+        .args(["-u", "--emit-jsonl", "--loglevel", "progress", "test"])
+        .current_dir("testenvironment")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+
+    let manifest = fs::read_to_string("testenvironment/test/sha1sum.txt").unwrap();
+    let entries: Vec<(&str, &str, &str)> = manifest
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| {
+            let (fields, path) = line.split_once("  ").unwrap();
+            let mut fields = fields.split(' ');
+            (fields.next().unwrap(), fields.next().unwrap(), path)
+        })
+        .collect();
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.lines().count(), entries.len());
+    for line in stdout.lines() {
+        let json: serde_json::Value = serde_json::from_str(line).unwrap();
+        let path = json["path"].as_str().unwrap();
+        let (hash, size, _) = entries
+            .iter()
+            .find(|(_, _, listed)| format!("./test/{}", &listed[2..]) == path)
+            .unwrap();
+        assert_eq!(json["hash"], *hash);
+        assert_eq!(json["size"].to_string(), *size);
+    }
+
+    teardown();
+}
+
 /// Sets up the testenvironment for all tests.
 /// Creates the folders `testenvironment`, `testenvironment/test` and `testenvironment/secondsecond`.
 /// Populates both subdirectories with 10 small and 5 medium sized files. Adds another big file to secondsecond.
@@ -0,0 +1,2266 @@
+extern crate arkhash;
+extern crate proptest;
+extern crate tempfile;
+
+use arkhash::manifest::{FileStamp, Manifest, ManifestEntry};
+use arkhash::util::DirWalker;
+use arkhash::{
+    Algorithm, ByteFormat, Hidden, LogLevel, ManifestFormat, Mode, Options, PathStyle, RunLock,
+    Symlinks, Threads,
+};
+use proptest::prelude::*;
+
+use std::fs;
+use std::io::prelude::*;
+use std::path::PathBuf;
+
+/// Tests the library API on a small directory outside of the working directory.
+///
+/// # Steps
+/// * Update checksums for the directory using arkhash::update
+/// * Verify the directory using arkhash::verify
+/// * Change a file and verify again
+///
+/// # Expected
+/// * the update report lists both files as hashed
+/// * the first verify report contains no failures and exit code 0
+/// * the second verify report lists the changed file and exit code 1
+#[test]
+fn library_update_verify_test() {
+    let folder = "testenvironment_lib";
+    let _ = fs::remove_dir_all(folder);
+    fs::create_dir_all(format!("{}/sub", folder)).unwrap();
+    fs::write(format!("{}/first", folder), "Small file").unwrap();
+    fs::write(format!("{}/sub/second", folder), "Small file").unwrap();
+
+    let opts = Options::builder()
+        .algorithm(Algorithm::Sha256)
+        .log_level(LogLevel::Quiet)
+        .threads(Threads::Fixed(2))
+        .folder(folder)
+        .build()
+        .unwrap();
+
+    let update_report = arkhash::update(&Options {
+        mode: Mode::Update,
+        ..opts.clone()
+    });
+    assert_eq!(update_report.directories.len(), 1);
+    assert_eq!(update_report.directories[0].hashed.len(), 2);
+    assert!(update_report.directories[0].failed.is_empty());
+
+    let verify_opts = Options {
+        mode: Mode::Verify,
+        ..opts
+    };
+    let report = arkhash::verify(&verify_opts);
+    assert_eq!(report.exit_code(), 0);
+
+    fs::OpenOptions::new()
+        .append(true)
+        .open(format!("{}/first", folder))
+        .unwrap()
+        .write_all(b" changed")
+        .unwrap();
+
+    let report = arkhash::verify(&verify_opts);
+    fs::remove_dir_all(folder).unwrap();
+    fs::remove_file(format!("to_check_{}.txt", folder)).unwrap();
+
+    assert_eq!(report.exit_code(), 1);
+    assert_eq!(report.directories[0].failed.len(), 1);
+    assert!(report.directories[0].failed[0].ends_with("first"));
+    assert!(report
+        .summary(ByteFormat::Binary)
+        .starts_with("Checked 1 directories, 0 OK, 1 failed"));
+}
+
+/// Tests the validation of the OptionsBuilder.
+///
+/// # Steps
+/// * Build Options with a known algorithm name
+/// * Build Options with an unknown algorithm name, zero threads and zero depth
+///
+/// # Expected
+/// * the known algorithm name is resolved
+/// * every invalid setting is rejected with an error, the unknown algorithm together with the available ones
+#[test]
+fn options_builder_test() {
+    let opts = Options::builder().algorithm_name("SHA512").build().unwrap();
+    assert_eq!(opts.algorithm, Algorithm::Sha512);
+    assert_eq!(opts.threads, Threads::Auto);
+
+    let err = Options::builder()
+        .algorithm_name("sha3")
+        .build()
+        .unwrap_err();
+    assert!(err.contains("sha3"));
+    assert!(err.contains("sha1, md5, sha224, sha256, sha384, sha512"));
+    assert!(Options::builder()
+        .threads(Threads::Fixed(0))
+        .build()
+        .is_err());
+    assert!(Options::builder().max_depth(0).build().is_err());
+}
+
+/// Tests writing and reading manifests in the GNU, the BSD and the plain format.
+///
+/// # Steps
+/// * Write a manifest containing a plain and an escaped path in every format
+/// * Read the manifests again, with the format option set to another format
+///
+/// # Expected
+/// * the lines are written in the chosen format, only the GNU format starts with a header and has stamps
+/// * every format is detected when reading, the entries are the same as the written ones
+#[test]
+fn manifest_format_round_trip_test() {
+    let folder = "testenvironment_manifest";
+    let _ = fs::remove_dir_all(folder);
+    fs::create_dir_all(folder).unwrap();
+    let hash = "a".repeat(64);
+    let entries = [
+        ManifestEntry {
+            hash: hash.clone(),
+            path: PathBuf::from("./first (copy) = 1"),
+            stamp: Some(FileStamp {
+                size: 10,
+                mtime_secs: 1500000000,
+                mtime_nanos: 1,
+            }),
+            metadata: None,
+        },
+        ManifestEntry {
+            hash: hash.clone(),
+            path: PathBuf::from("./back\\slash"),
+            stamp: None,
+            metadata: None,
+        },
+    ];
+
+    let cases = [
+        (
+            ManifestFormat::Gnu,
+            ManifestFormat::Bsd,
+            format!(
+                "# arkhash manifest v1: hash size mtime path\n{} 10 1500000000.000000001  ./first (copy) = 1\n\\{}  ./back\\\\slash\n",
+                hash, hash
+            ),
+        ),
+        (
+            ManifestFormat::Plain,
+            ManifestFormat::Gnu,
+            format!(
+                "{}  ./first (copy) = 1\n\\{}  ./back\\\\slash\n",
+                hash, hash
+            ),
+        ),
+        (
+            ManifestFormat::Bsd,
+            ManifestFormat::Gnu,
+            format!(
+                "SHA256 (./first (copy) = 1) = {}\n\\SHA256 (./back\\\\slash) = {}\n",
+                hash, hash
+            ),
+        ),
+    ];
+
+    for &(written, read, ref expected) in cases.iter() {
+        let opts = Options::builder()
+            .algorithm(Algorithm::Sha256)
+            .manifest_format(written)
+            .build()
+            .unwrap();
+        let mut manifest = Manifest::read(folder.as_ref(), &opts).unwrap();
+        for entry in entries.iter() {
+            manifest.upsert(entry.clone());
+        }
+        manifest.write(folder.as_ref(), &opts).unwrap();
+        let contents = fs::read_to_string(format!("{}/sha256sum.txt", folder)).unwrap();
+
+        let opts = Options {
+            manifest_format: read,
+            ..opts
+        };
+        let manifest = Manifest::read(folder.as_ref(), &opts).unwrap();
+        fs::remove_file(format!("{}/sha256sum.txt", folder)).unwrap();
+
+        assert_eq!(&contents, expected);
+        assert_eq!(manifest.entries().len(), 2);
+        for (read_entry, entry) in manifest.entries().iter().zip(entries.iter()) {
+            assert_eq!(read_entry.hash, entry.hash);
+            assert_eq!(read_entry.path, entry.path);
+        }
+    }
+
+    fs::remove_dir_all(folder).unwrap();
+}
+
+/// Tests reading the header of the extended manifest format.
+///
+/// # Steps
+/// * Read a manifest with a version 1 header
+/// * Read a manifest with a header of a newer version
+///
+/// # Expected
+/// * the header of version 1 is skipped, the line after it is read
+/// * the manifest of a newer version is refused with an error
+#[test]
+fn manifest_version_test() {
+    let folder = "testenvironment_manifest_version";
+    let _ = fs::remove_dir_all(folder);
+    fs::create_dir_all(folder).unwrap();
+    let opts = Options::builder().build().unwrap();
+    let line = format!("{} 4 1500000000.000000000  ./file\n", "b".repeat(40));
+
+    fs::write(
+        format!("{}/sha1sum.txt", folder),
+        format!("# arkhash manifest v1: hash size mtime path\n{}", line),
+    )
+    .unwrap();
+    let current = Manifest::read(folder.as_ref(), &opts);
+
+    fs::write(
+        format!("{}/sha1sum.txt", folder),
+        format!(
+            "# arkhash manifest v3: hash size mtime owner path\n{}",
+            line
+        ),
+    )
+    .unwrap();
+    let newer = Manifest::read(folder.as_ref(), &opts);
+
+    fs::remove_dir_all(folder).unwrap();
+
+    assert_eq!(current.unwrap().entries().len(), 1);
+    assert_eq!(
+        newer.err().map(|e| e.kind()),
+        Some(std::io::ErrorKind::InvalidData)
+    );
+}
+
+/// Tests that DirWalker returns the files of a directory in a stable order.
+///
+/// # Steps
+/// * Create files and directories whose names sort differently by bytes and by components
+/// * Walk the directory twice
+///
+/// # Expected
+/// * both walks return the same paths
+/// * the paths are sorted by their components, the files of a directory right after the directory
+#[test]
+fn dirwalker_order_test() {
+    let folder = "testenvironment_dirwalker";
+    let _ = fs::remove_dir_all(folder);
+    fs::create_dir_all(format!("{}/a", folder)).unwrap();
+    for file in &["b", "a-b", "a/2", "c", "a/1", "A"] {
+        fs::write(format!("{}/{}", folder, file), file).unwrap();
+    }
+
+    let walk = || {
+        DirWalker::new(
+            PathBuf::from(folder).as_path(),
+            true,
+            Symlinks::Skip,
+            Hidden::Exclude,
+            &[],
+        )
+        .collect::<Vec<PathBuf>>()
+    };
+    let first = walk();
+    let second = walk();
+    fs::remove_dir_all(folder).unwrap();
+
+    let expected: Vec<PathBuf> = ["./A", "./a/1", "./a/2", "./a-b", "./b", "./c"]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+    assert_eq!(first, expected);
+    assert_eq!(second, expected);
+}
+
+/// Tests that DirWalker skips the files excluded by .arkhashignore files.
+///
+/// # Steps
+/// * Create files, a .arkhashignore in the directory and one in a subdirectory
+/// * Walk the directory
+///
+/// # Expected
+/// * files matching a pattern are skipped unless a later pattern or a nested .arkhashignore includes them
+/// * patterns with a slash only match relative to their .arkhashignore, patterns with a trailing slash only directories
+#[test]
+fn dirwalker_ignore_test() {
+    let folder = "testenvironment_ignore";
+    let _ = fs::remove_dir_all(folder);
+    for directory in &["logs", "sub/logs", "sub/deep", "build"] {
+        fs::create_dir_all(format!("{}/{}", folder, directory)).unwrap();
+    }
+    for file in &[
+        "a.log",
+        "keep.log",
+        "logs/x",
+        "sub/b.log",
+        "sub/c.tmp",
+        "sub/logs/y",
+        "sub/deep/d.tmp",
+        "build/z",
+        "notes[1].txt",
+        "notes2.txt",
+    ] {
+        fs::write(format!("{}/{}", folder, file), file).unwrap();
+    }
+    fs::write(
+        format!("{}/.arkhashignore", folder),
+        "# logs and build output\n*.log\n!keep.log\n/logs/\nbuild\n**/*.tmp\nnotes[0-9].txt\n",
+    )
+    .unwrap();
+    fs::write(
+        format!("{}/sub/.arkhashignore", folder),
+        "!b.log\ndeep/*.tmp\n!c.tmp\n",
+    )
+    .unwrap();
+
+    let walked: Vec<PathBuf> = DirWalker::new(
+        PathBuf::from(folder).as_path(),
+        true,
+        Symlinks::Skip,
+        Hidden::Exclude,
+        &[],
+    )
+    .collect();
+    fs::remove_dir_all(folder).unwrap();
+
+    let expected: Vec<PathBuf> = [
+        "./keep.log",
+        "./notes[1].txt",
+        "./sub/b.log",
+        "./sub/c.tmp",
+        "./sub/logs/y",
+    ]
+    .iter()
+    .map(PathBuf::from)
+    .collect();
+    assert_eq!(walked, expected);
+}
+
+/// Tests formatting numbers of bytes at the boundaries of the units.
+///
+/// # Steps
+/// * Format numbers around 1 KiB, 1 MiB and 1 GiB with binary units, decimal units and raw
+///
+/// # Expected
+/// * numbers below the first unit are printed exactly, larger ones with two decimals of the largest fitting unit
+/// * numbers that would round up to 1024 of a unit are printed in the next unit
+#[test]
+fn format_bytes_test() {
+    use arkhash::util::format_bytes;
+
+    assert_eq!(format_bytes(0), "0 B");
+    assert_eq!(format_bytes(1023), "1023 B");
+    assert_eq!(format_bytes(1024), "1.00 KiB");
+    assert_eq!(format_bytes(1536), "1.50 KiB");
+    assert_eq!(format_bytes(1024 * 1024 - 1), "1.00 MiB");
+    assert_eq!(format_bytes(1024 * 1024), "1.00 MiB");
+    assert_eq!(format_bytes(1932735283), "1.80 GiB");
+    assert_eq!(format_bytes(u64::MAX), "16.00 EiB");
+
+    assert_eq!(ByteFormat::Si.format(999), "999 B");
+    assert_eq!(ByteFormat::Si.format(1000), "1.00 kB");
+    assert_eq!(ByteFormat::Si.format(1024), "1.02 kB");
+    assert_eq!(ByteFormat::Si.format(1932735283), "1.93 GB");
+
+    assert_eq!(ByteFormat::Raw.format(1024), "1024 bytes");
+    assert_eq!(ByteFormat::Raw.format(1932735283), "1932735283 bytes");
+}
+
+/// Tests normalizing paths without looking at the filesystem.
+///
+/// # Steps
+/// * Normalize paths with `.`, `..`, duplicate and trailing separators, relative and absolute
+///
+/// # Expected
+/// * relative paths start with ./, `.` components and duplicate separators are removed
+/// * `..` removes the component before it, leading `..` are kept in relative paths and dropped in absolute ones
+#[test]
+fn normalize_path_test() {
+    use arkhash::util::normalize_path;
+
+    let cases = vec![
+        ("./foo/../bar/baz.txt", "./bar/baz.txt"),
+        ("foo//baz", "./foo/baz"),
+        ("foo/./bar/", "./foo/bar"),
+        ("./a/b/../../c", "./c"),
+        ("a/..", "."),
+        ("../a/../../b", "./../../b"),
+        ("./../x", "./../x"),
+        ("/a/./b//../c", "/a/c"),
+        ("/../a", "/a"),
+        (".", "."),
+    ];
+    for (path, normalized) in cases {
+        assert_eq!(
+            normalize_path(&PathBuf::from(path)),
+            PathBuf::from(normalized),
+            "{}",
+            path
+        );
+    }
+}
+
+/// Tests deciding whether a progress bar fits on a line of the terminal.
+///
+/// # Steps
+/// * Check wide and narrow terminals and a terminal of unknown width with the same text
+///
+/// # Expected
+/// * The bar is drawn if the terminal fits the text and a bar of at least 10 characters, or its width is unknown
+#[test]
+fn progress_bar_width_test() {
+    use arkhash::progress::bar_fits;
+
+    assert!(bar_fits(Some(200), 60));
+    assert!(bar_fits(Some(79), 60));
+    assert!(!bar_fits(Some(78), 60));
+    assert!(!bar_fits(Some(40), 60));
+    assert!(bar_fits(None, 60));
+}
+
+/// Tests deciding whether the progress bars can be drawn on stdout.
+///
+/// # Steps
+/// * Check a terminal of known size, stdout that is not a terminal, dumb and unknown terminals
+///   and terminals of unknown or empty size
+///
+/// # Expected
+/// * Only the terminal with cursor control and a known size draws the bars, every other case falls back to plain lines
+#[test]
+fn progress_terminal_detection_test() {
+    use arkhash::progress::supports_bars;
+
+    assert!(supports_bars(true, Some("xterm-256color"), Some((24, 80))));
+    assert!(!supports_bars(
+        false,
+        Some("xterm-256color"),
+        Some((24, 80))
+    ));
+    assert!(!supports_bars(true, Some("dumb"), Some((24, 80))));
+    assert!(!supports_bars(true, Some(""), Some((24, 80))));
+    assert!(!supports_bars(true, Some("xterm"), None));
+    assert!(!supports_bars(true, Some("xterm"), Some((0, 0))));
+    assert!(!supports_bars(true, Some("xterm"), Some((24, 0))));
+    assert!(!supports_bars(false, None, None));
+    assert_eq!(supports_bars(true, None, Some((24, 80))), cfg!(windows));
+}
+
+/// Tests formatting the progress in the title of the terminal window.
+///
+/// # Steps
+/// * Format the title for some, all and no directories finished
+///
+/// # Expected
+/// * The title shows the percentage and number of finished directories, 100% if there are none
+#[test]
+fn window_title_test() {
+    use arkhash::progress::format_title;
+
+    assert_eq!(format_title(412, 650), "arkhash: 63% (412/650 dirs)");
+    assert_eq!(format_title(3, 3), "arkhash: 100% (3/3 dirs)");
+    assert_eq!(format_title(0, 0), "arkhash: 100% (0/0 dirs)");
+}
+
+/// Tests the format of the timestamps of log lines.
+///
+/// # Steps
+/// * Check patterns with known and unknown specifiers
+/// * Format a timestamp with a pattern without specifiers
+///
+/// # Expected
+/// * Only patterns with known specifiers are valid, the default and the RFC 3339 pattern among them
+/// * The timestamp is formatted with the pattern of the options
+#[test]
+fn time_format_test() {
+    use arkhash::util::{
+        is_valid_time_format, timestamp, DEFAULT_TIME_FORMAT, RFC3339_TIME_FORMAT,
+    };
+
+    assert!(is_valid_time_format(DEFAULT_TIME_FORMAT));
+    assert!(is_valid_time_format(RFC3339_TIME_FORMAT));
+    assert!(is_valid_time_format("%s"));
+    assert!(!is_valid_time_format("%Q"));
+    assert!(!is_valid_time_format("%"));
+
+    let opts = Options::builder().time_format("at %%now").build().unwrap();
+    assert_eq!(timestamp(&opts), "at %now");
+}
+
+/// Tests converting the settings of the configuration file to arguments.
+///
+/// # Steps
+/// * Parse a configuration file with strings, numbers, booleans and lists
+/// * Parse the resulting arguments followed by arguments of the command line
+/// * Parse configuration files with an unknown setting and a value of the wrong type
+///
+/// # Expected
+/// * Every setting becomes a long option, settings that are false are left out
+/// * The arguments of the command line take precedence over the configuration file
+/// * The invalid files are rejected
+#[test]
+fn config_file_test() {
+    use arkhash::config::parse;
+
+    let args = parse(
+        "algorithm = [\"md5\", \"sha256\"]\nthreads = 8\nrespect_gitignore = true\ncompress = false\nstate-dir = \"/var/lib/arkhash\"\n",
+    )
+    .unwrap();
+    assert_eq!(
+        args,
+        vec![
+            "--algorithm",
+            "md5,sha256",
+            "--respect-gitignore",
+            "--state-dir",
+            "/var/lib/arkhash",
+            "--threads",
+            "8"
+        ]
+    );
+
+    let mut command_line = vec!["arkhash".to_string()];
+    command_line.extend(args);
+    command_line.extend(vec!["--threads".to_string(), "2".to_string()]);
+    let opts = Options::new(command_line);
+    assert_eq!(opts.algorithms(), vec![Algorithm::Md5, Algorithm::Sha256]);
+    assert_eq!(opts.threads, Threads::Fixed(2));
+    assert!(opts.respect_gitignore);
+    assert!(!opts.compress);
+    assert_eq!(opts.state_dir, PathBuf::from("/var/lib/arkhash"));
+
+    assert!(parse("update = true\n").is_err());
+    assert!(parse("compress = \"yes\"\n").is_err());
+    assert!(parse("threads = true\n").is_err());
+    assert!(parse("threads = 1.5\n").is_err());
+    assert!(parse("algorithm = [1, 2]\n").is_err());
+}
+
+/// Tests converting environment variables holding settings to arguments.
+///
+/// # Steps
+/// * Convert variables with and without the prefix ARKHASH_, flags and unknown settings among them
+/// * Parse the resulting arguments preceded by a configuration file and followed by the command line
+/// * Convert a flag that is neither true nor false
+///
+/// # Expected
+/// * Only the known settings are converted, in the order of their names
+/// * The command line takes precedence over the environment, which takes precedence over the configuration file
+/// * The invalid flag is rejected
+#[test]
+fn env_args_test() {
+    use arkhash::config::{env_args, parse};
+
+    let vars = [
+        ("ARKHASH_THREADS", "8"),
+        ("ARKHASH_LOGLEVEL", "info"),
+        ("ARKHASH_ALGORITHM", "sha256"),
+        ("ARKHASH_STATE_DIR", "/var/lib/arkhash"),
+        ("ARKHASH_COMPRESS", "true"),
+        ("ARKHASH_XATTR", "0"),
+        ("ARKHASH_UNKNOWN", "1"),
+        ("HOME", "/root"),
+    ];
+    let args = env_args(
+        vars.iter()
+            .map(|&(name, value)| (name.to_string(), value.to_string())),
+    )
+    .unwrap();
+    assert_eq!(
+        args,
+        vec![
+            "--algorithm",
+            "sha256",
+            "--compress",
+            "--loglevel",
+            "info",
+            "--state-dir",
+            "/var/lib/arkhash",
+            "--threads",
+            "8"
+        ]
+    );
+
+    let mut command_line = vec!["arkhash".to_string()];
+    command_line.extend(parse("algorithm = \"md5\"\nthreads = 4\nmax-depth = 3\n").unwrap());
+    command_line.extend(args);
+    command_line.extend(vec!["-T".to_string(), "2".to_string()]);
+    let opts = Options::new(command_line);
+    assert_eq!(opts.algorithm, Algorithm::Sha256);
+    assert_eq!(opts.threads, Threads::Fixed(2));
+    assert_eq!(opts.max_depth, 3);
+    assert_eq!(opts.log_level, LogLevel::Info);
+    assert_eq!(opts.state_dir, PathBuf::from("/var/lib/arkhash"));
+    assert!(opts.compress);
+    assert!(!opts.xattr);
+
+    assert!(env_args(vec![("ARKHASH_WAIT".to_string(), "maybe".to_string())]).is_err());
+}
+
+/// Tests switching off flags of the configuration file with the environment and the command line.
+///
+/// # Steps
+/// * Read a configuration file setting quiet, respect-gitignore and threads
+/// * Merge it with variables setting ARKHASH_QUIET to false and ARKHASH_THREADS to 8
+/// * Parse the options of a command line without flags, and of one with --no-respect-gitignore
+///
+/// # Expected
+/// * The flag that is false in the environment is off, the one only set in the file is on
+/// * The value of the environment takes precedence over the file
+/// * --no-respect-gitignore switches off the flag of the file and is not passed on as an argument
+#[test]
+fn config_flag_override_test() {
+    use arkhash::config::{env_settings, parse_settings, with_settings};
+
+    let mut settings =
+        parse_settings("quiet = true\nrespect-gitignore = true\nthreads = 4\n").unwrap();
+    let opts = Options::new(with_settings(
+        vec!["arkhash".to_string(), "-u".to_string()],
+        settings.clone(),
+    ));
+    assert!(opts.quiet);
+
+    settings.extend(
+        env_settings(vec![
+            ("ARKHASH_QUIET".to_string(), "false".to_string()),
+            ("ARKHASH_THREADS".to_string(), "8".to_string()),
+        ])
+        .unwrap(),
+    );
+    let opts = Options::new(with_settings(
+        vec!["arkhash".to_string(), "-u".to_string()],
+        settings.clone(),
+    ));
+    assert!(!opts.quiet);
+    assert!(opts.respect_gitignore);
+    assert_eq!(opts.threads, Threads::Fixed(8));
+
+    let args = with_settings(
+        vec![
+            "arkhash".to_string(),
+            "-u".to_string(),
+            "--no-respect-gitignore".to_string(),
+        ],
+        settings,
+    );
+    assert!(!args.contains(&"--no-respect-gitignore".to_string()));
+    let opts = Options::new(args);
+    assert!(!opts.quiet);
+    assert!(!opts.respect_gitignore);
+}
+
+/// Tests parsing the number of threads given on the command line.
+///
+/// # Steps
+/// * Parse auto, 0, positive numbers and invalid values
+/// * Parse the options with `-T auto` and `-D 3`
+///
+/// # Expected
+/// * auto and 0 select the available parallelism, positive numbers a fixed pool, invalid values are rejected
+#[test]
+fn threads_arg_test() {
+    assert_eq!(Threads::from_arg("auto"), Some(Threads::Auto));
+    assert_eq!(Threads::from_arg("AUTO"), Some(Threads::Auto));
+    assert_eq!(Threads::from_arg("0"), Some(Threads::Auto));
+    assert_eq!(Threads::from_arg(" 8 "), Some(Threads::Fixed(8)));
+    assert_eq!(Threads::from_arg("-1"), None);
+    assert_eq!(Threads::from_arg("many"), None);
+
+    let opts = Options::new(
+        ["arkhash", "-T", "auto", "-D", "3"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect(),
+    );
+    assert_eq!(opts.threads, Threads::Auto);
+    assert_eq!(opts.parallel_directories, Threads::Fixed(3));
+    assert!(opts.folders.iter().all(|folder| folder != "auto"));
+}
+
+/// Tests setting the loglevel with repeated -v and -q flags.
+///
+/// # Steps
+/// * Map numbers of flags to loglevels
+/// * Parse the options with -v, -vv, -vvv, -qq, -vs, -v and -q combined and --loglevel combined with -v
+///
+/// # Expected
+/// * -v is info, -vv debug, -vvv and more trace, -q progress and -qq and more quiet
+/// * the flags count against each other, -vs still switches to subdir mode and -v overrides --loglevel
+/// * -c switches to verify mode
+#[test]
+fn verbosity_test() {
+    assert_eq!(LogLevel::from_verbosity(1), LogLevel::Info);
+    assert_eq!(LogLevel::from_verbosity(2), LogLevel::Debug);
+    assert_eq!(LogLevel::from_verbosity(5), LogLevel::Trace);
+    assert_eq!(LogLevel::from_verbosity(-1), LogLevel::Progress);
+    assert_eq!(LogLevel::from_verbosity(-5), LogLevel::Quiet);
+
+    let options = |args: &[&str]| {
+        let args = ["arkhash"].iter().chain(args).map(|arg| arg.to_string());
+        Options::new(args.collect())
+    };
+    let log_level = |args: &[&str]| options(args).log_level;
+    assert_eq!(log_level(&[]), LogLevel::Info);
+    assert_eq!(log_level(&["-v"]), LogLevel::Info);
+    assert_eq!(log_level(&["-vv"]), LogLevel::Debug);
+    assert_eq!(log_level(&["-v", "-v", "--verbose"]), LogLevel::Trace);
+    assert_eq!(log_level(&["-qq"]), LogLevel::Quiet);
+    assert_eq!(log_level(&["-vvv", "-q"]), LogLevel::Debug);
+    assert_eq!(
+        log_level(&["-vv", "--loglevel", "progress"]),
+        LogLevel::Debug
+    );
+    assert_eq!(log_level(&["--loglevel", "trace"]), LogLevel::Trace);
+
+    let opts = options(&["-vs"]);
+    assert!(opts.subdir_mode);
+    assert!(opts.loglevel_info() && !opts.loglevel_debug());
+    assert!(matches!(options(&["-cs"]).mode, Mode::Verify));
+    let opts = options(&["-vvv"]);
+    assert!(opts.loglevel_trace() && opts.loglevel_debug() && opts.loglevel_info());
+}
+
+/// Tests watch mode on a directory that changes while it is watched.
+///
+/// # Steps
+/// * Update checksums for a directory of two files
+/// * Watch it while one file is modified, one is deleted, a new and a hidden file are created
+/// * Stop watching
+///
+/// # Expected
+/// * the sha1sum.txt lists the modified and the new file with their new hashes
+/// * the line of the deleted file is removed, the hidden file is not added
+#[test]
+fn watch_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = tempfile::tempdir().unwrap();
+    let workdir = fs::canonicalize(dir.path()).unwrap();
+    fs::write(workdir.join("a"), "a").unwrap();
+    fs::write(workdir.join("b"), "b").unwrap();
+    let opts = Options::builder()
+        .log_level(LogLevel::Quiet)
+        .state_dir(state.path().to_str().unwrap())
+        .folder(workdir.to_str().unwrap())
+        .debounce(std::time::Duration::from_millis(100))
+        .build()
+        .unwrap();
+    arkhash::update(&Options {
+        mode: Mode::Update,
+        ..opts.clone()
+    });
+
+    let stop = std::sync::atomic::AtomicBool::new(false);
+    let report = std::thread::scope(|scope| {
+        let watcher = scope.spawn(|| {
+            arkhash::watch::watch_until(&opts, || stop.load(std::sync::atomic::Ordering::SeqCst))
+                .unwrap()
+        });
+        // the directory is watched once the watcher thread set it up
+        std::thread::sleep(std::time::Duration::from_millis(500));
+        fs::write(workdir.join("a"), "changed").unwrap();
+        fs::remove_file(workdir.join("b")).unwrap();
+        fs::write(workdir.join("c"), "c").unwrap();
+        fs::write(workdir.join(".hidden"), "hidden").unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(1000));
+        stop.store(true, std::sync::atomic::Ordering::SeqCst);
+        watcher.join().unwrap()
+    });
+
+    assert!(report.failed.is_empty(), "{:?}", report.failed);
+    assert_eq!(report.pruned, vec![PathBuf::from("./b")]);
+    let manifest = Manifest::read(&workdir, &opts).unwrap();
+    let paths: Vec<&PathBuf> = manifest.entries().iter().map(|entry| &entry.path).collect();
+    assert_eq!(paths, vec![&PathBuf::from("./a"), &PathBuf::from("./c")]);
+    let changed = arkhash::util::calculate_hash(PathBuf::from("./a"), &workdir, &opts).unwrap();
+    assert!(changed.starts_with(&manifest.get(std::path::Path::new("./a")).unwrap().hash));
+}
+
+/// Tests that the parallel discovery finds the same directories as the serial one.
+///
+/// # Steps
+/// * Create a tree of directories three levels deep, with a hidden and an ignored directory
+/// * Find its subdirectories two levels deep with find_subdirectories and discover_subdirectories
+/// * Discover them again, accepting only directories whose name ends with 1
+///
+/// # Expected
+/// * both find the same directories, without the hidden and the ignored one
+/// * the accept function filters the discovered directories
+#[test]
+fn discover_subdirectories_test() {
+    let dir = tempfile::tempdir().unwrap();
+    for i in 0..20 {
+        for j in 0..3 {
+            fs::create_dir_all(dir.path().join(format!("a{}/b{}/c", i, j))).unwrap();
+        }
+    }
+    fs::create_dir_all(dir.path().join("leaf")).unwrap();
+    fs::create_dir_all(dir.path().join(".hidden/b")).unwrap();
+    fs::create_dir_all(dir.path().join("ignored/b")).unwrap();
+    let folder = dir.path().to_str().unwrap();
+    let to_ignore = vec![dir.path().join("ignored")];
+    let opts = Options::builder()
+        .max_depth(2)
+        .threads(Threads::Fixed(4))
+        .build()
+        .unwrap();
+
+    let mut found: Vec<PathBuf> =
+        arkhash::util::find_subdirectories(folder, 2, Hidden::Exclude, to_ignore.clone(), &[])
+            .collect();
+    found.sort();
+    assert_eq!(found.len(), 61);
+    let folders = vec![(dir.path().to_path_buf(), to_ignore.clone())];
+    let mut discovered: Vec<PathBuf> =
+        arkhash::util::discover_subdirectories(folders, &opts, |_| true).collect();
+    discovered.sort();
+    assert_eq!(discovered, found);
+
+    let folders = vec![(dir.path().to_path_buf(), to_ignore)];
+    let accepted = arkhash::util::discover_subdirectories(folders, &opts, |directory| {
+        directory.to_string_lossy().ends_with('1')
+    });
+    assert_eq!(accepted.count(), 20);
+}
+
+/// Tests recording and checking permissions, owner and group with check_metadata.
+///
+/// # Steps
+/// * Update checksums for a directory with check_metadata
+/// * Change the permissions of a file without changing its content
+/// * Verify the directory without and with check_metadata
+/// * Update the directory again and verify it with check_metadata
+///
+/// # Expected
+/// * the hashsum file declares version 2 and lists the mode of the files
+/// * without check_metadata, the directory is ok
+/// * with check_metadata, the file is reported as metadata changed, not as changed, and the directory fails
+/// * the update records the new permissions without hashing the file again, the directory is ok afterwards
+#[cfg(unix)]
+#[test]
+fn check_metadata_test() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let state = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("first"), "Small file").unwrap();
+    fs::write(dir.path().join("second"), "Small file").unwrap();
+    fs::set_permissions(dir.path().join("first"), fs::Permissions::from_mode(0o644)).unwrap();
+    let opts = Options::builder()
+        .log_level(LogLevel::Quiet)
+        .state_dir(state.path().to_str().unwrap())
+        .folder(dir.path().to_str().unwrap())
+        .build()
+        .unwrap();
+    let update_opts = Options {
+        mode: Mode::Update,
+        check_metadata: true,
+        ..opts.clone()
+    };
+    let verify_opts = Options {
+        mode: Mode::Verify,
+        ..opts
+    };
+
+    arkhash::update(&update_opts);
+    let hashsums = fs::read_to_string(dir.path().join("sha1sum.txt")).unwrap();
+    assert!(hashsums.starts_with("# arkhash manifest v2: hash size mtime mode uid gid path\n"));
+    let manifest = Manifest::read(dir.path(), &verify_opts).unwrap();
+    let recorded = manifest.get("./first".as_ref()).unwrap().metadata.unwrap();
+    assert_eq!(recorded.mode, 0o644);
+    assert!(hashsums.contains(" 0644 "));
+
+    fs::set_permissions(dir.path().join("first"), fs::Permissions::from_mode(0o600)).unwrap();
+    let report = arkhash::verify(&verify_opts);
+    assert_eq!(report.exit_code(), 0);
+
+    let report = arkhash::verify(&Options {
+        check_metadata: true,
+        ..verify_opts.clone()
+    });
+    assert_eq!(report.exit_code(), 1);
+    assert!(report.directories[0].failed.is_empty());
+    assert_eq!(
+        report.directories[0].metadata_changed,
+        vec!["./first".to_string()]
+    );
+    assert_eq!(report.metadata_changed_files(), 1);
+
+    let update_report = arkhash::update(&update_opts);
+    assert!(update_report.directories[0].hashed.is_empty());
+    let report = arkhash::verify(&Options {
+        check_metadata: true,
+        ..verify_opts
+    });
+    assert_eq!(report.exit_code(), 0);
+}
+
+/// Tests restoring files that failed verification from a mirror with repair_from.
+///
+/// # Steps
+/// * Update checksums for a directory and copy its files to a mirror
+/// * Corrupt two files, one of them in the mirror as well, and delete a third one
+/// * Verify the directory with repair_from in a dry run
+/// * Verify the directory with repair_from
+///
+/// # Expected
+/// * the dry run lists the corrupted and the deleted file whose copies are correct as repaired,
+///   but leaves them failed and untouched
+/// * the verification restores them with their recorded modification time, only the file that is corrupted
+///   in the mirror as well stays failed and keeps its content
+#[test]
+fn repair_from_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let mirror = tempfile::tempdir().unwrap();
+    let state = tempfile::tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("sub")).unwrap();
+    fs::create_dir_all(mirror.path().join("sub")).unwrap();
+    for name in ["first", "second", "sub/third"].iter() {
+        fs::write(dir.path().join(name), format!("Small file {}", name)).unwrap();
+        fs::write(mirror.path().join(name), format!("Small file {}", name)).unwrap();
+    }
+    let opts = Options::builder()
+        .log_level(LogLevel::Quiet)
+        .state_dir(state.path().to_str().unwrap())
+        .folder(dir.path().to_str().unwrap())
+        .build()
+        .unwrap();
+    arkhash::update(&Options {
+        mode: Mode::Update,
+        ..opts.clone()
+    });
+    let verify_opts = Options {
+        mode: Mode::Verify,
+        repair_from: Some(mirror.path().to_path_buf()),
+        ..opts
+    };
+    let recorded = Manifest::read(dir.path(), &verify_opts)
+        .unwrap()
+        .get("./first".as_ref())
+        .unwrap()
+        .stamp
+        .clone()
+        .unwrap();
+
+    fs::write(dir.path().join("first"), "Corrupted").unwrap();
+    fs::write(dir.path().join("second"), "Corrupted").unwrap();
+    fs::write(mirror.path().join("second"), "Corrupted as well").unwrap();
+    fs::remove_file(dir.path().join("sub/third")).unwrap();
+
+    let report = arkhash::verify(&Options {
+        dry_run: true,
+        ..verify_opts.clone()
+    });
+    assert_eq!(
+        report.directories[0].repaired,
+        vec!["./first".to_string(), "./sub/third".to_string()]
+    );
+    assert_eq!(report.directories[0].failed.len(), 2);
+    assert_eq!(report.directories[0].missing.len(), 1);
+    assert_eq!(
+        fs::read_to_string(dir.path().join("first")).unwrap(),
+        "Corrupted"
+    );
+    assert!(!dir.path().join("sub/third").exists());
+
+    let report = arkhash::verify(&verify_opts);
+    assert_eq!(report.exit_code(), 1);
+    assert_eq!(report.repaired_files(), 2);
+    assert_eq!(report.directories[0].failed, vec!["./second".to_string()]);
+    assert!(report.directories[0].missing.is_empty());
+    assert_eq!(
+        fs::read_to_string(dir.path().join("first")).unwrap(),
+        "Small file first"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("sub/third")).unwrap(),
+        "Small file sub/third"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("second")).unwrap(),
+        "Corrupted"
+    );
+    let metadata = fs::metadata(dir.path().join("first")).unwrap();
+    assert_eq!(FileStamp::from_metadata(&metadata), Some(recorded));
+}
+
+/// Tests joining and normalizing Windows paths and writing them to the hashsum file.
+///
+/// # Steps
+/// * Normalize relative and absolute paths with backslashes as separators
+/// * Format an entry with such a path as a line of the hashsum file
+///
+/// # Expected
+/// * `.` and `..` components are removed, the drive of absolute paths is kept
+/// * The line uses `/` as separator and the path is not escaped
+#[cfg(windows)]
+#[test]
+fn windows_paths_test() {
+    use arkhash::util::normalize_path;
+    use std::path::Path;
+
+    assert_eq!(
+        normalize_path(Path::new(r".\pictures\..\docs\a.txt")),
+        PathBuf::from(r".\docs\a.txt")
+    );
+    assert_eq!(
+        normalize_path(&Path::new(r"C:\archive").join(r"pictures\..\docs\a.txt")),
+        PathBuf::from(r"C:\archive\docs\a.txt")
+    );
+
+    let entry = ManifestEntry {
+        hash: "da39a3ee5e6b4b0d3255bfef95601890afd80709".to_string(),
+        path: normalize_path(&Path::new(".").join("docs").join("a.txt")),
+        stamp: None,
+        metadata: None,
+    };
+    assert_eq!(
+        entry.to_plain_line(),
+        "da39a3ee5e6b4b0d3255bfef95601890afd80709  ./docs/a.txt\n"
+    );
+}
+
+/// Tests that paths of the hashsum file that only differ cosmetically are matched with the files.
+///
+/// # Steps
+/// * Write a hashsum file listing files with `.`, `..` and duplicate separators in their paths
+/// * Read it, verify the directory and update it
+///
+/// # Expected
+/// * the entries have normalized paths and are found by any spelling of the path
+/// * verify checks both files and passes
+/// * the update keeps the lines of both files, written with normalized paths
+#[test]
+fn normalized_manifest_paths_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("bar")).unwrap();
+    fs::write(dir.path().join("bar/baz.txt"), "abc").unwrap();
+    fs::write(dir.path().join("other"), "other").unwrap();
+    fs::write(
+        dir.path().join("sha1sum.txt"),
+        "a9993e364706816aba3e25717850c26c9cd0d89d  ./foo/../bar//./baz.txt\n\
+         d0941e68da8f38151ff86a61fc59f7c5cf9fcaa2  other\n",
+    )
+    .unwrap();
+
+    let opts = Options::builder()
+        .log_level(LogLevel::Quiet)
+        .state_dir(state.path().to_str().unwrap())
+        .folder(dir.path().to_str().unwrap())
+        .build()
+        .unwrap();
+    let manifest = Manifest::read(dir.path(), &opts).unwrap();
+    let paths: Vec<_> = manifest.entries().iter().map(|entry| &entry.path).collect();
+    assert_eq!(
+        paths,
+        vec![&PathBuf::from("./bar/baz.txt"), &PathBuf::from("./other")]
+    );
+    assert!(manifest.get(&PathBuf::from("bar//baz.txt")).is_some());
+
+    let report = arkhash::verify(&Options {
+        mode: Mode::Verify,
+        ..opts.clone()
+    })
+    .directories
+    .remove(0);
+    assert_eq!(report.listed_files, 2);
+    assert!(report.is_ok(), "{:?}", report);
+
+    arkhash::update(&Options {
+        mode: Mode::Update,
+        ..opts.clone()
+    });
+    let hashfile = fs::read_to_string(dir.path().join("sha1sum.txt")).unwrap();
+    assert_eq!(
+        hashfile
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .count(),
+        2
+    );
+    assert!(hashfile.contains("  ./bar/baz.txt\n"));
+}
+
+/// Tests formatting durations for completion messages.
+///
+/// # Steps
+/// * Format durations around a minute and an hour
+///
+/// # Expected
+/// * durations below a minute are printed in seconds with truncated tenths
+/// * longer durations are printed in minutes and seconds, and hours if needed
+#[test]
+fn format_duration_test() {
+    use arkhash::util::format_duration;
+    use std::time::Duration;
+
+    assert_eq!(format_duration(Duration::from_millis(0)), "0.0s");
+    assert_eq!(format_duration(Duration::from_millis(4250)), "4.2s");
+    assert_eq!(format_duration(Duration::from_millis(59999)), "59.9s");
+    assert_eq!(format_duration(Duration::from_secs(60)), "1m00s");
+    assert_eq!(format_duration(Duration::from_secs(192)), "3m12s");
+    assert_eq!(format_duration(Duration::from_secs(3599)), "59m59s");
+    assert_eq!(format_duration(Duration::from_secs(3600)), "1h00m00s");
+    assert_eq!(format_duration(Duration::from_secs(90061)), "25h01m01s");
+}
+
+/// Tests the checksum algorithms crc32 and adler32.
+///
+/// # Steps
+/// * Update checksums for a directory with both algorithms
+/// * Change a file and verify with both algorithms
+///
+/// # Expected
+/// * the crc32sum.txt and adler32sum.txt contain the checksums of the check values of both algorithms
+/// * the changed file is reported as failed by both algorithms
+#[test]
+fn checksum_algorithms_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("digits"), "123456789").unwrap();
+    fs::write(dir.path().join("wikipedia"), "Wikipedia").unwrap();
+
+    let cases = [
+        (Algorithm::Crc32, "crc32sum.txt", ["cbf43926", "adaac02e"]),
+        (
+            Algorithm::Adler32,
+            "adler32sum.txt",
+            ["091e01de", "11e60398"],
+        ),
+    ];
+
+    for &(algorithm, name, hashes) in cases.iter() {
+        let opts = Options::builder()
+            .algorithm(algorithm)
+            .log_level(LogLevel::Quiet)
+            .state_dir(state.path().to_str().unwrap())
+            .folder(dir.path().to_str().unwrap())
+            .build()
+            .unwrap();
+        arkhash::update(&Options {
+            mode: Mode::Update,
+            ..opts.clone()
+        });
+
+        let manifest = Manifest::read_file(&dir.path().join(name), &opts).unwrap();
+        assert_eq!(manifest.get("./digits".as_ref()).unwrap().hash, hashes[0]);
+        assert_eq!(
+            manifest.get("./wikipedia".as_ref()).unwrap().hash,
+            hashes[1]
+        );
+    }
+
+    fs::write(dir.path().join("digits"), "123456780").unwrap();
+    for &(algorithm, _, _) in cases.iter() {
+        let opts = Options::builder()
+            .algorithm(algorithm)
+            .mode(Mode::Verify)
+            .log_level(LogLevel::Quiet)
+            .state_dir(state.path().to_str().unwrap())
+            .folder(dir.path().to_str().unwrap())
+            .build()
+            .unwrap();
+        let report = arkhash::verify(&opts);
+        assert_eq!(report.directories[0].failed, vec!["./digits".to_string()]);
+    }
+}
+
+/// Tests the SHA-3 algorithms and the detection of truncated hashes.
+///
+/// # Steps
+/// * Update checksums for a directory with sha3-256 and sha3-512, once in the BSD format
+/// * Read the sha3-256sum.txt in the BSD format
+/// * Truncate the hash of a line of the sha3-512sum.txt and verify the directory
+///
+/// # Expected
+/// * the hashsum files are named after the algorithms and contain the hashes of the test vector
+/// * the BSD style lines are tagged with the name of the algorithm and read again
+/// * the truncated line is reported as malformed and the directory fails, the other file is still checked
+#[test]
+fn sha3_algorithms_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("abc"), "abc").unwrap();
+    fs::write(dir.path().join("other"), "other").unwrap();
+
+    let builder = || {
+        Options::builder()
+            .log_level(LogLevel::Quiet)
+            .state_dir(state.path().to_str().unwrap())
+            .folder(dir.path().to_str().unwrap())
+    };
+    let sha3_256 = builder()
+        .algorithm_name("sha3-256")
+        .manifest_format(ManifestFormat::Bsd)
+        .build()
+        .unwrap();
+    let sha3_512 = builder().algorithm(Algorithm::Sha3_512).build().unwrap();
+
+    for opts in [&sha3_256, &sha3_512].iter() {
+        arkhash::update(&Options {
+            mode: Mode::Update,
+            ..(*opts).clone()
+        });
+    }
+
+    let bsd = fs::read_to_string(dir.path().join("sha3-256sum.txt")).unwrap();
+    assert!(bsd.contains(
+        "SHA3-256 (./abc) = 3a985da74fe225b2045c172d6bd390bd855f086e3e9d525b46bfe24511431532\n"
+    ));
+    let manifest = Manifest::read(dir.path(), &sha3_256).unwrap();
+    assert_eq!(manifest.entries().len(), 2);
+    assert!(manifest.malformed_lines().is_empty());
+
+    let path = dir.path().join("sha3-512sum.txt");
+    let manifest = fs::read_to_string(&path).unwrap();
+    assert!(manifest.contains("b751850b1a57168a5693cd924b6b096e08f621827444f70d884f5d0240d2712e10e116e9192af3c91a7ec57647e3934057340b4cf408d5a56592f8274eec53f0 3 "));
+    let truncated: Vec<String> = manifest
+        .lines()
+        .map(|line| {
+            if line.ends_with("  ./abc") {
+                line[..100].to_string() + &line[128..]
+            } else {
+                line.to_string()
+            }
+        })
+        .collect();
+    fs::write(&path, truncated.join("\n") + "\n").unwrap();
+
+    let report = arkhash::verify(&Options {
+        mode: Mode::Verify,
+        ..sha3_512
+    });
+    let directory = &report.directories[0];
+    assert_eq!(directory.malformed_lines, vec![2]);
+    assert!(directory.failed.is_empty());
+    assert!(directory.missing.is_empty());
+    assert_eq!(report.exit_code(), 1);
+}
+
+/// Tests hashing with several algorithms in one pass.
+///
+/// # Steps
+/// * Hash a file with md5 and sha256 with calculate_hashes
+/// * Update checksums for a directory with "md5,sha256"
+/// * Change a line of the sha256sum.txt and verify the directory with both algorithms
+/// * Verify it again with md5 only and a new state directory
+///
+/// # Expected
+/// * calculate_hashes returns one line per algorithm, in the given order
+/// * both hashsum files are written and list both files
+/// * the file whose sha256 hash changed fails, although its md5 hash matches
+/// * md5 alone finds no failure
+#[test]
+fn multiple_algorithms_test() {
+    use arkhash::util::calculate_hashes;
+
+    let dir = tempfile::tempdir().unwrap();
+    let state = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("abc"), "abc").unwrap();
+    fs::write(dir.path().join("other"), "other").unwrap();
+
+    let builder = || {
+        Options::builder()
+            .log_level(LogLevel::Quiet)
+            .state_dir(state.path().to_str().unwrap())
+            .folder(dir.path().to_str().unwrap())
+    };
+    let opts = builder().algorithm_name("md5,sha256").build().unwrap();
+    assert_eq!(opts.algorithms(), vec![Algorithm::Md5, Algorithm::Sha256]);
+
+    let (hashlines, _) = calculate_hashes(
+        PathBuf::from("./abc"),
+        dir.path(),
+        &opts.algorithms(),
+        None,
+        &opts,
+    )
+    .unwrap();
+    assert_eq!(
+        hashlines,
+        vec![
+            "900150983cd24fb0d6963f7d28e17f72  ./abc\n".to_string(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  ./abc\n".to_string(),
+        ]
+    );
+
+    arkhash::update(&Options {
+        mode: Mode::Update,
+        ..opts.clone()
+    });
+    for &algorithm in [Algorithm::Md5, Algorithm::Sha256].iter() {
+        let single = builder().algorithm(algorithm).build().unwrap();
+        let manifest = Manifest::read(dir.path(), &single).unwrap();
+        assert_eq!(manifest.entries().len(), 2);
+    }
+
+    let path = dir.path().join("sha256sum.txt");
+    let manifest = fs::read_to_string(&path).unwrap();
+    fs::write(
+        &path,
+        manifest.replace(
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            &"0".repeat(64),
+        ),
+    )
+    .unwrap();
+
+    let report = arkhash::verify(&Options {
+        mode: Mode::Verify,
+        ..opts
+    });
+    assert_eq!(report.directories[0].failed, vec!["./abc".to_string()]);
+
+    // the failed directory is not verified again in the same month with the same state
+    let fresh_state = tempfile::tempdir().unwrap();
+    let md5 = builder()
+        .algorithm(Algorithm::Md5)
+        .mode(Mode::Verify)
+        .state_dir(fresh_state.path().to_str().unwrap())
+        .build()
+        .unwrap();
+    let report = arkhash::verify(&md5);
+    assert!(report.directories[0].failed.is_empty());
+    assert_eq!(report.exit_code(), 0);
+}
+
+/// Tests parsing hashsum lines with the regex of regex_from_opts.
+///
+/// # Steps
+/// * Parse a normal GNU line, a BSD line, lines with leading whitespace, the binary mode indicator,
+///   more than two spaces, an escaped path and a size and modification time
+/// * Parse lines that are no hashsum lines
+///
+/// # Expected
+/// * hashsum and path are split off every hashsum line, spaces after the separator belong to the path
+/// * the other lines are rejected
+#[test]
+fn regex_from_opts_test() {
+    use arkhash::util::{parse_hashline, regex_from_opts};
+
+    let opts = Options::builder().build().unwrap();
+    let re = regex_from_opts(&opts).unwrap();
+    let hash = "a".repeat(40);
+    let parse = |line: String| parse_hashline(&re, &line);
+    let parsed = |path: &str| Some((hash.clone(), PathBuf::from(path)));
+
+    assert_eq!(parse(format!("{}  ./file\n", hash)), parsed("./file"));
+    assert_eq!(
+        parse(format!("SHA1 (./file) = {}\n", hash)),
+        parsed("./file")
+    );
+    assert_eq!(parse(format!("  {}  ./file\r\n", hash)), parsed("./file"));
+    assert_eq!(parse(format!("\t{}  ./file", hash)), parsed("./file"));
+    assert_eq!(parse(format!("{} *./file", hash)), parsed("./file"));
+    assert_eq!(parse(format!("{}  *./file", hash)), parsed("*./file"));
+    assert_eq!(parse(format!("{}    ./file", hash)), parsed("  ./file"));
+    assert_eq!(parse(format!("\\{}  ./a\\nb", hash)), parsed("./a\nb"));
+    assert_eq!(
+        parse(format!("{} 4 1500000000.000000000  ./file", hash)),
+        parsed("./file")
+    );
+
+    assert_eq!(parse(format!("{} ./file", hash)), None);
+    assert_eq!(parse(format!("{}  ./file", "a".repeat(39))), None);
+    assert_eq!(
+        parse("# arkhash manifest v1: hash size mtime path".to_string()),
+        None
+    );
+}
+
+/// Tests reading an _algorithm_sum.txt with a pattern set by the user.
+///
+/// # Steps
+/// * Write an _algorithm_sum.txt with the path before the hash, separated by a tab
+/// * Read it with the default pattern and with a pattern matching the lines
+/// * Build Options with patterns that do not compile or lack the named group path
+///
+/// # Expected
+/// * the default pattern finds no lines, the custom pattern finds both
+/// * the invalid patterns are rejected when the Options are built
+#[test]
+fn manifest_pattern_test() {
+    let folder = "testenvironment_manifest_pattern";
+    let _ = fs::remove_dir_all(folder);
+    fs::create_dir_all(folder).unwrap();
+    let hash = "c".repeat(40);
+    fs::write(
+        format!("{}/sha1sum.txt", folder),
+        format!("./first\t{}\n./second\t{}\n", hash, hash),
+    )
+    .unwrap();
+
+    let default = Manifest::read(folder.as_ref(), &Options::builder().build().unwrap());
+    let custom_opts = Options::builder()
+        .manifest_pattern(r"^(?P<path>[^\t]+)\t(?P<hash>[[:xdigit:]]{40})$")
+        .build()
+        .unwrap();
+    let custom = Manifest::read(folder.as_ref(), &custom_opts);
+
+    fs::remove_dir_all(folder).unwrap();
+
+    assert!(default.unwrap().entries().is_empty());
+    let custom = custom.unwrap();
+    assert_eq!(custom.entries().len(), 2);
+    assert_eq!(custom.entries()[1].path, PathBuf::from("./second"));
+    assert_eq!(custom.entries()[1].hash, hash);
+
+    assert!(Options::builder()
+        .manifest_pattern("(?P<hash>")
+        .build()
+        .unwrap_err()
+        .starts_with("invalid manifest pattern"));
+    assert!(Options::builder()
+        .manifest_pattern("(?P<hash>.*)")
+        .build()
+        .unwrap_err()
+        .contains("path"));
+}
+
+/// The files written by write_round_trip_files and their sha1 hashes
+const ROUND_TRIP_FILES: [(&str, &str, &str); 3] = [
+    (
+        "first",
+        "first file",
+        "c9be619500b84cdc8391b9f00c3c532e2e5e0d33",
+    ),
+    (
+        "second",
+        "second file",
+        "13cab0433629cfe5307f0a71ee097f78fe671474",
+    ),
+    (
+        "third",
+        "third file",
+        "a595b40533cfa9605fd9454c588086830ee2c45a",
+    ),
+];
+
+/// Writes the files of ROUND_TRIP_FILES to a directory
+fn write_round_trip_files(dir: &std::path::Path) {
+    fs::create_dir_all(dir).unwrap();
+    for (name, content, _) in &ROUND_TRIP_FILES {
+        fs::write(dir.join(name), content).unwrap();
+    }
+}
+
+/// Asserts that the sha1sum.txt of a directory lists exactly the files of ROUND_TRIP_FILES with their hashes
+fn assert_round_trip_manifest(dir: &std::path::Path, opts: &Options) {
+    let manifest = Manifest::read(dir, opts).unwrap();
+    let mut entries: Vec<(String, String)> = manifest
+        .entries()
+        .iter()
+        .map(|entry| (entry.path.to_string_lossy().to_string(), entry.hash.clone()))
+        .collect();
+    entries.sort();
+
+    let expected: Vec<(String, String)> = ROUND_TRIP_FILES
+        .iter()
+        .map(|(name, _, hash)| (format!("./{}", name), hash.to_string()))
+        .collect();
+    assert_eq!(entries, expected);
+}
+
+/// Flips the lowest bit of the first byte of a file
+fn flip_byte(path: &std::path::Path) {
+    let mut content = fs::read(path).unwrap();
+    content[0] ^= 1;
+    fs::write(path, content).unwrap();
+}
+
+/// Tests that a failing write of the sha1sum.txt leaves the previous one untouched.
+///
+/// # Steps
+/// * Update checksums for a directory
+/// * Add a file and link the temporary file of the sha1sum.txt to /dev/full, so writing it fails
+/// * Write the manifest with the new file
+///
+/// # Expected
+/// * writing fails with an error
+/// * the sha1sum.txt still lists only the first file and the temporary file is removed
+#[test]
+#[cfg(target_os = "linux")]
+fn manifest_write_failure_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("abc"), "abc").unwrap();
+    let opts = Options::builder()
+        .log_level(LogLevel::Quiet)
+        .state_dir(state.path().to_str().unwrap())
+        .folder(dir.path().to_str().unwrap())
+        .build()
+        .unwrap();
+    arkhash::update(&Options {
+        mode: Mode::Update,
+        ..opts.clone()
+    });
+    let before = fs::read_to_string(dir.path().join("sha1sum.txt")).unwrap();
+
+    let temp_path = dir.path().join(".sha1sum.txt.tmp");
+    std::os::unix::fs::symlink("/dev/full", &temp_path).unwrap();
+    let mut manifest = Manifest::read(dir.path(), &opts).unwrap();
+    manifest.upsert(ManifestEntry {
+        hash: "0".repeat(40),
+        path: PathBuf::from("./new"),
+        stamp: None,
+        metadata: None,
+    });
+
+    assert!(manifest.write(dir.path(), &opts).is_err());
+    assert_eq!(
+        fs::read_to_string(dir.path().join("sha1sum.txt")).unwrap(),
+        before
+    );
+    assert!(fs::symlink_metadata(&temp_path).is_err());
+}
+
+/// Tests that only one process at a time holds the lock of a state directory.
+///
+/// # Steps
+/// * Acquire the lock of a state directory
+/// * Try to acquire it a second time
+/// * Release the first lock and try again
+///
+/// # Expected
+/// * the second attempt fails while the first lock is held and succeeds after it was released
+/// * the lock file is removed when the lock is released
+#[test]
+fn run_lock_test() {
+    let state = tempfile::tempdir().unwrap();
+    let opts = Options::builder()
+        .state_dir(state.path().join("state").to_str().unwrap())
+        .build()
+        .unwrap();
+
+    let lock = RunLock::try_acquire(&opts).unwrap().unwrap();
+    assert_eq!(lock.lock_path(), state.path().join("state/.arkhash.lock"));
+    assert!(RunLock::try_acquire(&opts).unwrap().is_none());
+
+    drop(lock);
+    assert!(!state.path().join("state/.arkhash.lock").exists());
+    let lock = RunLock::try_acquire(&opts).unwrap();
+    assert!(lock.is_some());
+
+    drop(lock);
+    assert!(!state.path().join("state/.arkhash.lock").exists());
+}
+
+/// Tests writing absolute paths to the hashsum file and reading both styles.
+///
+/// # Steps
+/// * Update checksums for a directory with absolute paths
+/// * Verify the directory, from the library and after a relative line of another file was appended
+/// * Update checksums for the directory with relative paths
+///
+/// # Expected
+/// * the hashsum file lists the absolute paths, which are read like relative ones
+/// * verify passes both times and checks all files
+/// * the update writes relative paths again
+#[test]
+fn absolute_paths_test() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("sub")).unwrap();
+    fs::write(dir.path().join("abc"), "abc").unwrap();
+    fs::write(dir.path().join("sub/other"), "other").unwrap();
+
+    let builder = |state: &tempfile::TempDir| {
+        Options::builder()
+            .log_level(LogLevel::Quiet)
+            .state_dir(state.path().to_str().unwrap())
+            .folder(dir.path().to_str().unwrap())
+    };
+    let verify = || {
+        let state = tempfile::tempdir().unwrap();
+        arkhash::verify(&builder(&state).mode(Mode::Verify).build().unwrap())
+            .directories
+            .remove(0)
+    };
+    let state = tempfile::tempdir().unwrap();
+    arkhash::update(
+        &builder(&state)
+            .mode(Mode::Update)
+            .paths(PathStyle::Absolute)
+            .build()
+            .unwrap(),
+    );
+
+    let manifest_path = dir.path().join("sha1sum.txt");
+    let hashfile = fs::read_to_string(&manifest_path).unwrap();
+    let absolute = std::path::absolute(dir.path()).unwrap();
+    assert!(hashfile.contains(&format!("  {}\n", absolute.join("sub/other").display())));
+    let manifest = Manifest::read(dir.path(), &builder(&state).build().unwrap()).unwrap();
+    assert!(manifest.get(&PathBuf::from("./sub/other")).is_some());
+
+    let report = verify();
+    assert_eq!(report.listed_files, 2);
+    assert!(report.is_ok());
+
+    fs::write(dir.path().join("third"), "third").unwrap();
+    fs::write(
+        &manifest_path,
+        format!(
+            "{}34fb3300b9a77bebdc988ec3edd0d4a6a42a26f9  ./third\n",
+            hashfile
+        ),
+    )
+    .unwrap();
+    let report = verify();
+    assert_eq!(report.listed_files, 3);
+    assert!(report.is_ok());
+
+    arkhash::update(&builder(&state).mode(Mode::Update).build().unwrap());
+    let hashfile = fs::read_to_string(&manifest_path).unwrap();
+    assert!(hashfile.contains("  ./sub/other\n"));
+    assert!(!hashfile.contains(&absolute.display().to_string()));
+}
+
+/// Tests writing and reading hashsum files compressed with gzip.
+///
+/// # Steps
+/// * Update checksums for a directory with --compress
+/// * Verify the directory without --compress
+/// * Write a plain sha1sum.txt next to it that lists a wrong hash and verify again
+/// * Truncate the sha1sum.txt.gz and verify again
+/// * Restore the sha1sum.txt.gz and update without --compress
+///
+/// # Expected
+/// * only the sha1sum.txt.gz is written, it is compressed and lists both files
+/// * the first verify reads it and is OK
+/// * the compressed file is preferred over the plain one
+/// * the truncated file can not be read and fails the directory
+/// * the update writes a plain sha1sum.txt again and removes the compressed one
+#[test]
+fn compress_test() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("abc"), "abc").unwrap();
+    fs::write(dir.path().join("other"), "other").unwrap();
+
+    let builder = |state: &tempfile::TempDir| {
+        Options::builder()
+            .log_level(LogLevel::Quiet)
+            .state_dir(state.path().to_str().unwrap())
+            .folder(dir.path().to_str().unwrap())
+    };
+    let verify = || {
+        let state = tempfile::tempdir().unwrap();
+        arkhash::verify(&builder(&state).mode(Mode::Verify).build().unwrap())
+            .directories
+            .remove(0)
+    };
+    let state = tempfile::tempdir().unwrap();
+    arkhash::update(
+        &builder(&state)
+            .mode(Mode::Update)
+            .compress(true)
+            .build()
+            .unwrap(),
+    );
+
+    let gzip_path = dir.path().join("sha1sum.txt.gz");
+    let compressed = fs::read(&gzip_path).unwrap();
+    assert_eq!(&compressed[..2], &[0x1f, 0x8b]);
+    assert!(!dir.path().join("sha1sum.txt").exists());
+    let manifest = Manifest::read(dir.path(), &builder(&state).build().unwrap()).unwrap();
+    assert_eq!(manifest.entries().len(), 2);
+
+    let report = verify();
+    assert_eq!(report.listed_files, 2);
+    assert!(report.is_ok());
+
+    fs::write(
+        dir.path().join("sha1sum.txt"),
+        format!("{}  ./abc\n", "0".repeat(40)),
+    )
+    .unwrap();
+    assert!(verify().is_ok());
+
+    fs::write(&gzip_path, &compressed[..compressed.len() / 2]).unwrap();
+    let report = verify();
+    assert!(report.error.is_some());
+    assert!(!report.is_ok());
+
+    fs::write(&gzip_path, &compressed).unwrap();
+    arkhash::update(&builder(&state).mode(Mode::Update).build().unwrap());
+    assert!(!gzip_path.exists());
+    let plain = fs::read_to_string(dir.path().join("sha1sum.txt")).unwrap();
+    assert_eq!(plain.lines().count(), 3);
+}
+
+/// Tests storing hashes in extended attributes.
+///
+/// # Steps
+/// * Format and parse the value of an attribute, parse improperly formatted values
+/// * Update checksums for a directory with a subdirectory with --xattr
+/// * Verify the directory, change a stored hash and verify again
+///
+/// # Expected
+/// * the parsed value equals the formatted one, the improperly formatted ones are rejected
+/// * no sha256sum.txt is written, every file carries its hash instead
+/// * the first verify is OK, the second one lists the file whose stored hash changed
+#[test]
+fn xattr_test() {
+    use arkhash::xattrs;
+
+    let stamp = FileStamp {
+        size: 3,
+        mtime_secs: 1_700_000_000,
+        mtime_nanos: 42,
+    };
+    let value = xattrs::format("900150983cd24fb0d6963f7d28e17f72", &stamp);
+    assert_eq!(
+        value,
+        "900150983cd24fb0d6963f7d28e17f72 3 1700000000.000000042"
+    );
+    assert_eq!(
+        xattrs::parse(&value),
+        Some(("900150983cd24fb0d6963f7d28e17f72".to_string(), stamp))
+    );
+    assert_eq!(xattrs::parse("hash 3 1700000000.000000042"), None);
+    assert_eq!(xattrs::parse("abc 3"), None);
+    assert_eq!(xattrs::parse("abc 3 1700000000.000000042 x"), None);
+
+    let dir = tempfile::tempdir().unwrap();
+    if !xattrs::is_supported(dir.path()) {
+        return;
+    }
+    fs::create_dir(dir.path().join("sub")).unwrap();
+    fs::write(dir.path().join("abc"), "abc").unwrap();
+    fs::write(dir.path().join("sub").join("other"), "other").unwrap();
+
+    // every verify gets a state directory of its own, a failed directory is not verified again
+    let builder = |state: &tempfile::TempDir| {
+        Options::builder()
+            .algorithm(Algorithm::Sha256)
+            .xattr(true)
+            .log_level(LogLevel::Quiet)
+            .state_dir(state.path().to_str().unwrap())
+            .folder(dir.path().to_str().unwrap())
+    };
+    let state = tempfile::tempdir().unwrap();
+    arkhash::update(&builder(&state).mode(Mode::Update).build().unwrap());
+
+    assert!(!dir.path().join("sha256sum.txt").exists());
+    let (hash, stamp) = xattrs::read(&dir.path().join("abc"), Algorithm::Sha256)
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        hash,
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+    assert_eq!(stamp.size, 3);
+
+    let report = arkhash::verify(&builder(&state).mode(Mode::Verify).build().unwrap());
+    assert_eq!(report.directories[0].listed_files, 2);
+    assert_eq!(report.exit_code(), 0);
+
+    let other = dir.path().join("sub").join("other");
+    let (_, stamp) = xattrs::read(&other, Algorithm::Sha256).unwrap().unwrap();
+    xattrs::write(&other, Algorithm::Sha256, &"0".repeat(64), &stamp).unwrap();
+    let state = tempfile::tempdir().unwrap();
+    let report = arkhash::verify(&builder(&state).mode(Mode::Verify).build().unwrap());
+    assert_eq!(
+        report.directories[0].failed,
+        vec!["./sub/other".to_string()]
+    );
+}
+
+/// Tests skipping files whose extended attribute can be trusted in verify mode.
+///
+/// # Steps
+/// * Update checksums for a directory, once into the sha1sum.txt and once into extended attributes
+/// * Change a file while keeping its size and modification time, as bit rot would
+/// * Verify the directory with and without --trust-xattr
+/// * Touch another file and verify with --trust-xattr again
+///
+/// # Expected
+/// * with --trust-xattr, no file is read and the rotten file goes unnoticed
+/// * without it, the rotten file fails
+/// * the touched file is read again, the other one is still trusted
+#[test]
+fn trust_xattr_test() {
+    let dir = tempfile::tempdir().unwrap();
+    if !arkhash::xattrs::is_supported(dir.path()) {
+        return;
+    }
+    fs::write(dir.path().join("abc"), "abc").unwrap();
+    fs::write(dir.path().join("other"), "other").unwrap();
+
+    let builder = |state: &tempfile::TempDir| {
+        Options::builder()
+            .log_level(LogLevel::Quiet)
+            .state_dir(state.path().to_str().unwrap())
+            .folder(dir.path().to_str().unwrap())
+    };
+    let state = tempfile::tempdir().unwrap();
+    for &xattr in [false, true].iter() {
+        arkhash::update(
+            &builder(&state)
+                .mode(Mode::Update)
+                .xattr(xattr)
+                .build()
+                .unwrap(),
+        );
+    }
+
+    let rotten = dir.path().join("abc");
+    let modified = fs::metadata(&rotten).unwrap().modified().unwrap();
+    fs::write(&rotten, "abd").unwrap();
+    fs::File::options()
+        .write(true)
+        .open(&rotten)
+        .unwrap()
+        .set_modified(modified)
+        .unwrap();
+
+    let verify = |trust_xattr: bool| {
+        let state = tempfile::tempdir().unwrap();
+        let opts = builder(&state)
+            .mode(Mode::Verify)
+            .trust_xattr(trust_xattr)
+            .build()
+            .unwrap();
+        arkhash::verify(&opts).directories.remove(0)
+    };
+    let trusted = verify(true);
+    assert_eq!(trusted.trusted_files, 2);
+    assert_eq!(trusted.bytes, 0);
+    assert!(trusted.is_ok());
+
+    let full = verify(false);
+    assert_eq!(full.trusted_files, 0);
+    assert_eq!(full.failed, vec!["./abc".to_string()]);
+
+    fs::write(dir.path().join("other"), "OTHER").unwrap();
+    let touched = verify(true);
+    assert_eq!(touched.trusted_files, 1);
+    assert_eq!(touched.failed, vec!["./other".to_string()]);
+}
+
+/// A hasher of a single byte, the XOR of every byte of the data
+struct XorHasher(u8);
+
+impl arkhash::Hasher for XorHasher {
+    fn update(&mut self, data: &[u8]) {
+        self.0 = data.iter().fold(self.0, |hash, byte| hash ^ byte);
+    }
+
+    fn finalize(self: Box<Self>) -> String {
+        format!("{:02x}", self.0)
+    }
+
+    fn name(&self) -> &str {
+        "xor8"
+    }
+}
+
+/// Tests registering a custom hasher.
+///
+/// # Steps
+/// * Register the algorithm xor8 and names that are taken or invalid
+/// * Update checksums for a directory with the algorithm chosen by its name
+/// * Change a file and verify the directory
+///
+/// # Expected
+/// * xor8 is available by its name, the taken and invalid names are rejected
+/// * the hash of the file is written to xor8sum.txt
+/// * verify mode detects the changed file
+#[test]
+fn custom_hasher_test() {
+    let algorithm = arkhash::register("xor8", "XOR8", 2, || Box::new(XorHasher(0))).unwrap();
+    assert_eq!(algorithm, Algorithm::Custom("xor8"));
+    assert_eq!(Algorithm::from_name("XOR8"), Some(algorithm));
+    assert!(Algorithm::available().ends_with("adler32, xor8"));
+    assert!(arkhash::register("sha1", "SHA1", 40, || Box::new(XorHasher(0))).is_err());
+    assert!(arkhash::register("Bad name", "BAD", 2, || Box::new(XorHasher(0))).is_err());
+
+    let dir = tempfile::tempdir().unwrap();
+    let state = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("a"), [0x0f, 0xf0]).unwrap();
+    let opts = |mode| {
+        Options::builder()
+            .mode(mode)
+            .algorithm_name("xor8")
+            .log_level(LogLevel::Quiet)
+            .state_dir(state.path().to_str().unwrap())
+            .folder(dir.path().to_str().unwrap())
+            .build()
+            .unwrap()
+    };
+
+    arkhash::update(&opts(Mode::Update));
+    let manifest = fs::read_to_string(dir.path().join("xor8sum.txt")).unwrap();
+    assert!(manifest.lines().last().unwrap().starts_with("ff 2 "));
+
+    fs::write(dir.path().join("a"), [0x0f]).unwrap();
+    let report = arkhash::verify(&opts(Mode::Verify));
+    assert_eq!(report.exit_code(), 1);
+}
+
+/// Tests the callback that is called for every file.
+///
+/// # Steps
+/// * Update checksums for a directory of three files with a callback collecting the events
+/// * Change one file, remove another one and verify the directory with the callback
+///
+/// # Expected
+/// * update mode reports every file as hashed, with its size
+/// * verify mode reports the unchanged file as OK, the changed one as changed and the removed one as missing
+#[test]
+fn file_callback_test() {
+    use arkhash::{FileEvent, FileOutcome};
+    use std::sync::{Arc, Mutex};
+
+    let dir = tempfile::tempdir().unwrap();
+    let state = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("a"), "a").unwrap();
+    fs::write(dir.path().join("b"), "bb").unwrap();
+    fs::write(dir.path().join("c"), "ccc").unwrap();
+
+    let run = |update: bool| {
+        let events: Arc<Mutex<Vec<FileEvent>>> = Arc::new(Mutex::new(Vec::new()));
+        let collected = Arc::clone(&events);
+        let opts = Options::builder()
+            .mode(if update { Mode::Update } else { Mode::Verify })
+            .log_level(LogLevel::Quiet)
+            .state_dir(state.path().to_str().unwrap())
+            .folder(dir.path().to_str().unwrap())
+            .on_file(move |event| collected.lock().unwrap().push(event.clone()))
+            .build()
+            .unwrap();
+        if update {
+            arkhash::update(&opts);
+        } else {
+            arkhash::verify(&opts);
+        }
+        let mut events = events.lock().unwrap().clone();
+        events.sort_by(|a, b| a.path.cmp(&b.path));
+        events
+            .into_iter()
+            .map(|event| {
+                assert_eq!(event.directory, dir.path());
+                (event.path, event.bytes, event.outcome)
+            })
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        run(true),
+        vec![
+            (PathBuf::from("./a"), 1, FileOutcome::Hashed),
+            (PathBuf::from("./b"), 2, FileOutcome::Hashed),
+            (PathBuf::from("./c"), 3, FileOutcome::Hashed),
+        ]
+    );
+
+    fs::write(dir.path().join("b"), "xx").unwrap();
+    fs::remove_file(dir.path().join("c")).unwrap();
+    assert_eq!(
+        run(false),
+        vec![
+            (PathBuf::from("./a"), 1, FileOutcome::Ok),
+            (PathBuf::from("./b"), 2, FileOutcome::Changed),
+            (PathBuf::from("./c"), 0, FileOutcome::Missing),
+        ]
+    );
+}
+
+/// A reporter recording the calls it receives
+struct CapturingReporter(std::sync::Arc<std::sync::Mutex<Vec<String>>>);
+
+impl arkhash::Reporter for CapturingReporter {
+    fn begin(&mut self, workdirs: &[String]) {
+        self.0
+            .lock()
+            .unwrap()
+            .push(format!("begin {}", workdirs.len()));
+    }
+
+    fn dir_started(&mut self, line: usize) {
+        self.0.lock().unwrap().push(format!("started {}", line));
+    }
+
+    fn file_checked(&mut self, line: usize, event: &arkhash::FileEvent) {
+        self.0
+            .lock()
+            .unwrap()
+            .push(format!("file {} {}", line, event.path.display()));
+    }
+
+    fn dir_finished(&mut self, line: usize, message: &str) {
+        self.0
+            .lock()
+            .unwrap()
+            .push(format!("finished {} {}", line, message));
+    }
+
+    fn end(&mut self) {
+        self.0.lock().unwrap().push("end".to_string());
+    }
+}
+
+/// Tests injecting a reporter into verify mode.
+///
+/// # Steps
+/// * Update checksums for a directory of a single file
+/// * Verify the directory with a reporter recording its calls
+///
+/// # Expected
+/// * the reporter receives the start of the directory, the file, the result of the directory and the end in order
+#[test]
+fn reporter_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("a"), "a").unwrap();
+    let builder = || {
+        Options::builder()
+            .log_level(LogLevel::Quiet)
+            .state_dir(state.path().to_str().unwrap())
+            .folder(dir.path().to_str().unwrap())
+    };
+    arkhash::update(&builder().mode(Mode::Update).build().unwrap());
+
+    let calls = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let opts = builder()
+        .mode(Mode::Verify)
+        .reporter(CapturingReporter(std::sync::Arc::clone(&calls)))
+        .build()
+        .unwrap();
+    assert_eq!(arkhash::verify(&opts).exit_code(), 0);
+    let calls = calls.lock().unwrap();
+    assert_eq!(calls.len(), 5);
+    assert_eq!(calls[..3], ["begin 1", "started 0", "file 0 ./a"]);
+    assert!(calls[3].starts_with("finished 0 checked: OK"));
+    assert_eq!(calls[4], "end");
+}
+
+/// Tests the round trip of update and verify on a single directory in a temporary directory.
+///
+/// # Steps
+/// * Update checksums for a directory of three files
+/// * Verify the directory
+/// * Flip a byte of one file and verify again
+///
+/// # Expected
+/// * the sha1sum.txt lists the three files with their hashes
+/// * the first verify report is OK
+/// * the second verify report lists exactly the changed file as failed
+#[test]
+fn round_trip_test() {
+    let root = tempfile::tempdir().unwrap();
+    let state = tempfile::tempdir().unwrap();
+    let dir = root.path().join("archive");
+    write_round_trip_files(&dir);
+
+    let opts = Options::builder()
+        .log_level(LogLevel::Quiet)
+        .state_dir(state.path().to_str().unwrap())
+        .folder(dir.to_str().unwrap())
+        .build()
+        .unwrap();
+
+    let update_report = arkhash::update(&Options {
+        mode: Mode::Update,
+        ..opts.clone()
+    });
+    assert_eq!(update_report.directories.len(), 1);
+    assert_eq!(update_report.hashed_files(), 3);
+    assert_round_trip_manifest(&dir, &opts);
+
+    let verify_opts = Options {
+        mode: Mode::Verify,
+        ..opts
+    };
+    let report = arkhash::verify(&verify_opts);
+    assert_eq!(report.exit_code(), 0);
+    assert!(report.directories[0].is_ok());
+
+    flip_byte(&dir.join("second"));
+    let report = arkhash::verify(&verify_opts);
+    assert_eq!(report.exit_code(), 1);
+    assert_eq!(report.directories[0].failed, vec!["./second".to_string()]);
+    assert!(report.directories[0].missing.is_empty());
+    assert!(report.directories[0].unreadable.is_empty());
+}
+
+/// Tests the round trip of update and verify in subdir mode in a temporary directory.
+///
+/// # Steps
+/// * Update checksums for the subdirectories of a directory, each containing three files
+/// * Verify the subdirectories
+/// * Flip a byte of one file in one subdirectory and verify again with a fresh state directory
+///
+/// # Expected
+/// * the sha1sum.txt of every subdirectory lists its three files with their hashes
+/// * the first verify report is OK for both subdirectories
+/// * the second verify report lists exactly the changed file as failed, the other subdirectory is OK
+#[test]
+fn round_trip_subdir_test() {
+    let root = tempfile::tempdir().unwrap();
+    let state = tempfile::tempdir().unwrap();
+    let subdirs = [root.path().join("one"), root.path().join("two")];
+    for subdir in &subdirs {
+        write_round_trip_files(subdir);
+    }
+
+    let opts = Options::builder()
+        .subdir_mode(true)
+        .log_level(LogLevel::Quiet)
+        .state_dir(state.path().to_str().unwrap())
+        .folder(root.path().to_str().unwrap())
+        .build()
+        .unwrap();
+
+    let update_report = arkhash::update(&Options {
+        mode: Mode::Update,
+        ..opts.clone()
+    });
+    assert_eq!(update_report.directories.len(), 2);
+    for subdir in &subdirs {
+        assert_round_trip_manifest(subdir, &opts);
+    }
+
+    let verify_opts = Options {
+        mode: Mode::Verify,
+        ..opts
+    };
+    let report = arkhash::verify(&verify_opts);
+    assert_eq!(report.exit_code(), 0);
+    assert_eq!(report.directories.len(), 2);
+    assert!(report.directories.iter().all(|d| d.is_ok()));
+
+    // the subdirectories are known good in the first state directory
+    let fresh_state = tempfile::tempdir().unwrap();
+    flip_byte(&subdirs[1].join("first"));
+    let report = arkhash::verify(&Options {
+        state_dir: fresh_state.path().to_path_buf(),
+        ..verify_opts
+    });
+    assert_eq!(report.exit_code(), 1);
+    let failed: Vec<_> = report.directories.iter().filter(|d| !d.is_ok()).collect();
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].path, subdirs[1]);
+    assert_eq!(failed[0].failed, vec!["./first".to_string()]);
+}
+
+/// Returns a strategy generating valid file names: no slash, no NUL, not "." or "..".
+/// Spaces, tabs, line breaks, backslashes, unicode and emoji are generated often.
+fn file_name_strategy() -> impl Strategy<Value = String> {
+    let special = prop::sample::select(vec![
+        ' ', '\t', '\n', '\r', '\\', '*', '(', ')', '=', '#', 'ä', 'ß', '漢', '😀', '\u{200b}',
+    ]);
+    let char_strategy = prop_oneof![
+        special,
+        any::<char>().prop_filter("no slash or NUL", |&c| c != '/' && c != '\0'),
+    ];
+    prop::collection::vec(char_strategy, 1..24)
+        .prop_map(|chars| chars.into_iter().collect::<String>())
+        .prop_filter("no . or ..", |name| name != "." && name != "..")
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    /// Tests that paths survive writing a line with calculate_hash and reading it with the manifest parser.
+    ///
+    /// # Steps
+    /// * Create a file with a generated name in a temporary directory
+    /// * Hash it with calculate_hash in the GNU and the BSD format
+    /// * Parse the line with Manifest::read_file and with parse_hashline
+    ///
+    /// # Expected
+    /// * both parsers return the path the file was hashed with and the hash of its contents
+    #[test]
+    fn hashline_path_round_trip_test(name in file_name_strategy()) {
+        use arkhash::util::{calculate_hash, parse_hashline, regex_from_opts};
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(&name), "Small file").unwrap();
+        let path = PathBuf::from(".").join(&name);
+
+        for &format in [ManifestFormat::Gnu, ManifestFormat::Bsd].iter() {
+            let opts = Options::builder()
+                .log_level(LogLevel::Quiet)
+                .manifest_format(format)
+                .build()
+                .unwrap();
+            let hashline = calculate_hash(path.clone(), dir.path(), &opts).unwrap();
+
+            let re = regex_from_opts(&opts).unwrap();
+            let (hash, parsed) = parse_hashline(&re, &hashline).unwrap();
+            prop_assert_eq!(&hash, "b85ff052083dea11bc843a9d910edb8219e166da");
+            prop_assert_eq!(&parsed, &path);
+
+            let manifest_path = dir.path().join("manifest.txt");
+            fs::write(&manifest_path, &hashline).unwrap();
+            let manifest = Manifest::read_file(&manifest_path, &opts).unwrap();
+            prop_assert_eq!(manifest.entries().len(), 1);
+            prop_assert_eq!(&manifest.entries()[0].hash, &hash);
+            prop_assert_eq!(&manifest.entries()[0].path, &path);
+        }
+    }
+
+    /// Tests that entries survive writing and reading a manifest in every format.
+    ///
+    /// # Steps
+    /// * Write manifests of entries with generated names and stamps, in every format and NUL separated
+    /// * Read the manifests again
+    ///
+    /// # Expected
+    /// * the entries are read back with their paths and hashes in the order they were written,
+    ///   the stamps only in the GNU format
+    #[test]
+    fn manifest_path_round_trip_test(
+        names in prop::collection::hash_set(file_name_strategy(), 1..8),
+        size in any::<u64>(),
+        mtime_secs in any::<u32>(),
+        mtime_nanos in 0u32..1_000_000_000,
+    ) {
+        let dir = tempfile::tempdir().unwrap();
+        let entries: Vec<ManifestEntry> = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| ManifestEntry {
+                hash: format!("{:040x}", i),
+                path: PathBuf::from(".").join(name),
+                stamp: Some(FileStamp {
+                    size,
+                    mtime_secs: mtime_secs.into(),
+                    mtime_nanos,
+                }),
+                metadata: None,
+            })
+            .collect();
+
+        for &format in [ManifestFormat::Gnu, ManifestFormat::Plain, ManifestFormat::Bsd].iter() {
+            for &null_separated in [false, true].iter() {
+                let opts = Options::builder()
+                    .manifest_format(format)
+                    .null_separated(null_separated)
+                    .build()
+                    .unwrap();
+                let mut manifest = Manifest::read(dir.path(), &opts).unwrap();
+                for entry in entries.iter() {
+                    manifest.upsert(entry.clone());
+                }
+                manifest.write(dir.path(), &opts).unwrap();
+
+                let read = Manifest::read(dir.path(), &opts).unwrap();
+                fs::remove_file(Manifest::path(dir.path(), opts.algorithm)).unwrap();
+
+                prop_assert_eq!(read.entries().len(), entries.len());
+                for (read_entry, entry) in read.entries().iter().zip(entries.iter()) {
+                    prop_assert_eq!(&read_entry.hash, &entry.hash);
+                    prop_assert_eq!(&read_entry.path, &entry.path);
+                    if format == ManifestFormat::Gnu {
+                        prop_assert_eq!(&read_entry.stamp, &entry.stamp);
+                    }
+                }
+            }
+        }
+    }
+}